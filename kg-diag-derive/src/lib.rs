@@ -12,10 +12,17 @@ use kg_diag::*;
 use proc_macro2::Span;
 
 decl_derive!([Detail, attributes(diag)] => detail_derive);
+decl_derive!([Diag, attributes(diag)] => diag_derive);
 
 struct DiagAttr {
     code: u32,
     severity: Severity,
+    transparent: bool,
+    flatten: bool,
+    span: Option<FieldShape>,
+    severity_field: bool,
+    cause: Option<FieldShape>,
+    help: Option<String>,
 }
 
 fn path_eq(path: &syn::Path, s: &str) -> bool {
@@ -25,26 +32,47 @@ fn path_eq(path: &syn::Path, s: &str) -> bool {
     false
 }
 
-fn detail_derive(mut st: synstructure::Structure) -> proc_macro2::TokenStream {
-    let mut code_offset: u32 = 0;
-    let mut severity = Severity::Failure;
-
-    let mut log_file = None;
+fn detail_derive(st: synstructure::Structure) -> proc_macro2::TokenStream {
+    detail_impl(st).unwrap_or_else(|err| err.to_compile_error())
+}
 
+fn open_log_file() -> syn::Result<Option<File>> {
     if let Ok(p) = std::env::var("KG_DIAG_OUTPUT") {
-        log_file = Some(File::with_options()
+        let f = File::with_options()
             .create(true)
             .write(true)
             .append(true)
             .truncate(false)
-            .open(p).unwrap());
+            .open(&p)
+            .map_err(|err| syn::Error::new(Span::call_site(), format!("cannot open KG_DIAG_OUTPUT file '{}': {}", p, err)))?;
+        Ok(Some(f))
+    } else {
+        Ok(None)
     }
+}
+
+fn log_err(err: std::io::Error) -> syn::Error {
+    syn::Error::new(Span::call_site(), format!("cannot write KG_DIAG_OUTPUT file: {}", err))
+}
+
+fn detail_impl(mut st: synstructure::Structure) -> syn::Result<proc_macro2::TokenStream> {
+    let mut code_offset: u32 = 0;
+    let mut severity = Severity::Failure;
+    let mut domain = None;
+    let mut code_range: Option<(u32, u32, syn::LitStr)> = None;
+    let mut constructors = false;
+    let mut register = false;
+    let mut krate = None;
+    let mut serialize = false;
+    let mut std_error = false;
 
-    if log_file.is_some() {
-        write!(log_file.as_mut().unwrap(), "{}\n", Span::call_site().unwrap().source_file().path().display()).unwrap();
+    let mut log_file = open_log_file()?;
+
+    if let Some(ref mut f) = log_file {
+        write!(f, "{}\n", Span::call_site().unwrap().source_file().path().display()).map_err(log_err)?;
     }
 
-    let container_attr = find_nested_attr(&st.ast().attrs, "diag");
+    let container_attr = find_nested_attr(&st.ast().attrs, "diag")?;
     if let Some(params) = container_attr {
         for p in params {
             match p {
@@ -53,7 +81,7 @@ fn detail_derive(mut st: synstructure::Structure) -> proc_macro2::TokenStream {
                     lit: syn::Lit::Int(ref i),
                     ..
                 })) if path_eq(path, "code_offset") => {
-                    code_offset = i.base10_parse().unwrap_or_default();
+                    code_offset = i.base10_parse()?;
                 }
                 syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
                     ref path,
@@ -61,11 +89,11 @@ fn detail_derive(mut st: synstructure::Structure) -> proc_macro2::TokenStream {
                     ..
                 })) if path_eq(path, "severity") => match Severity::try_from(s.value().as_ref()) {
                     Ok(s) => severity = s,
-                    Err(value) => panic!(format!(
+                    Err(value) => return Err(syn::Error::new_spanned(s, format!(
                         "invalid default severity \"{}\" for type {}",
                         value,
                         st.ast().ident
-                    )),
+                    ))),
                 },
                 syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
                     ref path,
@@ -73,17 +101,61 @@ fn detail_derive(mut st: synstructure::Structure) -> proc_macro2::TokenStream {
                     ..
                 })) if path_eq(path, "severity") => match Severity::try_from(c.value()) {
                     Ok(s) => severity = s,
-                    Err(value) => panic!(format!(
+                    Err(value) => return Err(syn::Error::new_spanned(c, format!(
                         "invalid default severity '{}' for type {}",
                         value,
                         st.ast().ident
-                    )),
+                    ))),
+                },
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    lit: syn::Lit::Str(ref s),
+                    ..
+                })) if path_eq(path, "domain") => {
+                    if s.value().is_empty() {
+                        return Err(syn::Error::new_spanned(s, format!(
+                            "empty domain for type {}",
+                            st.ast().ident
+                        )));
+                    }
+                    domain = Some(s.value());
+                }
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    lit: syn::Lit::Str(ref s),
+                    ..
+                })) if path_eq(path, "code_range") => match parse_code_range(&s.value()) {
+                    Some((start, end)) => code_range = Some((start, end, s.clone())),
+                    None => return Err(syn::Error::new_spanned(s, format!(
+                        "invalid code range \"{}\" for type {}, expected \"start..end\" or \"start..=end\"",
+                        s.value(),
+                        st.ast().ident
+                    ))),
                 },
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path_eq(path, "constructors") => {
+                    constructors = true;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path_eq(path, "register") => {
+                    register = true;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path_eq(path, "serialize") => {
+                    serialize = true;
+                }
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path_eq(path, "std_error") => {
+                    std_error = true;
+                }
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    lit: syn::Lit::Str(ref s),
+                    ..
+                })) if path_eq(path, "crate") => {
+                    krate = Some(s.parse::<syn::Path>()?);
+                }
                 _ => {
-                    panic!(format!(
+                    return Err(syn::Error::new_spanned(p, format!(
                         "invalid diag(...) attribute for type {}",
                         st.ast().ident
-                    ));
+                    )));
                 }
             }
         }
@@ -93,11 +165,20 @@ fn detail_derive(mut st: synstructure::Structure) -> proc_macro2::TokenStream {
     let mut code = code_offset + 1;
 
     for ref mut v in st.variants_mut() {
-        v.filter(|_| false);
+        let mut a = DiagAttr {
+            code,
+            severity,
+            transparent: false,
+            flatten: false,
+            span: None,
+            severity_field: false,
+            cause: None,
+            help: doc_comment(v.ast().attrs)?,
+        };
+        let mut severity_attr = None;
+        let mut code_attr = false;
 
-        let mut a = DiagAttr { code, severity };
-
-        let vattr = find_nested_attr(v.ast().attrs, "diag");
+        let vattr = find_nested_attr(v.ast().attrs, "diag")?;
         if let Some(params) = vattr {
             for p in params {
                 match p {
@@ -106,90 +187,526 @@ fn detail_derive(mut st: synstructure::Structure) -> proc_macro2::TokenStream {
                         lit: syn::Lit::Int(ref i),
                         ..
                     })) if path_eq(path, "code") => {
-                        a.code = code_offset + i.base10_parse::<u32>().unwrap_or_default();
+                        a.code = code_offset + i.base10_parse::<u32>()?;
+                        code_attr = true;
                     }
                     syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
                         ref path,
                         lit: syn::Lit::Str(ref s),
                         ..
                     })) if path_eq(path, "severity") => match Severity::try_from(s.value().as_ref()) {
-                        Ok(s) => a.severity = s,
-                        Err(value) => panic!(format!(
+                        Ok(sev) => {
+                            a.severity = sev;
+                            severity_attr = Some(p.clone());
+                        }
+                        Err(value) => return Err(syn::Error::new_spanned(s, format!(
                             "invalid severity \"{}\" for variant {}",
                             value,
                             v.ast().ident
-                        )),
+                        ))),
                     },
                     syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
                         ref path,
                         lit: syn::Lit::Char(ref c),
                         ..
                     })) if path_eq(path, "severity") => match Severity::try_from(c.value()) {
-                        Ok(s) => a.severity = s,
-                        Err(value) => panic!(format!(
+                        Ok(sev) => {
+                            a.severity = sev;
+                            severity_attr = Some(p.clone());
+                        }
+                        Err(value) => return Err(syn::Error::new_spanned(c, format!(
                             "invalid severity '{}' for variant {}",
                             value,
                             v.ast().ident
-                        )),
+                        ))),
                     },
+                    syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path_eq(path, "transparent") => {
+                        a.transparent = true;
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path_eq(path, "flatten") => {
+                        a.flatten = true;
+                    }
                     _ => {
-                        panic!(format!(
+                        return Err(syn::Error::new_spanned(p, format!(
                             "invalid diag(...) attribute for variant {}",
                             v.ast().ident
-                        ));
+                        )));
                     }
                 }
             }
         }
 
-        if a.code > code {
-            code = a.code + 1;
+        if a.transparent && a.flatten {
+            return Err(syn::Error::new_spanned(v.ast().ident, format!(
+                "variant {} cannot be both transparent and flattened",
+                v.ast().ident
+            )));
+        }
+
+        if a.transparent {
+            if v.bindings().len() != 1 {
+                return Err(syn::Error::new_spanned(v.ast().ident, format!(
+                    "transparent variant {} must have exactly one field",
+                    v.ast().ident
+                )));
+            }
+        } else if a.flatten {
+            if v.bindings().len() != 1 {
+                return Err(syn::Error::new_spanned(v.ast().ident, format!(
+                    "flattened variant {} must have exactly one field",
+                    v.ast().ident
+                )));
+            }
+            if !code_attr {
+                return Err(syn::Error::new_spanned(v.ast().ident, format!(
+                    "flattened variant {} requires explicit code, which is used as offset of inner codes",
+                    v.ast().ident
+                )));
+            }
         } else {
-            code += 1;
+            let mut span_field = None;
+            let mut severity_field = None;
+            let mut cause_field = None;
+            for f in v.ast().fields.iter() {
+                if has_field_flag(f, "span")? {
+                    if span_field.is_some() {
+                        return Err(syn::Error::new_spanned(f, format!(
+                            "multiple span fields in variant {}",
+                            v.ast().ident
+                        )));
+                    }
+                    span_field = Some(f);
+                }
+                if has_field_flag(f, "severity_field")? {
+                    if severity_field.is_some() {
+                        return Err(syn::Error::new_spanned(f, format!(
+                            "multiple severity fields in variant {}",
+                            v.ast().ident
+                        )));
+                    }
+                    severity_field = Some(f);
+                }
+                if has_field_flag(f, "cause")? {
+                    if cause_field.is_some() {
+                        return Err(syn::Error::new_spanned(f, format!(
+                            "multiple cause fields in variant {}",
+                            v.ast().ident
+                        )));
+                    }
+                    cause_field = Some(f);
+                }
+            }
+            if let (Some(_), Some(attr)) = (severity_field, severity_attr) {
+                return Err(syn::Error::new_spanned(attr, format!(
+                    "severity of variant {} is already taken from severity_field",
+                    v.ast().ident
+                )));
+            }
+            a.span = span_field.map(|f| FieldShape::of(&f.ty));
+            a.severity_field = severity_field.is_some();
+            a.cause = cause_field.map(|f| FieldShape::of(&f.ty));
+            // only fields referenced by generated methods are kept bound
+            v.filter(|b| {
+                ["span", "severity_field", "cause"].iter().any(|flag| has_field_flag(b.ast(), flag).unwrap_or(false))
+            });
+
+            if a.code > code {
+                code = a.code + 1;
+            } else {
+                code += 1;
+            }
         }
 
         attrs.push(a);
     }
 
 
-    for a in attrs.iter() {
-        for b in attrs.iter() {
-            if a as *const _ == b as *const _ {
-                continue;
-            }
+    for (i, a) in attrs.iter().enumerate().filter(|(_, a)| !a.delegates()) {
+        for b in attrs[..i].iter().filter(|b| !b.delegates()) {
             if a.code == b.code {
-                panic!(format!(
+                return Err(syn::Error::new_spanned(st.variants()[i].ast().ident, format!(
                     "duplicated code {} in type {}",
                     a.code,
                     st.ast().ident
-                ));
+                )));
             }
         }
     }
 
-    if log_file.is_some() {
-        for (v, a) in st.variants().iter().zip(attrs.iter()) {
-            write!(log_file.as_mut().unwrap(), "{}{:04}: {}::{}\n", a.severity.code_char(), a.code, st.ast().ident, v.ast().ident).unwrap();
+    if let Some((start, end, ref lit)) = code_range {
+        for (v, a) in st.variants().iter().zip(attrs.iter()).filter(|(_, a)| !a.delegates()) {
+            if a.code < start || a.code > end {
+                return Err(syn::Error::new_spanned(v.ast().ident, format!(
+                    "code {} of variant {} is outside of code range \"{}\" in type {}",
+                    a.code,
+                    v.ast().ident,
+                    lit.value(),
+                    st.ast().ident
+                )));
+            }
+        }
+    }
+
+    // Codes of flattened variants are only known to the compiler, so their collisions with other
+    // codes and with code range are checked during constant evaluation, on `offset + inner range`.
+    let direct_bounds = {
+        let mut codes = attrs.iter().filter(|a| !a.delegates()).map(|a| a.code);
+        match codes.next() {
+            Some(first) => {
+                let (min, max) = codes.fold((first, first), |(min, max), c| (min.min(c), max.max(c)));
+                quote! { Some((#min, #max)) }
+            }
+            None => quote! { None },
+        }
+    };
+
+    let flattened: Vec<_> = st.variants().iter().zip(attrs.iter())
+        .filter(|(_, a)| a.flatten)
+        .map(|(v, a)| (v.ast().ident, a.code, &v.ast().fields.iter().next().unwrap().ty))
+        .collect();
+
+    let flattened_bounds = flattened.iter().map(|&(_, offset, ty)| quote! {
+        if let Some((start, end)) = <#ty>::CODE_BOUNDS {
+            let (start, end) = (#offset + start, #offset + end);
+            bounds = match bounds {
+                Some((s, e)) => Some((if start < s { start } else { s }, if end > e { end } else { e })),
+                None => Some((start, end)),
+            };
+        }
+    });
+
+    let mut flattened_checks = Vec::new();
+    if st.ast().generics.params.is_empty() {
+        for (i, &(ident, offset, ty)) in flattened.iter().enumerate() {
+            let mut checks = Vec::new();
+            for (v, a) in st.variants().iter().zip(attrs.iter()).filter(|(_, a)| !a.delegates()) {
+                let code = a.code;
+                let msg = format!(
+                    "code {} of variant {} collides with codes of flattened variant {} in type {}",
+                    code,
+                    v.ast().ident,
+                    ident,
+                    st.ast().ident
+                );
+                checks.push(quote::quote_spanned! { v.ast().ident.span() =>
+                    if #code >= start && #code <= end {
+                        panic!(#msg);
+                    }
+                });
+            }
+            for &(other, other_offset, other_ty) in flattened[..i].iter() {
+                let msg = format!(
+                    "codes of flattened variant {} collide with codes of flattened variant {} in type {}",
+                    ident,
+                    other,
+                    st.ast().ident
+                );
+                checks.push(quote::quote_spanned! { ident.span() =>
+                    if let Some((other_start, other_end)) = <#other_ty>::CODE_BOUNDS {
+                        if #other_offset + other_start <= end && #other_offset + other_end >= start {
+                            panic!(#msg);
+                        }
+                    }
+                });
+            }
+            if let Some((range_start, range_end, ref lit)) = code_range {
+                let msg = format!(
+                    "codes of flattened variant {} are outside of code range \"{}\" in type {}",
+                    ident,
+                    lit.value(),
+                    st.ast().ident
+                );
+                checks.push(quote::quote_spanned! { ident.span() =>
+                    if start < #range_start || end > #range_end {
+                        panic!(#msg);
+                    }
+                });
+            }
+            flattened_checks.push(quote! {
+                #[allow(unused_variables)]
+                const _: () = {
+                    if let Some((start, end)) = <#ty>::CODE_BOUNDS {
+                        let (start, end) = (#offset + start, #offset + end);
+                        #(#checks)*
+                    }
+                };
+            });
+        }
+    }
+
+    if let Some(ref mut f) = log_file {
+        for (v, a) in st.variants().iter().zip(attrs.iter()).filter(|(_, a)| !a.delegates()) {
+            if let Some(ref domain) = domain {
+                write!(f, "{}-", domain).map_err(log_err)?;
+            }
+            write!(f, "{}{:04}: {}::{}\n", a.severity.code_char(), a.code, st.ast().ident, v.ast().ident).map_err(log_err)?;
         }
     }
 
     let mut attrs_it = attrs.iter();
-    let severity_body = st.each_variant(|_v| {
+    let severity_body = st.each_variant(|v| {
         let a = attrs_it.next().unwrap();
+        if a.delegates() {
+            let inner = &v.bindings()[0];
+            return quote! { kg_diag::Detail::severity(#inner) };
+        }
+        if a.severity_field {
+            let field = flagged_binding(v, "severity_field");
+            return quote! { *#field };
+        }
         let severity =
             syn::parse_str::<syn::Path>(&format!("kg_diag::Severity::{:?}", a.severity)).unwrap();
         quote! { #severity }
     });
 
     let mut attrs_it = attrs.iter();
-    let code_body = st.each_variant(|_v| {
+    let code_body = st.each_variant(|v| {
         let a = attrs_it.next().unwrap();
+        if a.transparent {
+            let inner = &v.bindings()[0];
+            return quote! { kg_diag::Detail::code(#inner) };
+        }
+        if a.flatten {
+            let inner = &v.bindings()[0];
+            let offset = a.code;
+            return quote! { #offset + kg_diag::Detail::code(#inner) };
+        }
         let code = a.code;
         quote! { #code }
     });
 
-    let p = st.underscore_const(true).gen_impl(quote! {
-        extern crate kg_diag;
+    let mut attrs_it = attrs.iter();
+    let span_body = st.each_variant(|v| {
+        let a = attrs_it.next().unwrap();
+        if a.delegates() {
+            let inner = &v.bindings()[0];
+            return quote! { kg_diag::Detail::span(#inner) };
+        }
+        match a.span {
+            Some(FieldShape::Optional) => {
+                let span = flagged_binding(v, "span");
+                quote! { *#span }
+            }
+            Some(_) => {
+                let span = flagged_binding(v, "span");
+                quote! { Some(*#span) }
+            }
+            None => quote! { None },
+        }
+    });
+
+    let mut attrs_it = attrs.iter();
+    let domain_body = st.each_variant(|v| {
+        let a = attrs_it.next().unwrap();
+        if a.transparent {
+            let inner = &v.bindings()[0];
+            return quote! { kg_diag::Detail::domain(#inner) };
+        }
+        match domain {
+            Some(ref domain) => quote! { Some(#domain) },
+            None if a.flatten => {
+                let inner = &v.bindings()[0];
+                quote! { kg_diag::Detail::domain(#inner) }
+            }
+            None => quote! { None },
+        }
+    });
+
+    let codes = st.variants().iter().zip(attrs.iter()).filter(|(_, a)| !a.delegates()).map(|(v, a)| {
+        let code = a.code;
+        let name = v.ast().ident.to_string();
+        let severity =
+            syn::parse_str::<syn::Path>(&format!("kg_diag::Severity::{:?}", a.severity)).unwrap();
+        quote! { (#code, #name, #severity) }
+    });
+
+    let variant_names = st.variants().iter().zip(attrs.iter()).filter(|(_, a)| !a.delegates()).map(|(v, a)| {
+        let code = a.code;
+        let name = v.ast().ident.to_string();
+        quote! { #code => Some(#name), }
+    });
+
+    // Flattened variants resolve names of codes above their offset through the inner type
+    let flattened_names = st.variants().iter().zip(attrs.iter()).filter(|(_, a)| a.flatten).map(|(v, a)| {
+        let offset = a.code;
+        let ty = &v.ast().fields.iter().next().unwrap().ty;
+        quote! {
+            if code > #offset {
+                if let Some(name) = <#ty>::variant_name(code - #offset) {
+                    return Some(name);
+                }
+            }
+        }
+    });
+
+    let flattened_codes = st.variants().iter().zip(attrs.iter()).filter(|(_, a)| a.flatten).map(|(v, a)| {
+        let offset = a.code;
+        let ty = &v.ast().fields.iter().next().unwrap().ty;
+        quote! {
+            codes.extend(<#ty>::all_codes().into_iter().map(|(code, name, severity)| (#offset + code, name, severity)));
+        }
+    });
+
+    let mut attrs_it = attrs.iter();
+    let help_body = st.each_variant(|v| {
+        let a = attrs_it.next().unwrap();
+        if a.delegates() {
+            let inner = &v.bindings()[0];
+            return quote! { kg_diag::Detail::help(#inner) };
+        }
+        match a.help {
+            Some(ref help) => quote! { Some(#help) },
+            None => quote! { None },
+        }
+    });
+
+    let mut constructor_fns = Vec::new();
+    if constructors {
+        if let syn::Data::Struct(_) | syn::Data::Union(_) = st.ast().data {
+            return Err(syn::Error::new_spanned(&st.ast().ident, format!(
+                "constructors can only be generated for enums, {} is not an enum",
+                st.ast().ident
+            )));
+        }
+        let mut names: Vec<(String, &syn::Ident)> = Vec::with_capacity(st.variants().len());
+        for v in st.variants().iter() {
+            let name = to_snake_case(&v.ast().ident.to_string());
+            if RESERVED_NAMES.contains(&name.as_str()) {
+                return Err(syn::Error::new_spanned(v.ast().ident, format!(
+                    "constructor of variant {} would conflict with generated {}()",
+                    v.ast().ident,
+                    name
+                )));
+            }
+            if let Some((_, other)) = names.iter().find(|(n, _)| *n == name) {
+                return Err(syn::Error::new_spanned(v.ast().ident, format!(
+                    "constructor of variant {} would conflict with constructor {}() of variant {}",
+                    v.ast().ident,
+                    name,
+                    other
+                )));
+            }
+            names.push((name.clone(), v.ast().ident));
+            let name = syn::Ident::new(&name, v.ast().ident.span());
+            let variant = v.ast().ident;
+            let (params, construct) = match *v.ast().fields {
+                syn::Fields::Named(ref fields) => {
+                    let names: Vec<_> = fields.named.iter().map(|f| f.ident.clone().unwrap()).collect();
+                    let types = fields.named.iter().map(|f| &f.ty);
+                    (quote! { #(#names: #types),* }, quote! { Self::#variant { #(#names),* } })
+                }
+                syn::Fields::Unnamed(ref fields) => {
+                    let names: Vec<_> = (0..fields.unnamed.len())
+                        .map(|i| syn::Ident::new(&format!("_{}", i), Span::call_site()))
+                        .collect();
+                    let types = fields.unnamed.iter().map(|f| &f.ty);
+                    (quote! { #(#names: #types),* }, quote! { Self::#variant(#(#names),*) })
+                }
+                syn::Fields::Unit => (quote! {}, quote! { Self::#variant }),
+            };
+            let doc = format!("Creates `BasicDiag` with stacktrace for `{}` variant.", variant);
+            constructor_fns.push(quote! {
+                #[doc = #doc]
+                #[allow(dead_code)]
+                pub fn #name(#params) -> kg_diag::BasicDiag where Self: kg_diag::Detail {
+                    kg_diag::BasicDiag::with_stacktrace(#construct, kg_diag::Stacktrace::new_skip(1))
+                }
+            });
+        }
+    }
+
+    // Registrations require `registry` feature of kg-diag
+    let registrations = if register {
+        let type_name = st.ast().ident.to_string();
+        let domain = match domain {
+            Some(ref domain) => quote! { Some(#domain) },
+            None => quote! { None },
+        };
+        st.variants().iter().zip(attrs.iter()).filter(|(_, a)| !a.delegates()).map(|(v, a)| {
+            let code = a.code;
+            let variant = v.ast().ident.to_string();
+            quote! {
+                kg_diag::inventory::submit! {
+                    #![crate = kg_diag]
+                    kg_diag::registry::Registration::new(#domain, #code, concat!(module_path!(), "::", #type_name), #variant)
+                }
+            }
+        }).collect()
+    } else {
+        Vec::new()
+    };
+
+    // Type parameters only need to satisfy `Detail` supertraits, except for wrapped fields
+    // (the only bindings left besides spans), which get `Detail` bound through `AddBounds::Fields`.
+    let type_params: Vec<syn::Ident> = st.ast().generics.type_params().map(|p| p.ident.clone()).collect();
+    for p in type_params {
+        st.add_where_predicate(syn::parse_quote! {
+            #p: ::std::marker::Send + ::std::marker::Sync + 'static + ::std::fmt::Debug + ::std::fmt::Display
+        });
+    }
+    st.add_bounds(synstructure::AddBounds::Fields);
+
+    let ident = &st.ast().ident;
+    let (impl_generics, ty_generics, where_clause) = st.ast().generics.split_for_impl();
+
+    let serialize_impl = if serialize {
+        let type_name = st.ast().ident.to_string();
+        quote! {
+            gen impl kg_diag::serde::Serialize for @Self {
+                fn serialize<__S: kg_diag::serde::Serializer>(&self, serializer: __S) -> ::std::result::Result<__S::Ok, __S::Error> {
+                    use kg_diag::serde::ser::SerializeStruct;
+                    let mut s = serializer.serialize_struct(#type_name, 4)?;
+                    s.serialize_field("code", &kg_diag::Detail::code(self))?;
+                    s.serialize_field("domain", &kg_diag::Detail::domain(self))?;
+                    s.serialize_field("severity", &kg_diag::Detail::severity(self))?;
+                    s.serialize_field("message", &::std::string::ToString::to_string(self))?;
+                    s.end()
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let std_error_impl = if std_error {
+        let mut attrs_it = attrs.iter();
+        let source_body = st.each_variant(|v| {
+            let a = attrs_it.next().unwrap();
+            let error = quote! { (dyn ::std::error::Error + 'static) };
+            match a.cause {
+                Some(shape) => {
+                    let cause = flagged_binding(v, "cause");
+                    match shape {
+                        FieldShape::Plain => quote! { Some(#cause as &#error) },
+                        FieldShape::Boxed => quote! { Some(&**#cause as &#error) },
+                        FieldShape::Optional => quote! { #cause.as_ref().map(|c| c as &#error) },
+                        FieldShape::OptionalBoxed => quote! { #cause.as_ref().map(|c| &**c as &#error) },
+                    }
+                }
+                None => quote! { None },
+            }
+        });
+        quote! {
+            gen impl ::std::error::Error for @Self {
+                fn source(&self) -> Option<&(dyn ::std::error::Error + 'static)> {
+                    match *self {
+                        #source_body
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let import = crate_import(&krate);
+
+    Ok(st.underscore_const(true).gen_impl(quote! {
+        #import
+
+        #serialize_impl
+
+        #std_error_impl
 
         gen impl kg_diag::Detail for @Self {
             fn severity(&self) -> kg_diag::Severity {
@@ -203,36 +720,477 @@ fn detail_derive(mut st: synstructure::Structure) -> proc_macro2::TokenStream {
                     #code_body
                 }
             }
+
+            fn domain(&self) -> Option<&'static str> {
+                match *self {
+                    #domain_body
+                }
+            }
+
+            fn span(&self) -> Option<kg_diag::Span> {
+                match *self {
+                    #span_body
+                }
+            }
+
+            fn help(&self) -> Option<&'static str> {
+                match *self {
+                    #help_body
+                }
+            }
         }
-    });
 
-    p
-}
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// Diagnostic codes of variants declared directly in this type, as
+            /// `(code, variant name, severity)`. Transparent and flattened variants are not listed,
+            /// as their codes come from the wrapped type; see `all_codes()`.
+            #[allow(dead_code)]
+            pub const CODES: &'static [(u32, &'static str, kg_diag::Severity)] = &[#(#codes),*];
+
+            /// Lowest and highest code listed by `all_codes()`, or `None` if there are none.
+            #[allow(dead_code)]
+            pub const CODE_BOUNDS: Option<(u32, u32)> = {
+                #[allow(unused_mut)]
+                let mut bounds: Option<(u32, u32)> = #direct_bounds;
+                #(#flattened_bounds)*
+                bounds
+            };
 
-fn find_nested_attr(attrs: &[syn::Attribute], id: &str) -> Option<Vec<syn::NestedMeta>> {
-    let doc_path: syn::Path = syn::Ident::new("doc", Span::call_site()).into();
+            /// All diagnostic codes this type can produce, including codes of flattened variants
+            /// offset by the variant code, sorted by code. Codes of transparent variants are
+            /// not fixed by this type and are not listed.
+            #[allow(dead_code)]
+            pub fn all_codes() -> Vec<(u32, &'static str, kg_diag::Severity)> {
+                let mut codes = Self::CODES.to_vec();
+                #(#flattened_codes)*
+                codes.sort_by_key(|c| c.0);
+                codes
+            }
 
-    let mut a = None;
-    for attr in attrs {
-        if attr.path != doc_path && attr.style == syn::AttrStyle::Outer {
-            let meta = {
-                let m = attr.parse_meta();
-                if let Ok(syn::Meta::List(syn::MetaList { path, nested, .. })) = m {
-                    if path_eq(&path, id) {
-                        Some(nested.into_iter().collect())
-                    } else {
+            /// Maps diagnostic code back to the name of the variant producing it. Resolves
+            /// exactly the codes listed by `all_codes()`.
+            #[allow(dead_code)]
+            pub fn variant_name(code: u32) -> Option<&'static str> {
+                match code {
+                    #(#variant_names)*
+                    _ => {
+                        #(#flattened_names)*
                         None
                     }
-                } else {
-                    None
                 }
-            };
-            if a.is_some() && meta.is_some() {
-                panic!(format!("multiple {}(...) attributes found", id))
+            }
+
+            #(#constructor_fns)*
+        }
+
+        #(#flattened_checks)*
+
+        #(#registrations)*
+    }))
+}
+
+impl DiagAttr {
+    /// Transparent and flattened variants take their details from the wrapped field.
+    fn delegates(&self) -> bool {
+        self.transparent || self.flatten
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FieldShape {
+    Plain,
+    Boxed,
+    Optional,
+    OptionalBoxed,
+}
+
+impl FieldShape {
+    fn of(ty: &syn::Type) -> FieldShape {
+        fn last_segment(ty: &syn::Type) -> Option<&syn::PathSegment> {
+            if let syn::Type::Path(syn::TypePath { ref path, .. }) = *ty {
+                path.segments.last()
             } else {
-                a = meta;
+                None
+            }
+        }
+
+        fn first_type_arg(seg: &syn::PathSegment) -> Option<&syn::Type> {
+            if let syn::PathArguments::AngleBracketed(ref args) = seg.arguments {
+                for a in args.args.iter() {
+                    if let syn::GenericArgument::Type(ref t) = *a {
+                        return Some(t);
+                    }
+                }
+            }
+            None
+        }
+
+        match last_segment(ty) {
+            Some(seg) if seg.ident == "Box" => FieldShape::Boxed,
+            Some(seg) if seg.ident == "Option" => match first_type_arg(seg).and_then(last_segment) {
+                Some(inner) if inner.ident == "Box" => FieldShape::OptionalBoxed,
+                _ => FieldShape::Optional,
+            },
+            _ => FieldShape::Plain,
+        }
+    }
+
+    fn access(&self, field: &proc_macro2::TokenStream, target: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match *self {
+            FieldShape::Plain => quote! { Some(&self.#field as &#target) },
+            FieldShape::Boxed => quote! { Some(&*self.#field as &#target) },
+            FieldShape::Optional => quote! { self.#field.as_ref().map(|f| f as &#target) },
+            FieldShape::OptionalBoxed => quote! { self.#field.as_ref().map(|f| &**f as &#target) },
+        }
+    }
+
+    fn access_mut(&self, field: &proc_macro2::TokenStream, target: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match *self {
+            FieldShape::Plain => quote! { Some(&mut self.#field as &mut #target) },
+            FieldShape::Boxed => quote! { Some(&mut *self.#field as &mut #target) },
+            FieldShape::Optional => quote! { self.#field.as_mut().map(|f| f as &mut #target) },
+            FieldShape::OptionalBoxed => quote! { self.#field.as_mut().map(|f| &mut **f as &mut #target) },
+        }
+    }
+}
+
+struct DiagField {
+    member: proc_macro2::TokenStream,
+    shape: FieldShape,
+}
+
+fn diag_derive(st: synstructure::Structure) -> proc_macro2::TokenStream {
+    diag_impl(st).unwrap_or_else(|err| err.to_compile_error())
+}
+
+fn diag_impl(mut st: synstructure::Structure) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match st.ast().data {
+        syn::Data::Struct(ref s) => &s.fields,
+        _ => return Err(syn::Error::new_spanned(&st.ast().ident, format!(
+            "Diag can only be derived for structs, {} is not a struct",
+            st.ast().ident
+        ))),
+    };
+
+    // other container keys are only meaningful for the Detail derive
+    let mut krate = None;
+    if let Some(params) = find_nested_attr(&st.ast().attrs, "diag")? {
+        for p in params {
+            if let syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                ref path,
+                lit: syn::Lit::Str(ref s),
+                ..
+            })) = p {
+                if path_eq(path, "crate") {
+                    krate = Some(s.parse::<syn::Path>()?);
+                }
+            }
+        }
+    }
+
+    let mut detail: Option<DiagField> = None;
+    let mut cause: Option<DiagField> = None;
+    let mut stacktrace: Option<DiagField> = None;
+
+    for (i, f) in fields.iter().enumerate() {
+        let member = match f.ident {
+            Some(ref ident) => quote! { #ident },
+            None => {
+                let index = syn::Index::from(i);
+                quote! { #index }
+            }
+        };
+        if let Some(params) = find_nested_attr(&f.attrs, "diag")? {
+            for p in params {
+                let slot = match p {
+                    syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path_eq(path, "detail") => &mut detail,
+                    syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path_eq(path, "cause") => &mut cause,
+                    syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path_eq(path, "stacktrace") => &mut stacktrace,
+                    _ => return Err(syn::Error::new_spanned(p, format!(
+                        "invalid diag(...) attribute for field {} in type {}",
+                        member,
+                        st.ast().ident
+                    ))),
+                };
+                if slot.is_some() {
+                    return Err(syn::Error::new_spanned(p, format!(
+                        "multiple fields marked with the same diag(...) attribute in type {}",
+                        st.ast().ident
+                    )));
+                }
+                *slot = Some(DiagField {
+                    member: member.clone(),
+                    shape: FieldShape::of(&f.ty),
+                });
+            }
+        }
+    }
+
+    let diag_path = quote! { dyn kg_diag::Diag };
+    let (cause_body, cause_mut_body) = match cause {
+        Some(ref c) => (c.shape.access(&c.member, &diag_path), c.shape.access_mut(&c.member, &diag_path)),
+        None => (quote! { None }, quote! { None }),
+    };
+
+    let stacktrace_body = match stacktrace {
+        Some(ref s) => s.shape.access(&s.member, &quote! { kg_diag::Stacktrace }),
+        None => quote! { None },
+    };
+
+    // Without a `#[diag(detail)]` field the type is expected to be a `Detail` itself (like
+    // `kg_diag::Errors`), so only the specializable methods are implemented.
+    let detail_methods = match detail {
+        Some(ref d) => {
+            let (field, detail_path) = (&d.member, quote! { dyn kg_diag::Detail });
+            let (access, access_mut) = match d.shape {
+                FieldShape::Plain => (quote! { &self.#field }, quote! { &mut self.#field }),
+                FieldShape::Boxed => (quote! { &*self.#field }, quote! { &mut *self.#field }),
+                _ => return Err(syn::Error::new_spanned(field, format!(
+                    "detail field in type {} cannot be optional",
+                    st.ast().ident
+                ))),
+            };
+            quote! {
+                fn detail(&self) -> &#detail_path {
+                    #access
+                }
+
+                fn detail_mut(&mut self) -> &mut #detail_path {
+                    #access_mut
+                }
+            }
+        }
+        None => quote! {},
+    };
+
+    st.add_bounds(synstructure::AddBounds::None);
+    let import = crate_import(&krate);
+
+    Ok(st.underscore_const(true).gen_impl(quote! {
+        #import
+
+        gen impl kg_diag::Diag for @Self {
+            #detail_methods
+
+            fn cause(&self) -> Option<&dyn kg_diag::Diag> {
+                #cause_body
+            }
+
+            fn cause_mut(&mut self) -> Option<&mut dyn kg_diag::Diag> {
+                #cause_mut_body
+            }
+
+            fn stacktrace(&self) -> Option<&kg_diag::Stacktrace> {
+                #stacktrace_body
+            }
+        }
+    }))
+}
+
+/// Builds `kg_diag::parse::Expected` from a list of character, byte and string literals or
+/// inclusive ranges of characters and bytes, e.g. `expected!['0'..='9', '_', "keyword true"]`.
+/// Elements are sorted and deduplicated at compile time (literals covered by ranges are
+/// dropped), and a single element is returned as is rather than wrapped in `OneOf`.
+#[proc_macro]
+pub fn expected(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expected_impl(input.into()).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+fn expected_impl(input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::TokenStream> {
+    use syn::parse::Parser;
+    use kg_diag::parse::Expected;
+
+    let parser = syn::punctuated::Punctuated::<syn::Expr, syn::Token![,]>::parse_terminated;
+    let exprs = parser.parse2(input)?;
+    if exprs.is_empty() {
+        return Err(syn::Error::new(Span::call_site(), "expected at least one element"));
+    }
+    let mut elems = Vec::with_capacity(exprs.len());
+    for e in exprs.iter() {
+        elems.push(parse_expected(e)?);
+    }
+    elems.sort();
+    elems.dedup();
+    let ranges: Vec<Expected> = elems
+        .iter()
+        .filter(|e| matches!(e, Expected::CharRange(..) | Expected::ByteRange(..)))
+        .cloned()
+        .collect();
+    elems.retain(|e| !ranges.iter().any(|r| match (e, r) {
+        (&Expected::Char(c), &Expected::CharRange(a, b)) => a <= c && c <= b,
+        (&Expected::Byte(c), &Expected::ByteRange(a, b)) => a <= c && c <= b,
+        _ => false,
+    }));
+    Ok(expected_tokens(&Expected::one_of(elems)))
+}
+
+fn parse_expected(e: &syn::Expr) -> syn::Result<kg_diag::parse::Expected> {
+    use kg_diag::parse::Expected;
+
+    fn lit(e: &syn::Expr) -> Option<&syn::Lit> {
+        match *e {
+            syn::Expr::Lit(syn::ExprLit { ref lit, .. }) => Some(lit),
+            _ => None,
+        }
+    }
+
+    match *e {
+        syn::Expr::Lit(syn::ExprLit { ref lit, .. }) => match *lit {
+            syn::Lit::Char(ref c) => return Ok(Expected::Char(c.value())),
+            syn::Lit::Byte(ref b) => return Ok(Expected::Byte(b.value())),
+            syn::Lit::Str(ref s) => return Ok(Expected::Custom(s.value())),
+            _ => {}
+        },
+        syn::Expr::Range(syn::ExprRange { ref from, ref limits, ref to, .. }) => {
+            if let syn::RangeLimits::HalfOpen(_) = *limits {
+                return Err(syn::Error::new_spanned(e, "only inclusive ranges (a..=b) are supported"));
+            }
+            let bounds = match (from.as_deref().and_then(lit), to.as_deref().and_then(lit)) {
+                (Some(&syn::Lit::Char(ref a)), Some(&syn::Lit::Char(ref b))) => {
+                    Some((a.value() <= b.value(), Expected::CharRange(a.value(), b.value())))
+                }
+                (Some(&syn::Lit::Byte(ref a)), Some(&syn::Lit::Byte(ref b))) => {
+                    Some((a.value() <= b.value(), Expected::ByteRange(a.value(), b.value())))
+                }
+                _ => None,
+            };
+            match bounds {
+                Some((true, range)) => return Ok(range),
+                Some((false, _)) => return Err(syn::Error::new_spanned(e, "range is empty")),
+                None => {}
+            }
+        }
+        _ => {}
+    }
+    Err(syn::Error::new_spanned(e, "expected character, byte or string literal, or an inclusive range of characters or bytes"))
+}
+
+fn expected_tokens(e: &kg_diag::parse::Expected) -> proc_macro2::TokenStream {
+    use kg_diag::parse::Expected;
+
+    match *e {
+        Expected::Byte(b) => quote! { ::kg_diag::parse::Expected::Byte(#b) },
+        Expected::ByteRange(a, b) => quote! { ::kg_diag::parse::Expected::ByteRange(#a, #b) },
+        Expected::Char(c) => quote! { ::kg_diag::parse::Expected::Char(#c) },
+        Expected::CharRange(a, b) => quote! { ::kg_diag::parse::Expected::CharRange(#a, #b) },
+        Expected::Custom(ref s) => {
+            quote! { ::kg_diag::parse::Expected::Custom(::std::string::String::from(#s)) }
+        }
+        Expected::OneOf(ref elems) => {
+            let elems = elems.iter().map(expected_tokens);
+            quote! { ::kg_diag::parse::Expected::OneOf(::std::vec![#(#elems),*]) }
+        }
+        // not produced by `expected!`
+        Expected::Or(..) | Expected::WithExample(..) => unreachable!(),
+    }
+}
+
+/// Collects `///` doc comments into a single text, stripping the leading space of each line.
+fn doc_comment(attrs: &[syn::Attribute]) -> syn::Result<Option<String>> {
+    let mut lines = Vec::new();
+    for attr in attrs {
+        if attr.style == syn::AttrStyle::Outer && path_eq(&attr.path, "doc") {
+            if let syn::Meta::NameValue(syn::MetaNameValue { lit: syn::Lit::Str(ref s), .. }) = attr.parse_meta()? {
+                let line = s.value();
+                lines.push(match line.strip_prefix(' ') {
+                    Some(l) => l.to_string(),
+                    None => line,
+                });
+            }
+        }
+    }
+    let doc = lines.join("\n");
+    let doc = doc.trim();
+    Ok(if doc.is_empty() { None } else { Some(doc.to_string()) })
+}
+
+/// Generated code refers to `kg_diag`, which can be aliased to a re-export with
+/// `#[diag(crate = "path")]`.
+fn crate_import(krate: &Option<syn::Path>) -> proc_macro2::TokenStream {
+    match *krate {
+        Some(ref path) => quote! { use #path as kg_diag; },
+        None => quote! { extern crate kg_diag; },
+    }
+}
+
+/// Methods of `Detail` and inherent items generated by the derive, which constructors must not shadow.
+const RESERVED_NAMES: &[&str] = &[
+    "severity", "code", "domain", "code_string", "span", "help", "type_id", "as_fmt_debug",
+    "as_fmt_display", "codes", "all_codes", "variant_name", "code_bounds",
+];
+
+/// Converts variant name to snake case, keeping acronyms together, so that `IOError` becomes
+/// `io_error` and `HttpError2` becomes `http_error2`.
+fn to_snake_case(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut res = String::with_capacity(s.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                let prev = chars[i - 1];
+                let next_lower = chars.get(i + 1).map_or(false, |n| n.is_lowercase());
+                if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower) {
+                    res.push('_');
+                }
+            }
+            res.extend(c.to_lowercase());
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// Parses `"start..end"` or `"start..=end"` into inclusive bounds.
+fn parse_code_range(s: &str) -> Option<(u32, u32)> {
+    let (start, end, inclusive) = if let Some(i) = s.find("..=") {
+        (&s[..i], &s[i + 3..], true)
+    } else if let Some(i) = s.find("..") {
+        (&s[..i], &s[i + 2..], false)
+    } else {
+        return None;
+    };
+    let start: u32 = start.trim().parse().ok()?;
+    let end: u32 = end.trim().parse().ok()?;
+    let end = if inclusive { end } else { end.checked_sub(1)? };
+    if start <= end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+fn flagged_binding<'a, 'b>(v: &'b synstructure::VariantInfo<'a>, flag: &str) -> &'b synstructure::BindingInfo<'a> {
+    v.bindings()
+        .iter()
+        .find(|b| has_field_flag(b.ast(), flag).unwrap_or(false))
+        .expect("flagged field binding")
+}
+
+fn has_field_flag(field: &syn::Field, flag: &str) -> syn::Result<bool> {
+    if let Some(params) = find_nested_attr(&field.attrs, "diag")? {
+        Ok(params.iter().any(|p| match *p {
+            syn::NestedMeta::Meta(syn::Meta::Path(ref path)) => path_eq(path, flag),
+            _ => false,
+        }))
+    } else {
+        Ok(false)
+    }
+}
+
+fn find_nested_attr(attrs: &[syn::Attribute], id: &str) -> syn::Result<Option<Vec<syn::NestedMeta>>> {
+    let mut a = None;
+    for attr in attrs {
+        if attr.style == syn::AttrStyle::Outer && path_eq(&attr.path, id) {
+            let nested = match attr.parse_meta()? {
+                syn::Meta::List(syn::MetaList { nested, .. }) => nested.into_iter().collect(),
+                m => return Err(syn::Error::new_spanned(m, format!("expected {}(...) attribute", id))),
+            };
+            if a.is_some() {
+                return Err(syn::Error::new_spanned(attr, format!("multiple {}(...) attributes found", id)));
             }
+            a = Some(nested);
         }
     }
-    a
+    Ok(a)
 }
@@ -10,7 +10,7 @@ use kg_diag::*;
 
 #[allow(unused)]
 #[derive(Debug, Detail, Display)]
-#[diag(code_offset = 1000)]
+#[diag(code_offset = 1000, constructors)]
 enum TestErrorKind {
     #[diag(code = 1, severity = 'E')]
     #[display(fmt = "empty error message")]
@@ -29,6 +29,60 @@ enum TestErrorKind {
     ErrorWithStruct { a: usize, b: usize },
 }
 
+#[test]
+fn codes_table() {
+    assert_eq!(TestErrorKind::CODES, &[
+        (1001, "ErrorEmpty", Severity::Error),
+        (1002, "ErrorWithPair", Severity::Failure),
+        (1003, "ErrorWithString", Severity::Error),
+        (1004, "ErrorWithStruct", Severity::Failure),
+    ]);
+    assert_eq!(TransparentErrorKind::CODES, &[(2001, "Custom", Severity::Error)]);
+}
+
+#[test]
+fn help_from_doc_comments() {
+    assert_eq!(TransparentErrorKind::Custom.help(), Some("Raised for custom errors.\n\nCheck the input."));
+    assert_eq!(TestErrorKind::ErrorEmpty.help(), None);
+}
+
+#[test]
+fn generated_constructors() {
+    let d = TestErrorKind::error_with_pair(1, 2);
+    assert_eq!(d.detail().code(), 1002);
+    assert!(d.stacktrace().is_some());
+
+    let d = TestErrorKind::error_with_struct(3, 4);
+    assert_eq!(d.detail().to_string(), "error with field a = 3 and field b = 4");
+
+    let d = TestErrorKind::error_empty();
+    assert_eq!(d.detail().code(), 1001);
+}
+
+#[allow(unused)]
+#[derive(Debug, Detail, Display)]
+#[diag(code_offset = 1100, constructors)]
+enum AcronymErrorKind {
+    #[display(fmt = "I/O error")]
+    IOError,
+
+    #[display(fmt = "HTTP server error {_0}")]
+    HTTPServerError(u16),
+}
+
+#[test]
+fn constructors_keep_acronyms_together() {
+    assert_eq!(AcronymErrorKind::io_error().detail().code(), 1101);
+    assert_eq!(AcronymErrorKind::http_server_error(500).detail().to_string(), "HTTP server error 500");
+}
+
+#[test]
+fn variant_name_lookup() {
+    assert_eq!(TestErrorKind::variant_name(1003), Some("ErrorWithString"));
+    assert_eq!(TestErrorKind::variant_name(3), None);
+    assert_eq!(TransparentErrorKind::variant_name(2001), Some("Custom"));
+}
+
 //FIXME (jc)
 #[test]
 fn code_deref() {
@@ -37,3 +91,318 @@ fn code_deref() {
     println!("{}", e.severity());
     println!("{}", e);
 }
+
+#[derive(Debug, Detail, Display)]
+#[diag(code_offset = 2000)]
+enum TransparentErrorKind {
+    #[diag(transparent)]
+    #[display(fmt = "{_0}")]
+    Io(IoErrorDetail),
+
+    /// Raised for custom errors.
+    ///
+    /// Check the input.
+    #[diag(code = 1, severity = 'E')]
+    #[display(fmt = "custom error")]
+    Custom,
+}
+
+#[test]
+fn transparent_delegates_to_inner_detail() {
+    let inner = IoErrorDetail::from(std::io::ErrorKind::NotFound);
+    let e = TransparentErrorKind::Io(inner.clone());
+    assert_eq!(e.code(), inner.code());
+    assert_eq!(e.severity(), inner.severity());
+
+    let e = TransparentErrorKind::Custom;
+    assert_eq!(e.code(), 2001);
+    assert_eq!(e.severity(), Severity::Error);
+}
+
+#[derive(Debug, Detail, Display)]
+#[diag(code_offset = 3000)]
+enum FlattenedErrorKind {
+    #[diag(flatten, code = 100)]
+    #[display(fmt = "{_0}")]
+    Transparent(TransparentErrorKind),
+
+    #[diag(flatten, code = 200)]
+    #[display(fmt = "{_0}")]
+    Domain(DomainErrorKind),
+
+    #[diag(code = 1, severity = 'W')]
+    #[display(fmt = "outer warning")]
+    Outer,
+}
+
+#[test]
+fn flatten_offsets_inner_codes() {
+    let e = FlattenedErrorKind::Transparent(TransparentErrorKind::Custom);
+    assert_eq!(e.code(), 5101);
+    assert_eq!(e.severity(), Severity::Error);
+    assert_eq!(e.help(), TransparentErrorKind::Custom.help());
+
+    let e = FlattenedErrorKind::Domain(DomainErrorKind::UnexpectedInput);
+    assert_eq!(e.code(), 3242);
+    assert_eq!(e.domain(), Some("PARSE"));
+
+    assert_eq!(FlattenedErrorKind::Outer.code(), 3001);
+    assert_eq!(FlattenedErrorKind::CODES, &[(3001, "Outer", Severity::Warning)]);
+    assert_eq!(FlattenedErrorKind::CODE_BOUNDS, Some((3001, 5101)));
+    assert_eq!(FlattenedErrorKind::variant_name(3001), Some("Outer"));
+    assert_eq!(FlattenedErrorKind::variant_name(5101), Some("Custom"));
+    assert_eq!(FlattenedErrorKind::variant_name(3242), Some("UnexpectedInput"));
+    assert_eq!(FlattenedErrorKind::variant_name(3100), None);
+}
+
+#[test]
+fn all_codes_include_flattened_codes() {
+    let codes = FlattenedErrorKind::all_codes();
+    assert_eq!(codes.first(), Some(&(3001, "Outer", Severity::Warning)));
+    assert!(codes.contains(&(5101, "Custom", Severity::Error)));
+    assert_eq!(codes.len(), 1 + TransparentErrorKind::all_codes().len() + DomainErrorKind::all_codes().len());
+    for &(code, name, _) in codes.iter() {
+        assert_eq!(FlattenedErrorKind::variant_name(code), Some(name));
+    }
+}
+
+#[derive(Debug, Diag)]
+struct WrappedDiag {
+    #[diag(detail)]
+    detail: TestErrorKind,
+    #[diag(cause)]
+    cause: Option<Box<dyn Diag>>,
+    #[diag(stacktrace)]
+    stacktrace: Option<Box<Stacktrace>>,
+}
+
+impl std::fmt::Display for WrappedDiag {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.detail, f)
+    }
+}
+
+#[test]
+fn derived_diag_exposes_cause() {
+    let cause = BasicDiag::new(IoErrorDetail::from(std::io::ErrorKind::NotFound));
+    let d = WrappedDiag {
+        detail: TestErrorKind::ErrorEmpty,
+        cause: Some(Box::new(cause)),
+        stacktrace: None,
+    };
+    assert_eq!(d.detail().code(), 1001);
+    assert!(d.stacktrace().is_none());
+    let c = d.cause().expect("cause expected");
+    assert!(c.downcast_ref::<BasicDiag>().is_some());
+}
+
+#[allow(unused)]
+#[derive(Debug, Detail, Display)]
+enum SpannedErrorKind {
+    #[display(fmt = "unexpected token")]
+    UnexpectedToken {
+        #[diag(span)]
+        span: Span,
+    },
+
+    #[display(fmt = "maybe located")]
+    MaybeLocated(usize, #[diag(span)] Option<Span>),
+
+    #[display(fmt = "no location")]
+    NoLocation,
+}
+
+#[test]
+fn span_field_accessor() {
+    let span = Span::with(8, 1, 0, 12, 1, 4);
+    let e = SpannedErrorKind::UnexpectedToken { span };
+    assert_eq!(e.span(), Some(span));
+    assert_eq!(SpannedErrorKind::MaybeLocated(1, None).span(), None);
+    assert_eq!(SpannedErrorKind::NoLocation.span(), None);
+
+    let mut r = MemCharReader::new(b"line 1;\nline 2;\n");
+    let d = ParseDiag::with_span_quote(e, &mut r);
+    assert_eq!(d.quotes().len(), 1);
+    assert_eq!(d.quotes()[0].span(), span);
+}
+
+#[allow(unused)]
+#[derive(Debug, Detail, Display)]
+#[diag(domain = "PARSE", code_offset = 40, serialize)]
+enum DomainErrorKind {
+    #[diag(code = 2, severity = 'E')]
+    #[display(fmt = "unexpected input")]
+    UnexpectedInput,
+
+    #[diag(transparent)]
+    #[display(fmt = "{_0}")]
+    Io(IoErrorDetail),
+}
+
+#[test]
+fn domain_prefixes_code_string() {
+    let e = DomainErrorKind::UnexpectedInput;
+    assert_eq!(e.domain(), Some("PARSE"));
+    assert_eq!(e.code_string(), "PARSE-E0042");
+
+    let e = DomainErrorKind::Io(IoErrorDetail::Fmt);
+    assert_eq!(e.domain(), None);
+    assert_eq!(e.code_string(), "F0099");
+}
+
+#[test]
+fn serialize_detail() {
+    let json = serde_json::to_string(&DomainErrorKind::UnexpectedInput).unwrap();
+    assert_eq!(json, r#"{"code":42,"domain":"PARSE","severity":"error","message":"unexpected input"}"#);
+}
+
+mod result_alias {
+    #[allow(unused)]
+    type Result<T> = std::result::Result<T, String>;
+
+    #[derive(Debug, Detail, Display)]
+    #[diag(code_offset = 800, serialize)]
+    pub enum AliasedErrorKind {
+        #[display(fmt = "aliased error")]
+        Aliased,
+    }
+}
+
+#[test]
+fn serialize_with_result_alias_in_scope() {
+    let json = serde_json::to_string(&result_alias::AliasedErrorKind::Aliased).unwrap();
+    assert_eq!(json, r#"{"code":801,"domain":null,"severity":"failure","message":"aliased error"}"#);
+}
+
+
+#[derive(Debug, Detail, Display)]
+#[diag(code_offset = 400)]
+enum GenericErrorKind<T, D> where T: Clone {
+    #[diag(code = 1, severity = 'E')]
+    #[display(fmt = "invalid value {_0}")]
+    Invalid(T),
+
+    #[diag(transparent)]
+    #[display(fmt = "{_0}")]
+    Inner(D),
+}
+
+#[test]
+fn generic_detail() {
+    let e: GenericErrorKind<u32, TestErrorKind> = GenericErrorKind::Invalid(42);
+    assert_eq!(e.code(), 401);
+    assert_eq!(e.severity(), Severity::Error);
+
+    let e: GenericErrorKind<u32, TestErrorKind> = GenericErrorKind::Inner(TestErrorKind::ErrorEmpty);
+    assert_eq!(e.code(), 1001);
+
+    let d: BasicDiag = e.into();
+    assert_eq!(d.detail().code(), 1001);
+}
+
+
+#[derive(Debug, Detail, Display)]
+#[diag(code_offset = 500, severity = "warning", code_range = "501..=599", register)]
+enum LintErrorKind {
+    #[display(fmt = "unused variable {name}")]
+    UnusedVariable {
+        name: String,
+        #[diag(severity_field)]
+        level: Severity,
+    },
+
+    #[diag(severity = 'E')]
+    #[display(fmt = "unknown lint")]
+    UnknownLint,
+}
+
+#[test]
+fn severity_from_field() {
+    let e = LintErrorKind::UnusedVariable { name: "a".into(), level: Severity::Error };
+    assert_eq!(e.severity(), Severity::Error);
+    assert_eq!(e.code(), 501);
+
+    let e = LintErrorKind::UnusedVariable { name: "a".into(), level: Severity::Info };
+    assert_eq!(e.severity(), Severity::Info);
+    assert_eq!(LintErrorKind::UnknownLint.severity(), Severity::Error);
+}
+
+// this crate has no direct `inventory` dependency, registrations must only refer to `kg_diag`
+#[test]
+fn registered_codes() {
+    let regs: Vec<_> = kg_diag::registry::registrations()
+        .filter(|r| r.type_name.ends_with("::LintErrorKind"))
+        .map(|r| (r.code, r.variant))
+        .collect();
+    assert_eq!(regs.len(), 2);
+    assert!(regs.contains(&(501, "UnusedVariable")));
+    assert!(regs.contains(&(502, "UnknownLint")));
+    assert!(kg_diag::registry::check_collisions().is_ok());
+}
+
+mod facade {
+    pub use kg_diag as diag;
+}
+
+#[derive(Debug, Detail, Display)]
+#[diag(crate = "crate::facade::diag", code_offset = 600)]
+enum FacadeErrorKind {
+    #[display(fmt = "facade error")]
+    Facade,
+}
+
+#[test]
+fn crate_path_override() {
+    assert_eq!(FacadeErrorKind::Facade.code(), 601);
+}
+
+#[derive(Debug, Detail, Display)]
+#[diag(code_offset = 700, std_error)]
+enum StdErrorKind {
+    #[display(fmt = "read failed")]
+    Read {
+        #[diag(cause)]
+        cause: std::io::Error,
+    },
+
+    #[display(fmt = "parse failed")]
+    Parse(#[diag(cause)] Option<Box<std::num::ParseIntError>>),
+
+    #[display(fmt = "no cause")]
+    Plain,
+}
+
+#[test]
+fn std_error_source() {
+    use std::error::Error;
+
+    fn as_error<E: Error>(e: &E) -> &dyn Error {
+        e
+    }
+
+    let e = StdErrorKind::Read {
+        cause: std::io::Error::new(std::io::ErrorKind::Other, "disk on fire"),
+    };
+    assert_eq!(as_error(&e).source().unwrap().to_string(), "disk on fire");
+    assert_eq!(e.code(), 701);
+
+    let cause = "x".parse::<i32>().unwrap_err();
+    let e = StdErrorKind::Parse(Some(Box::new(cause.clone())));
+    assert_eq!(e.source().unwrap().to_string(), cause.to_string());
+    assert!(StdErrorKind::Parse(None).source().is_none());
+    assert!(StdErrorKind::Plain.source().is_none());
+}
+
+#[test]
+fn expected_macro() {
+    use kg_diag::parse::Expected;
+
+    let e = expected!["keyword true", '_', '0'..='9', '_', '5'];
+    assert_eq!(e, Expected::OneOf(vec![
+        Expected::CharRange('0', '9'),
+        Expected::Char('_'),
+        Expected::Custom("keyword true".into()),
+    ]));
+    assert_eq!(expected![b'a'..=b'f', b'x'], Expected::one_of(vec![Expected::ByteRange(b'a', b'f'), Expected::Byte(b'x')]));
+    assert_eq!(expected!['x'], Expected::Char('x'));
+}
@@ -0,0 +1,12 @@
+#[macro_use]
+extern crate kg_diag_derive;
+
+#[derive(Debug, Detail)]
+#[diag(code_offset = 100, code_range = "100..103")]
+enum ErrorKind {
+    First,
+    Second,
+    Third,
+}
+
+fn main() {}
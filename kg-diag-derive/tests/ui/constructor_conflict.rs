@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate kg_diag_derive;
+
+#[derive(Debug, Detail)]
+#[diag(constructors)]
+enum ErrorKind {
+    IoError,
+    IOError,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate kg_diag_derive;
+
+#[derive(Debug, Diag)]
+enum WrappedDiag {
+    Empty,
+}
+
+fn main() {}
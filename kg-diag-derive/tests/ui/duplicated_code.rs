@@ -0,0 +1,12 @@
+#[macro_use]
+extern crate kg_diag_derive;
+
+#[derive(Debug, Detail)]
+enum ErrorKind {
+    #[diag(code = 1)]
+    First,
+    #[diag(code = 1)]
+    Second,
+}
+
+fn main() {}
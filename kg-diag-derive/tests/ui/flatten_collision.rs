@@ -0,0 +1,26 @@
+#![feature(min_specialization)]
+
+#[macro_use]
+extern crate kg_diag_derive;
+#[macro_use]
+extern crate kg_display_derive;
+
+#[derive(Debug, Detail, Display)]
+enum InnerKind {
+    #[diag(code = 5)]
+    #[display(fmt = "inner")]
+    Inner,
+}
+
+#[derive(Debug, Detail, Display)]
+enum ErrorKind {
+    #[diag(flatten, code = 100)]
+    #[display(fmt = "{_0}")]
+    Flattened(InnerKind),
+
+    #[diag(code = 105)]
+    #[display(fmt = "outer")]
+    Outer,
+}
+
+fn main() {}
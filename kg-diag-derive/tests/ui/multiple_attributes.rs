@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate kg_diag_derive;
+
+#[derive(Debug, Detail)]
+enum ErrorKind {
+    #[diag(code = 1)]
+    #[diag(severity = "error")]
+    Empty,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+#[macro_use]
+extern crate kg_diag_derive;
+
+use kg_diag::Severity;
+
+#[derive(Debug, Detail)]
+enum ErrorKind {
+    #[diag(severity = "error")]
+    Lint(#[diag(severity_field)] Severity),
+}
+
+fn main() {}
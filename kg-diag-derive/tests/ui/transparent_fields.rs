@@ -0,0 +1,10 @@
+#[macro_use]
+extern crate kg_diag_derive;
+
+#[derive(Debug, Detail)]
+enum ErrorKind {
+    #[diag(transparent)]
+    Pair(usize, usize),
+}
+
+fn main() {}
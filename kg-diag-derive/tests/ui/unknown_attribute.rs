@@ -0,0 +1,10 @@
+#[macro_use]
+extern crate kg_diag_derive;
+
+#[derive(Debug, Detail)]
+#[diag(offset = 100)]
+enum ErrorKind {
+    Empty,
+}
+
+fn main() {}
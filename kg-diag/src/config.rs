@@ -0,0 +1,186 @@
+//! Crate-wide configuration of defaults, so that applications can set their policy once at
+//! startup instead of passing options through every call site:
+//!
+//! ```
+//! use kg_diag::config::{self, Config, StacktraceMode};
+//!
+//! config::configure(Config {
+//!     stacktrace: StacktraceMode::Never,
+//!     quote_lines: 1,
+//!     ..Config::default()
+//! });
+//! ```
+//!
+//! Parts of an application that need different settings, like a worker rendering diagnostics
+//! for a machine-readable report, can override the configuration on their own thread only,
+//! until returned guard is dropped:
+//!
+//! ```
+//! use kg_diag::config::{self, ColorPolicy, Config};
+//!
+//! let _guard = config::scoped(Config {
+//!     color: ColorPolicy::Never,
+//!     ..(*config::config()).clone()
+//! });
+//! assert_eq!(config::config().color, ColorPolicy::Never);
+//! ```
+
+use std::cell::RefCell;
+use std::sync::{Arc, RwLock};
+
+/// When diagnostics created with `From<Detail>` capture a stacktrace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StacktraceMode {
+    Always,
+    Never,
+    /// Only in builds with debug assertions enabled.
+    Debug,
+}
+
+impl StacktraceMode {
+    pub fn enabled(&self) -> bool {
+        match *self {
+            StacktraceMode::Always => true,
+            StacktraceMode::Never => false,
+            StacktraceMode::Debug => cfg!(debug_assertions),
+        }
+    }
+}
+
+/// When emitters use colored output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorPolicy {
+    /// Colors are used if output is a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorPolicy {
+    /// Environment variable disabling colors (unless they are forced with `Always`) when set
+    /// to anything but an empty string, see <https://no-color.org>.
+    pub const NO_COLOR_ENV: &'static str = "NO_COLOR";
+
+    /// Tells if colors should be used for output, given whether it is a terminal.
+    pub fn enabled(&self, tty: bool) -> bool {
+        match *self {
+            ColorPolicy::Auto => {
+                tty && std::env::var_os(Self::NO_COLOR_ENV).map_or(true, |v| v.is_empty())
+            }
+            ColorPolicy::Always => true,
+            ColorPolicy::Never => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub stacktrace: StacktraceMode,
+    /// Number of context lines before and after quoted spans, see `Reader::quote_default()`.
+    pub quote_lines: u32,
+    pub color: ColorPolicy,
+    /// Number of columns tab characters are expanded to in rendered quotes.
+    pub tab_width: usize,
+    /// Render details shown by alternate (`{:#}`) formatting by default.
+    pub verbose: bool,
+    /// Colors of colored output, see `ColorPolicy`.
+    #[cfg(feature = "color")]
+    pub palette: crate::render::Palette,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            stacktrace: StacktraceMode::Debug,
+            quote_lines: 2,
+            color: ColorPolicy::Auto,
+            tab_width: 4,
+            verbose: false,
+            #[cfg(feature = "color")]
+            palette: crate::render::Palette::default(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref CONFIG: RwLock<Arc<Config>> = RwLock::new(Arc::new(Config::default()));
+}
+
+thread_local! {
+    static SCOPED: RefCell<Option<Arc<Config>>> = RefCell::new(None);
+}
+
+/// Installs crate-wide configuration. Meant to be called once at startup, since diagnostics
+/// created before keep defaults they were created with.
+pub fn configure(config: Config) {
+    *CONFIG.write().unwrap() = Arc::new(config);
+}
+
+/// Overrides crate-wide configuration on the current thread for the lifetime of returned
+/// guard, without affecting other threads. Guards can be nested, dropping one restores
+/// the configuration that was in effect when it was created.
+pub fn scoped(config: Config) -> ConfigGuard {
+    let prev = SCOPED.with(|s| s.replace(Some(Arc::new(config))));
+    ConfigGuard { prev }
+}
+
+/// Currently installed configuration (`Config::default()` unless `configure()` was called),
+/// or the one set with `scoped()` on the current thread.
+pub fn config() -> Arc<Config> {
+    if let Some(config) = SCOPED.with(|s| s.borrow().clone()) {
+        return config;
+    }
+    CONFIG.read().unwrap().clone()
+}
+
+/// Guard returned by `scoped()`, restoring previous configuration of the current thread on drop.
+#[derive(Debug)]
+pub struct ConfigGuard {
+    prev: Option<Arc<Config>>,
+}
+
+impl Drop for ConfigGuard {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        SCOPED.with(|s| *s.borrow_mut() = prev);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn configured_defaults() {
+        // scoped to this thread, so that tests running in parallel keep defaults
+        let guard = scoped(Config {
+            stacktrace: StacktraceMode::Never,
+            quote_lines: 0,
+            ..Config::default()
+        });
+        let mut r = MemCharReader::new(b"line 1\nline 2\nline 3\n");
+        let d = ParseDiag::with_span_quote(
+            IoErrorDetail::MissingFinalNewline { pos: Position::with(7, 1, 0) },
+            &mut r,
+        );
+        drop(guard);
+
+        assert!(d.stacktrace().is_none());
+        assert_eq!(d.quotes()[0].source(), "line 2");
+        assert_eq!(config().quote_lines, Config::default().quote_lines);
+    }
+
+    #[test]
+    fn nested_scopes_restore_previous_config() {
+        let outer = scoped(Config { quote_lines: 5, ..Config::default() });
+        {
+            let _inner = scoped(Config { quote_lines: 7, ..Config::default() });
+            assert_eq!(config().quote_lines, 7);
+            std::thread::spawn(|| assert_eq!(config().quote_lines, Config::default().quote_lines)).join().unwrap();
+        }
+        assert_eq!(config().quote_lines, 5);
+        drop(outer);
+        assert_eq!(config().quote_lines, Config::default().quote_lines);
+    }
+}
@@ -0,0 +1,244 @@
+//! Per-thread context values captured into every diagnostic created on that thread.
+
+use std::cell::RefCell;
+#[cfg(feature = "timing")]
+use std::time::{Duration, Instant};
+
+thread_local! {
+    static VALUES: RefCell<Vec<(String, String)>> = RefCell::new(Vec::new());
+}
+
+#[cfg(feature = "timing")]
+thread_local! {
+    static TASKS: RefCell<Vec<(String, Instant)>> = RefCell::new(Vec::new());
+}
+
+/// Sets context `value` for `key` on the current thread, returning previous value if any.
+pub fn set<K: Into<String>, V: Into<String>>(key: K, value: V) -> Option<String> {
+    let key = key.into();
+    let value = value.into();
+    VALUES.with(|v| {
+        let mut v = v.borrow_mut();
+        if let Some(e) = v.iter_mut().find(|e| e.0 == key) {
+            Some(std::mem::replace(&mut e.1, value))
+        } else {
+            v.push((key, value));
+            None
+        }
+    })
+}
+
+pub fn get(key: &str) -> Option<String> {
+    VALUES.with(|v| v.borrow().iter().find(|e| e.0 == key).map(|e| e.1.clone()))
+}
+
+pub fn remove(key: &str) -> Option<String> {
+    VALUES.with(|v| {
+        let mut v = v.borrow_mut();
+        let index = v.iter().position(|e| e.0 == key)?;
+        Some(v.remove(index).1)
+    })
+}
+
+pub fn clear() {
+    VALUES.with(|v| v.borrow_mut().clear())
+}
+
+/// Sets context value for the lifetime of returned guard, restoring previous value on drop.
+pub fn scoped<K: Into<String>, V: Into<String>>(key: K, value: V) -> ContextGuard {
+    let key = key.into();
+    let prev = set(key.clone(), value);
+    ContextGuard { key, prev }
+}
+
+/// Enters a named task scope on the current thread, left when returned guard is dropped.
+/// Diagnostics created within the scope record time elapsed since it was entered.
+#[cfg(feature = "timing")]
+pub fn task<N: Into<String>>(name: N) -> TaskGuard {
+    TASKS.with(|t| t.borrow_mut().push((name.into(), Instant::now())));
+    TaskGuard { _private: () }
+}
+
+#[cfg(feature = "timing")]
+fn current_task() -> Option<TaskTiming> {
+    TASKS.with(|t| {
+        t.borrow().last().map(|&(ref task, start)| TaskTiming {
+            task: task.clone(),
+            elapsed: start.elapsed(),
+        })
+    })
+}
+
+/// Captures current thread context values, `None` if there are none.
+#[cfg(not(feature = "timing"))]
+pub fn capture() -> Option<Box<Context>> {
+    VALUES.with(|v| {
+        let v = v.borrow();
+        if v.is_empty() {
+            None
+        } else {
+            Some(Box::new(Context { values: v.clone() }))
+        }
+    })
+}
+
+/// Captures current thread context values and timing of the current task, `None` if there are
+/// none.
+#[cfg(feature = "timing")]
+pub fn capture() -> Option<Box<Context>> {
+    let timing = current_task();
+    VALUES.with(|v| {
+        let v = v.borrow();
+        if v.is_empty() && timing.is_none() {
+            None
+        } else {
+            Some(Box::new(Context { values: v.clone(), timing }))
+        }
+    })
+}
+
+#[cfg(feature = "timing")]
+#[derive(Debug)]
+pub struct TaskGuard {
+    _private: (),
+}
+
+#[cfg(feature = "timing")]
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        TASKS.with(|t| t.borrow_mut().pop());
+    }
+}
+
+/// Time elapsed in the innermost task scope when a diagnostic was created.
+#[cfg(feature = "timing")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskTiming {
+    pub task: String,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug)]
+pub struct ContextGuard {
+    key: String,
+    prev: Option<String>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        match self.prev.take() {
+            Some(prev) => {
+                set(std::mem::replace(&mut self.key, String::new()), prev);
+            }
+            None => {
+                remove(&self.key);
+            }
+        }
+    }
+}
+
+/// Context values captured at diagnostic creation.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Context {
+    values: Vec<(String, String)>,
+    #[cfg(feature = "timing")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timing: Option<TaskTiming>,
+}
+
+impl Context {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.iter().find(|e| e.0 == key).map(|e| e.1.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|e| (e.0.as_str(), e.1.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    #[cfg(feature = "timing")]
+    pub fn timing(&self) -> Option<&TaskTiming> {
+        self.timing.as_ref()
+    }
+
+    /// Writes details shown only in verbose (alternate) output.
+    pub(crate) fn fmt_verbose(&self, f: &mut dyn std::fmt::Write) -> std::fmt::Result {
+        #[cfg(feature = "timing")]
+        {
+            if let Some(ref t) = self.timing {
+                write!(f, "in task '{}' after {:?}\n", t.task, t.elapsed)?;
+            }
+        }
+        #[cfg(not(feature = "timing"))]
+        {
+            let _ = f;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, (k, v)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}={}", k, v)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BasicDiag, Diag, IoErrorDetail};
+
+    #[test]
+    fn context_captured_in_diag() {
+        let d = BasicDiag::new(IoErrorDetail::Fmt);
+        assert!(d.context().is_none());
+
+        let _request = scoped("request", "42");
+        let _file = scoped("file", "config.toml");
+        let d = BasicDiag::new(IoErrorDetail::Fmt);
+        let c = d.context().unwrap();
+        assert_eq!(c.get("request"), Some("42"));
+        assert_eq!(c.get("file"), Some("config.toml"));
+        assert_eq!(c.to_string(), "request=42, file=config.toml");
+    }
+
+    #[test]
+    fn scoped_restores_previous_value() {
+        set("user", "a");
+        {
+            let _g = scoped("user", "b");
+            assert_eq!(get("user").as_deref(), Some("b"));
+        }
+        assert_eq!(get("user").as_deref(), Some("a"));
+        clear();
+        assert!(capture().is_none());
+    }
+
+    #[cfg(feature = "timing")]
+    #[test]
+    fn timing_recorded_in_task_scope() {
+        {
+            let _t = task("parsing config");
+            std::thread::sleep(Duration::from_millis(5));
+            let d = BasicDiag::new(IoErrorDetail::Fmt);
+            let t = d.context().unwrap().timing().unwrap();
+            assert_eq!(t.task, "parsing config");
+            assert!(t.elapsed >= Duration::from_millis(5));
+            assert!(format!("{:#}", d).contains("in task 'parsing config' after"));
+        }
+        assert!(capture().is_none());
+    }
+}
@@ -1,9 +1,10 @@
 use std::any::TypeId;
 use std::convert::TryFrom;
 use std::fmt::{Debug, Display};
-use crate::{BasicDiag, Diag};
+use crate::{BasicDiag, Diag, Span};
 
-#[derive(Debug, Display, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[derive(Debug, Display, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Severity {
     #[display("info")]
     Info,
@@ -88,6 +89,18 @@ pub trait Detail: Display + Debug + Send + Sync + 'static {
 
     fn code(&self) -> u32;
 
+    /// Namespace of the code (like `"PARSE"`), used as a prefix of the formatted code.
+    fn domain(&self) -> Option<&'static str>;
+
+    /// Formatted code, like `E0042` or `PARSE-E0042` when detail has a domain.
+    fn code_string(&self) -> String;
+
+    /// Location in the source this detail refers to, if known.
+    fn span(&self) -> Option<Span>;
+
+    /// Longer explanation of the diagnostic, if available.
+    fn help(&self) -> Option<&'static str>;
+
     fn type_id(&self) -> TypeId;
 
     fn as_fmt_debug(&self) -> &dyn std::fmt::Debug;
@@ -104,6 +117,22 @@ impl<T: Detail> Detail for T {
         0
     }
 
+    default fn domain(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn code_string(&self) -> String {
+        format_code(self.domain(), self.severity(), self.code())
+    }
+
+    default fn span(&self) -> Option<Span> {
+        None
+    }
+
+    default fn help(&self) -> Option<&'static str> {
+        None
+    }
+
     default fn type_id(&self) -> TypeId {
         TypeId::of::<Self>()
     }
@@ -117,6 +146,14 @@ impl<T: Detail> Detail for T {
     }
 }
 
+/// Formats code like `Detail::code_string()`, with the letter of given `severity`.
+pub(crate) fn format_code(domain: Option<&str>, severity: Severity, code: u32) -> String {
+    match domain {
+        Some(domain) => format!("{}-{}{:04}", domain, severity.code_char(), code),
+        None => format!("{}{:04}", severity.code_char(), code),
+    }
+}
+
 impl dyn Detail {
     pub fn downcast_ref<T: Detail>(&self) -> Option<&T> {
         if self.type_id() == TypeId::of::<T>() {
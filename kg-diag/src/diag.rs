@@ -1,5 +1,6 @@
 use std::any::TypeId;
 use std::fmt::{Debug, Display};
+use std::path::PathBuf;
 use std::raw::TraitObject;
 
 use super::*;
@@ -15,11 +16,67 @@ pub trait Diag: Display + Debug + Send + Sync + 'static {
 
     fn stacktrace(&self) -> Option<&Stacktrace>;
 
+    fn quotes(&self) -> &[Quote] {
+        &[]
+    }
+
+    /// Maximum number of quotes rendered when displaying this diagnostic, remaining ones are
+    /// only summarized. `quotes()` always returns all of them.
+    fn max_quotes(&self) -> Option<usize> {
+        None
+    }
+
+    /// Fix-it suggestions, rendered after quotes as `help:` lines.
+    fn suggestions(&self) -> &[Suggestion] {
+        &[]
+    }
+
+    /// Severity of this diagnostic, which is the detail severity unless it was re-classified
+    /// by `policy::CodePolicy` when this diagnostic was created.
+    fn effective_severity(&self) -> Severity {
+        self.detail().severity()
+    }
+
+    /// Formatted code like `Detail::code_string()`, but with the letter of `effective_severity()`.
+    fn effective_code_string(&self) -> String {
+        let detail = self.detail();
+        detail::format_code(detail.domain(), self.effective_severity(), detail.code())
+    }
+
+    /// Thread context values captured when this diagnostic was created.
+    fn context(&self) -> Option<&Context> {
+        None
+    }
+
+    /// Key ordering diagnostics by location of the first quote, then by code.
+    fn sort_key(&self) -> SortKey {
+        let quote = self.quotes().first();
+        SortKey {
+            path: quote.and_then(|q| q.path()).map(|p| p.to_path_buf()),
+            span: quote.map(|q| q.span()),
+            code: self.detail().code(),
+        }
+    }
+
     fn type_id(&self) -> TypeId {
         TypeId::of::<Self>()
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortKey {
+    pub path: Option<PathBuf>,
+    pub span: Option<Span>,
+    pub code: u32,
+}
+
+/// Called for every created diagnostic, returns severity forced by `policy::CodePolicy`.
+fn created(detail: &dyn Detail) -> Option<Severity> {
+    let severity = policy::severity_for(detail);
+    stats::record_diag(severity.unwrap_or_else(|| detail.severity()));
+    severity
+}
+
 impl dyn Diag {
     pub fn downcast_ref<T: Diag>(&self) -> Option<&T> {
         if self.type_id() == TypeId::of::<T>() {
@@ -37,28 +94,17 @@ impl dyn Diag {
         }
     }
 
+    /// Renders this diagnostic like `Display`, with severities, carets and file headers colored
+    /// like in rustc output, unless colors are disabled (see `Layout::to_colored_string()`).
+    #[cfg(feature = "color")]
+    pub fn render_colored(&self) -> String {
+        render::Layout::of_diag(self, &render::RenderOptions::from_env()).to_colored_string()
+    }
+
     fn display(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let d = self.detail();
-        write!(
-            f,
-            "{} [{}{:04}]: {}\n",
-            d.severity(),
-            d.severity().code_char(),
-            d.code(),
-            d
-        )?;
-        if let Some(parse_diag) = self.downcast_ref::<ParseDiag>() {
-            for q in parse_diag.quotes().iter() {
-                std::fmt::Display::fmt(q, f)?;
-            }
-        }
-        if let Some(c) = self.cause() {
-            write!(f, "caused by: {}", c)?;
-        }
-        if let Some(s) = self.stacktrace() {
-            write!(f, "{}", s)?;
-        }
-        Ok(())
+        let mut options = render::RenderOptions::from_env();
+        options.verbose |= f.alternate();
+        render::renderer().render(self, &options, f)
     }
 }
 
@@ -89,6 +135,8 @@ pub struct BasicDiag {
     detail: DetailHolder,
     cause: Option<Box<dyn Diag>>,
     stacktrace: Option<Box<Stacktrace>>,
+    context: Option<Box<Context>>,
+    severity: Option<Severity>,
 }
 
 impl BasicDiag {
@@ -96,6 +144,8 @@ impl BasicDiag {
         BasicDiag {
             cause: None,
             stacktrace: None,
+            context: context::capture(),
+            severity: created(&detail),
             detail: DetailHolder::new(detail),
         }
     }
@@ -104,6 +154,8 @@ impl BasicDiag {
         BasicDiag {
             cause: Some(Box::new(cause)),
             stacktrace: None,
+            context: context::capture(),
+            severity: created(&detail),
             detail: DetailHolder::new(detail),
         }
     }
@@ -112,6 +164,8 @@ impl BasicDiag {
         BasicDiag {
             cause: None,
             stacktrace: Some(Box::new(stacktrace)),
+            context: context::capture(),
+            severity: created(&detail),
             detail: DetailHolder::new(detail),
         }
     }
@@ -124,6 +178,8 @@ impl BasicDiag {
         BasicDiag {
             cause: Some(Box::new(cause)),
             stacktrace: Some(Box::new(stacktrace)),
+            context: context::capture(),
+            severity: created(&detail),
             detail: DetailHolder::new(detail),
         }
     }
@@ -149,19 +205,24 @@ impl Diag for BasicDiag {
     fn stacktrace(&self) -> Option<&Stacktrace> {
         self.stacktrace.as_ref().map(|s| s.as_ref())
     }
-}
 
-impl<T: Detail> From<T> for BasicDiag {
-    #[cfg(debug_assertions)]
-    #[inline(always)]
-    fn from(detail: T) -> Self {
-        BasicDiag::with_stacktrace(detail, Stacktrace::new())
+    fn context(&self) -> Option<&Context> {
+        self.context.as_ref().map(|c| c.as_ref())
     }
 
-    #[cfg(not(debug_assertions))]
+    fn effective_severity(&self) -> Severity {
+        self.severity.unwrap_or_else(|| self.detail().severity())
+    }
+}
+
+impl<T: Detail> From<T> for BasicDiag {
     #[inline(always)]
     fn from(detail: T) -> Self {
-        BasicDiag::new(detail)
+        if config::config().stacktrace.enabled() {
+            BasicDiag::with_stacktrace(detail, Stacktrace::new())
+        } else {
+            BasicDiag::new(detail)
+        }
     }
 }
 
@@ -270,30 +331,38 @@ pub struct SimpleDiag {
     detail: Box<dyn Detail>,
     cause: Option<Box<dyn Diag>>,
     stacktrace: Option<Box<Stacktrace>>,
+    context: Option<Box<Context>>,
+    severity: Option<Severity>,
 }
 
 impl SimpleDiag {
     pub fn new<T: Detail>(detail: T) -> SimpleDiag {
         SimpleDiag {
+            severity: created(&detail),
             detail: box detail,
             cause: None,
             stacktrace: None,
+            context: context::capture(),
         }
     }
 
     pub fn with_cause<T: Detail, E: Diag>(detail: T, cause: E) -> SimpleDiag {
         SimpleDiag {
+            severity: created(&detail),
             detail: box detail,
             cause: Some(Box::new(cause)),
             stacktrace: None,
+            context: context::capture(),
         }
     }
 
     pub fn with_stacktrace<T: Detail>(detail: T, stacktrace: Stacktrace) -> SimpleDiag {
         SimpleDiag {
+            severity: created(&detail),
             detail: box detail,
             cause: None,
             stacktrace: Some(Box::new(stacktrace)),
+            context: context::capture(),
         }
     }
 
@@ -303,9 +372,11 @@ impl SimpleDiag {
         stacktrace: Stacktrace,
     ) -> SimpleDiag {
         SimpleDiag {
+            severity: created(&detail),
             detail: box detail,
             cause: Some(Box::new(cause)),
             stacktrace: Some(Box::new(stacktrace)),
+            context: context::capture(),
         }
     }
 }
@@ -330,19 +401,24 @@ impl Diag for SimpleDiag {
     fn stacktrace(&self) -> Option<&Stacktrace> {
         self.stacktrace.as_ref().map(|s| s.as_ref())
     }
-}
 
-impl<T: Detail> From<T> for SimpleDiag {
-    #[cfg(debug_assertions)]
-    #[inline(always)]
-    fn from(detail: T) -> Self {
-        SimpleDiag::with_stacktrace(detail, Stacktrace::new_skip(1))
+    fn context(&self) -> Option<&Context> {
+        self.context.as_ref().map(|c| c.as_ref())
     }
 
-    #[cfg(not(debug_assertions))]
+    fn effective_severity(&self) -> Severity {
+        self.severity.unwrap_or_else(|| self.detail().severity())
+    }
+}
+
+impl<T: Detail> From<T> for SimpleDiag {
     #[inline(always)]
     fn from(detail: T) -> Self {
-        SimpleDiag::new(detail)
+        if config::config().stacktrace.enabled() {
+            SimpleDiag::with_stacktrace(detail, Stacktrace::new_skip(1))
+        } else {
+            SimpleDiag::new(detail)
+        }
     }
 }
 
@@ -356,35 +432,51 @@ impl Display for SimpleDiag {
 pub struct ParseDiag {
     detail: Box<dyn Detail>,
     quotes: Vec<Quote>,
+    max_quotes: Option<usize>,
+    suggestions: Vec<Suggestion>,
     cause: Option<Box<dyn Diag>>,
     stacktrace: Option<Box<Stacktrace>>,
+    context: Option<Box<Context>>,
+    severity: Option<Severity>,
 }
 
 impl ParseDiag {
     pub fn new<T: Detail>(detail: T) -> ParseDiag {
         ParseDiag {
+            severity: created(&detail),
             detail: box detail,
             quotes: Vec::new(),
+            max_quotes: None,
+            suggestions: Vec::new(),
             cause: None,
             stacktrace: None,
+            context: context::capture(),
         }
     }
 
     pub fn with_cause<T: Detail, E: Diag>(detail: T, cause: E) -> ParseDiag {
         ParseDiag {
+            severity: created(&detail),
             detail: box detail,
             quotes: Vec::new(),
+            max_quotes: None,
+            suggestions: Vec::new(),
             cause: Some(Box::new(cause)),
             stacktrace: None,
+            context: context::capture(),
         }
     }
 
     pub fn with_stacktrace<T: Detail>(detail: T, stacktrace: Stacktrace) -> ParseDiag {
         ParseDiag {
+            severity: created(&detail),
             detail: box detail,
             quotes: Vec::new(),
+            max_quotes: None,
+            suggestions: Vec::new(),
             cause: None,
             stacktrace: Some(Box::new(stacktrace)),
+            context: context::capture(),
         }
     }
 
@@ -394,13 +486,18 @@ impl ParseDiag {
         stacktrace: Stacktrace,
     ) -> ParseDiag {
         ParseDiag {
+            severity: created(&detail),
             detail: box detail,
             quotes: Vec::new(),
+            max_quotes: None,
+            suggestions: Vec::new(),
             cause: Some(Box::new(cause)),
             stacktrace: Some(Box::new(stacktrace)),
+            context: context::capture(),
         }
     }
 
+    /// Same as `Diag::quotes()`, kept so that callers need not import `Diag`.
     pub fn quotes(&self) -> &[Quote] {
         &self.quotes
     }
@@ -408,6 +505,25 @@ impl ParseDiag {
     pub fn add_quote(&mut self, quote: Quote) {
         self.quotes.push(quote)
     }
+
+    pub fn add_suggestion(&mut self, suggestion: Suggestion) {
+        self.suggestions.push(suggestion)
+    }
+
+    /// Limits number of quotes rendered by `Display`, see `Diag::max_quotes()`.
+    pub fn set_max_quotes(&mut self, max: usize) {
+        self.max_quotes = Some(max);
+    }
+
+    /// Creates diagnostic quoting the span reported by `detail.span()`, if any.
+    pub fn with_span_quote<T: Detail, R: Reader + ?Sized>(detail: T, reader: &mut R) -> ParseDiag {
+        let span = detail.span();
+        let mut d = ParseDiag::from(detail);
+        if let Some(span) = span {
+            d.add_quote(reader.quote_default(span.start, span.end, "".into()));
+        }
+        d
+    }
 }
 
 impl Diag for ParseDiag {
@@ -430,19 +546,36 @@ impl Diag for ParseDiag {
     fn stacktrace(&self) -> Option<&Stacktrace> {
         self.stacktrace.as_ref().map(|s| s.as_ref())
     }
-}
 
-impl<T: Detail> From<T> for ParseDiag {
-    #[cfg(debug_assertions)]
-    #[inline(always)]
-    fn from(detail: T) -> Self {
-        ParseDiag::with_stacktrace(detail, Stacktrace::new_skip(1))
+    fn context(&self) -> Option<&Context> {
+        self.context.as_ref().map(|c| c.as_ref())
+    }
+
+    fn effective_severity(&self) -> Severity {
+        self.severity.unwrap_or_else(|| self.detail().severity())
+    }
+
+    fn quotes(&self) -> &[Quote] {
+        &self.quotes
     }
 
-    #[cfg(not(debug_assertions))]
+    fn max_quotes(&self) -> Option<usize> {
+        self.max_quotes
+    }
+
+    fn suggestions(&self) -> &[Suggestion] {
+        &self.suggestions
+    }
+}
+
+impl<T: Detail> From<T> for ParseDiag {
     #[inline(always)]
     fn from(detail: T) -> Self {
-        ParseDiag::new(detail)
+        if config::config().stacktrace.enabled() {
+            ParseDiag::with_stacktrace(detail, Stacktrace::new_skip(1))
+        } else {
+            ParseDiag::new(detail)
+        }
     }
 }
 
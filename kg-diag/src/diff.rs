@@ -0,0 +1,138 @@
+//! Comparison of diagnostic reports, e.g. recorded for a base branch and for a pull request,
+//! so that checks can fail only on newly introduced diagnostics.
+
+use std::collections::HashMap;
+
+use crate::render::{Layout, RenderOptions, Style};
+use super::*;
+
+/// Result of `diff()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagDiff {
+    /// Diagnostics of the new report not matched with any of the old report.
+    pub introduced: Vec<DiagRecord>,
+    /// Diagnostics of the old report not matched with any of the new report.
+    pub fixed: Vec<DiagRecord>,
+    /// Diagnostics of the new report matched with ones of the old report.
+    pub unchanged: Vec<DiagRecord>,
+}
+
+impl DiagDiff {
+    /// Number of introduced diagnostics with severity of at least `severity`, e.g.
+    /// `introduced_count(Severity::Warning)` to fail on new warnings and errors.
+    pub fn introduced_count(&self, severity: Severity) -> usize {
+        self.introduced.iter().filter(|d| d.severity >= severity).count()
+    }
+
+    /// Renders a summary followed by introduced diagnostics (quoted from `sources`, like
+    /// `DiagRecord::render_with()`) and headers of fixed ones.
+    pub fn render_with(&self, sources: &SourceMap) -> String {
+        self.layout_with(sources, &RenderOptions::from_env()).to_string()
+    }
+
+    pub fn layout_with(&self, sources: &SourceMap, options: &RenderOptions) -> Layout {
+        let mut l = Layout::new();
+        l.push(self.to_string(), Style::Message);
+        l.newline();
+        if !self.introduced.is_empty() {
+            l.newline();
+            l.push("introduced:", Style::Note);
+            l.newline();
+            for d in self.introduced.iter() {
+                l.append(d.layout_with(sources, options));
+            }
+        }
+        if !self.fixed.is_empty() {
+            l.newline();
+            l.push("fixed:", Style::Note);
+            l.newline();
+            for d in self.fixed.iter() {
+                d.push_header(&mut l);
+            }
+        }
+        l
+    }
+}
+
+impl std::fmt::Display for DiagDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} introduced, {} fixed, {} unchanged",
+            self.introduced.len(),
+            self.fixed.len(),
+            self.unchanged.len()
+        )
+    }
+}
+
+/// Compares reports `old` and `new`. Diagnostics are matched by `DiagRecord::key()`; if more
+/// diagnostics of the old report share a key, the one quoted closest to the new one is taken.
+pub fn diff(old: &[DiagRecord], new: &[DiagRecord]) -> DiagDiff {
+    let mut candidates: HashMap<DiagKey, Vec<usize>> = HashMap::new();
+    for (i, d) in old.iter().enumerate() {
+        candidates.entry(d.key()).or_default().push(i);
+    }
+
+    let mut matched = vec![false; old.len()];
+    let mut diff = DiagDiff::default();
+    for d in new {
+        let line = d.line().unwrap_or(0) as i64;
+        let distance = |i: usize| (old[i].line().unwrap_or(0) as i64 - line).abs();
+        let found = candidates.get_mut(&d.key()).and_then(|c| {
+            let (k, _) = c.iter().enumerate().min_by_key(|&(_, &i)| distance(i))?;
+            Some(c.remove(k))
+        });
+        match found {
+            Some(i) => {
+                matched[i] = true;
+                diff.unchanged.push(d.clone());
+            }
+            None => diff.introduced.push(d.clone()),
+        }
+    }
+    diff.fixed = old
+        .iter()
+        .zip(matched)
+        .filter(|&(_, m)| !m)
+        .map(|(d, _)| d.clone())
+        .collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records diagnostics quoting single characters of `input` at given positions.
+    fn report(input: &str, positions: &[(usize, u32, u32)]) -> Vec<DiagRecord> {
+        let mut r = MemCharReader::with_path("conf.txt", input.as_bytes());
+        positions
+            .iter()
+            .map(|&(offset, line, column)| {
+                let from = Position::with(offset, line, column);
+                let to = Position::with(offset + 1, line, column + 1);
+                let mut d = ParseDiag::new(IoErrorDetail::Fmt);
+                d.add_quote(r.quote(from, to, 0, 0, "".into()));
+                DiagRecord::of(&d)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_reports() {
+        let old = report("a = x\nb = y\n", &[(4, 0, 4), (10, 1, 4)]);
+        let new = report("c = z\n  a = x\n", &[(4, 0, 4), (12, 1, 6)]);
+        let d = diff(&old, &new);
+        assert_eq!(d.introduced, vec![new[0].clone()]);
+        assert_eq!(d.fixed, vec![old[1].clone()]);
+        assert_eq!(d.unchanged, vec![new[1].clone()]);
+        assert_eq!(d.introduced_count(Severity::Warning), 1);
+        assert_eq!(d.introduced_count(Severity::Critical), 0);
+        assert_eq!(d.to_string(), "1 introduced, 1 fixed, 1 unchanged");
+
+        let text = d.layout_with(&SourceMap::new(), &RenderOptions::default()).to_string();
+        assert!(text.contains("c = z"));
+        assert!(!text.contains("b = y"));
+    }
+}
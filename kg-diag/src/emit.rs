@@ -0,0 +1,343 @@
+//! Writing diagnostics to output streams. [`Emitter`] renders diagnostics as they are
+//! reported, and can keep a status line (like `Checking 412/900 files`) below them which is
+//! erased and redrawn as diagnostics are streamed above it.
+//!
+//! Each diagnostic is written with a single write and flushed before `emit()` returns, so
+//! outputs never contain partially written diagnostics, even when shared with other writers.
+
+use std::io::Write;
+
+use crate::render::{CodeFormat, Layout, RenderOptions};
+use super::*;
+
+/// Erases the current terminal line and moves the cursor to its start.
+const ERASE_LINE: &str = "\r\x1b[2K";
+
+/// Streams diagnostics are written to by `Emitter::std()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Routing {
+    /// All diagnostics are written to the standard error stream.
+    Stderr,
+    /// Information and warnings are written to the standard output stream, errors to the
+    /// standard error stream.
+    BySeverity,
+}
+
+impl Default for Routing {
+    fn default() -> Routing {
+        Routing::Stderr
+    }
+}
+
+pub struct Emitter {
+    out: Box<dyn Write + Send>,
+    tty: bool,
+    /// Output of diagnostics less severe than errors, if routed apart from `out`.
+    info_out: Option<Box<dyn Write + Send>>,
+    options: RenderOptions,
+    /// Colors of emitted diagnostics, if enabled.
+    #[cfg(feature = "color")]
+    palette: Option<crate::render::Palette>,
+    /// Status line currently shown, if any.
+    status: Option<String>,
+}
+
+impl Emitter {
+    /// Emitter writing to `out`. Status lines are only shown if `tty` is `true`, since
+    /// erasing them requires a terminal, and quoted lines are cut to fit terminal width
+    /// (see `RenderOptions::detect_terminal_width()`). With `color` feature enabled,
+    /// diagnostics are colored as specified by crate configuration (see
+    /// `ColorPolicy::enabled()`).
+    pub fn new(out: Box<dyn Write + Send>, tty: bool) -> Emitter {
+        #[cfg(feature = "color")]
+        let palette = {
+            let config = config::config();
+            if config.color.enabled(tty) {
+                Some(config.palette.clone())
+            } else {
+                None
+            }
+        };
+        let mut options = RenderOptions::from_env();
+        if tty {
+            options.terminal_width = RenderOptions::detect_terminal_width();
+        }
+        Emitter {
+            out,
+            tty,
+            info_out: None,
+            options,
+            #[cfg(feature = "color")]
+            palette,
+            status: None,
+        }
+    }
+
+    /// Emitter writing to the standard error stream.
+    pub fn stderr() -> Emitter {
+        Emitter::new(Box::new(std::io::stderr()), atty::is(atty::Stream::Stderr))
+    }
+
+    /// Emitter writing to standard streams as specified by `routing`. Status line is always
+    /// written to the standard error stream.
+    pub fn std(routing: Routing) -> Emitter {
+        match routing {
+            Routing::Stderr => Emitter::stderr(),
+            Routing::BySeverity => Emitter::stderr().with_info_output(Box::new(std::io::stdout())),
+        }
+    }
+
+    /// Routes diagnostics less severe than errors (i.e. information and warnings) to `out`.
+    /// Errors and the status line are still written to the main output.
+    pub fn with_info_output(mut self, out: Box<dyn Write + Send>) -> Emitter {
+        self.info_out = Some(out);
+        self
+    }
+
+    pub fn with_options(mut self, options: RenderOptions) -> Emitter {
+        self.options = options;
+        self
+    }
+
+    /// Sets format of codes in headers of emitted diagnostics.
+    pub fn with_code_format(mut self, format: CodeFormat) -> Emitter {
+        self.options.code_format = format;
+        self
+    }
+
+    /// Colors emitted diagnostics with `palette`, or disables colors if `None`.
+    #[cfg(feature = "color")]
+    pub fn with_palette(mut self, palette: Option<crate::render::Palette>) -> Emitter {
+        self.palette = palette;
+        self
+    }
+
+    pub fn options(&self) -> &RenderOptions {
+        &self.options
+    }
+
+    pub fn is_tty(&self) -> bool {
+        self.tty
+    }
+
+    pub fn is_colored(&self) -> bool {
+        #[cfg(feature = "color")]
+        return self.palette.is_some();
+        #[cfg(not(feature = "color"))]
+        return false;
+    }
+
+    /// Writes `diag`, keeping the status line (if shown) below it.
+    pub fn emit(&mut self, diag: &dyn Diag) -> std::io::Result<()> {
+        let layout = Layout::of_diag(diag, &self.options);
+        #[cfg(feature = "color")]
+        let mut text = match self.palette {
+            Some(ref palette) => {
+                let mut s = String::new();
+                let _ = layout.write_ansi(&mut s, palette);
+                s
+            }
+            None => layout.to_string(),
+        };
+        #[cfg(not(feature = "color"))]
+        let mut text = layout.to_string();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        if diag.effective_severity().is_error() {
+            return self.write_above_status(&text);
+        }
+        match self.info_out {
+            Some(ref mut info_out) => {
+                // status line is erased, so that the diagnostic does not follow it on terminal
+                let status = match self.status {
+                    Some(ref status) if self.tty => status,
+                    _ => {
+                        info_out.write_all(text.as_bytes())?;
+                        return info_out.flush();
+                    }
+                };
+                self.out.write_all(ERASE_LINE.as_bytes())?;
+                self.out.flush()?;
+                info_out.write_all(text.as_bytes())?;
+                info_out.flush()?;
+                self.out.write_all(status.as_bytes())?;
+                self.out.flush()
+            }
+            None => self.write_above_status(&text),
+        }
+    }
+
+    /// Writes all `diags` in order.
+    pub fn emit_all<'a, I>(&mut self, diags: I) -> std::io::Result<()>
+    where
+        I: IntoIterator<Item = &'a dyn Diag>,
+    {
+        for d in diags {
+            self.emit(d)?;
+        }
+        Ok(())
+    }
+
+    /// Shows `status` in place of the current status line.
+    pub fn set_status<S: Into<String>>(&mut self, status: S) -> std::io::Result<()> {
+        let status = status.into();
+        if self.tty {
+            write!(self.out, "{}{}", ERASE_LINE, status)?;
+            self.out.flush()?;
+        }
+        self.status = Some(status);
+        Ok(())
+    }
+
+    /// Shows progress status, e.g. `progress("Checking", 412, 900, "files")` shows
+    /// `Checking 412/900 files`.
+    pub fn progress(
+        &mut self,
+        label: &str,
+        current: usize,
+        total: usize,
+        unit: &str,
+    ) -> std::io::Result<()> {
+        if unit.is_empty() {
+            self.set_status(format!("{} {}/{}", label, current, total))
+        } else {
+            self.set_status(format!("{} {}/{} {}", label, current, total, unit))
+        }
+    }
+
+    pub fn status(&self) -> Option<&str> {
+        self.status.as_ref().map(|s| s.as_str())
+    }
+
+    /// Erases the status line.
+    pub fn clear_status(&mut self) -> std::io::Result<()> {
+        if self.status.take().is_some() && self.tty {
+            write!(self.out, "{}", ERASE_LINE)?;
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_above_status(&mut self, text: &str) -> std::io::Result<()> {
+        let status = match self.status {
+            Some(ref status) if self.tty => status,
+            _ => {
+                self.out.write_all(text.as_bytes())?;
+                return self.out.flush();
+            }
+        };
+        let text = format!("{}{}{}", ERASE_LINE, text, status);
+        self.out.write_all(text.as_bytes())?;
+        self.out.flush()
+    }
+}
+
+impl std::fmt::Debug for Emitter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Emitter")
+            .field("tty", &self.tty)
+            .field("info_out", &self.info_out.is_some())
+            .field("options", &self.options)
+            .field("color", &self.is_colored())
+            .field("status", &self.status)
+            .finish()
+    }
+}
+
+impl Drop for Emitter {
+    fn drop(&mut self) {
+        let _ = self.clear_status();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    /// Output buffer shared with the emitter writing into it.
+    #[derive(Debug, Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Emitter writing to terminal `out`, without colors.
+    fn tty_emitter(out: &Output) -> Emitter {
+        let e = Emitter::new(Box::new(out.clone()), true).with_options(RenderOptions::default());
+        #[cfg(feature = "color")]
+        let e = e.with_palette(None);
+        e
+    }
+
+    fn warning() -> ParseDiag {
+        ParseDiag::new(IoErrorDetail::MissingFinalNewline { pos: Position::new() })
+    }
+
+    #[test]
+    fn status_redrawn_below_diags() {
+        let out = Output::default();
+        let mut e = tty_emitter(&out);
+        e.progress("Checking", 1, 2, "files").unwrap();
+        e.emit(&warning()).unwrap();
+        e.progress("Checking", 2, 2, "files").unwrap();
+        drop(e);
+
+        let header = Layout::of_diag(&warning(), &RenderOptions::default()).to_string();
+        assert_eq!(
+            out.text(),
+            format!(
+                "{0}Checking 1/2 files{0}{1}Checking 1/2 files{0}Checking 2/2 files{0}",
+                ERASE_LINE, header
+            )
+        );
+    }
+
+    #[test]
+    fn routing_by_severity() {
+        let (out, info_out) = (Output::default(), Output::default());
+        let mut e = tty_emitter(&out).with_info_output(Box::new(info_out.clone()));
+        let error = ParseDiag::new(IoErrorDetail::Fmt);
+        e.set_status("Checking").unwrap();
+        e.emit(&warning()).unwrap();
+        e.emit(&error).unwrap();
+        drop(e);
+
+        let warning = Layout::of_diag(&warning(), &RenderOptions::default()).to_string();
+        let error = Layout::of_diag(&error, &RenderOptions::default()).to_string();
+        assert_eq!(info_out.text(), warning);
+        assert_eq!(
+            out.text(),
+            format!("{0}Checking{0}Checking{0}{1}Checking{0}", ERASE_LINE, error)
+        );
+    }
+
+    #[test]
+    fn no_status_without_tty() {
+        let out = Output::default();
+        let mut e =
+            Emitter::new(Box::new(out.clone()), false).with_options(RenderOptions::default());
+        e.set_status("Checking").unwrap();
+        e.emit(&warning()).unwrap();
+        assert_eq!(e.status(), Some("Checking"));
+        drop(e);
+
+        assert!(!out.text().contains('\x1b'));
+        assert!(!out.text().contains("Checking"));
+    }
+}
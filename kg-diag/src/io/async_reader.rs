@@ -0,0 +1,358 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::*;
+
+/// Asynchronous counterpart of `ByteReader`.
+#[async_trait]
+pub trait AsyncByteReader: Send {
+    fn path(&self) -> Option<&Path>;
+
+    fn position(&self) -> Position;
+
+    async fn next_byte(&mut self) -> IoResult<Option<u8>>;
+
+    async fn peek_byte(&mut self, lookahead: usize) -> IoResult<Option<u8>>;
+
+    async fn skip_bytes(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_byte().await?;
+        }
+        Ok(())
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote;
+}
+
+/// Asynchronous counterpart of `CharReader`.
+#[async_trait]
+pub trait AsyncCharReader: Send {
+    fn path(&self) -> Option<&Path>;
+
+    fn position(&self) -> Position;
+
+    async fn next_char(&mut self) -> IoResult<Option<char>>;
+
+    async fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>>;
+
+    async fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>>;
+
+    async fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_char().await?;
+        }
+        Ok(())
+    }
+
+    async fn match_str(&mut self, s: &str) -> IoResult<bool>;
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote;
+}
+
+/// Reader over `tokio::io::AsyncRead` stream. All data read from the stream is retained, so
+/// that quotes can refer to already consumed input.
+#[derive(Debug)]
+pub struct AsyncStreamReader<R: AsyncRead + Unpin + Send> {
+    inner: R,
+    path: Option<PathBuf>,
+    data: Vec<u8>,
+    eof: bool,
+    chunk_size: usize,
+    pos: Position,
+    c: char,
+    clen: usize,
+    line_breaks: LineBreakPolicy,
+}
+
+impl<R: AsyncRead + Unpin + Send> AsyncStreamReader<R> {
+    pub fn new(inner: R) -> AsyncStreamReader<R> {
+        AsyncStreamReader {
+            inner,
+            path: None,
+            data: Vec::new(),
+            eof: false,
+            chunk_size: 4096,
+            pos: Position::new(),
+            c: '\0',
+            clen: 0,
+            line_breaks: LineBreakPolicy::LF,
+        }
+    }
+
+    pub fn with_path<P: Into<PathBuf>>(inner: R, path: P) -> AsyncStreamReader<R> {
+        let mut r = Self::new(inner);
+        r.path = Some(path.into());
+        r
+    }
+
+    pub fn with_line_break_policy(mut self, policy: LineBreakPolicy) -> AsyncStreamReader<R> {
+        self.line_breaks = policy;
+        self
+    }
+
+    /// Data read from the stream so far.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads from the stream until at least `end` bytes are available, returns `false` if
+    /// the stream ended before.
+    async fn fill(&mut self, end: usize) -> IoResult<bool> {
+        while self.data.len() < end && !self.eof {
+            let len = self.data.len();
+            self.data.resize(len + self.chunk_size, 0);
+            let n = self.inner.read(&mut self.data[len..]).await;
+            let n = match n {
+                Ok(n) => n,
+                Err(err) => {
+                    self.data.truncate(len);
+                    return Err(err.into());
+                }
+            };
+            self.data.truncate(len + n);
+            if n == 0 {
+                self.eof = true;
+            }
+        }
+        Ok(self.data.len() >= end)
+    }
+
+    async fn decode_at(&mut self, offset: usize) -> IoResult<Option<(char, usize)>> {
+        if !self.fill(offset + 1).await? {
+            return Ok(None);
+        }
+        let b = self.data[offset];
+        let len = if b < 0b10000000u8 {
+            return Ok(Some((b as char, 1)));
+        } else if b < 0b11000000u8 {
+            return Err(IoErrorDetail::Utf8InvalidEncoding { offset, len: 1 });
+        } else if b < 0b11100000u8 {
+            2
+        } else if b < 0b11110000u8 {
+            3
+        } else if b <= 0b11110100u8 {
+            4
+        } else {
+            return Err(IoErrorDetail::Utf8InvalidEncoding { offset, len: 4 });
+        };
+        if !self.fill(offset + len).await? {
+            return Err(IoErrorDetail::Utf8UnexpectedEof { offset });
+        }
+        match std::str::from_utf8(&self.data[offset..offset + len]) {
+            Ok(s) => Ok(s.chars().next().map(|c| (c, len))),
+            Err(_) => Err(IoErrorDetail::Utf8InvalidEncoding { offset, len }),
+        }
+    }
+
+    async fn step(&mut self, mut pos: Position, c: char, len: usize) -> IoResult<Position> {
+        pos.offset += len;
+        let next = if c == '\r' && self.fill(pos.offset + 1).await? {
+            Some(self.data[pos.offset])
+        } else {
+            None
+        };
+        if self.line_breaks.is_line_break(c, next) {
+            pos.inc_line();
+        } else {
+            pos.inc_column();
+        }
+        Ok(pos)
+    }
+
+    fn quote_data(
+        &self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        Quote::new(
+            self.path.as_ref().map(|p| p.as_path()),
+            &self.data,
+            from,
+            to,
+            lines_before,
+            lines_after,
+            message,
+        )
+    }
+}
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + Send> AsyncByteReader for AsyncStreamReader<R> {
+    fn path(&self) -> Option<&Path> {
+        self.path.as_ref().map(|p| p.as_path())
+    }
+
+    fn position(&self) -> Position {
+        self.pos
+    }
+
+    async fn next_byte(&mut self) -> IoResult<Option<u8>> {
+        // any decoded character is consumed first
+        if self.clen > 0 {
+            self.pos = self.step(self.pos, self.c, self.clen).await?;
+            self.clen = 0;
+        }
+        if !self.fill(self.pos.offset + 1).await? {
+            return Ok(None);
+        }
+        let b = self.data[self.pos.offset];
+        self.pos.offset += 1;
+        if b == b'\n' {
+            self.pos.inc_line();
+        } else if b & 0b11000000u8 != 0b10000000u8 {
+            self.pos.inc_column();
+        }
+        Ok(Some(b))
+    }
+
+    async fn peek_byte(&mut self, lookahead: usize) -> IoResult<Option<u8>> {
+        let offset = self.pos.offset + self.clen + lookahead;
+        if self.fill(offset + 1).await? {
+            Ok(Some(self.data[offset]))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        self.quote_data(from, to, lines_before, lines_after, message)
+    }
+}
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + Send> AsyncCharReader for AsyncStreamReader<R> {
+    fn path(&self) -> Option<&Path> {
+        self.path.as_ref().map(|p| p.as_path())
+    }
+
+    fn position(&self) -> Position {
+        self.pos
+    }
+
+    async fn next_char(&mut self) -> IoResult<Option<char>> {
+        if self.clen > 0 {
+            self.pos = self.step(self.pos, self.c, self.clen).await?;
+            self.clen = 0;
+        }
+        match self.decode_at(self.pos.offset).await? {
+            Some((c, len)) => {
+                self.c = c;
+                self.clen = len;
+                Ok(Some(c))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        Ok(self.peek_char_pos(lookahead).await?.map(|(c, _)| c))
+    }
+
+    async fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        if self.clen == 0 && self.next_char().await?.is_none() {
+            return Ok(None);
+        }
+        let (mut c, mut len, mut pos) = (self.c, self.clen, self.pos);
+        for _ in 0..lookahead {
+            pos = self.step(pos, c, len).await?;
+            match self.decode_at(pos.offset).await? {
+                Some((nc, nlen)) => {
+                    c = nc;
+                    len = nlen;
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some((c, pos)))
+    }
+
+    async fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        let offset = self.pos.offset;
+        if self.fill(offset + s.len()).await? {
+            Ok(&self.data[offset..offset + s.len()] == s.as_bytes())
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        self.quote_data(from, to, lines_before, lines_after, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn async_char_reader_mirrors_sync() {
+        let input = "ab\nć老\nx";
+        let mut a = AsyncStreamReader::new(input.as_bytes());
+        a.chunk_size = 2;
+        let mut m = MemCharReader::new(input.as_bytes());
+
+        assert_eq!(a.peek_char(3).await.unwrap(), Some('ć'));
+        assert!(a.match_str("ab\n").await.unwrap());
+        m.peek_char(0).unwrap();
+        loop {
+            let c = a.next_char().await.unwrap();
+            assert_eq!(c, m.next_char().unwrap());
+            assert_eq!(AsyncCharReader::position(&a), m.position());
+            if c.is_none() {
+                break;
+            }
+        }
+
+        let (from, to) = (Position::with(3, 1, 0), Position::with(5, 1, 1));
+        let q = AsyncCharReader::quote(&mut a, from, to, 0, 0, "".into());
+        assert_eq!(q.source(), "ć老");
+    }
+
+    #[tokio::test]
+    async fn async_byte_reader() {
+        let mut a = AsyncStreamReader::new(&b"a\nb"[..]);
+        assert_eq!(a.peek_byte(2).await.unwrap(), Some(b'b'));
+        a.skip_bytes(2).await.unwrap();
+        assert_eq!(AsyncByteReader::position(&a), Position::with(2, 1, 0));
+        assert_eq!(a.next_byte().await.unwrap(), Some(b'b'));
+        assert_eq!(a.next_byte().await.unwrap(), None);
+    }
+}
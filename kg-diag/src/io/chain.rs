@@ -0,0 +1,291 @@
+use super::*;
+
+/// `CharReader` over several inputs (segments) read one after another, e.g. files included
+/// by preprocessing directives.
+///
+/// Positions are relative to the segment currently being read, and `path()`, `slice()` and
+/// `quote()` refer to it as well, so that diagnostics point to the right file. Seeking only
+/// moves within the current segment, while rollback returns to the segment the mark was
+/// taken in.
+#[derive(Debug, Clone, Default)]
+pub struct ChainReader<'a> {
+    segments: Vec<MemCharReader<'a>>,
+    index: usize,
+}
+
+impl<'a> ChainReader<'a> {
+    pub fn new() -> ChainReader<'a> {
+        ChainReader {
+            segments: Vec::new(),
+            index: 0,
+        }
+    }
+
+    pub fn with_segment<P: AsRef<Path> + ?Sized + 'a>(
+        mut self,
+        path: &'a P,
+        input: &'a [u8],
+    ) -> ChainReader<'a> {
+        self.push_reader(MemCharReader::with_path(path, input));
+        self
+    }
+
+    /// Appends segment read by `reader`, which is read from the start.
+    pub fn push_reader(&mut self, mut reader: MemCharReader<'a>) {
+        reader.seek(Position::new()).unwrap();
+        self.segments.push(reader);
+    }
+
+    /// Index of the segment currently being read.
+    pub fn segment_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Moves past exhausted segments, returning `true` if the current segment changed.
+    fn next_segment(&mut self) -> bool {
+        let index = self.index;
+        while self.index + 1 < self.segments.len() && self.segments[self.index].eof() {
+            self.index += 1;
+            self.segments[self.index].seek(Position::new()).unwrap();
+        }
+        self.index != index
+    }
+
+    fn current(&mut self) -> Option<&mut MemCharReader<'a>> {
+        self.next_segment();
+        self.segments.get_mut(self.index)
+    }
+}
+
+impl<'a> Reader for ChainReader<'a> {
+    fn path(&self) -> Option<&Path> {
+        self.segments.get(self.index).and_then(|s| s.path())
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.segments.get(self.index).map_or(Some(0), |s| s.len())
+    }
+
+    fn eof(&self) -> bool {
+        self.segments.iter().skip(self.index).all(|s| s.eof())
+    }
+
+    fn position(&self) -> Position {
+        self.segments.get(self.index).map_or(Position::new(), |s| s.position())
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        match self.segments.get_mut(self.index) {
+            Some(s) => s.seek(pos),
+            None => Ok(()),
+        }
+    }
+
+    fn reset(&mut self) -> IoResult<()> {
+        self.index = 0;
+        match self.segments.first_mut() {
+            Some(s) => s.seek(Position::new()),
+            None => Ok(()),
+        }
+    }
+
+    fn mark(&mut self) -> Mark {
+        match self.segments.get_mut(self.index) {
+            Some(s) => s.mark().with_segment(self.index),
+            None => Mark::new(Position::new()),
+        }
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        match self.segments.get_mut(mark.segment()) {
+            Some(s) => {
+                // following segments are read from the start again once reached
+                s.rollback(mark)?;
+                self.index = mark.segment();
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        match self.segments.get_mut(self.index) {
+            Some(s) => s.input(),
+            None => Ok(Cow::Borrowed("")),
+        }
+    }
+
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        match self.segments.get_mut(self.index) {
+            Some(s) => s.slice(start, end),
+            None => Ok(Cow::Borrowed("")),
+        }
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        match self.segments.get_mut(self.index) {
+            Some(s) => s.quote(from, to, lines_before, lines_after, message),
+            None => MemCharReader::new(b"").quote(from, to, lines_before, lines_after, message),
+        }
+    }
+}
+
+impl<'a> CharReader for ChainReader<'a> {
+    fn next_char(&mut self) -> IoResult<Option<char>> {
+        let c = match self.current() {
+            Some(s) => s.next_char()?,
+            None => return Ok(None),
+        };
+        if c.is_none() && self.next_segment() {
+            self.segments[self.index].next_char()
+        } else {
+            Ok(c)
+        }
+    }
+
+    fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        Ok(self.peek_char_pos(lookahead)?.map(|(c, _)| c))
+    }
+
+    fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        let s = match self.current() {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let cp = s.peek_char_pos(0)?;
+        if lookahead == 0 || cp.is_none() {
+            return Ok(cp);
+        }
+        if let Some(cp) = s.peek_char_pos(lookahead)? {
+            return Ok(Some(cp));
+        }
+        // lookahead reaches following segments
+        let mut r = self.clone();
+        for _ in 0..lookahead {
+            if r.next_char()?.is_none() {
+                return Ok(None);
+            }
+        }
+        r.peek_char_pos(0)
+    }
+
+    fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_char()?;
+        }
+        Ok(())
+    }
+
+    fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        let r = match self.current() {
+            Some(r) => r,
+            None => return Ok(s.is_empty()),
+        };
+        if r.match_str(s)? {
+            return Ok(true);
+        }
+        let left = r.len().unwrap_or(0).saturating_sub(r.position().offset);
+        if left >= s.len() || self.index + 1 == self.segments.len() {
+            return Ok(false);
+        }
+        // string may continue in following segments
+        for (i, c) in s.chars().enumerate() {
+            if self.peek_char(i)? != Some(c) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn match_str_term(&mut self, s: &str, f: &mut dyn FnMut(Option<char>) -> bool) -> IoResult<bool> {
+        if self.match_str(s)? {
+            let n = s.chars().count();
+            Ok(f(self.peek_char(n)?))
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn skip_ascii_while(&mut self, f: &mut dyn FnMut(u8) -> bool) -> IoResult<usize> {
+        let mut n = 0;
+        while let Some(s) = self.current() {
+            n += s.skip_ascii_while(&mut *f)?;
+            if !s.eof() || !self.next_segment() {
+                break;
+            }
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader<'a>() -> ChainReader<'a> {
+        ChainReader::new()
+            .with_segment("a.txt", b"ab\nc")
+            .with_segment("empty.txt", b"")
+            .with_segment("b.txt", b"de\n")
+    }
+
+    #[test]
+    fn reads_across_segments() {
+        let mut r = reader();
+        let mut chars = Vec::new();
+        while let Some((c, p)) = r.peek_char_pos(0).unwrap() {
+            chars.push((c, r.path().unwrap().to_str().unwrap().to_string(), p));
+            r.next_char().unwrap();
+        }
+        assert!(r.eof());
+        assert_eq!(chars.len(), 7);
+        assert_eq!(chars[3], ('c', "a.txt".to_string(), Position::with(3, 1, 0)));
+        assert_eq!(chars[4], ('d', "b.txt".to_string(), Position::with(0, 0, 0)));
+        assert_eq!(r.segment_index(), 2);
+    }
+
+    #[test]
+    fn lookahead_across_segments() {
+        let mut r = reader();
+        r.skip_chars(4).unwrap();
+        assert_eq!(r.peek_char(0).unwrap(), Some('c'));
+        assert_eq!(r.peek_char(2).unwrap(), Some('e'));
+        assert_eq!(r.peek_char(4).unwrap(), None);
+        assert!(r.match_str("cde").unwrap());
+        assert!(!r.match_str("cdx").unwrap());
+        assert_eq!(r.segment_index(), 0);
+
+        assert_eq!(r.skip_ascii_while(&mut |b| b != b'\n').unwrap(), 3);
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('\n', Position::with(2, 0, 2))));
+        let q = r.quote_default(Position::with(0, 0, 0), Position::with(2, 0, 2), "here".into());
+        assert_eq!(q.path(), Some(Path::new("b.txt")));
+    }
+
+    #[test]
+    fn rollback_across_segments() {
+        let mut r = reader();
+        r.skip_chars(4).unwrap();
+        let mark = r.mark();
+        r.skip_chars(2).unwrap();
+        assert_eq!(r.peek_char(0).unwrap(), Some('e'));
+        assert_eq!(r.segment_index(), 2);
+
+        r.rollback(mark).unwrap();
+        assert_eq!(r.segment_index(), 0);
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('c', Position::with(3, 1, 0))));
+        r.skip_chars(2).unwrap();
+        assert_eq!(r.path(), Some(Path::new("b.txt")));
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('e', Position::with(1, 0, 1))));
+    }
+}
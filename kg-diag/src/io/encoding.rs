@@ -0,0 +1,322 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use encoding_rs::Encoding;
+
+use super::*;
+
+/// Run of consecutive characters having the same width in decoded and original input.
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    utf8: usize,
+    orig: usize,
+    utf8_width: usize,
+    orig_width: usize,
+    count: usize,
+}
+
+/// Input decoded to UTF-8 from detected encoding, remembering original byte offsets so that
+/// positions reported by readers refer to the original input.
+#[derive(Debug, Clone)]
+pub struct DecodedInput {
+    path: Option<PathBuf>,
+    encoding: &'static Encoding,
+    bom_len: usize,
+    orig_len: usize,
+    text: String,
+    runs: Vec<Run>,
+}
+
+impl DecodedInput {
+    /// Decodes `input`, detecting encoding by BOM. Input without BOM is assumed UTF-8 if
+    /// valid, and windows-1252 otherwise.
+    pub fn decode(input: &[u8]) -> DecodedInput {
+        Self::decode_with_fallback(input, encoding_rs::WINDOWS_1252)
+    }
+
+    /// Like `decode()`, with `fallback` used for input without BOM which is not valid UTF-8.
+    pub fn decode_with_fallback(input: &[u8], fallback: &'static Encoding) -> DecodedInput {
+        match Encoding::for_bom(input) {
+            Some((encoding, bom_len)) => Self::decode_as(&input[bom_len..], encoding, bom_len),
+            None if std::str::from_utf8(input).is_ok() => {
+                Self::decode_as(input, encoding_rs::UTF_8, 0)
+            }
+            None => Self::decode_as(input, fallback, 0),
+        }
+    }
+
+    /// Reads and decodes file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> IoResult<DecodedInput> {
+        let data = FileBuffer::open(path.as_ref())?.into_data();
+        Ok(Self::decode(&data).with_path(path.as_ref()))
+    }
+
+    fn decode_as(input: &[u8], encoding: &'static Encoding, offset: usize) -> DecodedInput {
+        let mut d = DecodedInput {
+            path: None,
+            encoding,
+            bom_len: offset,
+            orig_len: offset + input.len(),
+            text: String::new(),
+            runs: Vec::new(),
+        };
+        if encoding == encoding_rs::UTF_8 {
+            if let Ok(text) = std::str::from_utf8(input) {
+                d.text = text.to_string();
+                d.runs.push(Run {
+                    utf8: 0,
+                    orig: offset,
+                    utf8_width: 1,
+                    orig_width: 1,
+                    count: input.len(),
+                });
+                return d;
+            }
+        }
+
+        // input is fed byte by byte, so that every decoded character can be attributed
+        // to the original bytes it was decoded from
+        let mut decoder = encoding.new_decoder_without_bom_handling();
+        let mut pending = offset;
+        for i in 0..=input.len() {
+            let (src, last) = if i < input.len() {
+                (&input[i..i + 1], false)
+            } else {
+                (&input[i..], true)
+            };
+            let len = d.text.len();
+            d.text.reserve(decoder.max_utf8_buffer_length(src.len()).unwrap_or(16));
+            let _ = decoder.decode_to_string(src, &mut d.text, last);
+            let end = offset + i + src.len();
+            let decoded = d.text[len..].chars().map(|c| c.len_utf8()).collect::<Vec<_>>();
+            for (k, w) in decoded.into_iter().enumerate() {
+                let orig_width = if k == 0 { end - pending } else { 0 };
+                d.push_char(w, orig_width);
+            }
+            if d.text.len() > len {
+                pending = end;
+            }
+        }
+        d
+    }
+
+    fn push_char(&mut self, utf8_width: usize, orig_width: usize) {
+        if let Some(r) = self.runs.last_mut() {
+            if r.utf8_width == utf8_width && r.orig_width == orig_width {
+                r.count += 1;
+                return;
+            }
+        }
+        let (utf8, orig) = match self.runs.last() {
+            Some(r) => (r.utf8 + r.utf8_width * r.count, r.orig + r.orig_width * r.count),
+            None => (0, self.bom_len),
+        };
+        self.runs.push(Run {
+            utf8,
+            orig,
+            utf8_width,
+            orig_width,
+            count: 1,
+        });
+    }
+
+    pub fn with_path<P: Into<PathBuf>>(mut self, path: P) -> DecodedInput {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Maps offset in decoded text to offset in original input.
+    pub fn original_offset(&self, offset: usize) -> usize {
+        let i = match self.runs.binary_search_by(|r| r.utf8.cmp(&offset)) {
+            Ok(i) => i,
+            Err(0) => return self.bom_len,
+            Err(i) => i - 1,
+        };
+        let r = &self.runs[i];
+        let k = ((offset - r.utf8) / r.utf8_width).min(r.count);
+        r.orig + k * r.orig_width
+    }
+
+    /// Maps offset in original input to offset in decoded text, rounding down to the start of
+    /// the character decoded from byte at `offset`.
+    pub fn decoded_offset(&self, offset: usize) -> usize {
+        let i = match self.runs.binary_search_by(|r| r.orig.cmp(&offset)) {
+            Ok(i) => i,
+            Err(0) => return 0,
+            Err(i) => i - 1,
+        };
+        let r = &self.runs[i];
+        if r.orig_width == 0 {
+            return r.utf8;
+        }
+        let k = ((offset - r.orig) / r.orig_width).min(r.count);
+        r.utf8 + k * r.utf8_width
+    }
+
+    pub fn char_reader(&self) -> DecodedCharReader {
+        let inner = match self.path {
+            Some(ref path) => MemCharReader::with_path(path, self.text.as_bytes()),
+            None => MemCharReader::new(self.text.as_bytes()),
+        };
+        DecodedCharReader { input: self, inner }
+    }
+
+    fn to_original(&self, mut pos: Position) -> Position {
+        pos.offset = self.original_offset(pos.offset);
+        pos
+    }
+
+    fn to_decoded(&self, mut pos: Position) -> Position {
+        pos.offset = self.decoded_offset(pos.offset);
+        pos
+    }
+}
+
+/// `CharReader` over `DecodedInput`. Reported and accepted positions have offsets in
+/// the original (encoded) input, while lines and columns count decoded characters.
+#[derive(Debug, Clone)]
+pub struct DecodedCharReader<'a> {
+    input: &'a DecodedInput,
+    inner: MemCharReader<'a>,
+}
+
+impl<'a> Reader for DecodedCharReader<'a> {
+    fn path(&self) -> Option<&Path> {
+        self.inner.path()
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.input.orig_len)
+    }
+
+    fn eof(&self) -> bool {
+        self.inner.eof()
+    }
+
+    fn position(&self) -> Position {
+        self.input.to_original(self.inner.position())
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        self.inner.seek(self.input.to_decoded(pos))
+    }
+
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        Ok(Cow::Borrowed(&self.input.text))
+    }
+
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        if end < start {
+            return Err(IoErrorDetail::InvalidOffset {
+                offset: end,
+                len: self.input.orig_len,
+            });
+        }
+        let start = self.input.decoded_offset(start);
+        let end = self.input.decoded_offset(end);
+        Ok(Cow::Borrowed(&self.input.text[start..end]))
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        let (from, to) = (self.input.to_decoded(from), self.input.to_decoded(to));
+        self.inner.quote(from, to, lines_before, lines_after, message)
+    }
+}
+
+impl<'a> CharReader for DecodedCharReader<'a> {
+    fn next_char(&mut self) -> IoResult<Option<char>> {
+        self.inner.next_char()
+    }
+
+    fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        self.inner.peek_char(lookahead)
+    }
+
+    fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        let input = self.input;
+        Ok(self
+            .inner
+            .peek_char_pos(lookahead)?
+            .map(|(c, p)| (c, input.to_original(p))))
+    }
+
+    fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        self.inner.skip_chars(skip)
+    }
+
+    fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        self.inner.match_str(s)
+    }
+
+    fn match_str_term(&mut self, s: &str, f: &mut dyn FnMut(Option<char>) -> bool) -> IoResult<bool> {
+        self.inner.match_str_term(s, f)
+    }
+
+    fn skip_ascii_while(&mut self, f: &mut dyn FnMut(u8) -> bool) -> IoResult<usize> {
+        self.inner.skip_ascii_while(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf16le_with_bom() {
+        let mut bytes = vec![0xFF, 0xFE];
+        for u in "a\nąb".encode_utf16() {
+            bytes.extend_from_slice(&u.to_le_bytes());
+        }
+        let input = DecodedInput::decode(&bytes);
+        assert_eq!(input.encoding(), encoding_rs::UTF_16LE);
+        assert_eq!(input.text(), "a\nąb");
+
+        let mut r = input.char_reader();
+        r.skip_chars(3).unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('ą', Position::with(6, 1, 0))));
+        r.next_char().unwrap();
+        assert_eq!(r.next_char().unwrap(), Some('b'));
+        assert_eq!(r.position(), Position::with(8, 1, 1));
+
+        r.seek(Position::with(6, 1, 0)).unwrap();
+        assert_eq!(r.next_char().unwrap(), Some('ą'));
+        assert_eq!(r.slice(4, 8).unwrap(), "\ną");
+        assert_eq!(r.slice(8, 4).unwrap_err(), IoErrorDetail::InvalidOffset { offset: 4, len: 10 });
+    }
+
+    #[test]
+    fn legacy_fallback() {
+        let input = DecodedInput::decode(b"caf\xe9 x");
+        assert_eq!(input.encoding(), encoding_rs::WINDOWS_1252);
+        assert_eq!(input.text(), "café x");
+        assert_eq!(input.original_offset(6), 5);
+        assert_eq!(input.decoded_offset(5), 6);
+
+        let mut r = input.char_reader();
+        r.skip_chars(6).unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('x', Position::with(5, 0, 5))));
+    }
+
+    #[test]
+    fn utf8_is_identity() {
+        let input = DecodedInput::decode("zażółć".as_bytes());
+        assert_eq!(input.encoding(), encoding_rs::UTF_8);
+        assert_eq!(input.original_offset(3), 3);
+        assert_eq!(input.decoded_offset(5), 5);
+    }
+}
@@ -29,9 +29,101 @@ pub enum IoErrorDetail {
     Utf8UnexpectedEof {
         offset: usize,
     },
+    MissingFinalNewline {
+        pos: Position,
+    },
+    /// Offset is past the end of input (of `len` bytes) or not at a character boundary.
+    InvalidOffset {
+        offset: usize,
+        len: usize,
+    },
+    /// Seek to `offset` before `window_start`, the first byte of input retained by a reader
+    /// keeping only a window of input (see `SeekCharReader`).
+    BacktrackLimit {
+        offset: usize,
+        window_start: usize,
+    },
+    /// Input read at `pos` exceeds limit `max` of a `Limited` reader.
+    LimitExceeded {
+        limit: LimitKind,
+        max: usize,
+        pos: Position,
+    },
+    /// Rollback to mark taken at `pos` in an included source that was already exhausted and
+    /// dropped (see `IncludeReader`).
+    MarkExpired {
+        pos: Position,
+    },
     Fmt,
 }
 
+/// Codes of details caused by `std::io::Error`, by error kind. Codes are assigned explicitly,
+/// since discriminants of `std::io::ErrorKind` are not stable. Kinds not listed here get
+/// the code of `ErrorKind::Other`.
+const IO_CODES: [(std::io::ErrorKind, u32); 18] = {
+    use std::io::ErrorKind::*;
+    [
+        (NotFound, 1),
+        (PermissionDenied, 2),
+        (ConnectionRefused, 3),
+        (ConnectionReset, 4),
+        (ConnectionAborted, 5),
+        (NotConnected, 6),
+        (AddrInUse, 7),
+        (AddrNotAvailable, 8),
+        (BrokenPipe, 9),
+        (AlreadyExists, 10),
+        (WouldBlock, 11),
+        (InvalidInput, 12),
+        (InvalidData, 13),
+        (TimedOut, 14),
+        (WriteZero, 15),
+        (Interrupted, 16),
+        (Other, IoErrorDetail::CODE_IO_OTHER),
+        (UnexpectedEof, 18),
+    ]
+};
+
+fn io_code(kind: std::io::ErrorKind) -> u32 {
+    IO_CODES.iter().find(|e| e.0 == kind).map_or(IoErrorDetail::CODE_IO_OTHER, |e| e.1)
+}
+
+/// Codes of I/O and parse errors defined in this crate. Values of these constants are
+/// guaranteed not to change, and codes of all built-in details lie within `CODE_RANGE`.
+impl IoErrorDetail {
+    pub const CODE_RANGE: std::ops::RangeInclusive<u32> = 1..=99;
+    /// Details caused by `std::io::Error` have codes starting at `CODE_IO_BASE`, one for each
+    /// error kind, up to `CODE_IO_BASE + 17`.
+    pub const CODE_IO_BASE: u32 = 1;
+    /// Code of details caused by `std::io::Error` of kind `Other`, or of a kind without its own code.
+    pub const CODE_IO_OTHER: u32 = 17;
+    pub const CODE_UTF8_INVALID_ENCODING: u32 = 21;
+    pub const CODE_UTF8_UNEXPECTED_EOF: u32 = 22;
+    pub const CODE_MISSING_FINAL_NEWLINE: u32 = 23;
+    pub const CODE_INVALID_OFFSET: u32 = 24;
+    pub const CODE_BACKTRACK_LIMIT: u32 = 25;
+    pub const CODE_LIMIT_EXCEEDED: u32 = 26;
+    pub const CODE_MARK_EXPIRED: u32 = 27;
+    pub const CODE_FMT: u32 = 99;
+
+    /// All codes used by `IoErrorDetail`, with short descriptions.
+    pub fn codes() -> Vec<(u32, &'static str)> {
+        let mut codes: Vec<_> = IO_CODES
+            .iter()
+            .map(|&(kind, code)| (code, kind_str(kind)))
+            .collect();
+        codes.push((Self::CODE_UTF8_INVALID_ENCODING, "invalid utf-8 encoding"));
+        codes.push((Self::CODE_UTF8_UNEXPECTED_EOF, "unexpected <EOF> in utf-8 encoding"));
+        codes.push((Self::CODE_MISSING_FINAL_NEWLINE, "no newline at end of file"));
+        codes.push((Self::CODE_INVALID_OFFSET, "invalid input offset"));
+        codes.push((Self::CODE_BACKTRACK_LIMIT, "seek beyond retained input"));
+        codes.push((Self::CODE_LIMIT_EXCEEDED, "input limit exceeded"));
+        codes.push((Self::CODE_MARK_EXPIRED, "mark in dropped source"));
+        codes.push((Self::CODE_FMT, "formatting error"));
+        codes
+    }
+}
+
 impl IoErrorDetail {
     pub fn kind(&self) -> std::io::ErrorKind {
         match *self {
@@ -41,9 +133,23 @@ impl IoErrorDetail {
             IoErrorDetail::CurrentDirSet { kind, .. } => kind,
             IoErrorDetail::Utf8InvalidEncoding { .. } => std::io::ErrorKind::InvalidData,
             IoErrorDetail::Utf8UnexpectedEof { .. } => std::io::ErrorKind::UnexpectedEof,
+            IoErrorDetail::MissingFinalNewline { .. } => std::io::ErrorKind::InvalidData,
+            IoErrorDetail::InvalidOffset { .. } => std::io::ErrorKind::InvalidInput,
+            IoErrorDetail::BacktrackLimit { .. } => std::io::ErrorKind::InvalidInput,
+            IoErrorDetail::LimitExceeded { .. } => std::io::ErrorKind::InvalidData,
+            IoErrorDetail::MarkExpired { .. } => std::io::ErrorKind::InvalidInput,
             IoErrorDetail::Fmt => std::io::ErrorKind::Other,
         }
     }
+    /// Checks if the error is caused by input ending in the middle of a character, i.e. it may
+    /// not occur once more input is available (see `MemCharReader::resume()`).
+    pub fn needs_more_input(&self) -> bool {
+        match *self {
+            IoErrorDetail::Utf8UnexpectedEof { .. } => true,
+            _ => false,
+        }
+    }
+
     pub fn file_not_found(path: PathBuf, file_type: FileType, op_type: OpType) -> IoErrorDetail {
         IoErrorDetail::IoPath {
             kind: std::io::ErrorKind::NotFound,
@@ -55,45 +161,67 @@ impl IoErrorDetail {
 }
 
 impl Detail for IoErrorDetail {
+    fn severity(&self) -> Severity {
+        match *self {
+            IoErrorDetail::MissingFinalNewline { .. } => Severity::Warning,
+            _ => Severity::Failure,
+        }
+    }
+
     fn code(&self) -> u32 {
         match *self {
-            IoErrorDetail::Io { kind, message: _ } => 1 + kind as u32,
-            IoErrorDetail::IoPath { kind, .. } => 1 + kind as u32,
-            IoErrorDetail::CurrentDirGet { kind } => 1 + kind as u32,
-            IoErrorDetail::CurrentDirSet { kind, .. } => 1 + kind as u32,
-            IoErrorDetail::Utf8InvalidEncoding { .. } => 21,
-            IoErrorDetail::Utf8UnexpectedEof { .. } => 22,
-            IoErrorDetail::Fmt => 99,
+            IoErrorDetail::Io { kind, message: _ } => io_code(kind),
+            IoErrorDetail::IoPath { kind, .. } => io_code(kind),
+            IoErrorDetail::CurrentDirGet { kind } => io_code(kind),
+            IoErrorDetail::CurrentDirSet { kind, .. } => io_code(kind),
+            IoErrorDetail::Utf8InvalidEncoding { .. } => Self::CODE_UTF8_INVALID_ENCODING,
+            IoErrorDetail::Utf8UnexpectedEof { .. } => Self::CODE_UTF8_UNEXPECTED_EOF,
+            IoErrorDetail::MissingFinalNewline { .. } => Self::CODE_MISSING_FINAL_NEWLINE,
+            IoErrorDetail::InvalidOffset { .. } => Self::CODE_INVALID_OFFSET,
+            IoErrorDetail::BacktrackLimit { .. } => Self::CODE_BACKTRACK_LIMIT,
+            IoErrorDetail::LimitExceeded { .. } => Self::CODE_LIMIT_EXCEEDED,
+            IoErrorDetail::MarkExpired { .. } => Self::CODE_MARK_EXPIRED,
+            IoErrorDetail::Fmt => Self::CODE_FMT,
+        }
+    }
+
+    fn span(&self) -> Option<Span> {
+        match *self {
+            IoErrorDetail::MissingFinalNewline { pos } => Some(Span::with_pos(pos, pos)),
+            IoErrorDetail::LimitExceeded { pos, .. } => Some(Span::with_pos(pos, pos)),
+            _ => None,
         }
     }
 }
 
+fn kind_str(kind: std::io::ErrorKind) -> &'static str {
+    use std::io::ErrorKind;
+    match kind {
+        ErrorKind::NotFound => "not found",
+        ErrorKind::PermissionDenied => "permission denied",
+        ErrorKind::ConnectionRefused => "connection refused",
+        ErrorKind::ConnectionReset => "connection reset",
+        ErrorKind::ConnectionAborted => "connection aborted",
+        ErrorKind::NotConnected => "not connected",
+        ErrorKind::AddrInUse => "address in use",
+        ErrorKind::AddrNotAvailable => "address not available",
+        ErrorKind::BrokenPipe => "broken pipe",
+        ErrorKind::AlreadyExists => "already exists",
+        ErrorKind::WouldBlock => "operation would block",
+        ErrorKind::InvalidInput => "invalid input parameter",
+        ErrorKind::InvalidData => "invalid data",
+        ErrorKind::TimedOut => "timed out",
+        ErrorKind::WriteZero => "write zero",
+        ErrorKind::Interrupted => "operation interrupted",
+        ErrorKind::Other => "other os error",
+        ErrorKind::UnexpectedEof => "unexpected end of file",
+        // kinds added to std later
+        _ => "other I/O error",
+    }
+}
+
 impl std::fmt::Display for IoErrorDetail {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        fn kind_str(kind: std::io::ErrorKind) -> &'static str {
-            use std::io::ErrorKind;
-            match kind {
-                ErrorKind::NotFound => "not found",
-                ErrorKind::PermissionDenied => "permission denied",
-                ErrorKind::ConnectionRefused => "connection refused",
-                ErrorKind::ConnectionReset => "connection reset",
-                ErrorKind::ConnectionAborted => "connection aborted",
-                ErrorKind::NotConnected => "not connected",
-                ErrorKind::AddrInUse => "address in use",
-                ErrorKind::AddrNotAvailable => "address not available",
-                ErrorKind::BrokenPipe => "broken pipe",
-                ErrorKind::AlreadyExists => "already exists",
-                ErrorKind::WouldBlock => "operation would block",
-                ErrorKind::InvalidInput => "invalid input parameter",
-                ErrorKind::InvalidData => "invalid data",
-                ErrorKind::TimedOut => "timed out",
-                ErrorKind::WriteZero => "write zero",
-                ErrorKind::Interrupted => "operation interrupted",
-                ErrorKind::Other => "other os error",
-                ErrorKind::UnexpectedEof => "unexpected end of file",
-                _ => unreachable!(),
-            }
-        }
         match *self {
             IoErrorDetail::Io { kind, ref message } => {
                 write!(f, "{}", kind_str(kind))?;
@@ -133,6 +261,32 @@ impl std::fmt::Display for IoErrorDetail {
             IoErrorDetail::Utf8UnexpectedEof { offset } => {
                 write!(f, "unexpected <EOF> in utf-8 encoding at offset {}", offset)?;
             }
+            IoErrorDetail::MissingFinalNewline { .. } => {
+                write!(f, "no newline at end of file")?;
+            }
+            IoErrorDetail::InvalidOffset { offset, len } => {
+                if offset > len {
+                    write!(f, "offset {} is past the end of input ({} bytes)", offset, len)?;
+                } else {
+                    write!(f, "offset {} is not at a character boundary", offset)?;
+                }
+            }
+            IoErrorDetail::BacktrackLimit {
+                offset,
+                window_start,
+            } => {
+                write!(
+                    f,
+                    "cannot seek back to offset {}, input before offset {} is not retained",
+                    offset, window_start
+                )?;
+            }
+            IoErrorDetail::LimitExceeded { limit, max, .. } => {
+                write!(f, "{} limit of {} exceeded", limit, max)?;
+            }
+            IoErrorDetail::MarkExpired { pos } => {
+                write!(f, "cannot roll back to {}, source of the mark was already dropped", pos)?;
+            }
             IoErrorDetail::Fmt => {
                 write!(f, "formatting error")?;
             }
@@ -56,9 +56,26 @@ impl From<std::fs::FileType> for FileType {
     }
 }
 
+#[derive(Debug)]
+enum FileData {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl FileData {
+    fn as_slice(&self) -> &[u8] {
+        match *self {
+            FileData::Owned(ref data) => data,
+            #[cfg(feature = "mmap")]
+            FileData::Mapped(ref map) => map,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct FileBuffer {
-    data: Vec<u8>,
+    data: FileData,
     path: PathBuf,
 }
 
@@ -71,12 +88,43 @@ impl FileBuffer {
         let mut data: Vec<u8> = Vec::with_capacity(m.len() as usize);
         f.read_to_end(&mut data)
             .info(path.as_ref(), OpType::Read, FileType::File)?;
+        Ok(FileBuffer {
+            data: FileData::Owned(data),
+            path: path.into(),
+        })
+    }
+
+    /// Maps file into memory instead of reading it. The file must not be modified while
+    /// mapped, otherwise contents seen through readers are undefined.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: Into<PathBuf> + AsRef<Path>>(path: P) -> IoResult<FileBuffer> {
+        let f = File::open(path.as_ref()).info(path.as_ref(), OpType::Read, FileType::File)?;
+        let m = f
+            .metadata()
+            .info(path.as_ref(), OpType::Read, FileType::File)?;
+        // empty files cannot be mapped on all platforms
+        let data = if m.len() == 0 {
+            FileData::Owned(Vec::new())
+        } else {
+            let map = unsafe { memmap2::Mmap::map(&f) }
+                .info(path.as_ref(), OpType::Read, FileType::File)?;
+            FileData::Mapped(map)
+        };
         Ok(FileBuffer {
             data,
             path: path.into(),
         })
     }
 
+    /// Checks if contents are memory-mapped, see `open_mmap()`.
+    pub fn is_mapped(&self) -> bool {
+        match self.data {
+            FileData::Owned(_) => false,
+            #[cfg(feature = "mmap")]
+            FileData::Mapped(_) => true,
+        }
+    }
+
     pub fn create<P: Into<PathBuf> + AsRef<Path>>(path: P) -> IoResult<FileBuffer> {
         OpenOptions::new()
             .create(true)
@@ -85,32 +133,38 @@ impl FileBuffer {
             .open(path.as_ref())
             .info(path.as_ref(), OpType::Create, FileType::File)?;
         Ok(FileBuffer {
-            data: Vec::new(),
+            data: FileData::Owned(Vec::new()),
             path: path.into(),
         })
     }
 
     pub fn char_reader(&self) -> MemCharReader {
-        MemCharReader::with_path(&self.path, &self.data)
+        MemCharReader::with_path(&self.path, self.data.as_slice())
     }
 
     pub fn byte_reader(&self) -> MemByteReader {
-        MemByteReader::with_path(&self.path, &self.data)
+        MemByteReader::with_path(&self.path, self.data.as_slice())
+    }
+
+    /// See `MemCharReader::check_final_newline()`.
+    pub fn check_final_newline(&self) -> IoResult<Option<ParseDiag>> {
+        self.char_reader().check_final_newline()
     }
 
     pub fn as_slice(&self) -> &[u8] {
-        &self.data
+        self.data.as_slice()
     }
 
     pub fn write(&mut self, data: &[u8]) -> IoResult<()> {
-        self.data = data.to_owned();
+        // drops the mapping (if any) before the file is truncated
+        self.data = FileData::Owned(data.to_owned());
         let mut f = OpenOptions::new()
             .create(true)
             .truncate(true)
             .write(true)
             .open(&self.path)
             .info(&self.path, OpType::Write, FileType::File)?;
-        f.write(&self.data)
+        f.write(self.data.as_slice())
             .info(&self.path, OpType::Write, FileType::File)?;
         f.sync_data()
             .info(&self.path, OpType::Write, FileType::File)?;
@@ -118,7 +172,11 @@ impl FileBuffer {
     }
 
     pub fn into_data(self) -> Vec<u8> {
-        self.data
+        match self.data {
+            FileData::Owned(data) => data,
+            #[cfg(feature = "mmap")]
+            FileData::Mapped(map) => map.to_vec(),
+        }
     }
 }
 
@@ -258,6 +316,28 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn open_mmap() {
+        use tempfile::NamedTempFile;
+
+        let mut tmpfile = NamedTempFile::new().unwrap();
+        tmpfile.write_all(b"line 1\nline 2\n").unwrap();
+
+        let f = FileBuffer::open_mmap(tmpfile.path()).unwrap();
+        assert!(f.is_mapped());
+        assert_eq!(f.as_slice(), b"line 1\nline 2\n");
+        let mut r = f.char_reader();
+        r.skip_chars(8).unwrap();
+        assert_eq!(r.next_char().unwrap(), Some('i'));
+        assert_eq!(r.position(), Position::with(8, 1, 1));
+
+        let empty = NamedTempFile::new().unwrap();
+        let f = FileBuffer::open_mmap(empty.path()).unwrap();
+        assert!(!f.is_mapped());
+        assert!(f.as_slice().is_empty());
+    }
+
     #[test]
     fn read_to_string() {
         use std::io::{Seek, SeekFrom};
@@ -0,0 +1,278 @@
+use super::*;
+
+/// Location of an include directive, see `IncludeReader`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IncludeSite {
+    pub path: Option<PathBuf>,
+    pub pos: Position,
+}
+
+impl std::fmt::Display for IncludeSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.path {
+            Some(ref path) => write!(f, "{}:{}", path.display(), self.pos),
+            None => write!(f, "{}", self.pos),
+        }
+    }
+}
+
+/// `CharReader` for languages with include directives. Sources pushed with `push_source()`
+/// (or `push_reader()`) are read in place of the including source, which is resumed once
+/// the included one is exhausted.
+///
+/// Quotes record the include stack, so that rendered diagnostics show where the quoted
+/// source was included from. Positions, seeking and lookahead are limited to the source
+/// currently being read. Rollback returns to the source the mark was taken in, dropping
+/// sources included after it; it fails with `IoErrorDetail::MarkExpired` if that source
+/// was already exhausted and dropped.
+pub struct IncludeReader<'a> {
+    frames: Vec<Box<dyn CharReader + 'a>>,
+    sites: Vec<IncludeSite>,
+    /// Identifiers of frames recorded in marks, assigned in order sources are pushed.
+    ids: Vec<usize>,
+    next_id: usize,
+}
+
+impl<'a> IncludeReader<'a> {
+    pub fn new<R: CharReader + 'a>(root: R) -> IncludeReader<'a> {
+        IncludeReader {
+            frames: vec![Box::new(root)],
+            sites: Vec::new(),
+            ids: vec![0],
+            next_id: 1,
+        }
+    }
+
+    /// Starts reading `reader`, included at the current position. The current character
+    /// of the including source will be read again after the included source.
+    pub fn push_reader<R: CharReader + 'a>(&mut self, reader: R) {
+        let top = self.top();
+        self.sites.push(IncludeSite {
+            path: top.path().map(|p| p.to_path_buf()),
+            pos: top.position(),
+        });
+        self.frames.push(Box::new(reader));
+        self.ids.push(self.next_id);
+        self.next_id += 1;
+    }
+
+    pub fn push_source<P: AsRef<Path> + ?Sized + 'a>(&mut self, path: &'a P, input: &'a [u8]) {
+        self.push_reader(MemCharReader::with_path(path, input))
+    }
+
+    /// Number of sources being read, including the root one.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Include directives the current source was included by, innermost first.
+    pub fn include_stack(&self) -> Vec<IncludeSite> {
+        self.sites.iter().rev().cloned().collect()
+    }
+
+    fn top(&self) -> &dyn CharReader {
+        self.frames.last().unwrap().as_ref()
+    }
+
+    fn top_mut(&mut self) -> &mut (dyn CharReader + 'a) {
+        self.frames.last_mut().unwrap().as_mut()
+    }
+
+    /// Drops exhausted included sources, returning `true` if any was dropped.
+    fn pop_exhausted(&mut self) -> IoResult<bool> {
+        let mut popped = false;
+        while self.frames.len() > 1 && self.top_mut().peek_char(0)?.is_none() {
+            self.frames.pop();
+            self.sites.pop();
+            self.ids.pop();
+            popped = true;
+        }
+        Ok(popped)
+    }
+}
+
+impl<'a> std::fmt::Debug for IncludeReader<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("IncludeReader")
+            .field("path", &self.path())
+            .field("position", &self.position())
+            .field("sites", &self.sites)
+            .finish()
+    }
+}
+
+impl<'a> Reader for IncludeReader<'a> {
+    fn path(&self) -> Option<&Path> {
+        self.top().path()
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.top().len()
+    }
+
+    fn eof(&self) -> bool {
+        self.frames.iter().all(|r| r.eof())
+    }
+
+    fn position(&self) -> Position {
+        self.top().position()
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        self.top_mut().seek(pos)
+    }
+
+    fn mark(&mut self) -> Mark {
+        let id = *self.ids.last().unwrap();
+        self.top_mut().mark().with_segment(id)
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        let depth = match self.ids.iter().position(|&id| id == mark.segment()) {
+            Some(index) => index + 1,
+            None => return Err(IoErrorDetail::MarkExpired { pos: mark.position() }),
+        };
+        self.frames.truncate(depth);
+        self.sites.truncate(depth - 1);
+        self.ids.truncate(depth);
+        self.top_mut().rollback(mark)
+    }
+
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        self.top_mut().input()
+    }
+
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        self.top_mut().slice(start, end)
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        let mut q = self.top_mut().quote(from, to, lines_before, lines_after, message);
+        q.included_from = self.include_stack();
+        q
+    }
+}
+
+impl<'a> CharReader for IncludeReader<'a> {
+    fn next_char(&mut self) -> IoResult<Option<char>> {
+        self.pop_exhausted()?;
+        let c = self.top_mut().next_char()?;
+        if c.is_none() && self.pop_exhausted()? {
+            // current character of the including source was not consumed yet
+            self.top_mut().peek_char(0)
+        } else {
+            Ok(c)
+        }
+    }
+
+    fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        self.pop_exhausted()?;
+        self.top_mut().peek_char(lookahead)
+    }
+
+    fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        self.pop_exhausted()?;
+        self.top_mut().peek_char_pos(lookahead)
+    }
+
+    fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_char()?;
+        }
+        Ok(())
+    }
+
+    fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        self.pop_exhausted()?;
+        self.top_mut().match_str(s)
+    }
+
+    fn match_str_term(&mut self, s: &str, f: &mut dyn FnMut(Option<char>) -> bool) -> IoResult<bool> {
+        self.pop_exhausted()?;
+        self.top_mut().match_str_term(s, f)
+    }
+
+    fn skip_ascii_while(&mut self, f: &mut dyn FnMut(u8) -> bool) -> IoResult<usize> {
+        let mut n = 0;
+        loop {
+            self.pop_exhausted()?;
+            n += self.top_mut().skip_ascii_while(&mut *f)?;
+            if self.depth() == 1 || !self.top().eof() {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn included_sources_read_in_place() {
+        let mut r = IncludeReader::new(MemCharReader::with_path("main.conf", b"a\n@inc\nb\n"));
+        r.skip_chars(7).unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('\n', Position::with(6, 1, 4))));
+        r.push_source("inc.conf", b"xy");
+        assert_eq!(r.depth(), 2);
+
+        let mut s = String::new();
+        while let Some(c) = r.peek_char(0).unwrap() {
+            if c == 'y' {
+                let q = r.quote(r.position(), r.position(), 0, 0, "".into());
+                assert_eq!(q.path(), Some(Path::new("inc.conf")));
+                assert_eq!(q.included_from()[0].to_string(), "main.conf:2:5");
+            }
+            s.push(c);
+            r.next_char().unwrap();
+        }
+        assert_eq!(s, "xy\nb\n");
+        assert_eq!(r.depth(), 1);
+        assert!(r.eof());
+    }
+
+    #[test]
+    fn nested_includes_at_end() {
+        let mut r = IncludeReader::new(MemCharReader::new(b"a"));
+        r.peek_char(0).unwrap();
+        r.push_source("1.conf", b"b");
+        r.peek_char(0).unwrap();
+        r.push_source("2.conf", b"");
+        let stack = r.include_stack();
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack[0].path.as_ref().unwrap(), Path::new("1.conf"));
+        assert_eq!(stack[1].path, None);
+
+        let mut s = String::new();
+        while let Some(c) = r.peek_char(0).unwrap() {
+            s.push(c);
+            r.next_char().unwrap();
+        }
+        assert_eq!(s, "ba");
+    }
+
+    #[test]
+    fn rollback_restores_include_depth() {
+        let mut r = IncludeReader::new(MemCharReader::new(b"ab"));
+        r.peek_char(0).unwrap();
+        let mark = r.mark();
+        r.push_source("1.conf", b"xy");
+        assert_eq!(r.peek_char(0).unwrap(), Some('x'));
+        let inner = r.mark();
+
+        r.rollback(mark).unwrap();
+        assert_eq!(r.depth(), 1);
+        assert_eq!(r.peek_char(0).unwrap(), Some('a'));
+        match r.rollback(inner) {
+            Err(IoErrorDetail::MarkExpired { pos }) => assert_eq!(pos, Position::new()),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use super::*;
+
+/// Limit checked by `Limited` reader, see `IoErrorDetail::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitKind {
+    /// Total number of bytes of input.
+    InputSize,
+    /// Number of columns of a line.
+    LineLength,
+    /// Number of bytes of a token scanned with `scan()`, `scan_span()` or `skip_while_span()`.
+    TokenLength,
+}
+
+impl std::fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            LimitKind::InputSize => write!(f, "input size"),
+            LimitKind::LineLength => write!(f, "line length"),
+            LimitKind::TokenLength => write!(f, "token length"),
+        }
+    }
+}
+
+/// Limits of input read through `Limited` reader. Unset limits are not checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Limits {
+    /// Maximum total number of bytes.
+    pub max_input: Option<usize>,
+    /// Maximum number of columns of a line (as counted by the underlying reader), excluding
+    /// line break.
+    pub max_line_len: Option<u32>,
+    /// Maximum number of bytes of a scanned token.
+    pub max_token_len: Option<usize>,
+}
+
+impl Limits {
+    pub const NONE: Limits = Limits {
+        max_input: None,
+        max_line_len: None,
+        max_token_len: None,
+    };
+
+    pub fn with_max_input(mut self, max: usize) -> Limits {
+        self.max_input = Some(max);
+        self
+    }
+
+    pub fn with_max_line_len(mut self, max: u32) -> Limits {
+        self.max_line_len = Some(max);
+        self
+    }
+
+    pub fn with_max_token_len(mut self, max: usize) -> Limits {
+        self.max_token_len = Some(max);
+        self
+    }
+}
+
+/// `CharReader` adapter failing with `IoErrorDetail::LimitExceeded` as soon as a character
+/// beyond configured `Limits` is read, so that services parsing untrusted input are not
+/// exposed to pathological inputs (e.g. a single multi-gigabyte line or token).
+#[derive(Debug)]
+pub struct Limited<R: CharReader> {
+    inner: R,
+    limits: Limits,
+}
+
+impl<R: CharReader> Limited<R> {
+    pub fn new(inner: R, limits: Limits) -> Limited<R> {
+        Limited { inner, limits }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn check(&self, c: char, pos: Position) -> IoResult<()> {
+        if let Some(max) = self.limits.max_input {
+            if pos.offset + c.len_utf8() > max {
+                return Err(IoErrorDetail::LimitExceeded {
+                    limit: LimitKind::InputSize,
+                    max,
+                    pos,
+                });
+            }
+        }
+        if let Some(max) = self.limits.max_line_len {
+            if pos.column >= max && !is_line_end(c) {
+                return Err(IoErrorDetail::LimitExceeded {
+                    limit: LimitKind::LineLength,
+                    max: max as usize,
+                    pos,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn checked(&self, cp: Option<(char, Position)>) -> IoResult<Option<(char, Position)>> {
+        if let Some((c, pos)) = cp {
+            self.check(c, pos)?;
+        }
+        Ok(cp)
+    }
+}
+
+/// Line breaks (and carriage return preceding them) do not count into line length.
+fn is_line_end(c: char) -> bool {
+    match c {
+        '\n' | '\r' | '\u{000C}' | '\u{2028}' | '\u{2029}' => true,
+        _ => false,
+    }
+}
+
+impl<R: CharReader> Reader for Limited<R> {
+    fn path(&self) -> Option<&Path> {
+        self.inner.path()
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.inner.len()
+    }
+
+    fn eof(&self) -> bool {
+        self.inner.eof()
+    }
+
+    fn position(&self) -> Position {
+        self.inner.position()
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        self.inner.seek(pos)
+    }
+
+    fn mark(&mut self) -> Mark {
+        self.inner.mark()
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        self.inner.rollback(mark)
+    }
+
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        self.inner.input()
+    }
+
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        self.inner.slice(start, end)
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        self.inner.quote(from, to, lines_before, lines_after, message)
+    }
+}
+
+impl<R: CharReader> CharReader for Limited<R> {
+    fn next_char(&mut self) -> IoResult<Option<char>> {
+        match self.inner.next_char()? {
+            Some(c) => {
+                self.check(c, self.inner.position())?;
+                Ok(Some(c))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        Ok(self.peek_char_pos(lookahead)?.map(|(c, _)| c))
+    }
+
+    fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        let cp = self.inner.peek_char_pos(lookahead)?;
+        self.checked(cp)
+    }
+
+    fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_char()?;
+        }
+        Ok(())
+    }
+
+    fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        if self.inner.match_str(s)? {
+            // matched characters must be within limits as well
+            self.peek_char_pos(s.chars().count().saturating_sub(1))?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn match_str_term(
+        &mut self,
+        s: &str,
+        f: &mut dyn FnMut(Option<char>) -> bool,
+    ) -> IoResult<bool> {
+        if self.match_str(s)? {
+            let n = s.chars().count();
+            Ok(f(self.peek_char(n)?))
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn scan(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<Cow<str>> {
+        let span = self.skip_while_span(f)?;
+        self.slice_pos(span.start, span.end)
+    }
+
+    fn skip_while_span(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<Span> {
+        let start = self.position();
+        while let Some((c, pos)) = self.peek_char_pos(0)? {
+            if !f(c) {
+                break;
+            }
+            if let Some(max) = self.limits.max_token_len {
+                if pos.offset + c.len_utf8() - start.offset > max {
+                    return Err(IoErrorDetail::LimitExceeded {
+                        limit: LimitKind::TokenLength,
+                        max,
+                        pos: start,
+                    });
+                }
+            }
+            self.next_char()?;
+        }
+        Ok(Span::with_pos(start, self.position()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_exceeded() {
+        let limits = Limits::NONE.with_max_line_len(4).with_max_token_len(3);
+        let mut r = Limited::new(MemCharReader::new(b"abc\nabcd\nabcde"), limits);
+        assert_eq!(r.scan(&mut |c| c.is_alphabetic()).unwrap(), "abc");
+        r.next_char().unwrap();
+        assert_eq!(
+            r.scan(&mut |c| c.is_alphabetic()).unwrap_err(),
+            IoErrorDetail::LimitExceeded {
+                limit: LimitKind::TokenLength,
+                max: 3,
+                pos: Position::with(4, 1, 0),
+            }
+        );
+        assert!(r.skip_to_line(2).unwrap());
+        r.skip_chars(3).unwrap();
+        let err = r.next_char().unwrap_err();
+        assert_eq!(
+            err,
+            IoErrorDetail::LimitExceeded {
+                limit: LimitKind::LineLength,
+                max: 4,
+                pos: Position::with(13, 2, 4),
+            }
+        );
+        assert_eq!(err.to_string(), "line length limit of 4 exceeded");
+
+        let mut r = Limited::new(MemCharReader::new(b"abc"), Limits::NONE.with_max_input(2));
+        assert!(r.skip_chars(3).is_err());
+    }
+}
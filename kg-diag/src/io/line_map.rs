@@ -0,0 +1,197 @@
+use super::*;
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// Index of line start offsets in a buffer, answering offset to line queries in O(log n).
+///
+/// Lines are broken as set by `LineBreakPolicy`, so that they match positions of readers
+/// with the same policy. Line map does not hold the buffer, so methods that need to count
+/// characters take it as a parameter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMap {
+    starts: Vec<usize>,
+    /// Lines terminated by a three byte line break (U+2028 or U+2029).
+    long_breaks: Vec<u32>,
+    line_breaks: LineBreakPolicy,
+    len: usize,
+}
+
+impl LineMap {
+    /// Line map with only `'\n'` treated as a line break (`LineBreakPolicy::LF`).
+    pub fn new(data: &[u8]) -> LineMap {
+        LineMap::with_line_break_policy(data, LineBreakPolicy::LF)
+    }
+
+    pub fn with_line_break_policy(data: &[u8], policy: LineBreakPolicy) -> LineMap {
+        let mut starts = Vec::with_capacity(data.len() / 32 + 1);
+        let mut long_breaks = Vec::new();
+        starts.push(0);
+        if policy == LineBreakPolicy::LF {
+            starts.extend(memchr::memchr_iter(b'\n', data).map(|p| p + 1));
+        } else {
+            let mut i = 0;
+            while i < data.len() {
+                let len = match data[i] {
+                    b'\n' => 1,
+                    b'\r' if policy.cr && data.get(i + 1) != Some(&b'\n') => 1,
+                    0x0C if policy.form_feed => 1,
+                    0xE2 if policy.unicode
+                        && data.get(i + 1) == Some(&0x80)
+                        && (data.get(i + 2) == Some(&0xA8) || data.get(i + 2) == Some(&0xA9)) =>
+                    {
+                        long_breaks.push((starts.len() - 1) as u32);
+                        3
+                    }
+                    _ => 0,
+                };
+                if len > 0 {
+                    i += len;
+                    starts.push(i);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        LineMap {
+            starts,
+            long_breaks,
+            line_breaks: policy,
+            len: data.len(),
+        }
+    }
+
+    pub fn line_break_policy(&self) -> LineBreakPolicy {
+        self.line_breaks
+    }
+
+    /// Length of the indexed buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of lines, counting the (possibly empty) line after the last line break.
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Zero-based line containing byte at `offset`. Line breaks belong to lines they end.
+    pub fn line_of(&self, offset: usize) -> u32 {
+        match self.starts.binary_search(&offset) {
+            Ok(i) => i as u32,
+            Err(i) => (i - 1) as u32,
+        }
+    }
+
+    /// Byte range of `line`, without the terminating line break.
+    pub fn line_span(&self, line: u32) -> Option<Range<usize>> {
+        let line = line as usize;
+        let start = *self.starts.get(line)?;
+        let end = match self.starts.get(line + 1) {
+            Some(&next) if self.long_breaks.binary_search(&(line as u32)).is_ok() => next - 3,
+            Some(&next) => next - 1,
+            None => self.len,
+        };
+        Some(start..end)
+    }
+
+    /// Position of byte at `offset` in `data` (the buffer this map was built from), with
+    /// columns counted in characters.
+    pub fn offset_to_position(&self, data: &[u8], offset: usize) -> Position {
+        debug_assert_eq!(data.len(), self.len);
+        let line = self.line_of(offset);
+        let start = self.starts[line as usize];
+        let column = data[start..offset]
+            .iter()
+            .filter(|&&b| b & 0b11000000u8 != 0b10000000u8)
+            .count();
+        Position::with(offset, line, column as u32)
+    }
+
+    /// Byte range of lines quoted for span `start..end` with given number of context lines,
+    /// along with the number of the first quoted line (computed relative to `start.line`).
+    pub fn frame(
+        &self,
+        start: Position,
+        end: Position,
+        lines_before: u32,
+        lines_after: u32,
+    ) -> (usize, usize, u32) {
+        let first = self.line_of(start.offset);
+        let before = first.min(lines_before);
+        let last = (self.line_of(end.offset) + lines_after).min(self.starts.len() as u32 - 1);
+        let off1 = self.starts[(first - before) as usize];
+        let off2 = self.line_span(last).map_or(self.len, |s| s.end);
+        (off1, off2, start.line.saturating_sub(before))
+    }
+
+    /// Text of lines `off1..off2` returned by `frame()`, with line breaks other than `'\n'`
+    /// replaced by `'\n'`, so that quoted lines are split the same way they were counted.
+    pub fn frame_text<'a>(&self, data: &'a [u8], off1: usize, off2: usize) -> Cow<'a, str> {
+        if self.line_breaks == LineBreakPolicy::LF {
+            return String::from_utf8_lossy(&data[off1..off2]);
+        }
+        let mut text = String::with_capacity(off2 - off1);
+        for line in self.line_of(off1)..=self.line_of(off2) {
+            let span = self.line_span(line).unwrap();
+            if line > self.line_of(off1) {
+                text.push('\n');
+            }
+            text.push_str(&String::from_utf8_lossy(&data[span.start..span.end.min(off2)]));
+        }
+        text.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &[u8] = "line 1\nline ąą 2\n\nline 4\n".as_bytes();
+
+    #[test]
+    fn lines_and_positions() {
+        let map = LineMap::new(INPUT);
+        assert_eq!(map.line_count(), 5);
+        assert_eq!(map.line_span(1), Some(7..18));
+        assert_eq!(map.line_span(2), Some(19..19));
+        assert_eq!(map.line_span(4), Some(27..27));
+        assert_eq!(map.line_span(5), None);
+        assert_eq!(map.line_of(6), 0);
+        assert_eq!(map.line_of(7), 1);
+        assert_eq!(map.offset_to_position(INPUT, 16), Position::with(16, 1, 7));
+        assert_eq!(map.offset_to_position(INPUT, 26), Position::with(26, 3, 6));
+        assert_eq!(map.offset_to_position(INPUT, 27), Position::with(27, 4, 0));
+    }
+
+    #[test]
+    fn lines_broken_by_policy() {
+        let input = "a\rb\r\nc\u{2028}d\x0Ce".as_bytes();
+        let map = LineMap::with_line_break_policy(input, LineBreakPolicy::LF_CR);
+        assert_eq!(map.line_count(), 3);
+        assert_eq!(map.line_span(0), Some(0..1));
+        assert_eq!(map.line_span(1), Some(2..4));
+        assert_eq!(map.offset_to_position(input, 2), Position::with(2, 1, 0));
+
+        let map = LineMap::with_line_break_policy(input, LineBreakPolicy::ALL);
+        assert_eq!(map.line_count(), 5);
+        assert_eq!(map.line_span(2), Some(5..6));
+        assert_eq!(map.line_span(3), Some(9..10));
+        assert_eq!(map.offset_to_position(input, 11), Position::with(11, 4, 0));
+        assert_eq!(map.frame_text(input, 0, input.len()), "a\nb\r\nc\nd\ne");
+    }
+
+    #[test]
+    fn frame_clamped_to_input() {
+        let map = LineMap::new(INPUT);
+        let pos = |offset| map.offset_to_position(INPUT, offset);
+        assert_eq!(map.frame(pos(8), pos(12), 0, 0), (7, 18, 1));
+        assert_eq!(map.frame(pos(8), pos(12), 1, 1), (0, 19, 0));
+        assert_eq!(map.frame(pos(25), pos(26), 5, 0), (0, 26, 0));
+        assert_eq!(map.frame(pos(19), pos(19), 0, 2), (19, 27, 2));
+    }
+}
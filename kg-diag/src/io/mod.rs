@@ -1,22 +1,74 @@
+#[cfg(feature = "async")]
+pub use self::async_reader::{AsyncByteReader, AsyncCharReader, AsyncStreamReader};
+#[cfg(feature = "encoding")]
+pub use self::encoding::{DecodedCharReader, DecodedInput};
+pub use self::chain::ChainReader;
 pub use self::error::{IoErrorDetail, ResultExt};
+pub use self::include::{IncludeReader, IncludeSite};
 pub use self::fs::{FileBuffer, FileType, OpType};
-pub use self::reader::{ByteReader, CharReader, MemByteReader, MemCharReader, Reader};
-
+pub use self::limits::{LimitKind, Limited, Limits};
+pub use self::line_map::LineMap;
+pub use self::newlines::NormalizedNewlines;
+pub use self::raw::RawByteReader;
+pub use self::reader::{
+    ByteReader, CharPositions, CharReader, CharReaderExt, Chars, ColumnMode, FileCharReader,
+    LineBreakPolicy, Lookahead, Mark, MemByteReader, MemCharReader, PopMode, PositionConfig, Reader,
+    ReaderSnapshot, Transaction,
+};
+#[cfg(feature = "metrics")]
+pub use self::reader::ReaderMetrics;
+pub use self::recording::RecordingReader;
+pub use self::seek::SeekCharReader;
+pub use self::source_map::{
+    ContentHash, Snippet, SnippetLine, SourceId, SourceMap, SourceSpan,
+};
+
+#[cfg(feature = "async")]
+mod async_reader;
+mod chain;
+#[cfg(feature = "encoding")]
+mod encoding;
 pub mod error;
 pub mod fs;
+mod include;
+mod limits;
+mod line_map;
+mod newlines;
+mod raw;
 mod reader;
+mod recording;
+mod seek;
+mod source_map;
 
 pub type IoResult<T> = std::result::Result<T, IoErrorDetail>;
 
+/// Display width of `c`, `None` for control characters.
+#[cfg(feature = "unicode-width")]
+pub(crate) fn char_width(c: char) -> Option<usize> {
+    unicode_width::UnicodeWidthChar::width(c)
+}
+
+/// Without `unicode-width` feature every character (besides control ones) is assumed to take
+/// a single column.
+#[cfg(not(feature = "unicode-width"))]
+pub(crate) fn char_width(c: char) -> Option<usize> {
+    if c.is_control() {
+        None
+    } else {
+        Some(1)
+    }
+}
+
 use super::*;
 
 use std;
 use std::borrow::Cow;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
 pub struct Position {
     pub offset: usize,
     pub line: u32,
@@ -63,7 +115,7 @@ impl Default for Position {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
 pub struct Span {
     pub start: Position,
     pub end: Position,
@@ -122,12 +174,20 @@ impl Default for Span {
 
 #[derive(Debug, Clone)]
 pub struct Quote {
-    path: Option<PathBuf>,
+    path: Option<Arc<Path>>,
+    /// Set for quotes created by `SourceMap::quote()`.
+    source_id: Option<SourceId>,
+    /// Set for quotes created by `IncludeReader`, innermost site first.
+    included_from: Vec<IncludeSite>,
     span: Span,
     offset: usize,
     line: u32,
-    source: String,
+    source: Arc<str>,
     message: String,
+    /// Secondary spans marked within quoted lines, with their messages.
+    labels: Vec<(Span, String)>,
+    /// Lines before and after the span to be loaded, for quotes created by `deferred()`.
+    deferred: Option<(u32, u32)>,
 }
 
 #[allow(unused)]
@@ -141,45 +201,140 @@ impl Quote {
         lines_after: u32,
         message: Cow<'a, str>,
     ) -> Quote {
-        let mut line = 0;
-        let mut off1 = 0;
-        let mut off2 = data.len();
-        let mut lines = 0;
-
-        let before = &data[0..start.offset];
-        for (p, c) in before.iter().rev().enumerate() {
-            if *c == b'\n' {
-                if lines < lines_before {
-                    lines += 1;
-                } else {
-                    off1 = start.offset - p;
-                    line = start.line - lines_before;
-                    break;
-                }
-            }
-        }
+        let line_map = LineMap::new(data);
+        Quote::with_line_map(path, data, &line_map, start, end, lines_before, lines_after, message)
+    }
 
-        let after = &data[end.offset..];
-        lines = 0;
-        for (p, c) in after.iter().enumerate() {
-            if *c == b'\n' {
-                if lines < lines_after {
-                    lines += 1;
-                } else {
-                    off2 = end.offset + p;
-                    break;
-                }
-            }
-        }
+    /// Like `new()`, using `line_map` built for `data` instead of building a new one.
+    /// Worthwhile when many quotes are created from the same buffer, and needed for inputs
+    /// read with `LineBreakPolicy` other than `LineBreakPolicy::LF`, whose quoted lines are
+    /// broken by `line_map`.
+    pub fn with_line_map<'a>(
+        path: Option<&Path>,
+        data: &[u8],
+        line_map: &LineMap,
+        start: Position,
+        end: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<'a, str>,
+    ) -> Quote {
+        let (off1, off2, line) = line_map.frame(start, end, lines_before, lines_after);
+        let source: Arc<str> = line_map.frame_text(data, off1, off2).into();
+        Quote::with_source(path.map(Arc::from), start, end, off1, line, source, message)
+    }
 
+    /// Creates quote with already extracted `source`, starting at `offset` on line `line`.
+    /// Allows sharing source text between quotes (see `SourceMap::quote()`).
+    pub(crate) fn with_source<'a>(
+        path: Option<Arc<Path>>,
+        start: Position,
+        end: Position,
+        offset: usize,
+        line: u32,
+        source: Arc<str>,
+        message: Cow<'a, str>,
+    ) -> Quote {
+        stats::record_quote();
         Quote {
-            path: path.map(|p| p.to_path_buf()),
+            path,
+            source_id: None,
+            included_from: Vec::new(),
             span: Span::with_pos(start, end),
-            offset: off1,
+            offset,
             line,
-            source: String::from_utf8_lossy(&data[off1..off2]).into(),
+            source,
             message: message.into(),
+            labels: Vec::new(),
+            deferred: None,
+        }
+    }
+
+    /// Creates quote of `span` in file `path` without reading the file. Quoted lines (as many
+    /// around the span as set by `Config::quote_lines`) are loaded when the quote is rendered,
+    /// so that diagnostics collected over many files do not hold copies of their sources.
+    pub fn deferred<'a, P: AsRef<Path>>(path: P, span: Span, message: Cow<'a, str>) -> Quote {
+        let lines = config::config().quote_lines;
+        let mut q = Quote::with_source(
+            Some(Arc::from(path.as_ref())),
+            span.start,
+            span.end,
+            0,
+            span.start.line,
+            Arc::from(""),
+            message,
+        );
+        q.deferred = Some((lines, lines));
+        q
+    }
+
+    /// Returns `true` for quotes created by `deferred()`, which have no source text until
+    /// resolved.
+    pub fn is_deferred(&self) -> bool {
+        self.deferred.is_some()
+    }
+
+    /// Loads quoted lines of a deferred quote from its file. Other quotes are returned as is.
+    pub fn resolve(&self) -> IoResult<Quote> {
+        let (before, after) = match self.deferred {
+            Some(lines) => lines,
+            None => return Ok(self.clone()),
+        };
+        let path = self.path.as_deref().unwrap();
+        let data = FileBuffer::open(path)?;
+        let data = data.as_slice();
+        if self.span.end.offset > data.len() {
+            return Err(IoErrorDetail::InvalidOffset {
+                offset: self.span.end.offset,
+                len: data.len(),
+            });
         }
+        let q = Quote::new(
+            Some(path),
+            data,
+            self.span.start,
+            self.span.end,
+            before,
+            after,
+            self.message.as_str().into(),
+        );
+        Ok(self.resolved(q))
+    }
+
+    /// Like `resolve()`, taking quoted lines from `sources` if the file was loaded there.
+    /// Files not found in `sources` are read as in `resolve()`.
+    pub fn resolve_with(&self, sources: &SourceMap) -> IoResult<Quote> {
+        let (before, after) = match self.deferred {
+            Some(lines) => lines,
+            None => return Ok(self.clone()),
+        };
+        let id = match self.path.as_deref().and_then(|p| sources.find(p)) {
+            Some(id) => id,
+            None => return self.resolve(),
+        };
+        let len = sources.data(id).len();
+        if self.span.end.offset > len {
+            return Err(IoErrorDetail::InvalidOffset {
+                offset: self.span.end.offset,
+                len,
+            });
+        }
+        let q = sources.quote(
+            id,
+            self.span.start,
+            self.span.end,
+            before,
+            after,
+            self.message.as_str().into(),
+        );
+        Ok(self.resolved(q))
+    }
+
+    /// Copies labels and include sites of this quote to `quote` resolved from it.
+    fn resolved(&self, mut quote: Quote) -> Quote {
+        quote.included_from = self.included_from.clone();
+        quote.labels = self.labels.clone();
+        quote
     }
 
     pub fn start(&self) -> Position {
@@ -190,10 +345,30 @@ impl Quote {
         self.span.end
     }
 
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Source this quote was taken from, if it was created by `SourceMap::quote()`.
+    pub fn source_id(&self) -> Option<SourceId> {
+        self.source_id
+    }
+
     pub fn span(&self) -> Span {
         self.span
     }
 
+    /// Include directives the quoted source was included by (see `IncludeReader`),
+    /// innermost first.
+    pub fn included_from(&self) -> &[IncludeSite] {
+        &self.included_from
+    }
+
+    /// Quoted span along with its source, see `source_id()`.
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.source_id.map(|id| SourceSpan::new(id, self.span))
+    }
+
     pub fn message(&self) -> &str {
         &self.message
     }
@@ -209,56 +384,36 @@ impl Quote {
     pub fn source(&self) -> &str {
         &self.source
     }
+
+    /// Secondary labels, in order of addition.
+    pub fn labels(&self) -> &[(Span, String)] {
+        &self.labels
+    }
+
+    /// Adds secondary label, marking `span` with `message` in the same snippet as the quoted
+    /// span (e.g. "opened here" for a mismatched closing delimiter). Labels outside quoted
+    /// lines are rendered as notes below the snippet, see `Reader::quote_labeled()` for
+    /// quoting lines of all labels.
+    pub fn add_label<S: Into<String>>(&mut self, span: Span, message: S) {
+        self.labels.push((span, message.into()));
+    }
+
+    pub fn with_label<S: Into<String>>(mut self, span: Span, message: S) -> Quote {
+        self.add_label(span, message);
+        self
+    }
+
+    /// Renders this quote like `Display`, with ANSI colors unless they are disabled (see
+    /// `render::Layout::to_colored_string()`).
+    #[cfg(feature = "color")]
+    pub fn render_colored(&self) -> String {
+        render::Layout::of_quote(self, &render::RenderOptions::from_env()).to_colored_string()
+    }
 }
 
 impl std::fmt::Display for Quote {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        use std::cmp;
-
-        let show_line_numbers = self.path.is_some() || self.line != 0 || self.source.len() > 1;
-        let line_chars = if show_line_numbers {
-            cmp::max(
-                ((self.line + self.source.len() as u32 + 1) as f64)
-                    .log10()
-                    .ceil() as usize,
-                3,
-            )
-        } else {
-            0
-        };
-        let mut ln = self.line;
-        if self.path.is_some() {
-            write!(
-                f,
-                "{0:>1$} {2}:{3}\n",
-                " -->",
-                line_chars,
-                self.path.as_ref().unwrap().to_str().unwrap(),
-                self.span.start
-            )?;
-        }
-        for s in self.source.lines() {
-            if show_line_numbers {
-                write!(f, "{0:>1$}| ", ln + 1, line_chars)?;
-            }
-            if ln == self.span.start.line && ln == self.span.end.line {
-                write!(f, "{}\n", s)?;
-                if show_line_numbers {
-                    write!(f, "{0:1$}| ", " ", line_chars)?;
-                }
-                for _ in 0..self.span.start.column {
-                    write!(f, " ")?;
-                }
-                for _ in self.span.start.column..self.span.end.column {
-                    write!(f, "^")?;
-                }
-                write!(f, " {}\n", self.message)?;
-            } else {
-                write!(f, "{}\n", s)?;
-            }
-            ln += 1;
-        }
-        Ok(())
+        render::renderer().render_quote(self, &render::RenderOptions::from_env(), f)
     }
 }
 
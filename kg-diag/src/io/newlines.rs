@@ -0,0 +1,187 @@
+use super::*;
+
+/// `CharReader` adapter presenting `"\r\n"` and lone `'\r'` as a single `'\n'`, so grammars
+/// only need to handle one kind of line ending. Positions are those of the underlying reader,
+/// so spans and quotes point at original input (a normalized `"\r\n"` is positioned at its
+/// `'\r'`). Slices are normalized as well.
+///
+/// For lone `'\r'` to start new lines in reported positions, the underlying reader should use
+/// `LineBreakPolicy::LF_CR` (or `ALL`).
+#[derive(Debug)]
+pub struct NormalizedNewlines<R: CharReader> {
+    inner: R,
+    /// Current character of `inner`, if decoded.
+    current: Option<char>,
+}
+
+impl<R: CharReader> NormalizedNewlines<R> {
+    pub fn new(inner: R) -> NormalizedNewlines<R> {
+        NormalizedNewlines {
+            inner,
+            current: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Finds `lookahead`-th normalized character, counting `"\r\n"` as one.
+    fn peek_normalized(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        let mut i = 0;
+        let mut n = 0;
+        loop {
+            let (c, pos) = match self.inner.peek_char_pos(i)? {
+                Some(cp) => cp,
+                None => return Ok(None),
+            };
+            if i == 0 {
+                self.current = Some(c);
+            }
+            let len = if c == '\r' && self.inner.peek_char(i + 1)? == Some('\n') { 2 } else { 1 };
+            if n == lookahead {
+                let c = if c == '\r' { '\n' } else { c };
+                return Ok(Some((c, pos)));
+            }
+            i += len;
+            n += 1;
+        }
+    }
+}
+
+fn normalize(s: Cow<str>) -> Cow<str> {
+    if s.contains('\r') {
+        Cow::Owned(s.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        s
+    }
+}
+
+impl<R: CharReader> Reader for NormalizedNewlines<R> {
+    fn path(&self) -> Option<&Path> {
+        self.inner.path()
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.inner.len()
+    }
+
+    fn eof(&self) -> bool {
+        self.inner.eof()
+    }
+
+    fn position(&self) -> Position {
+        self.inner.position()
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        self.current = None;
+        self.inner.seek(pos)
+    }
+
+    fn mark(&mut self) -> Mark {
+        self.inner.mark()
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        self.current = mark.current_char();
+        self.inner.rollback(mark)
+    }
+
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        self.inner.input().map(normalize)
+    }
+
+    /// Returns normalized slice of the underlying input. Offsets are those of original input.
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        self.inner.slice(start, end).map(normalize)
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        self.inner.quote(from, to, lines_before, lines_after, message)
+    }
+}
+
+impl<R: CharReader> CharReader for NormalizedNewlines<R> {
+    fn next_char(&mut self) -> IoResult<Option<char>> {
+        if self.current == Some('\r') && self.inner.peek_char(1)? == Some('\n') {
+            self.inner.next_char()?;
+        }
+        let c = self.inner.next_char()?;
+        self.current = c;
+        Ok(c.map(|c| if c == '\r' { '\n' } else { c }))
+    }
+
+    fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        Ok(self.peek_normalized(lookahead)?.map(|(c, _)| c))
+    }
+
+    fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        self.peek_normalized(lookahead)
+    }
+
+    fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_char()?;
+        }
+        Ok(())
+    }
+
+    fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        for (i, c) in s.chars().enumerate() {
+            if self.peek_char(i)? != Some(c) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn match_str_term(
+        &mut self,
+        s: &str,
+        f: &mut dyn FnMut(Option<char>) -> bool,
+    ) -> IoResult<bool> {
+        if self.match_str(s)? {
+            let n = s.chars().count();
+            Ok(f(self.peek_char(n)?))
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_line_endings() {
+        let input = MemCharReader::new(b"a\r\nb\rc\n");
+        let mut r = NormalizedNewlines::new(input.with_line_break_policy(LineBreakPolicy::LF_CR));
+        let mut chars = Vec::new();
+        while let Some((c, pos)) = r.peek_char_pos(0).unwrap() {
+            chars.push((c, pos.offset, pos.line));
+            r.next_char().unwrap();
+        }
+        assert_eq!(
+            chars,
+            vec![('a', 0, 0), ('\n', 1, 0), ('b', 3, 1), ('\n', 4, 1), ('c', 5, 2), ('\n', 6, 2)]
+        );
+
+        r.seek(Position::new()).unwrap();
+        assert!(r.match_str("a\nb\n").unwrap());
+        assert_eq!(r.scan(&mut |c| c != 'c').unwrap(), "a\nb\n");
+        assert_eq!(r.position(), Position::with(5, 2, 0));
+    }
+}
@@ -0,0 +1,248 @@
+use super::*;
+
+/// Number of bytes in a row of hexdump quotes.
+const ROW_LEN: usize = 16;
+
+/// `ByteReader` for binary formats. Unlike `MemByteReader`, input is not expected to be UTF-8,
+/// so reading never fails with encoding errors. Positions only track offsets, unless line
+/// counting is enabled with `with_line_counting()`, in which case `0x0A` bytes start new
+/// lines (and columns count bytes).
+///
+/// Quotes are hexdump-style (like `hexdump -C`), with lines of quotes being rows of 16 bytes
+/// and quoted bytes marked in the hexadecimal column.
+#[derive(Debug, Clone)]
+pub struct RawByteReader<'a> {
+    path: Option<&'a Path>,
+    data: &'a [u8],
+    pos: Position,
+    count_lines: bool,
+}
+
+impl<'a> RawByteReader<'a> {
+    pub fn new(input: &'a [u8]) -> RawByteReader<'a> {
+        RawByteReader {
+            path: None,
+            data: input,
+            pos: Position::new(),
+            count_lines: false,
+        }
+    }
+
+    pub fn with_path<P: AsRef<Path> + ?Sized + 'a>(
+        path: &'a P,
+        input: &'a [u8],
+    ) -> RawByteReader<'a> {
+        RawByteReader {
+            path: Some(path.as_ref()),
+            ..RawByteReader::new(input)
+        }
+    }
+
+    pub fn with_line_counting(mut self, count_lines: bool) -> RawByteReader<'a> {
+        self.count_lines = count_lines;
+        self
+    }
+
+    pub fn line_counting(&self) -> bool {
+        self.count_lines
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Input from the current byte on.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos.offset.min(self.data.len())..]
+    }
+
+    /// Position after advancing from `pos` over `bytes`.
+    fn advance(&self, mut pos: Position, bytes: &[u8]) -> Position {
+        pos.offset += bytes.len();
+        if self.count_lines {
+            match memchr::memrchr(b'\n', bytes) {
+                Some(last) => {
+                    pos.line += memchr::memchr_iter(b'\n', bytes).count() as u32;
+                    pos.column = (bytes.len() - last - 1) as u32;
+                }
+                None => pos.column += bytes.len() as u32,
+            }
+        }
+        pos
+    }
+
+    /// Position of `offset` in hexdump quotes, with rows as lines.
+    fn dump_pos(offset: usize) -> Position {
+        let i = offset % ROW_LEN;
+        // second half of a row is separated by an extra space
+        let column = 10 + 3 * i + (i >= ROW_LEN / 2) as usize;
+        Position::with(offset, (offset / ROW_LEN) as u32, column as u32)
+    }
+
+    fn dump_row(&self, row: usize, out: &mut String) {
+        use std::fmt::Write;
+
+        let start = (row * ROW_LEN).min(self.data.len());
+        let bytes = &self.data[start..(start + ROW_LEN).min(self.data.len())];
+        let _ = write!(out, "{:08x}  ", row * ROW_LEN);
+        for i in 0..ROW_LEN {
+            if i == ROW_LEN / 2 {
+                out.push(' ');
+            }
+            match bytes.get(i) {
+                Some(b) => {
+                    let _ = write!(out, "{:02x} ", b);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str(" |");
+        out.extend(bytes.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' }));
+        out.push('|');
+    }
+}
+
+impl<'a> Reader for RawByteReader<'a> {
+    fn path(&self) -> Option<&Path> {
+        self.path
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.data.len())
+    }
+
+    fn eof(&self) -> bool {
+        self.pos.offset >= self.data.len()
+    }
+
+    fn position(&self) -> Position {
+        self.pos
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        stats::record_seek();
+        self.pos = pos;
+        Ok(())
+    }
+
+    /// Input is converted lossily, with invalid UTF-8 sequences replaced.
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        Ok(String::from_utf8_lossy(self.data))
+    }
+
+    /// Slice is converted lossily, with invalid UTF-8 sequences replaced.
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        Ok(String::from_utf8_lossy(&self.data[start..end]))
+    }
+
+    /// Quotes rows of hexdump containing bytes `from..to`, along with `lines_before` and
+    /// `lines_after` surrounding rows.
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        let start = RawByteReader::dump_pos(from.offset);
+        let end = if to.offset > from.offset {
+            let mut end = RawByteReader::dump_pos(to.offset - 1);
+            end.offset = to.offset;
+            end.column += 2;
+            end
+        } else {
+            Position::with(start.offset, start.line, start.column + 2)
+        };
+        let last_row = self.data.len().saturating_sub(1) / ROW_LEN;
+        let first = start.line.saturating_sub(lines_before) as usize;
+        let last = (end.line as usize + lines_after as usize).min(last_row).max(end.line as usize);
+
+        let mut source = String::new();
+        for row in first..=last {
+            if row > first {
+                source.push('\n');
+            }
+            self.dump_row(row, &mut source);
+        }
+        Quote::with_source(
+            self.path.map(Arc::from),
+            start,
+            end,
+            first * ROW_LEN,
+            first as u32,
+            source.into(),
+            message,
+        )
+    }
+}
+
+impl<'a> ByteReader for RawByteReader<'a> {
+    fn next_byte(&mut self) -> IoResult<Option<u8>> {
+        match self.data.get(self.pos.offset).cloned() {
+            Some(b) => {
+                self.pos = self.advance(self.pos, &[b]);
+                Ok(Some(b))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn peek_byte(&mut self, lookahead: usize) -> IoResult<Option<u8>> {
+        Ok(self.data.get(self.pos.offset + lookahead).cloned())
+    }
+
+    fn peek_byte_pos(&mut self, lookahead: usize) -> IoResult<Option<(u8, Position)>> {
+        let offset = self.pos.offset + lookahead;
+        match self.data.get(offset) {
+            Some(&b) => {
+                let pos = self.advance(self.pos, &self.data[self.pos.offset..offset]);
+                Ok(Some((b, pos)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn skip_bytes(&mut self, skip: usize) -> IoResult<()> {
+        let start = self.pos.offset.min(self.data.len());
+        let end = (self.pos.offset + skip).min(self.data.len());
+        self.pos = self.advance(self.pos, &self.data[start..end]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &[u8] = b"GIF89a\x01\x00\xff\n\x80";
+
+    #[test]
+    fn reads_invalid_utf8() {
+        let mut r = RawByteReader::new(INPUT);
+        r.skip_bytes(8).unwrap();
+        assert_eq!(r.next_byte().unwrap(), Some(0xff));
+        assert_eq!(r.peek_byte_pos(1).unwrap(), Some((0x80, Position::with(10, 0, 0))));
+        r.skip_bytes(5).unwrap();
+        assert!(r.eof());
+        assert_eq!(r.next_byte().unwrap(), None);
+
+        let mut r = RawByteReader::new(INPUT).with_line_counting(true);
+        r.skip_bytes(9).unwrap();
+        assert_eq!(r.position(), Position::with(9, 0, 9));
+        r.next_byte().unwrap();
+        assert_eq!(r.peek_byte_pos(0).unwrap(), Some((0x80, Position::with(10, 1, 0))));
+    }
+
+    #[test]
+    fn hexdump_quote() {
+        let mut r = RawByteReader::with_path("image.gif", INPUT);
+        let q = r.quote(Position::with(8, 0, 0), Position::with(10, 0, 0), 1, 1, "".into());
+        assert_eq!(
+            q.source(),
+            "00000000  47 49 46 38 39 61 01 00  ff 0a 80                 |GIF89a.....|"
+        );
+        assert_eq!(q.span(), Span::with(8, 0, 35, 10, 0, 40));
+        assert_eq!(&q.source()[35..40], "ff 0a");
+    }
+}
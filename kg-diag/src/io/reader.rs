@@ -1,6 +1,10 @@
 use std::borrow::Cow;
 use std::char;
-use std::path::Path;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use super::*;
 
@@ -29,6 +33,17 @@ pub trait Reader {
         self.seek(Default::default())
     }
 
+    /// Saves current state of the reader, to be restored with `rollback()`. Unlike
+    /// `position()`, a mark also keeps the current character, so after rollback the reader
+    /// behaves exactly as it did when the mark was taken.
+    fn mark(&mut self) -> Mark {
+        Mark::new(self.position())
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        self.seek(mark.position())
+    }
+
     fn quote(
         &mut self,
         from: Position,
@@ -37,6 +52,136 @@ pub trait Reader {
         lines_after: u32,
         message: Cow<str>,
     ) -> Quote;
+
+    /// Quotes with number of context lines from crate configuration (`Config::quote_lines`).
+    fn quote_default(&mut self, from: Position, to: Position, message: Cow<str>) -> Quote {
+        let lines = config::config().quote_lines;
+        self.quote(from, to, lines, lines, message)
+    }
+
+    /// Quotes span `from..to` like `quote_default()`, with secondary `labels` (spans with
+    /// messages) marked in the same snippet. Quoted lines cover all labeled spans, e.g. both
+    /// an opening delimiter and the mismatched closing one.
+    fn quote_labeled(
+        &mut self,
+        from: Position,
+        to: Position,
+        labels: &[(Span, &str)],
+        message: Cow<str>,
+    ) -> Quote {
+        let lines = config::config().quote_lines;
+        let start = labels.iter().map(|l| l.0.start).fold(from, std::cmp::min);
+        let end = labels.iter().map(|l| l.0.end).fold(to, std::cmp::max);
+        let mut q = self.quote(start, end, lines, lines, message);
+        q.span = Span::with_pos(from, to);
+        for &(span, message) in labels {
+            q.add_label(span, message);
+        }
+        q
+    }
+}
+
+/// Reader state saved with `Reader::mark()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark {
+    pos: Position,
+    c: char,
+    len: usize,
+    segment: usize,
+}
+
+impl Mark {
+    pub fn new(pos: Position) -> Mark {
+        Mark {
+            pos,
+            c: '\0',
+            len: 0,
+            segment: 0,
+        }
+    }
+
+    fn with_char(pos: Position, c: char, len: usize) -> Mark {
+        Mark { pos, c, len, segment: 0 }
+    }
+
+    /// Records input segment the mark was taken in, for readers over several inputs.
+    pub(crate) fn with_segment(self, segment: usize) -> Mark {
+        Mark { segment, ..self }
+    }
+
+    pub(crate) fn segment(&self) -> usize {
+        self.segment
+    }
+
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    /// Character current when the mark was taken, if it was decoded.
+    pub(crate) fn current_char(&self) -> Option<char> {
+        if self.len > 0 {
+            Some(self.c)
+        } else {
+            None
+        }
+    }
+}
+
+/// Guard for speculative reading. Reader is rolled back to the state from the start
+/// of the transaction when the guard is dropped, unless `commit()` was called.
+#[derive(Debug)]
+pub struct Transaction<'r, R: Reader + ?Sized> {
+    reader: &'r mut R,
+    mark: Mark,
+    committed: bool,
+}
+
+impl<'r, R: Reader + ?Sized> Transaction<'r, R> {
+    pub fn begin(reader: &'r mut R) -> Transaction<'r, R> {
+        let mark = reader.mark();
+        Transaction {
+            reader,
+            mark,
+            committed: false,
+        }
+    }
+
+    pub fn mark(&self) -> Mark {
+        self.mark
+    }
+
+    /// Keeps everything read within the transaction.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+
+    /// Rolls back explicitly, reporting errors that are ignored when the guard is dropped.
+    pub fn rollback(mut self) -> IoResult<()> {
+        self.committed = true;
+        self.reader.rollback(self.mark)
+    }
+}
+
+impl<'r, R: Reader + ?Sized> std::ops::Deref for Transaction<'r, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.reader
+    }
+}
+
+impl<'r, R: Reader + ?Sized> std::ops::DerefMut for Transaction<'r, R> {
+    fn deref_mut(&mut self) -> &mut R {
+        self.reader
+    }
+}
+
+impl<'r, R: Reader + ?Sized> Drop for Transaction<'r, R> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.reader.rollback(self.mark);
+        }
+    }
 }
 
 pub trait ByteReader: Reader {
@@ -60,82 +205,961 @@ pub trait CharReader: Reader {
 
     fn match_str(&mut self, s: &str) -> IoResult<bool>;
 
-    fn match_str_term(&mut self, s: &str, f: &mut dyn FnMut(Option<char>) -> bool) -> IoResult<bool>;
+    fn match_str_term(&mut self, s: &str, f: &mut dyn FnMut(Option<char>) -> bool) -> IoResult<bool>;
+
+    /// Like `match_str()`, with characters compared case-insensitively (using Unicode simple
+    /// lowercase mapping).
+    fn match_str_ignore_case(&mut self, s: &str) -> IoResult<bool> {
+        // current character must be decoded first for lookahead to be counted from it
+        if self.peek_char(0)?.is_none() {
+            return Ok(s.is_empty());
+        }
+        for (i, c) in s.chars().enumerate() {
+            match self.peek_char(i)? {
+                Some(k) if k == c || k.to_lowercase().eq(c.to_lowercase()) => {}
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Matches `s` like `match_str()`, advancing past it if matched.
+    fn consume_str(&mut self, s: &str) -> IoResult<bool> {
+        if self.match_str(s)? {
+            self.peek_char(0)?;
+            self.skip_chars(s.chars().count())?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Matches `s` like `match_str_ignore_case()`, advancing past it if matched.
+    fn consume_str_ignore_case(&mut self, s: &str) -> IoResult<bool> {
+        if self.match_str_ignore_case(s)? {
+            self.skip_chars(s.chars().count())?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn match_char(&mut self, c: char) -> IoResult<bool> {
+        if let Some(k) = self.peek_char(0)? {
+            Ok(c == k)
+        } else {
+            Ok(false)
+        }
+    }
+
+    #[inline]
+    fn skip_whitespace(&mut self) -> IoResult<()> {
+        while let Some(c) = self.peek_char(0)? {
+            if c.is_whitespace() {
+                self.next_char()?;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn skip_whitespace_nonl(&mut self) -> IoResult<()> {
+        while let Some(c) = self.peek_char(0)? {
+            if c.is_whitespace() && c != '\n' {
+                self.next_char()?;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn scan(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<Cow<str>> {
+        let s = self.position().offset;
+        while let Some(c) = self.peek_char(0)? {
+            if f(c) {
+                self.next_char()?;
+            } else {
+                break;
+            }
+        }
+        let offset = self.position().offset;
+        self.slice(s, offset)
+    }
+
+    /// Like `scan()`, additionally returning span of the scanned text.
+    #[inline]
+    fn scan_span(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<(Cow<str>, Span)> {
+        let span = self.skip_while_span(f)?;
+        Ok((self.slice_pos(span.start, span.end)?, span))
+    }
+
+    /// Advances to the next occurrence of `s` (which becomes current input) and returns its
+    /// position. If `s` is not found, reader is left at the end of input and `None` is returned.
+    fn skip_until_str(&mut self, s: &str) -> IoResult<Option<Position>> {
+        while self.peek_char(0)?.is_some() {
+            if self.match_str(s)? {
+                return Ok(Some(self.position()));
+            }
+            self.next_char()?;
+        }
+        Ok(None)
+    }
+
+    #[inline]
+    fn skip_until(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<()> {
+        while let Some(c) = self.peek_char(0)? {
+            if f(c) {
+                break;
+            } else {
+                self.next_char()?;
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn skip_while(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<()> {
+        while let Some(c) = self.peek_char(0)? {
+            if f(c) {
+                self.next_char()?;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `skip_while()`, returning span of the skipped characters.
+    #[inline]
+    fn skip_while_span(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<Span> {
+        let start = self.position();
+        self.skip_while(f)?;
+        Ok(Span::with_pos(start, self.position()))
+    }
+
+    /// Skips a run of ASCII characters (other than line breaks) accepted by `f`, returning number
+    /// of skipped characters. Readers with direct access to input data can scan bytes instead
+    /// of decoding characters one by one.
+    fn skip_ascii_while(&mut self, f: &mut dyn FnMut(u8) -> bool) -> IoResult<usize> {
+        let mut n = 0;
+        while let Some(c) = self.peek_char(0)? {
+            if c.is_ascii() && c != '\n' && f(c as u8) {
+                self.next_char()?;
+                n += 1;
+            } else {
+                break;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Returns up to `n` characters starting with the current one, without consuming them.
+    /// Fewer characters are returned at the end of input.
+    fn peek_str(&mut self, n: usize) -> IoResult<Cow<str>> {
+        let mut s = String::new();
+        if n == 0 {
+            return Ok(Cow::Owned(s));
+        }
+        let mut c = self.peek_char(0)?;
+        let mark = self.mark();
+        let mut i = 0;
+        while let Some(ch) = c {
+            s.push(ch);
+            i += 1;
+            if i == n {
+                break;
+            }
+            c = self.next_char()?;
+        }
+        self.rollback(mark)?;
+        Ok(Cow::Owned(s))
+    }
+
+    /// Reads the rest of the current line, returning its text (without the line break) and
+    /// span. Reader is left at the start of the following line. A `'\r'` preceding `'\n'`
+    /// is treated as part of the line break regardless of line break policy. Returns `None`
+    /// at the end of input.
+    fn read_line(&mut self) -> IoResult<Option<(Cow<str>, Span)>> {
+        let start = match self.peek_char_pos(0)? {
+            Some((_, pos)) => pos,
+            None => return Ok(None),
+        };
+        // last two characters of the line, the last one being a line break unless the line
+        // ends with input
+        let mut last = None;
+        let mut prev = None;
+        while let Some((c, pos)) = self.peek_char_pos(0)? {
+            if pos.line != start.line {
+                break;
+            }
+            prev = last;
+            last = Some((c, pos));
+            self.next_char()?;
+        }
+        let end = match (prev, last) {
+            (Some(('\r', pos)), Some(('\n', _))) => pos,
+            (_, Some(('\n', pos))) => pos,
+            (_, Some((_, pos))) if self.position().line != start.line => pos,
+            _ => self.position(),
+        };
+        let text = self.slice_pos(start, end)?;
+        Ok(Some((text, Span::with_pos(start, end))))
+    }
+
+    /// Consumes the current grapheme cluster (as defined by Unicode extended grapheme cluster
+    /// rules), returning its text and span. Reader is left at the character following it.
+    /// Returns `None` at the end of input.
+    #[cfg(feature = "graphemes")]
+    fn next_grapheme(&mut self) -> IoResult<Option<(Cow<str>, Span)>> {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let (c, start) = match self.peek_char_pos(0)? {
+            Some(cp) => cp,
+            None => return Ok(None),
+        };
+        let mut s = c.to_string();
+        while let Some(c) = self.next_char()? {
+            s.push(c);
+            // stop at the first character not extending the cluster
+            if s.graphemes(true).next().map_or(0, |g| g.len()) < s.len() {
+                break;
+            }
+        }
+        let end = self.position();
+        Ok(Some((self.slice_pos(start, end)?, Span::with_pos(start, end))))
+    }
+
+    /// Like `next_grapheme()`, without consuming the grapheme cluster.
+    #[cfg(feature = "graphemes")]
+    fn peek_grapheme(&mut self) -> IoResult<Option<(Cow<str>, Span)>> {
+        self.peek_char(0)?;
+        let mark = self.mark();
+        let span = self.next_grapheme()?.map(|(_, span)| span);
+        self.rollback(mark)?;
+        match span {
+            Some(span) => Ok(Some((self.slice_pos(span.start, span.end)?, span))),
+            None => Ok(None),
+        }
+    }
+
+    /// Advances to the start of line `line`. Returns `false` if the input ends before that
+    /// line, or if the reader is already past its start (reader is not moved back).
+    fn skip_to_line(&mut self, line: u32) -> IoResult<bool> {
+        while let Some((_, pos)) = self.peek_char_pos(0)? {
+            if pos.line >= line {
+                return Ok(pos.line == line && pos.column == 0);
+            }
+            self.next_char()?;
+        }
+        Ok(false)
+    }
+
+    /// Iterator over characters starting with the current one. Each returned character is
+    /// consumed only when the iterator is advanced again, so the last one returned stays
+    /// current, e.g. after `chars().take_while(..)` the first rejected character is the current one.
+    fn chars(&mut self) -> Chars<Self>
+    where
+        Self: Sized,
+    {
+        Chars::new(self)
+    }
+
+    /// Like `chars()`, with each character paired with its position.
+    fn char_positions(&mut self) -> CharPositions<Self>
+    where
+        Self: Sized,
+    {
+        CharPositions(Chars::new(self))
+    }
+}
+
+/// Helpers for `CharReader`, implemented for all readers including trait objects. Results are
+/// owned, so they do not keep the reader borrowed, and predicates are taken by value, so they
+/// can be passed without `&mut` reborrowing. Useful for parser utilities written against
+/// `&mut dyn CharReader`.
+pub trait CharReaderExt: CharReader {
+    /// Like `scan()`, returning owned text.
+    fn scan_owned<F: FnMut(char) -> bool>(&mut self, mut f: F) -> IoResult<String> {
+        self.scan(&mut f).map(Cow::into_owned)
+    }
+
+    /// Like `scan_span()`, returning owned text.
+    fn scan_span_owned<F: FnMut(char) -> bool>(&mut self, mut f: F) -> IoResult<(String, Span)> {
+        let (s, span) = self.scan_span(&mut f)?;
+        Ok((s.into_owned(), span))
+    }
+
+    /// Like `slice()`, returning owned text.
+    fn slice_owned(&mut self, start: usize, end: usize) -> IoResult<String> {
+        self.slice(start, end).map(Cow::into_owned)
+    }
+
+    /// Like `slice_pos()`, returning owned text.
+    fn slice_pos_owned(&mut self, from: Position, to: Position) -> IoResult<String> {
+        self.slice_pos(from, to).map(Cow::into_owned)
+    }
+
+    /// Like `skip_while()`, with predicate taken by value.
+    fn skip_while_with<F: FnMut(char) -> bool>(&mut self, mut f: F) -> IoResult<Span> {
+        self.skip_while_span(&mut f)
+    }
+
+    /// Like `skip_until()`, with predicate taken by value.
+    fn skip_until_with<F: FnMut(char) -> bool>(&mut self, mut f: F) -> IoResult<()> {
+        self.skip_until(&mut f)
+    }
+}
+
+impl<R: CharReader + ?Sized> CharReaderExt for R {}
+
+/// Iterator returned by `CharReader::chars()`. Iteration stops after the first error.
+#[derive(Debug)]
+pub struct Chars<'r, R: CharReader + ?Sized> {
+    reader: &'r mut R,
+    started: bool,
+    done: bool,
+}
+
+impl<'r, R: CharReader + ?Sized> Chars<'r, R> {
+    pub fn new(reader: &'r mut R) -> Chars<'r, R> {
+        Chars {
+            reader,
+            started: false,
+            done: false,
+        }
+    }
+
+    fn next_pos(&mut self) -> Option<IoResult<(char, Position)>> {
+        if self.done {
+            return None;
+        }
+        // the previously returned character is consumed only now, so that it stays
+        // current if iteration is not continued
+        if self.started {
+            if let Err(err) = self.reader.next_char() {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+        self.started = true;
+        match self.reader.peek_char_pos(0) {
+            Ok(Some(cp)) => Some(Ok(cp)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<'r, R: CharReader + ?Sized> Iterator for Chars<'r, R> {
+    type Item = IoResult<char>;
+
+    fn next(&mut self) -> Option<IoResult<char>> {
+        self.next_pos().map(|r| r.map(|(c, _)| c))
+    }
+}
+
+/// Iterator returned by `CharReader::char_positions()`.
+#[derive(Debug)]
+pub struct CharPositions<'r, R: CharReader + ?Sized>(Chars<'r, R>);
+
+impl<'r, R: CharReader + ?Sized> CharPositions<'r, R> {
+    pub fn new(reader: &'r mut R) -> CharPositions<'r, R> {
+        CharPositions(Chars::new(reader))
+    }
+}
+
+impl<'r, R: CharReader + ?Sized> Iterator for CharPositions<'r, R> {
+    type Item = IoResult<(char, Position)>;
+
+    fn next(&mut self) -> Option<IoResult<(char, Position)>> {
+        self.0.next_pos()
+    }
+}
+
+/// Forwards all reader methods (including provided ones, which readers may override)
+/// to the dereferenced reader.
+macro_rules! forward_reader {
+    () => {
+        fn path(&self) -> Option<&Path> {
+            (**self).path()
+        }
+
+        fn len(&self) -> Option<usize> {
+            (**self).len()
+        }
+
+        fn eof(&self) -> bool {
+            (**self).eof()
+        }
+
+        fn position(&self) -> Position {
+            (**self).position()
+        }
+
+        fn seek(&mut self, pos: Position) -> IoResult<()> {
+            (**self).seek(pos)
+        }
+
+        fn input(&mut self) -> IoResult<Cow<str>> {
+            (**self).input()
+        }
+
+        fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+            (**self).slice(start, end)
+        }
+
+        fn slice_pos(&mut self, from: Position, to: Position) -> IoResult<Cow<str>> {
+            (**self).slice_pos(from, to)
+        }
+
+        fn reset(&mut self) -> IoResult<()> {
+            (**self).reset()
+        }
+
+        fn mark(&mut self) -> Mark {
+            (**self).mark()
+        }
+
+        fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+            (**self).rollback(mark)
+        }
+
+        fn quote(
+            &mut self,
+            from: Position,
+            to: Position,
+            lines_before: u32,
+            lines_after: u32,
+            message: Cow<str>,
+        ) -> Quote {
+            (**self).quote(from, to, lines_before, lines_after, message)
+        }
+
+        fn quote_default(&mut self, from: Position, to: Position, message: Cow<str>) -> Quote {
+            (**self).quote_default(from, to, message)
+        }
+
+        fn quote_labeled(
+            &mut self,
+            from: Position,
+            to: Position,
+            labels: &[(Span, &str)],
+            message: Cow<str>,
+        ) -> Quote {
+            (**self).quote_labeled(from, to, labels, message)
+        }
+    };
+}
+
+macro_rules! forward_byte_reader {
+    () => {
+        fn next_byte(&mut self) -> IoResult<Option<u8>> {
+            (**self).next_byte()
+        }
+
+        fn peek_byte(&mut self, lookahead: usize) -> IoResult<Option<u8>> {
+            (**self).peek_byte(lookahead)
+        }
+
+        fn peek_byte_pos(&mut self, lookahead: usize) -> IoResult<Option<(u8, Position)>> {
+            (**self).peek_byte_pos(lookahead)
+        }
+
+        fn skip_bytes(&mut self, skip: usize) -> IoResult<()> {
+            (**self).skip_bytes(skip)
+        }
+    };
+}
+
+macro_rules! forward_char_reader {
+    () => {
+        fn next_char(&mut self) -> IoResult<Option<char>> {
+            (**self).next_char()
+        }
+
+        fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+            (**self).peek_char(lookahead)
+        }
+
+        fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+            (**self).peek_char_pos(lookahead)
+        }
+
+        fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+            (**self).skip_chars(skip)
+        }
+
+        fn match_str(&mut self, s: &str) -> IoResult<bool> {
+            (**self).match_str(s)
+        }
+
+        fn match_str_term(
+            &mut self,
+            s: &str,
+            f: &mut dyn FnMut(Option<char>) -> bool,
+        ) -> IoResult<bool> {
+            (**self).match_str_term(s, f)
+        }
+
+        fn match_str_ignore_case(&mut self, s: &str) -> IoResult<bool> {
+            (**self).match_str_ignore_case(s)
+        }
+
+        fn consume_str(&mut self, s: &str) -> IoResult<bool> {
+            (**self).consume_str(s)
+        }
+
+        fn consume_str_ignore_case(&mut self, s: &str) -> IoResult<bool> {
+            (**self).consume_str_ignore_case(s)
+        }
+
+        fn match_char(&mut self, c: char) -> IoResult<bool> {
+            (**self).match_char(c)
+        }
+
+        fn skip_whitespace(&mut self) -> IoResult<()> {
+            (**self).skip_whitespace()
+        }
+
+        fn skip_whitespace_nonl(&mut self) -> IoResult<()> {
+            (**self).skip_whitespace_nonl()
+        }
+
+        fn scan(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<Cow<str>> {
+            (**self).scan(f)
+        }
+
+        fn scan_span(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<(Cow<str>, Span)> {
+            (**self).scan_span(f)
+        }
+
+        fn skip_until_str(&mut self, s: &str) -> IoResult<Option<Position>> {
+            (**self).skip_until_str(s)
+        }
+
+        fn skip_until(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<()> {
+            (**self).skip_until(f)
+        }
+
+        fn skip_while(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<()> {
+            (**self).skip_while(f)
+        }
+
+        fn skip_while_span(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<Span> {
+            (**self).skip_while_span(f)
+        }
+
+        fn skip_ascii_while(&mut self, f: &mut dyn FnMut(u8) -> bool) -> IoResult<usize> {
+            (**self).skip_ascii_while(f)
+        }
+
+        fn peek_str(&mut self, n: usize) -> IoResult<Cow<str>> {
+            (**self).peek_str(n)
+        }
+
+        fn read_line(&mut self) -> IoResult<Option<(Cow<str>, Span)>> {
+            (**self).read_line()
+        }
+
+        fn skip_to_line(&mut self, line: u32) -> IoResult<bool> {
+            (**self).skip_to_line(line)
+        }
+
+        #[cfg(feature = "graphemes")]
+        fn next_grapheme(&mut self) -> IoResult<Option<(Cow<str>, Span)>> {
+            (**self).next_grapheme()
+        }
+
+        #[cfg(feature = "graphemes")]
+        fn peek_grapheme(&mut self) -> IoResult<Option<(Cow<str>, Span)>> {
+            (**self).peek_grapheme()
+        }
+    };
+}
+
+impl<'r, R: Reader + ?Sized> Reader for &'r mut R {
+    forward_reader!();
+}
+
+impl<R: Reader + ?Sized> Reader for Box<R> {
+    forward_reader!();
+}
+
+impl<'r, R: ByteReader + ?Sized> ByteReader for &'r mut R {
+    forward_byte_reader!();
+}
+
+impl<R: ByteReader + ?Sized> ByteReader for Box<R> {
+    forward_byte_reader!();
+}
+
+impl<'r, R: CharReader + ?Sized> CharReader for &'r mut R {
+    forward_char_reader!();
+}
+
+impl<R: CharReader + ?Sized> CharReader for Box<R> {
+    forward_char_reader!();
+}
+
+/// `CharReader` adapter keeping characters decoded ahead of the current one in a ring buffer,
+/// so that repeated `peek_char()` with lookahead and `peek_str()` are cheap regardless of
+/// the underlying reader.
+#[derive(Debug)]
+pub struct Lookahead<R: CharReader> {
+    inner: R,
+    /// Current character and the ones following it, with positions. When not empty, the last
+    /// character is the current one of `inner`.
+    buf: VecDeque<(char, Position)>,
+}
+
+impl<R: CharReader> Lookahead<R> {
+    pub fn new(inner: R) -> Lookahead<R> {
+        Self::with_capacity(inner, 8)
+    }
+
+    /// Creates adapter expecting lookahead of at most `capacity` characters. The buffer grows
+    /// if more are peeked.
+    pub fn with_capacity(inner: R, capacity: usize) -> Lookahead<R> {
+        Lookahead {
+            inner,
+            buf: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the underlying reader, positioned at the current character.
+    pub fn into_inner(mut self) -> IoResult<R> {
+        self.sync()?;
+        Ok(self.inner)
+    }
+
+    /// Decodes characters until `lookahead` is buffered or end of input is reached.
+    fn fill(&mut self, lookahead: usize) -> IoResult<()> {
+        if self.buf.is_empty() {
+            match self.inner.peek_char_pos(0)? {
+                Some(cp) => self.buf.push_back(cp),
+                None => return Ok(()),
+            }
+        }
+        while self.buf.len() <= lookahead {
+            match self.inner.next_char()? {
+                Some(c) => self.buf.push_back((c, self.inner.position())),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves `inner` back to the current character, emptying the buffer.
+    fn sync(&mut self) -> IoResult<()> {
+        if self.buf.len() > 1 {
+            let (c, pos) = self.buf[0];
+            self.inner.rollback(Mark::with_char(pos, c, c.len_utf8()))?;
+        }
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<R: CharReader> Reader for Lookahead<R> {
+    fn path(&self) -> Option<&Path> {
+        self.inner.path()
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.inner.len()
+    }
+
+    fn eof(&self) -> bool {
+        self.buf.is_empty() && self.inner.eof()
+    }
+
+    fn position(&self) -> Position {
+        match self.buf.front() {
+            Some(&(_, pos)) => pos,
+            None => self.inner.position(),
+        }
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        self.buf.clear();
+        self.inner.seek(pos)
+    }
+
+    fn mark(&mut self) -> Mark {
+        match self.buf.front() {
+            Some(&(c, pos)) => Mark::with_char(pos, c, c.len_utf8()),
+            None => self.inner.mark(),
+        }
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        self.buf.clear();
+        self.inner.rollback(mark)
+    }
+
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        self.inner.input()
+    }
+
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        self.inner.slice(start, end)
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        self.inner.quote(from, to, lines_before, lines_after, message)
+    }
+}
+
+impl<R: CharReader> CharReader for Lookahead<R> {
+    fn next_char(&mut self) -> IoResult<Option<char>> {
+        match self.buf.len() {
+            0 => self.inner.next_char(),
+            1 => {
+                self.buf.clear();
+                self.inner.next_char()
+            }
+            _ => {
+                self.buf.pop_front();
+                Ok(self.buf.front().map(|&(c, _)| c))
+            }
+        }
+    }
+
+    fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        Ok(self.peek_char_pos(lookahead)?.map(|(c, _)| c))
+    }
+
+    fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        self.fill(lookahead)?;
+        Ok(self.buf.get(lookahead).cloned())
+    }
+
+    fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_char()?;
+        }
+        Ok(())
+    }
+
+    fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        for (i, c) in s.chars().enumerate() {
+            if self.peek_char(i)? != Some(c) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
 
-    fn match_char(&mut self, c: char) -> IoResult<bool> {
-        if let Some(k) = self.peek_char(0)? {
-            Ok(c == k)
+    fn match_str_term(&mut self, s: &str, f: &mut dyn FnMut(Option<char>) -> bool) -> IoResult<bool> {
+        if self.match_str(s)? {
+            let n = s.chars().count();
+            Ok(f(self.peek_char(n)?))
         } else {
             Ok(false)
         }
     }
 
-    #[inline]
-    fn skip_whitespace(&mut self) -> IoResult<()> {
-        while let Some(c) = self.peek_char(0)? {
-            if c.is_whitespace() {
-                self.next_char()?;
-            } else {
-                break;
-            }
+    fn peek_str(&mut self, n: usize) -> IoResult<Cow<str>> {
+        if n > 0 {
+            self.fill(n - 1)?;
         }
-        Ok(())
+        Ok(Cow::Owned(self.buf.iter().take(n).map(|&(c, _)| c).collect()))
     }
+}
 
-    #[inline]
-    fn skip_whitespace_nonl(&mut self) -> IoResult<()> {
-        while let Some(c) = self.peek_char(0)? {
-            if c.is_whitespace() && c != '\n' {
-                self.next_char()?;
-            } else {
-                break;
-            }
-        }
-        Ok(())
+/// State of `MemCharReader` not tied to its input, taken with `snapshot()` to continue reading
+/// with `MemCharReader::resume()` after the input buffer grows.
+#[derive(Debug, Clone)]
+pub struct ReaderSnapshot {
+    mark: Mark,
+    line_breaks: LineBreakPolicy,
+    positions: PositionConfig,
+    saved: Vec<Mark>,
+}
+
+impl ReaderSnapshot {
+    pub fn position(&self) -> Position {
+        self.mark.position()
     }
+}
+
+/// Counters of work done by a reader, see `MemCharReader::metrics()`. Unlike global counters
+/// of `stats` feature, these are kept per reader, for profiling parsers' use of their reader.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ReaderMetrics {
+    pub chars_decoded: u64,
+    /// Number of bytes the reader advanced over. Bytes read again after seeking back are
+    /// counted again.
+    pub bytes_consumed: u64,
+    pub peeks: u64,
+    /// Largest lookahead passed to `peek_char()` or `peek_char_pos()`.
+    pub max_lookahead: usize,
+}
+
+/// What `MemCharReader::pop_pos()` does with the popped position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PopMode {
+    /// Stay at the current position, e.g. after a successful speculative parse.
+    Discard,
+    /// Move back to the popped position.
+    Restore,
+}
+
+/// Determines which characters, besides `'\n'`, increment `Position::line`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LineBreakPolicy {
+    /// Lone carriage return (`"\r\n"` is still a single line break).
+    pub cr: bool,
+    /// Unicode line separator (U+2028) and paragraph separator (U+2029).
+    pub unicode: bool,
+    /// Form feed (U+000C).
+    pub form_feed: bool,
+}
 
+impl LineBreakPolicy {
+    pub const LF: LineBreakPolicy = LineBreakPolicy {
+        cr: false,
+        unicode: false,
+        form_feed: false,
+    };
+
+    pub const LF_CR: LineBreakPolicy = LineBreakPolicy {
+        cr: true,
+        unicode: false,
+        form_feed: false,
+    };
+
+    pub const ALL: LineBreakPolicy = LineBreakPolicy {
+        cr: true,
+        unicode: true,
+        form_feed: true,
+    };
+
+    /// Checks if `c` followed by byte `next` ends a line.
     #[inline]
-    fn scan(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<Cow<str>> {
-        let s = self.position().offset;
-        while let Some(c) = self.peek_char(0)? {
-            if f(c) {
-                self.next_char()?;
-            } else {
-                break;
-            }
+    pub fn is_line_break(&self, c: char, next: Option<u8>) -> bool {
+        match c {
+            '\n' => true,
+            '\r' => self.cr && next != Some(b'\n'),
+            '\u{000C}' => self.form_feed,
+            '\u{2028}' | '\u{2029}' => self.unicode,
+            _ => false,
         }
-        let offset = self.position().offset;
-        self.slice(s, offset)
     }
 
     #[inline]
-    fn skip_until(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<()> {
-        while let Some(c) = self.peek_char(0)? {
-            if f(c) {
-                break;
-            } else {
-                self.next_char()?;
+    fn may_break_ascii(&self, b: u8) -> bool {
+        b == b'\n' || (self.cr && b == b'\r') || (self.form_feed && b == 0x0C)
+    }
+}
+
+impl Default for LineBreakPolicy {
+    fn default() -> LineBreakPolicy {
+        LineBreakPolicy::LF
+    }
+}
+
+/// How characters advance `Position::column`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColumnMode {
+    /// Every character takes one column.
+    Chars,
+    /// Zero-width characters (like combining marks) share column with the preceding character,
+    /// approximating grapheme clusters. Without `unicode-width` feature same as `Chars`.
+    Graphemes,
+    /// Columns as displayed by terminals and editors, with wide (e.g. CJK) characters taking
+    /// two columns. Without `unicode-width` feature (enabled by default) same as `Chars`.
+    DisplayWidth,
+    /// UTF-16 code units, with characters outside of the Basic Multilingual Plane taking two
+    /// columns. Matches positions used by the Language Server Protocol.
+    Utf16,
+}
+
+/// Determines how positions advance within a line, so that reported columns can match
+/// what editors show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PositionConfig {
+    /// Tab characters advance column to the next multiple of `tab_width`, if set.
+    pub tab_width: Option<u32>,
+    /// Carriage return of `"\r\n"` takes no column, i.e. the whole sequence is treated
+    /// as a single line break.
+    pub crlf: bool,
+    pub columns: ColumnMode,
+}
+
+impl PositionConfig {
+    /// Every character is one column wide.
+    pub const CHARS: PositionConfig = PositionConfig {
+        tab_width: None,
+        crlf: false,
+        columns: ColumnMode::Chars,
+    };
+
+    /// Number of columns taken by character `c`, starting at `column` and followed by
+    /// byte `next`.
+    pub fn column_width(&self, c: char, column: u32, next: Option<u8>) -> u32 {
+        match c {
+            '\t' if self.tab_width.is_some() => {
+                let w = self.tab_width.unwrap().max(1);
+                w - column % w
             }
+            '\r' if self.crlf && next == Some(b'\n') => 0,
+            _ => match self.columns {
+                ColumnMode::Chars => 1,
+                ColumnMode::Graphemes => match char_width(c) {
+                    Some(0) => 0,
+                    _ => 1,
+                },
+                ColumnMode::DisplayWidth => char_width(c).unwrap_or(1) as u32,
+                ColumnMode::Utf16 => c.len_utf16() as u32,
+            },
         }
-        Ok(())
     }
 
+    /// Checks if ASCII byte `b` may take other than one column.
     #[inline]
-    fn skip_while(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<()> {
-        while let Some(c) = self.peek_char(0)? {
-            if f(c) {
-                self.next_char()?;
-            } else {
-                break;
-            }
-        }
-        Ok(())
+    fn may_widen_ascii(&self, b: u8) -> bool {
+        (self.tab_width.is_some() && b == b'\t')
+            || (self.crlf && b == b'\r')
+            || (self.columns != ColumnMode::Chars && self.columns != ColumnMode::Utf16 && b < 0x20)
+    }
+}
+
+impl Default for PositionConfig {
+    fn default() -> PositionConfig {
+        PositionConfig::CHARS
     }
 }
 
+/// Strips a single byte order mark, either UTF-8 encoded or with its bytes mistakenly encoded
+/// as separate characters.
 fn consume_bom(input: &[u8]) -> &[u8] {
     let mut input= input;
-    if input.len() >= 6 {
+    if input.starts_with("\u{FEFF}".as_bytes()) {
+        input = &input[3..];
+    } else if input.len() >= 6 {
         if &input[..6] == "\u{EF}\u{BB}\u{BF}".as_bytes() {
             input = &input[6..input.len()];
         }
@@ -150,6 +1174,19 @@ pub struct MemCharReader<'a> {
     pos: Position,
     c: char,
     len: usize,
+    line_breaks: LineBreakPolicy,
+    positions: PositionConfig,
+    /// Built on first quote and shared by clones.
+    line_map: Option<Arc<LineMap>>,
+    /// Characters following the current one (with their positions and lengths), decoded by
+    /// `peek_char()` with lookahead. Only valid while the current character is decoded.
+    ahead: VecDeque<(char, Position, usize)>,
+    /// Positions saved with `push_pos()`.
+    saved: Vec<Mark>,
+    /// Input as string, if the reader was created with `from_str()`.
+    text: Option<&'a str>,
+    #[cfg(feature = "metrics")]
+    metrics: ReaderMetrics,
 }
 
 impl<'a> MemCharReader<'a> {
@@ -161,6 +1198,14 @@ impl<'a> MemCharReader<'a> {
             pos: Position::new(),
             c: '\0',
             len: 0,
+            line_breaks: LineBreakPolicy::LF,
+            positions: PositionConfig::CHARS,
+            line_map: None,
+            ahead: VecDeque::new(),
+            saved: Vec::new(),
+            text: None,
+            #[cfg(feature = "metrics")]
+            metrics: ReaderMetrics::default(),
         }
     }
 
@@ -175,6 +1220,306 @@ impl<'a> MemCharReader<'a> {
             pos: Position::new(),
             c: '\0',
             len: 0,
+            line_breaks: LineBreakPolicy::LF,
+            positions: PositionConfig::CHARS,
+            line_map: None,
+            ahead: VecDeque::new(),
+            saved: Vec::new(),
+            text: None,
+            #[cfg(feature = "metrics")]
+            metrics: ReaderMetrics::default(),
+        }
+    }
+
+    /// Reader of string `input`. Since input is known to be valid UTF-8, slicing never needs
+    /// to validate it (see `slice_str()`).
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(input: &'a str) -> MemCharReader<'a> {
+        let r = MemCharReader::new(input.as_bytes());
+        // BOM is stripped by `new()`, which only removes whole characters
+        let text = &input[input.len() - r.data.len()..];
+        MemCharReader {
+            text: Some(text),
+            ..r
+        }
+    }
+
+    pub fn with_line_break_policy(mut self, policy: LineBreakPolicy) -> MemCharReader<'a> {
+        self.set_line_break_policy(policy);
+        self
+    }
+
+    pub fn line_break_policy(&self) -> LineBreakPolicy {
+        self.line_breaks
+    }
+
+    pub fn set_line_break_policy(&mut self, policy: LineBreakPolicy) {
+        self.line_breaks = policy;
+        self.ahead.clear();
+        self.line_map = None;
+    }
+
+    /// Line map of the input, built for the current line break policy on first use.
+    fn line_map(&mut self) -> Arc<LineMap> {
+        let (data, policy) = (self.data, self.line_breaks);
+        self.line_map
+            .get_or_insert_with(|| Arc::new(LineMap::with_line_break_policy(data, policy)))
+            .clone()
+    }
+
+    pub fn with_position_config(mut self, config: PositionConfig) -> MemCharReader<'a> {
+        self.set_position_config(config);
+        self
+    }
+
+    pub fn position_config(&self) -> PositionConfig {
+        self.positions
+    }
+
+    pub fn set_position_config(&mut self, config: PositionConfig) {
+        self.positions = config;
+        self.ahead.clear();
+    }
+
+    /// Saves the current position on a stack, to be popped with `pop_pos()`. Allows nested
+    /// speculative parsing without passing saved positions around.
+    pub fn push_pos(&mut self) {
+        let mark = Reader::mark(self);
+        self.saved.push(mark);
+    }
+
+    /// Pops position saved with `push_pos()`, moving back to it if `mode` is
+    /// `PopMode::Restore`. Returns the popped position.
+    ///
+    /// # Panics
+    ///
+    /// If there is no saved position.
+    pub fn pop_pos(&mut self, mode: PopMode) -> IoResult<Position> {
+        let mark = self.saved.pop().expect("pop_pos() called without matching push_pos()");
+        let pos = mark.position();
+        if mode == PopMode::Restore {
+            Reader::rollback(self, mark)?;
+        }
+        Ok(pos)
+    }
+
+    /// Number of positions saved with `push_pos()` and not popped yet.
+    pub fn saved_pos_count(&self) -> usize {
+        self.saved.len()
+    }
+
+    /// Saves state of the reader (position, configuration and positions saved with
+    /// `push_pos()`), to be restored by `resume()`.
+    pub fn snapshot(&self) -> ReaderSnapshot {
+        ReaderSnapshot {
+            mark: Mark::with_char(self.pos, self.c, self.len),
+            line_breaks: self.line_breaks,
+            positions: self.positions,
+            saved: self.saved.clone(),
+        }
+    }
+
+    /// Reader over `input` continuing from `snapshot` taken from a reader over a prefix of
+    /// `input`. Readers borrow their input, so a reader cannot be kept while its buffer grows
+    /// (e.g. in a REPL, or as network chunks arrive); instead, a snapshot is taken when more
+    /// input is needed (see `IoErrorDetail::needs_more_input()`) and reading is resumed over
+    /// the extended buffer. Marks and positions taken before remain valid.
+    ///
+    /// Path is not kept by snapshots, see `resume_with_path()`.
+    pub fn resume(input: &'a [u8], snapshot: ReaderSnapshot) -> MemCharReader<'a> {
+        MemCharReader::new(input).resumed(snapshot)
+    }
+
+    /// Like `resume()`, with `path` of the input.
+    pub fn resume_with_path<P: AsRef<Path> + ?Sized + 'a>(
+        path: &'a P,
+        input: &'a [u8],
+        snapshot: ReaderSnapshot,
+    ) -> MemCharReader<'a> {
+        MemCharReader::with_path(path, input).resumed(snapshot)
+    }
+
+    fn resumed(mut self, snapshot: ReaderSnapshot) -> MemCharReader<'a> {
+        self.line_breaks = snapshot.line_breaks;
+        self.positions = snapshot.positions;
+        self.saved = snapshot.saved;
+        self.pos = snapshot.mark.pos;
+        self.c = snapshot.mark.c;
+        self.len = snapshot.mark.len;
+        self
+    }
+
+    /// Counters of this reader's work, accumulated since creation or `reset_metrics()`.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &ReaderMetrics {
+        &self.metrics
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset_metrics(&mut self) {
+        self.metrics = ReaderMetrics::default();
+    }
+
+    #[inline(always)]
+    fn record_peek(&mut self, _lookahead: usize) {
+        stats::record_peek();
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.peeks += 1;
+            self.metrics.max_lookahead = self.metrics.max_lookahead.max(_lookahead);
+        }
+    }
+
+    /// Records advancing from `offset` to the current position.
+    #[inline(always)]
+    fn record_advance(&mut self, _offset: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.bytes_consumed += self.pos.offset.saturating_sub(_offset) as u64;
+        }
+    }
+
+    /// Whole input, without byte order mark.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Input from the current character on.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos.offset.min(self.data.len())..]
+    }
+
+    /// Like `remaining()`, validated as UTF-8.
+    pub fn rest_str(&self) -> IoResult<&'a str> {
+        let offset = self.pos.offset.min(self.data.len());
+        self.slice_str(offset, self.data.len())
+    }
+
+    /// Slice of input between byte offsets `start` and `end`. Unlike `Reader::slice()`, never
+    /// panics nor assumes validity of input: for readers created with `from_str()` offsets are
+    /// checked to be at character boundaries, otherwise the slice is validated as UTF-8.
+    pub fn slice_str(&self, start: usize, end: usize) -> IoResult<&'a str> {
+        if let Some(text) = self.text {
+            return match text.get(start..end) {
+                Some(s) => Ok(s),
+                None => Err(IoErrorDetail::InvalidOffset {
+                    offset: if text.is_char_boundary(start.min(text.len())) { end } else { start },
+                    len: text.len(),
+                }),
+            };
+        }
+        let data = match self.data.get(start..end) {
+            Some(data) => data,
+            None => {
+                return Err(IoErrorDetail::InvalidOffset {
+                    offset: start.max(end),
+                    len: self.data.len(),
+                })
+            }
+        };
+        std::str::from_utf8(data).map_err(|err| match err.error_len() {
+            Some(len) => IoErrorDetail::Utf8InvalidEncoding {
+                offset: start + err.valid_up_to(),
+                len,
+            },
+            None => IoErrorDetail::Utf8UnexpectedEof {
+                offset: start + err.valid_up_to(),
+            },
+        })
+    }
+
+    /// Moves to byte `offset`, computing its line and column. Fails if `offset` is past the
+    /// end of input or not at a character boundary.
+    ///
+    /// Lines are found with a `LineMap` (shared with quotes), built for the line break policy
+    /// of this reader.
+    pub fn seek_offset(&mut self, offset: usize) -> IoResult<()> {
+        let boundary = match self.data.get(offset) {
+            Some(&b) => b & 0b11000000u8 != 0b10000000u8,
+            None => offset == self.data.len(),
+        };
+        if !boundary {
+            return Err(IoErrorDetail::InvalidOffset {
+                offset,
+                len: self.data.len(),
+            });
+        }
+        let line_map = self.line_map();
+        if self.positions == PositionConfig::CHARS {
+            let pos = line_map.offset_to_position(self.data, offset);
+            return self.seek(pos);
+        }
+        let line = line_map.line_of(offset);
+        let start = Position::with(line_map.line_span(line).unwrap().start, line, 0);
+        self.seek(start)?;
+        self.advance_to(offset)
+    }
+
+    /// Moves back to the character preceding the current one and returns it, or `None` at
+    /// the start of input. Only the current line is decoded again to find the column.
+    pub fn prev_char(&mut self) -> IoResult<Option<char>> {
+        let (c, start) = match self.decode_prev(self.pos.offset)? {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        let mut line = self.pos.line;
+        if self.line_breaks.is_line_break(c, self.data.get(self.pos.offset).cloned()) {
+            line = line.saturating_sub(1);
+        }
+        let line_start = self.line_start(start)?;
+        self.pos = Position::with(line_start, line, 0);
+        self.c = '\0';
+        self.len = 0;
+        self.advance_to(start)?;
+        self.next()?;
+        Ok(Some(self.c))
+    }
+
+    /// Returns the character preceding the current one, without moving.
+    pub fn peek_prev_char(&self) -> IoResult<Option<char>> {
+        Ok(self.decode_prev(self.pos.offset)?.map(|(c, _)| c))
+    }
+
+    /// Decodes character ending at `offset`, returned along with its starting offset.
+    fn decode_prev(&self, offset: usize) -> IoResult<Option<(char, usize)>> {
+        if offset == 0 {
+            return Ok(None);
+        }
+        let mut start = offset - 1;
+        while start > 0 && offset - start < 4 && self.data[start] & 0b11000000u8 == 0b10000000u8 {
+            start -= 1;
+        }
+        match std::str::from_utf8(&self.data[start..offset]) {
+            Ok(s) => Ok(s.chars().next().map(|c| (c, start))),
+            Err(_) => Err(IoErrorDetail::Utf8InvalidEncoding {
+                offset: start,
+                len: offset - start,
+            }),
+        }
+    }
+
+    /// Offset of the start of line containing character at `offset`.
+    fn line_start(&self, mut offset: usize) -> IoResult<usize> {
+        while let Some((c, start)) = self.decode_prev(offset)? {
+            if self.line_breaks.is_line_break(c, self.data.get(offset).cloned()) {
+                break;
+            }
+            offset = start;
+        }
+        Ok(offset)
+    }
+
+    /// Returns a warning quoting the end of input if the last line is not terminated
+    /// with a line break. Empty input is not reported.
+    pub fn check_final_newline(&self) -> IoResult<Option<ParseDiag>> {
+        match self.decode_prev(self.data.len())? {
+            Some((c, _)) if !self.line_breaks.is_line_break(c, None) => {
+                let mut r = self.clone();
+                r.seek_offset(self.data.len())?;
+                let detail = IoErrorDetail::MissingFinalNewline { pos: r.position() };
+                Ok(Some(ParseDiag::with_span_quote(detail, &mut r)))
+            }
+            _ => Ok(None),
         }
     }
 
@@ -191,13 +1536,106 @@ impl<'a> MemCharReader<'a> {
         })
     }
 
+    /// Moves to character starting at `offset`, leaving it not decoded yet (like `seek()`).
+    fn advance_to(&mut self, offset: usize) -> IoResult<()> {
+        fn count_chars(bytes: &[u8]) -> u32 {
+            bytes.iter().filter(|&&b| b & 0b11000000u8 != 0b10000000u8).count() as u32
+        }
+
+        let start = self.pos.offset;
+        if self.line_breaks == LineBreakPolicy::LF && self.positions == PositionConfig::CHARS {
+            // lines and columns can be counted directly on bytes
+            let bytes = &self.data[self.pos.offset..offset];
+            match memchr::memrchr(b'\n', bytes) {
+                Some(last) => {
+                    self.pos.line += memchr::memchr_iter(b'\n', bytes).count() as u32;
+                    self.pos.column = count_chars(&bytes[last + 1..]);
+                }
+                None => self.pos.column += count_chars(bytes),
+            }
+            self.pos.offset = offset;
+        } else {
+            if self.len == 0 {
+                self.next()?;
+            }
+            while self.pos.offset < offset {
+                self.next()?;
+            }
+        }
+        self.c = '\0';
+        self.len = 0;
+        self.ahead.clear();
+        self.record_advance(start);
+        Ok(())
+    }
+
+    /// Skips characters accepted by `f`. Runs of ASCII characters are tested directly on input
+    /// bytes and skipped at once, only other characters are decoded.
+    fn skip_decoded_while(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<()> {
+        loop {
+            let start = self.pos.offset;
+            let n = self.data[start..]
+                .iter()
+                .take_while(|&&b| b < 0b10000000u8 && f(b as char))
+                .count();
+            if n > 0 {
+                self.advance_to(start + n)?;
+            }
+            match self.peek_char(0)? {
+                Some(c) if !c.is_ascii() && f(c) => {
+                    self.next_char()?;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Decodes characters ahead of the current one, until `lookahead` of them are cached.
+    /// Returns `false` if input ends before that.
+    fn decode_ahead(&mut self, lookahead: usize) -> IoResult<bool> {
+        if self.len == 0 {
+            self.next()?;
+            if self.len == 0 {
+                return Ok(false);
+            }
+        }
+        if self.ahead.len() >= lookahead {
+            return Ok(true);
+        }
+        // decoding continues from the last cached character, current one is restored after
+        let current = (self.pos, self.c, self.len);
+        if let Some(&(c, pos, len)) = self.ahead.back() {
+            self.pos = pos;
+            self.c = c;
+            self.len = len;
+        }
+        let mut res = Ok(true);
+        while self.ahead.len() < lookahead {
+            if let Err(err) = self.next() {
+                res = Err(err);
+                break;
+            }
+            if self.len == 0 {
+                res = Ok(false);
+                break;
+            }
+            self.ahead.push_back((self.c, self.pos, self.len));
+        }
+        let (pos, c, len) = current;
+        self.pos = pos;
+        self.c = c;
+        self.len = len;
+        res
+    }
+
     fn next(&mut self) -> IoResult<()> {
         if self.len > 0 {
             self.pos.offset += self.len;
-            if self.c == '\n' {
+            let next = self.data.get(self.pos.offset).cloned();
+            if self.line_breaks.is_line_break(self.c, next) {
                 self.pos.inc_line();
             } else {
-                self.pos.inc_column();
+                self.pos.column += self.positions.column_width(self.c, self.pos.column, next);
             }
             self.len = 0;
         }
@@ -209,13 +1647,18 @@ impl<'a> MemCharReader<'a> {
                 return Ok(());
             }
             let b = *self.data.get_unchecked(i);
+            stats::record_char_decoded();
+            #[cfg(feature = "metrics")]
+            {
+                self.metrics.chars_decoded += 1;
+            }
             if b < 0b10000000u8 {
                 self.len = 1;
                 self.c = char::from_u32_unchecked(b as u32);
             } else if b < 0b11000000u8 {
                 return self.encoding_err(1);
             } else if b < 0b11100000u8 {
-                if len < i + 1 {
+                if len < i + 2 {
                     return self.eof_err();
                 }
                 self.len = 2;
@@ -224,7 +1667,7 @@ impl<'a> MemCharReader<'a> {
                     ((b & 0b00011111u8) as u32).wrapping_shl(6) + (b1 & 0b00111111u8) as u32,
                 );
             } else if b < 0b11110000u8 {
-                if len < i + 2 {
+                if len < i + 3 {
                     return self.eof_err();
                 }
                 self.len = 3;
@@ -236,7 +1679,7 @@ impl<'a> MemCharReader<'a> {
                         + (b2 & 0b00111111u8) as u32,
                 );
             } else if b <= 0b11110100u8 {
-                if len < i + 3 {
+                if len < i + 4 {
                     return self.eof_err();
                 }
                 self.len = 4;
@@ -275,15 +1718,33 @@ impl<'a> Reader for MemCharReader<'a> {
     }
 
     fn seek(&mut self, pos: Position) -> IoResult<()> {
+        stats::record_seek();
         self.pos = pos;
         self.c = '\0';
         self.len = 0;
+        self.ahead.clear();
         Ok(())
     }
 
-    /// will panic in debug if slice is not a valid utf8
+    fn mark(&mut self) -> Mark {
+        Mark::with_char(self.pos, self.c, self.len)
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        stats::record_seek();
+        self.pos = mark.pos;
+        self.c = mark.c;
+        self.len = mark.len;
+        self.ahead.clear();
+        Ok(())
+    }
+
+    /// will panic in debug if slice is not a valid utf8 (unless created with `from_str()`)
     #[cfg(debug_assertions)]
     fn input(&mut self) -> IoResult<Cow<str>> {
+        if let Some(text) = self.text {
+            return Ok(Cow::Borrowed(text));
+        }
         Ok(Cow::Borrowed(
             std::str::from_utf8(&self.data).expect("input must be a valid utf8"),
         ))
@@ -291,14 +1752,20 @@ impl<'a> Reader for MemCharReader<'a> {
 
     #[cfg(not(debug_assertions))]
     fn input(&mut self) -> IoResult<Cow<str>> {
+        if let Some(text) = self.text {
+            return Ok(Cow::Borrowed(text));
+        }
         Ok(Cow::Borrowed(unsafe {
             std::str::from_utf8_unchecked(&self.data)
         }))
     }
 
-    /// will panic in debug if slice is not a valid utf8
+    /// will panic in debug if slice is not a valid utf8 (unless created with `from_str()`)
     #[cfg(debug_assertions)]
     fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        if self.text.is_some() {
+            return self.slice_str(start, end).map(Cow::Borrowed);
+        }
         Ok(Cow::Borrowed(
             std::str::from_utf8(&self.data[start..end]).expect("slice must be a valid utf8"),
         ))
@@ -306,6 +1773,9 @@ impl<'a> Reader for MemCharReader<'a> {
 
     #[cfg(not(debug_assertions))]
     fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        if self.text.is_some() {
+            return self.slice_str(start, end).map(Cow::Borrowed);
+        }
         Ok(Cow::Borrowed(unsafe {
             std::str::from_utf8_unchecked(&self.data[start..end])
         }))
@@ -319,9 +1789,11 @@ impl<'a> Reader for MemCharReader<'a> {
         lines_after: u32,
         message: Cow<str>,
     ) -> Quote {
-        Quote::new(
+        let line_map = self.line_map();
+        Quote::with_line_map(
             self.path,
             self.data,
+            &line_map,
             from,
             to,
             lines_before,
@@ -333,7 +1805,16 @@ impl<'a> Reader for MemCharReader<'a> {
 
 impl<'a> CharReader for MemCharReader<'a> {
     fn next_char(&mut self) -> IoResult<Option<char>> {
+        let start = self.pos.offset;
+        if let Some((c, pos, len)) = self.ahead.pop_front() {
+            self.pos = pos;
+            self.c = c;
+            self.len = len;
+            self.record_advance(start);
+            return Ok(Some(c));
+        }
         self.next()?;
+        self.record_advance(start);
         if self.len > 0 {
             Ok(Some(self.c))
         } else {
@@ -342,38 +1823,33 @@ impl<'a> CharReader for MemCharReader<'a> {
     }
 
     fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        self.record_peek(lookahead);
         if lookahead == 0 {
             if self.len == 0 {
                 self.next_char()
             } else {
                 Ok(Some(self.c))
             }
+        } else if self.decode_ahead(lookahead)? {
+            Ok(Some(self.ahead[lookahead - 1].0))
         } else {
-            let mut r = self.clone();
-            for _ in 0..lookahead {
-                if let None = r.next_char()? {
-                    return Ok(None);
-                }
-            }
-            Ok(Some(r.c))
+            Ok(None)
         }
     }
 
     fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        self.record_peek(lookahead);
         if lookahead == 0 {
             if self.len == 0 {
                 self.next_char().map(|c| c.map(|c| (c, self.position())))
             } else {
                 return Ok(Some((self.c, self.pos)));
             }
+        } else if self.decode_ahead(lookahead)? {
+            let (c, pos, _) = self.ahead[lookahead - 1];
+            Ok(Some((c, pos)))
         } else {
-            let mut r = self.clone();
-            for _ in 0..lookahead {
-                if let None = r.next_char()? {
-                    return Ok(None);
-                }
-            }
-            Ok(Some((r.c, r.pos)))
+            Ok(None)
         }
     }
 
@@ -406,6 +1882,84 @@ impl<'a> CharReader for MemCharReader<'a> {
             Ok(false)
         }
     }
+
+    fn skip_ascii_while(&mut self, f: &mut dyn FnMut(u8) -> bool) -> IoResult<usize> {
+        let line_breaks = self.line_breaks;
+        let positions = self.positions;
+        let n = self.data[self.pos.offset..]
+            .iter()
+            .take_while(|&&b| {
+                b < 0b10000000u8
+                    && !line_breaks.may_break_ascii(b)
+                    && !positions.may_widen_ascii(b)
+                    && f(b)
+            })
+            .count();
+        if n > 0 {
+            self.pos.offset += n;
+            self.pos.column += n as u32;
+            self.c = '\0';
+            self.len = 0;
+            self.ahead.clear();
+            self.record_advance(self.pos.offset - n);
+        }
+        Ok(n)
+    }
+
+    fn skip_whitespace(&mut self) -> IoResult<()> {
+        self.skip_decoded_while(&mut |c| c.is_whitespace())
+    }
+
+    fn skip_whitespace_nonl(&mut self) -> IoResult<()> {
+        self.skip_decoded_while(&mut |c| c.is_whitespace() && c != '\n')
+    }
+
+    fn skip_until(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<()> {
+        self.skip_decoded_while(&mut |c| !f(c))
+    }
+
+    fn skip_while(&mut self, f: &mut dyn FnMut(char) -> bool) -> IoResult<()> {
+        self.skip_decoded_while(f)
+    }
+
+    /// Borrows characters from input data, without decoding them one by one.
+    fn peek_str(&mut self, n: usize) -> IoResult<Cow<str>> {
+        let start = self.pos.offset;
+        let rest = &self.data[start..];
+        let len = rest
+            .iter()
+            .enumerate()
+            .filter(|&(_, &b)| b & 0b11000000u8 != 0b10000000u8)
+            .nth(n)
+            .map_or(rest.len(), |(i, _)| i);
+        match std::str::from_utf8(&rest[..len]) {
+            Ok(s) => Ok(Cow::Borrowed(s)),
+            Err(err) => match err.error_len() {
+                Some(len) => Err(IoErrorDetail::Utf8InvalidEncoding {
+                    offset: start + err.valid_up_to(),
+                    len,
+                }),
+                None => Err(IoErrorDetail::Utf8UnexpectedEof {
+                    offset: start + err.valid_up_to(),
+                }),
+            },
+        }
+    }
+
+    /// Searches input bytes with `memchr`. Skipped input is not validated as UTF-8.
+    fn skip_until_str(&mut self, s: &str) -> IoResult<Option<Position>> {
+        let start = self.pos.offset;
+        match memchr::memmem::find(&self.data[start..], s.as_bytes()) {
+            Some(i) => {
+                self.advance_to(start + i)?;
+                Ok(Some(self.pos))
+            }
+            None => {
+                self.advance_to(self.data.len())?;
+                Ok(None)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -414,6 +1968,7 @@ pub struct MemByteReader<'a> {
     data: &'a [u8],
     pos: Position,
     left: usize,
+    positions: PositionConfig,
 }
 
 impl<'a> MemByteReader<'a> {
@@ -423,6 +1978,7 @@ impl<'a> MemByteReader<'a> {
             data: input,
             pos: Position::new(),
             left: 0,
+            positions: PositionConfig::CHARS,
         }
     }
 
@@ -432,9 +1988,28 @@ impl<'a> MemByteReader<'a> {
             data: input,
             pos: Position::new(),
             left: 0,
+            positions: PositionConfig::CHARS,
         }
     }
 
+    pub fn with_position_config(mut self, config: PositionConfig) -> MemByteReader<'a> {
+        self.positions = config;
+        self
+    }
+
+    pub fn position_config(&self) -> PositionConfig {
+        self.positions
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Input from the current byte on.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos.offset.min(self.data.len())..]
+    }
+
     fn encoding_err<T>(&mut self, len: usize) -> IoResult<T> {
         Err(IoErrorDetail::Utf8InvalidEncoding {
             offset: self.pos.offset,
@@ -447,6 +2022,16 @@ impl<'a> MemByteReader<'a> {
             offset: self.pos.offset,
         })
     }
+
+    /// Advances column past character starting at `off` and `len` bytes long.
+    fn inc_column(&mut self, off: usize, len: usize) {
+        let next = self.data.get(off + len).cloned();
+        let c = match self.data.get(off..off + len).map(std::str::from_utf8) {
+            Some(Ok(s)) => s.chars().next().unwrap_or('\0'),
+            _ => '\u{FFFD}',
+        };
+        self.pos.column += self.positions.column_width(c, self.pos.column, next);
+    }
 }
 
 impl<'a> Reader for MemByteReader<'a> {
@@ -467,10 +2052,22 @@ impl<'a> Reader for MemByteReader<'a> {
     }
 
     fn seek(&mut self, pos: Position) -> IoResult<()> {
+        stats::record_seek();
         self.pos = pos;
         Ok(())
     }
 
+    fn mark(&mut self) -> Mark {
+        Mark::with_char(self.pos, '\0', self.left)
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        stats::record_seek();
+        self.pos = mark.pos;
+        self.left = mark.len;
+        Ok(())
+    }
+
     /// will panic in debug if slice is not a valid utf8
     #[cfg(debug_assertions)]
     fn input(&mut self) -> IoResult<Cow<str>> {
@@ -534,7 +2131,7 @@ impl<'a> ByteReader for MemByteReader<'a> {
                         self.pos.inc_line();
                     } else if b < 0b10000000 {
                         self.left = 0;
-                        self.pos.inc_column();
+                        self.inc_column(off, 1);
                     } else if b < 0b11000000 {
                         return self.encoding_err(1);
                     } else if b < 0b11100000 {
@@ -546,7 +2143,11 @@ impl<'a> ByteReader for MemByteReader<'a> {
                     } else {
                         return self.encoding_err(3);
                     }
-                } else if b >= 0b11000000 {
+                    // multi-byte characters take their columns at the leading byte
+                    if self.left > 0 {
+                        self.inc_column(off, self.left + 1);
+                    }
+                } else if b & 0b11000000 == 0b10000000 {
                     self.left -= 1;
                 } else {
                     return self.encoding_err(self.left);
@@ -595,6 +2196,344 @@ impl<'a> ByteReader for MemByteReader<'a> {
     }
 }
 
+/// Character reader streaming UTF-8 input from a file through a fixed-size buffer, for inputs
+/// too large to be loaded at once with `FileBuffer`.
+#[derive(Debug)]
+pub struct FileCharReader {
+    path: PathBuf,
+    file: File,
+    len: usize,
+    start: usize,
+    buf: Vec<u8>,
+    buf_offset: usize,
+    buf_size: usize,
+    pos: Position,
+    c: char,
+    clen: usize,
+    line_breaks: LineBreakPolicy,
+}
+
+impl FileCharReader {
+    pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+    pub fn open<P: AsRef<Path>>(path: P) -> IoResult<FileCharReader> {
+        Self::with_buffer_size(path, Self::DEFAULT_BUFFER_SIZE)
+    }
+
+    pub fn with_buffer_size<P: AsRef<Path>>(path: P, buf_size: usize) -> IoResult<FileCharReader> {
+        let path = path.as_ref();
+        let file = File::open(path).info(path, OpType::Read, FileType::File)?;
+        let len = file
+            .metadata()
+            .info(path, OpType::Read, FileType::File)?
+            .len() as usize;
+        let mut r = FileCharReader {
+            path: path.to_path_buf(),
+            file,
+            len,
+            start: 0,
+            buf: Vec::new(),
+            buf_offset: 0,
+            // at least one full utf-8 sequence must fit in the buffer
+            buf_size: buf_size.max(4),
+            pos: Position::new(),
+            c: '\0',
+            clen: 0,
+            line_breaks: LineBreakPolicy::LF,
+        };
+        r.fill(0, 3)?;
+        if r.buf.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            r.start = 3;
+            r.len -= 3;
+            r.buf.drain(..3);
+        }
+        Ok(r)
+    }
+
+    pub fn with_line_break_policy(mut self, policy: LineBreakPolicy) -> FileCharReader {
+        self.line_breaks = policy;
+        self
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buf_size
+    }
+
+    fn read_at(&mut self, offset: usize, len: usize) -> IoResult<Vec<u8>> {
+        let mut data = vec![0; len.min(self.len.saturating_sub(offset))];
+        self.file
+            .seek(SeekFrom::Start((self.start + offset) as u64))
+            .info(&self.path, OpType::Read, FileType::File)?;
+        self.file
+            .read_exact(&mut data)
+            .info(&self.path, OpType::Read, FileType::File)?;
+        Ok(data)
+    }
+
+    /// Makes sure that buffer contains bytes `offset..offset + len` (or up to the end of input).
+    fn fill(&mut self, offset: usize, len: usize) -> IoResult<()> {
+        let end = (offset + len).min(self.len);
+        if offset >= self.buf_offset && end <= self.buf_offset + self.buf.len() {
+            return Ok(());
+        }
+        // keep current position buffered if possible, as it is likely to be accessed again
+        let from = if offset >= self.pos.offset && end - self.pos.offset <= self.buf_size {
+            self.pos.offset
+        } else {
+            offset
+        };
+        self.buf = self.read_at(from, self.buf_size.max(end - from))?;
+        self.buf_offset = from;
+        Ok(())
+    }
+
+    fn byte_at(&mut self, offset: usize) -> IoResult<Option<u8>> {
+        if offset >= self.len {
+            return Ok(None);
+        }
+        self.fill(offset, 1)?;
+        Ok(Some(self.buf[offset - self.buf_offset]))
+    }
+
+    fn decode_at(&mut self, offset: usize) -> IoResult<Option<(char, usize)>> {
+        let b = match self.byte_at(offset)? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        stats::record_char_decoded();
+        let len = if b < 0b10000000u8 {
+            return Ok(Some((b as char, 1)));
+        } else if b < 0b11000000u8 {
+            return Err(IoErrorDetail::Utf8InvalidEncoding { offset, len: 1 });
+        } else if b < 0b11100000u8 {
+            2
+        } else if b < 0b11110000u8 {
+            3
+        } else if b <= 0b11110100u8 {
+            4
+        } else {
+            return Err(IoErrorDetail::Utf8InvalidEncoding { offset, len: 4 });
+        };
+        if offset + len > self.len {
+            return Err(IoErrorDetail::Utf8UnexpectedEof { offset });
+        }
+        self.fill(offset, len)?;
+        let i = offset - self.buf_offset;
+        match std::str::from_utf8(&self.buf[i..i + len]) {
+            Ok(s) => Ok(s.chars().next().map(|c| (c, len))),
+            Err(_) => Err(IoErrorDetail::Utf8InvalidEncoding { offset, len }),
+        }
+    }
+
+    /// Returns position following character `c` of length `len` found at `pos`.
+    fn step(&mut self, mut pos: Position, c: char, len: usize) -> IoResult<Position> {
+        pos.offset += len;
+        let next = if c == '\r' { self.byte_at(pos.offset)? } else { None };
+        if self.line_breaks.is_line_break(c, next) {
+            pos.inc_line();
+        } else {
+            pos.inc_column();
+        }
+        Ok(pos)
+    }
+
+    /// Quotes span `from..to` like `Reader::quote()`, reading only the quoted lines from the
+    /// file (in chunks of the buffer size).
+    pub fn try_quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> IoResult<Quote> {
+        let policy = self.line_breaks;
+        let chunk = self.buf_size;
+        let mut start = from.offset.min(self.len);
+        let mut end = to.offset.max(start).min(self.len);
+        let mut data = self.read_at(start, end - start)?;
+        // read back until the first quoted line is preceded by a line break
+        while start > 0 {
+            let map = LineMap::with_line_break_policy(&data, policy);
+            if map.line_of(from.offset.min(end) - start) > lines_before {
+                break;
+            }
+            let prev = start.saturating_sub(chunk);
+            let mut head = self.read_at(prev, start - prev)?;
+            head.extend_from_slice(&data);
+            data = head;
+            start = prev;
+        }
+        // read on until the last quoted line is followed by a line break
+        while end < self.len {
+            let map = LineMap::with_line_break_policy(&data, policy);
+            let last = map.line_count() as u32 - 1;
+            if last - map.line_of(to.offset.max(start).min(end) - start) > lines_after {
+                break;
+            }
+            let next = (end + chunk).min(self.len);
+            let tail = self.read_at(end, next - end)?;
+            data.extend_from_slice(&tail);
+            end = next;
+        }
+
+        let map = LineMap::with_line_break_policy(&data, policy);
+        let local = |p: Position| Position::with(p.offset.max(start).min(end) - start, p.line, p.column);
+        let (off1, off2, line) = map.frame(local(from), local(to), lines_before, lines_after);
+        let source: Arc<str> = map.frame_text(&data, off1, off2).into();
+        Ok(Quote::with_source(
+            Some(Arc::from(self.path.as_path())),
+            from,
+            to,
+            start + off1,
+            line,
+            source,
+            message,
+        ))
+    }
+
+    fn read_str(&mut self, start: usize, end: usize) -> IoResult<String> {
+        let data = self.read_at(start, end - start)?;
+        String::from_utf8(data).map_err(|err| IoErrorDetail::Utf8InvalidEncoding {
+            offset: start + err.utf8_error().valid_up_to(),
+            len: err.utf8_error().error_len().unwrap_or(0),
+        })
+    }
+}
+
+impl Reader for FileCharReader {
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.len)
+    }
+
+    fn eof(&self) -> bool {
+        self.pos.offset >= self.len
+    }
+
+    fn position(&self) -> Position {
+        self.pos
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        stats::record_seek();
+        self.pos = pos;
+        self.c = '\0';
+        self.clen = 0;
+        Ok(())
+    }
+
+    fn mark(&mut self) -> Mark {
+        Mark::with_char(self.pos, self.c, self.clen)
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        stats::record_seek();
+        self.pos = mark.pos;
+        self.c = mark.c;
+        self.clen = mark.len;
+        Ok(())
+    }
+
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        let len = self.len;
+        self.read_str(0, len).map(Cow::Owned)
+    }
+
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        self.read_str(start, end).map(Cow::Owned)
+    }
+
+    /// See `FileCharReader::try_quote()`. If the file cannot be read, returns a deferred quote
+    /// (see `Quote::deferred()`), which reports the error when rendered.
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        match self.try_quote(from, to, lines_before, lines_after, message.clone()) {
+            Ok(q) => q,
+            Err(_) => {
+                let mut q = Quote::deferred(&self.path, Span::with_pos(from, to), message);
+                q.deferred = Some((lines_before, lines_after));
+                q
+            }
+        }
+    }
+}
+
+impl CharReader for FileCharReader {
+    fn next_char(&mut self) -> IoResult<Option<char>> {
+        if self.clen > 0 {
+            self.pos = self.step(self.pos, self.c, self.clen)?;
+            self.clen = 0;
+        }
+        match self.decode_at(self.pos.offset)? {
+            Some((c, len)) => {
+                self.c = c;
+                self.clen = len;
+                Ok(Some(c))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        Ok(self.peek_char_pos(lookahead)?.map(|(c, _)| c))
+    }
+
+    fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        stats::record_peek();
+        if self.clen == 0 && self.next_char()?.is_none() {
+            return Ok(None);
+        }
+        let (mut c, mut len, mut pos) = (self.c, self.clen, self.pos);
+        for _ in 0..lookahead {
+            pos = self.step(pos, c, len)?;
+            match self.decode_at(pos.offset)? {
+                Some((nc, nlen)) => {
+                    c = nc;
+                    len = nlen;
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some((c, pos)))
+    }
+
+    fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_char()?;
+        }
+        Ok(())
+    }
+
+    fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        let offset = self.pos.offset;
+        if s.len() > self.len.saturating_sub(offset) {
+            return Ok(false);
+        }
+        self.fill(offset, s.len())?;
+        let i = offset - self.buf_offset;
+        Ok(&self.buf[i..i + s.len()] == s.as_bytes())
+    }
+
+    fn match_str_term(&mut self, s: &str, f: &mut dyn FnMut(Option<char>) -> bool) -> IoResult<bool> {
+        if self.match_str(s)? {
+            let next = self.decode_at(self.pos.offset + s.len())?;
+            Ok(f(next.map(|(c, _)| c)))
+        } else {
+            Ok(false)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -630,6 +2569,561 @@ mod tests {
         }
     }
 
+    fn lines(input: &str, policy: LineBreakPolicy) -> Vec<u32> {
+        let mut r = MemCharReader::new(input.as_bytes()).with_line_break_policy(policy);
+        let mut lines = Vec::new();
+        while let Some((_, p)) = r.peek_char_pos(0).unwrap() {
+            lines.push(p.line);
+            r.next_char().unwrap();
+        }
+        lines
+    }
+
+    #[test]
+    fn line_break_policy() {
+        let input = "a\rb\r\nc\u{2028}d\u{000C}e";
+        assert_eq!(lines(input, LineBreakPolicy::LF), vec![0, 0, 0, 0, 0, 1, 1, 1, 1, 1]);
+        assert_eq!(lines(input, LineBreakPolicy::LF_CR), vec![0, 0, 1, 1, 1, 2, 2, 2, 2, 2]);
+        assert_eq!(lines(input, LineBreakPolicy::ALL), vec![0, 0, 1, 1, 1, 2, 2, 3, 3, 4]);
+    }
+
+    #[test]
+    fn skip_ascii_while_stops_at_line_break() {
+        let mut r = MemCharReader::new(b"ab\rcd").with_line_break_policy(LineBreakPolicy::LF_CR);
+        assert_eq!(r.skip_ascii_while(&mut |_| true).unwrap(), 2);
+        r.next_char().unwrap();
+        assert_eq!(r.next_char().unwrap(), Some('c'));
+        assert_eq!(r.position().line, 1);
+        assert_eq!(r.position().column, 0);
+    }
+
+    #[test]
+    fn quote_with_cr_line_breaks() {
+        let mut r = MemCharReader::new(b"line 1\rline 2\rline 3\r")
+            .with_line_break_policy(LineBreakPolicy::LF_CR);
+        r.seek_offset(12).unwrap();
+        let from = r.position();
+        assert_eq!(from, Position::with(12, 1, 5));
+        r.next_char().unwrap();
+        let q = r.quote(from, r.position(), 1, 0, "x".into());
+        assert_eq!(q.line(), 0);
+        assert_eq!(q.source(), "line 1\nline 2");
+    }
+
+    #[test]
+    fn final_newline_check() {
+        let d = MemCharReader::new(b"a\nbc")
+            .check_final_newline()
+            .unwrap()
+            .expect("warning expected");
+        assert_eq!(d.detail().severity(), Severity::Warning);
+        assert_eq!(d.quotes()[0].start(), Position::with(4, 1, 2));
+
+        assert!(MemCharReader::new(b"a\nbc\n").check_final_newline().unwrap().is_none());
+        assert!(MemCharReader::new(b"").check_final_newline().unwrap().is_none());
+        assert!(MemCharReader::new(b"a\r")
+            .with_line_break_policy(LineBreakPolicy::LF_CR)
+            .check_final_newline()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn file_char_reader_small_buffer() {
+        use std::io::Write;
+
+        let input = "line 1: aąć\nline 2: 老\r\nline 3";
+        let mut tmpfile = tempfile::NamedTempFile::new().unwrap();
+        write!(tmpfile, "\u{FEFF}{}", input).unwrap();
+        let path = tmpfile.path().to_path_buf();
+
+        let mut f = FileCharReader::with_buffer_size(&path, 5).unwrap();
+        let mut m = MemCharReader::new(input.as_bytes());
+        assert_eq!(f.len(), Some(input.len()));
+        assert_eq!(f.peek_char_pos(8).unwrap(), Some(('a', Position::with(8, 0, 8))));
+        loop {
+            assert_eq!(f.match_str("line").unwrap(), m.match_str("line").unwrap());
+            let c = f.next_char().unwrap();
+            assert_eq!(c, m.next_char().unwrap());
+            assert_eq!(f.position(), m.position());
+            if c.is_none() {
+                break;
+            }
+        }
+        assert_eq!(f.slice(8, 13).unwrap(), "aąć");
+        let q = f.quote(Position::with(8, 0, 8), Position::with(9, 0, 9), 0, 0, "".into());
+        assert_eq!(q.path(), Some(path.as_path()));
+        assert_eq!(q.source(), "line 1: aąć");
+
+        let (from, to) = (Position::with(22, 1, 8), Position::with(25, 1, 9));
+        let q = f.try_quote(from, to, 1, 1, "".into()).unwrap();
+        assert_eq!(q.source(), m.quote(from, to, 1, 1, "".into()).source());
+        assert_eq!(q.line(), 0);
+    }
+
+    #[test]
+    fn transaction_rollback() {
+        let mut r = MemCharReader::new(b"abc def");
+        assert_eq!(r.next_char().unwrap(), Some('a'));
+        {
+            let mut t = Transaction::begin(&mut r);
+            t.skip_chars(3).unwrap();
+            assert_eq!(t.peek_char(0).unwrap(), Some(' '));
+        }
+        assert_eq!(r.position(), Position::with(0, 0, 0));
+        assert_eq!(r.next_char().unwrap(), Some('b'));
+
+        let mut t = Transaction::begin(&mut r);
+        t.skip_chars(2).unwrap();
+        t.commit();
+        assert_eq!(r.next_char().unwrap(), Some('d'));
+
+        let m = r.mark();
+        r.skip_chars(2).unwrap();
+        r.rollback(m).unwrap();
+        assert_eq!(r.next_char().unwrap(), Some('e'));
+    }
+
+    #[cfg(feature = "unicode-width")]
+    #[test]
+    fn position_config_columns() {
+        fn columns(input: &str, config: PositionConfig) -> Vec<u32> {
+            let mut r = MemCharReader::new(input.as_bytes()).with_position_config(config);
+            r.char_positions().map(|c| c.unwrap().1.column).collect()
+        }
+
+        let tabs = PositionConfig {
+            tab_width: Some(4),
+            ..PositionConfig::CHARS
+        };
+        assert_eq!(columns("a\tb\t\tc", PositionConfig::CHARS), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(columns("a\tb\t\tc", tabs), vec![0, 1, 4, 5, 8, 12]);
+
+        let crlf = PositionConfig {
+            crlf: true,
+            ..PositionConfig::CHARS
+        };
+        assert_eq!(columns("ab\r\nc", crlf), vec![0, 1, 2, 2, 0]);
+
+        let width = PositionConfig {
+            columns: ColumnMode::DisplayWidth,
+            ..PositionConfig::CHARS
+        };
+        assert_eq!(columns("老a老e\u{301}x", width), vec![0, 2, 3, 5, 6, 6]);
+        let graphemes = PositionConfig {
+            columns: ColumnMode::Graphemes,
+            ..PositionConfig::CHARS
+        };
+        assert_eq!(columns("老a老e\u{301}x", graphemes), vec![0, 1, 2, 3, 4, 4]);
+        let utf16 = PositionConfig {
+            columns: ColumnMode::Utf16,
+            ..PositionConfig::CHARS
+        };
+        assert_eq!(columns("老a😀e\u{301}x", utf16), vec![0, 1, 2, 4, 5, 6]);
+
+        let mut r = MemByteReader::new("老\tx".as_bytes()).with_position_config(PositionConfig {
+            tab_width: Some(4),
+            ..width
+        });
+        r.skip_bytes(4).unwrap();
+        assert_eq!(r.position(), Position::with(4, 0, 4));
+    }
+
+    #[test]
+    fn peek_str() {
+        let input = "ab\nćd";
+        let mut r = MemCharReader::new(input.as_bytes());
+        r.next_char().unwrap();
+        assert_eq!(r.peek_str(4).unwrap(), "ab\nć");
+        assert_eq!(r.peek_str(10).unwrap(), input);
+        assert!(matches!(r.peek_str(4).unwrap(), Cow::Borrowed(_)));
+
+        let mut l = Lookahead::new(MemCharReader::new(input.as_bytes()));
+        assert_eq!(l.peek_str(4).unwrap(), "ab\nć");
+        assert_eq!(l.peek_char_pos(3).unwrap(), Some(('ć', Position::with(3, 1, 0))));
+        assert_eq!(l.position(), Position::with(0, 0, 0));
+        assert_eq!(l.next_char().unwrap(), Some('b'));
+        assert!(l.match_str("b\nćd").unwrap());
+        let m = l.mark();
+        l.skip_chars(2).unwrap();
+        assert_eq!(l.peek_char_pos(0).unwrap(), Some(('ć', Position::with(3, 1, 0))));
+        l.rollback(m).unwrap();
+        assert_eq!(l.peek_str(2).unwrap(), "b\n");
+
+        let mut r = l.into_inner().unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('b', Position::with(1, 0, 1))));
+        assert_eq!(r.next_char().unwrap(), Some('\n'));
+    }
+
+    #[test]
+    fn read_lines() {
+        let mut r = MemCharReader::new(b"[a]\r\nb = 1\n\nc");
+        let mut lines = Vec::new();
+        while let Some((line, span)) = r.read_line().unwrap() {
+            lines.push((line.into_owned(), span));
+        }
+        assert_eq!(
+            lines,
+            vec![
+                ("[a]".to_string(), Span::with(0, 0, 0, 3, 0, 3)),
+                ("b = 1".to_string(), Span::with(5, 1, 0, 10, 1, 5)),
+                ("".to_string(), Span::with(11, 2, 0, 11, 2, 0)),
+                ("c".to_string(), Span::with(12, 3, 0, 13, 3, 1)),
+            ]
+        );
+
+        let mut r = MemCharReader::new(b"a\rb\rc").with_line_break_policy(LineBreakPolicy::LF_CR);
+        assert!(r.skip_to_line(2).unwrap());
+        assert_eq!(r.read_line().unwrap(), Some(("c".into(), Span::with(4, 2, 0, 5, 2, 1))));
+        assert!(!r.skip_to_line(1).unwrap());
+        assert!(!r.skip_to_line(5).unwrap());
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn graphemes() {
+        let mut r = MemCharReader::new("e\u{301}\u{1F44D}\u{1F3FD}\r\nx".as_bytes());
+        let mut clusters = Vec::new();
+        assert_eq!(
+            r.peek_grapheme().unwrap(),
+            Some(("e\u{301}".into(), Span::with(0, 0, 0, 3, 0, 2)))
+        );
+        while let Some((g, span)) = r.next_grapheme().unwrap() {
+            clusters.push((g.into_owned(), span.start.offset, span.end.offset));
+        }
+        assert_eq!(
+            clusters,
+            vec![
+                ("e\u{301}".to_string(), 0, 3),
+                ("\u{1F44D}\u{1F3FD}".to_string(), 3, 11),
+                ("\r\n".to_string(), 11, 13),
+                ("x".to_string(), 13, 14),
+            ]
+        );
+    }
+
+    #[test]
+    fn str_input() {
+        let mut r = MemCharReader::from_str("\u{FEFF}zażółć");
+        assert_eq!(r.input().unwrap(), "zażółć");
+        assert_eq!(r.slice(2, 4).unwrap(), "ż");
+        assert_eq!(
+            r.slice_str(0, 3).unwrap_err(),
+            IoErrorDetail::InvalidOffset { offset: 3, len: 10 }
+        );
+        r.skip_chars(3).unwrap();
+        assert_eq!(r.rest_str().unwrap(), "żółć");
+
+        // only one BOM is stripped, so that text and data stay in sync
+        for &input in ["\u{FEFF}\u{FEFF}x", "\u{FEFF}\u{EF}\u{BB}\u{BF}x"].iter() {
+            let mut r = MemCharReader::from_str(input);
+            assert_eq!(r.input().unwrap(), &input[3..]);
+            assert_eq!(r.rest_str().unwrap(), &input[3..]);
+            assert_eq!(r.slice_str(0, input.len() - 3).unwrap(), &input[3..]);
+        }
+
+        let r = MemCharReader::new(b"a\xffb");
+        assert_eq!(
+            r.slice_str(0, 3).unwrap_err(),
+            IoErrorDetail::Utf8InvalidEncoding { offset: 1, len: 1 }
+        );
+        assert_eq!(r.slice_str(2, 3).unwrap(), "b");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn reader_metrics() {
+        let mut r = MemCharReader::new(b"ab  cd");
+        r.peek_char(0).unwrap();
+        r.peek_char(2).unwrap();
+        r.next_char().unwrap();
+        r.skip_ascii_while(&mut |b| b != b'c').unwrap();
+        let m = *r.metrics();
+        assert_eq!(m.peeks, 2);
+        assert_eq!(m.max_lookahead, 2);
+        assert_eq!(m.chars_decoded, 3);
+        assert_eq!(m.bytes_consumed, 4);
+
+        r.reset_metrics();
+        assert_eq!(*r.metrics(), ReaderMetrics::default());
+    }
+
+    /// Parser utility over trait object, returning `key` and `value` of `key = value`.
+    fn key_value(r: &mut dyn CharReader) -> IoResult<(String, String)> {
+        let key = r.scan_owned(|c| c.is_alphanumeric())?;
+        r.skip_while_with(|c| c == ' ' || c == '=')?;
+        let (value, _) = r.scan_span_owned(|c| c != '\n')?;
+        Ok((key, value))
+    }
+
+    #[test]
+    fn dyn_reader_helpers() {
+        let mut r = MemCharReader::new(b"name = kg-diag\n");
+        assert_eq!(key_value(&mut r).unwrap(), ("name".into(), "kg-diag".into()));
+        let r: &mut dyn CharReader = &mut r;
+        assert_eq!(r.slice_owned(0, 4).unwrap(), "name");
+    }
+
+    #[test]
+    fn resume_after_input_grows() {
+        let mut buf = b"key = \xC5".to_vec();
+        let mut r = MemCharReader::new(&buf);
+        let start = r.mark();
+        r.skip_chars(6).unwrap();
+        let err = r.next_char().unwrap_err();
+        assert!(err.needs_more_input());
+        let snapshot = r.snapshot();
+        drop(r);
+
+        buf.extend_from_slice(b"\xBC\n");
+        let mut r = MemCharReader::resume(&buf, snapshot);
+        assert_eq!(r.next_char().unwrap(), Some('ż'));
+        assert_eq!(r.position(), Position::with(6, 0, 6));
+        r.rollback(start).unwrap();
+        assert_eq!(r.peek_str(3).unwrap(), "key");
+
+        let err = MemCharReader::new(b"\xff").next_char().unwrap_err();
+        assert!(!err.needs_more_input());
+    }
+
+    #[test]
+    fn position_stack() {
+        let mut r = MemCharReader::new(b"a(b(c))");
+        r.peek_char(0).unwrap();
+        r.push_pos();
+        r.skip_chars(2).unwrap();
+        r.push_pos();
+        r.skip_chars(2).unwrap();
+        assert_eq!(r.saved_pos_count(), 2);
+        assert_eq!(r.pop_pos(PopMode::Restore).unwrap(), Position::with(2, 0, 2));
+        assert_eq!(r.peek_char(0).unwrap(), Some('b'));
+        r.next_char().unwrap();
+        assert_eq!(r.pop_pos(PopMode::Discard).unwrap(), Position::with(0, 0, 0));
+        assert_eq!(r.peek_char(0).unwrap(), Some('('));
+        assert_eq!(r.saved_pos_count(), 0);
+    }
+
+    #[test]
+    fn borrowed_and_boxed_readers() {
+        fn word<R: CharReader>(mut r: R) -> String {
+            r.skip_whitespace().unwrap();
+            r.scan(&mut |c| c.is_alphabetic()).unwrap().into_owned()
+        }
+
+        let mut r = MemCharReader::new(b" ab cd ef");
+        assert_eq!(word(&mut r), "ab");
+        let d: &mut dyn CharReader = &mut r;
+        assert_eq!(word(d), "cd");
+        let mut b: Box<dyn CharReader> = Box::new(r);
+        assert_eq!(word(&mut b), "ef");
+        assert!(b.eof());
+
+        let mut r = MemByteReader::new(b"ab");
+        let mut b: Box<dyn ByteReader + '_> = Box::new(&mut r);
+        assert_eq!(b.next_byte().unwrap(), Some(b'a'));
+        drop(b);
+        assert_eq!(r.position().offset, 1);
+    }
+
+    #[test]
+    fn remaining_input() {
+        let input = b"key = \xC4\x87 \xFF";
+        let mut r = MemCharReader::new(input);
+        assert_eq!(r.data(), input);
+        r.skip_chars(7).unwrap();
+        assert_eq!(r.remaining(), b"\xC4\x87 \xFF");
+        let err = r.rest_str().unwrap_err();
+        assert_eq!(err, IoErrorDetail::Utf8InvalidEncoding { offset: 9, len: 1 });
+
+        let mut r = MemCharReader::new("key = ć;".as_bytes());
+        r.skip_chars(7).unwrap();
+        assert_eq!(r.rest_str().unwrap(), "ć;");
+        r.seek(Position::with(100, 0, 100)).unwrap();
+        assert!(r.remaining().is_empty());
+
+        let mut r = MemByteReader::new(b"ab\xff");
+        r.skip_bytes(1).unwrap();
+        assert_eq!(r.remaining(), b"b\xff");
+    }
+
+    #[test]
+    fn skip_ascii_runs() {
+        let input = "  \t\u{a0}\u{2028} x\n\n  y ća1";
+        let mut r = MemCharReader::new(input.as_bytes());
+        r.skip_whitespace().unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('x', Position::with(9, 0, 6))));
+        r.next_char().unwrap();
+        r.skip_whitespace_nonl().unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('\n', Position::with(10, 0, 7))));
+        r.skip_whitespace().unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('y', Position::with(14, 2, 2))));
+        r.skip_until(&mut |c| c.is_alphabetic() && c != 'y').unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('ć', Position::with(16, 2, 4))));
+        r.skip_while(&mut |c| c.is_alphabetic()).unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('1', Position::with(19, 2, 6))));
+
+        let mut r =
+            MemCharReader::new(input.as_bytes()).with_line_break_policy(LineBreakPolicy::ALL);
+        r.skip_whitespace().unwrap();
+        assert_eq!(r.peek_char_pos(0).unwrap(), Some(('x', Position::with(9, 1, 1))));
+    }
+
+    #[test]
+    fn seek_offset() {
+        let input = "a\tb\r\nć\r老\nx";
+        let configs = [
+            (LineBreakPolicy::LF, PositionConfig::CHARS),
+            (LineBreakPolicy::LF, PositionConfig { tab_width: Some(4), ..Default::default() }),
+            (LineBreakPolicy::LF_CR, PositionConfig::CHARS),
+        ];
+        for &(policy, positions) in configs.iter() {
+            let mut r = MemCharReader::new(input.as_bytes())
+                .with_line_break_policy(policy)
+                .with_position_config(positions);
+            let mut expected = Vec::new();
+            while let Some(cp) = r.peek_char_pos(0).unwrap() {
+                expected.push(cp);
+                r.next_char().unwrap();
+            }
+            let end = r.position();
+            for &(c, pos) in expected.iter().rev() {
+                r.seek_offset(pos.offset).unwrap();
+                assert_eq!(r.peek_char_pos(0).unwrap(), Some((c, pos)));
+            }
+            r.seek_offset(input.len()).unwrap();
+            assert_eq!(r.position(), end);
+        }
+
+        let mut r = MemCharReader::new(input.as_bytes());
+        let err = r.seek_offset(6).unwrap_err();
+        assert_eq!(err, IoErrorDetail::InvalidOffset { offset: 6, len: input.len() });
+        assert!(r.seek_offset(input.len() + 1).is_err());
+    }
+
+    #[test]
+    fn prev_char() {
+        let input = "a\tb\r\nć\r老\u{2028}x";
+        let positions = PositionConfig {
+            tab_width: Some(4),
+            crlf: true,
+            columns: ColumnMode::DisplayWidth,
+        };
+        let mut r = MemCharReader::new(input.as_bytes())
+            .with_line_break_policy(LineBreakPolicy::ALL)
+            .with_position_config(positions);
+        let mut expected = Vec::new();
+        while let Some(cp) = r.peek_char_pos(0).unwrap() {
+            expected.push(cp);
+            r.next_char().unwrap();
+        }
+        assert_eq!(r.peek_prev_char().unwrap(), Some('x'));
+
+        for &(c, pos) in expected.iter().rev() {
+            assert_eq!(r.prev_char().unwrap(), Some(c));
+            assert_eq!(r.position(), pos);
+        }
+        assert_eq!(r.peek_prev_char().unwrap(), None);
+        assert_eq!(r.prev_char().unwrap(), None);
+        assert_eq!(r.next_char().unwrap(), Some(expected[1].0));
+    }
+
+    #[test]
+    fn peek_char_lookahead_cache() {
+        let input = "a\rć老\n x";
+        let mut r = MemCharReader::new(input.as_bytes())
+            .with_line_break_policy(LineBreakPolicy::LF_CR);
+        let mut expected = Vec::new();
+        let mut s = r.clone();
+        while let Some(cp) = s.peek_char_pos(0).unwrap() {
+            expected.push(cp);
+            s.next_char().unwrap();
+        }
+
+        assert_eq!(r.peek_char_pos(3).unwrap(), Some(expected[3]));
+        assert_eq!(r.peek_char_pos(1).unwrap(), Some(expected[1]));
+        assert_eq!(r.peek_char(expected.len()).unwrap(), None);
+        for (i, &cp) in expected.iter().enumerate() {
+            assert_eq!(r.peek_char_pos(0).unwrap(), Some(cp));
+            if i + 2 < expected.len() {
+                assert_eq!(r.peek_char_pos(2).unwrap(), Some(expected[i + 2]));
+            }
+            r.next_char().unwrap();
+        }
+        assert!(r.eof());
+
+        r.seek(expected[1].1).unwrap();
+        assert_eq!(r.peek_char(2).unwrap(), Some(expected[3].0));
+        r.set_line_break_policy(LineBreakPolicy::LF);
+        assert_eq!(r.peek_char_pos(1).unwrap(), Some(('ć', Position::with(2, 0, 2))));
+    }
+
+    #[test]
+    fn skip_until_str() {
+        let input = "/* a\r\n ć */ x";
+        for &policy in [LineBreakPolicy::LF, LineBreakPolicy::LF_CR].iter() {
+            let mut r = MemCharReader::new(input.as_bytes()).with_line_break_policy(policy);
+            assert!(r.consume_str("/*").unwrap());
+            assert_eq!(r.skip_until_str("*/").unwrap(), Some(Position::with(10, 1, 3)));
+            assert!(r.consume_str("*/").unwrap());
+            assert_eq!(r.peek_char(1).unwrap(), Some('x'));
+            assert_eq!(r.skip_until_str("*/").unwrap(), None);
+            assert!(r.eof());
+            assert_eq!(r.position(), Position::with(14, 1, 7));
+        }
+    }
+
+    #[test]
+    fn consume_str() {
+        let mut r = MemCharReader::new("SELECT ŻÓŁW from t".as_bytes());
+        assert!(!r.consume_str("select").unwrap());
+        assert!(r.match_str_ignore_case("select").unwrap());
+        assert!(r.consume_str_ignore_case("Select").unwrap());
+        assert_eq!(r.peek_char(0).unwrap(), Some(' '));
+        assert!(r.consume_str(" ").unwrap());
+        assert!(r.consume_str_ignore_case("żółw").unwrap());
+        assert_eq!(r.position(), Position::with(15, 0, 11));
+        assert!(!r.match_str_ignore_case(" FROM tx").unwrap());
+        assert!(r.consume_str(" from").unwrap());
+        assert_eq!(r.next_char().unwrap(), Some('t'));
+    }
+
+    #[test]
+    fn scan_with_span() {
+        let mut r = MemCharReader::new("let źdźbło = 1;".as_bytes());
+        let (word, span) = r.scan_span(&mut |c| c.is_alphabetic()).unwrap();
+        assert_eq!(word, "let");
+        assert_eq!(span, Span::with(0, 0, 0, 3, 0, 3));
+        r.skip_whitespace().unwrap();
+        let (word, span) = r.scan_span(&mut |c| c.is_alphabetic()).unwrap();
+        assert_eq!(word, "źdźbło");
+        assert_eq!(span, Span::with(4, 0, 4, 13, 0, 10));
+        let span = r.skip_while_span(&mut |c| c == ' ' || c == '=').unwrap();
+        assert_eq!(span, Span::with(13, 0, 10, 16, 0, 13));
+    }
+
+    #[test]
+    fn char_iterators() {
+        let mut r = MemCharReader::new("ab1\nć".as_bytes());
+        let word: String = r
+            .chars()
+            .map(|c| c.unwrap())
+            .take_while(|c| c.is_alphabetic())
+            .collect();
+        assert_eq!(word, "ab");
+        assert_eq!(r.peek_char(0).unwrap(), Some('1'));
+
+        let positions: Vec<_> = r.char_positions().map(|c| c.unwrap()).collect();
+        assert_eq!(
+            positions,
+            vec![
+                ('1', Position::with(2, 0, 2)),
+                ('\n', Position::with(3, 0, 3)),
+                ('ć', Position::with(4, 1, 0)),
+            ]
+        );
+        assert!(r.eof());
+    }
+
     #[test]
     fn char_reader_diacritics() {
         let input = "老aąćżńęóź";
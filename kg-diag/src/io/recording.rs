@@ -0,0 +1,209 @@
+use super::*;
+
+/// `CharReader` decorator recording every character consumed from the underlying reader,
+/// along with its span. The log can be replayed (e.g. by error recovery re-examining skipped
+/// input) or dumped to see exactly what a lexer consumed.
+///
+/// A character is consumed when the reader advances past it, so peeking never adds to the log.
+/// Characters consumed again after seeking back are recorded again.
+#[derive(Debug)]
+pub struct RecordingReader<R: CharReader> {
+    inner: R,
+    /// Current character of `inner`, if decoded.
+    current: Option<(char, Position)>,
+    log: Vec<(char, Span)>,
+}
+
+impl<R: CharReader> RecordingReader<R> {
+    pub fn new(inner: R) -> RecordingReader<R> {
+        RecordingReader {
+            inner,
+            current: None,
+            log: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Consumed characters with their spans, in order of consumption.
+    pub fn log(&self) -> &[(char, Span)] {
+        &self.log
+    }
+
+    pub fn clear(&mut self) {
+        self.log.clear();
+    }
+
+    /// Iterator over consumed characters with their positions.
+    pub fn replay(&self) -> impl Iterator<Item = (char, Position)> + '_ {
+        self.log.iter().map(|&(c, span)| (c, span.start))
+    }
+
+    /// Spans of consumed input, with characters consumed one after another merged into a
+    /// single span.
+    pub fn spans(&self) -> Vec<Span> {
+        let mut spans: Vec<Span> = Vec::new();
+        for &(_, span) in self.log.iter() {
+            match spans.last_mut() {
+                Some(last) if last.end == span.start => last.end = span.end,
+                _ => spans.push(span),
+            }
+        }
+        spans
+    }
+
+    /// Consumed input, one merged span per line, e.g. `1:1-4: "abc"`.
+    pub fn dump(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let mut text = String::new();
+        let mut span: Option<Span> = None;
+        for &(c, s) in self.log.iter() {
+            match span {
+                Some(ref mut span) if span.end == s.start => span.end = s.end,
+                _ => {
+                    if let Some(span) = span {
+                        let _ = writeln!(out, "{}: {:?}", span, text);
+                    }
+                    text.clear();
+                    span = Some(s);
+                }
+            }
+            text.push(c);
+        }
+        if let Some(span) = span {
+            let _ = writeln!(out, "{}: {:?}", span, text);
+        }
+        out
+    }
+}
+
+impl<R: CharReader> Reader for RecordingReader<R> {
+    fn path(&self) -> Option<&Path> {
+        self.inner.path()
+    }
+
+    fn len(&self) -> Option<usize> {
+        self.inner.len()
+    }
+
+    fn eof(&self) -> bool {
+        self.inner.eof()
+    }
+
+    fn position(&self) -> Position {
+        self.inner.position()
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        self.current = None;
+        self.inner.seek(pos)
+    }
+
+    fn mark(&mut self) -> Mark {
+        self.inner.mark()
+    }
+
+    fn rollback(&mut self, mark: Mark) -> IoResult<()> {
+        self.current = mark.current_char().map(|c| (c, mark.position()));
+        self.inner.rollback(mark)
+    }
+
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        self.inner.input()
+    }
+
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        self.inner.slice(start, end)
+    }
+
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        self.inner.quote(from, to, lines_before, lines_after, message)
+    }
+}
+
+impl<R: CharReader> CharReader for RecordingReader<R> {
+    fn next_char(&mut self) -> IoResult<Option<char>> {
+        let consumed = self.current.take();
+        let c = self.inner.next_char()?;
+        if let Some((k, pos)) = consumed {
+            self.log.push((k, Span::with_pos(pos, self.inner.position())));
+        }
+        self.current = c.map(|c| (c, self.inner.position()));
+        Ok(c)
+    }
+
+    fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        Ok(self.peek_char_pos(lookahead)?.map(|(c, _)| c))
+    }
+
+    fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        if self.current.is_none() {
+            // peeking decodes the current character, so that it is consumed by `next_char()`
+            self.current = self.inner.peek_char_pos(0)?;
+        }
+        if lookahead == 0 {
+            Ok(self.current)
+        } else {
+            self.inner.peek_char_pos(lookahead)
+        }
+    }
+
+    fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_char()?;
+        }
+        Ok(())
+    }
+
+    fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        self.inner.match_str(s)
+    }
+
+    fn match_str_term(
+        &mut self,
+        s: &str,
+        f: &mut dyn FnMut(Option<char>) -> bool,
+    ) -> IoResult<bool> {
+        self.inner.match_str_term(s, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_consumed_input() {
+        let mut r = RecordingReader::new(MemCharReader::new(b"let x\n= 1;"));
+        assert_eq!(r.scan(&mut |c| c.is_alphabetic()).unwrap(), "let");
+        assert_eq!(r.log().len(), 3);
+        let mark = r.mark();
+        r.skip_whitespace().unwrap();
+        r.rollback(mark).unwrap();
+        r.skip_chars(2).unwrap();
+        r.skip_whitespace().unwrap();
+        r.next_char().unwrap();
+
+        assert_eq!(r.replay().map(|(c, _)| c).collect::<String>(), "let  x\n=");
+        assert_eq!(
+            r.spans(),
+            vec![Span::with(0, 0, 0, 4, 0, 4), Span::with(3, 0, 3, 7, 1, 1)]
+        );
+        assert_eq!(r.dump(), "1:1-5: \"let \"\n1:4-2:2: \" x\\n=\"\n");
+    }
+}
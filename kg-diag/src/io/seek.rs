@@ -0,0 +1,437 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use super::*;
+
+/// Number of bytes around quoted span read from the source by `SeekCharReader::quote()`.
+/// Context lines are truncated if they do not fit.
+const QUOTE_CONTEXT: usize = 16 * 1024;
+
+/// Character reader over UTF-8 input from a `Read + Seek` source, keeping only a window of
+/// input in memory. Input up to `backtrack()` bytes before the current position is retained,
+/// so parsers can seek back (or slice input) within that distance. Seeking further back fails
+/// with `IoErrorDetail::BacktrackLimit`.
+///
+/// Suitable for inputs too large to be buffered whole, when parsers need limited backtracking
+/// that pure streaming does not allow.
+#[derive(Debug)]
+pub struct SeekCharReader<R: Read + Seek> {
+    source: R,
+    path: Option<PathBuf>,
+    /// Offset of input start (after byte order mark, if any) in the source.
+    start: u64,
+    len: usize,
+    /// Input offset the source is positioned at.
+    source_offset: usize,
+    /// Retained input, starting at `buf_offset`.
+    buf: Vec<u8>,
+    buf_offset: usize,
+    chunk_size: usize,
+    backtrack: usize,
+    pos: Position,
+    c: char,
+    clen: usize,
+    line_breaks: LineBreakPolicy,
+}
+
+impl<R: Read + Seek> SeekCharReader<R> {
+    pub const DEFAULT_CHUNK_SIZE: usize = 8 * 1024;
+    pub const DEFAULT_BACKTRACK: usize = 64 * 1024;
+
+    /// Reader of input from the current position of `source` to its end.
+    pub fn new(source: R) -> IoResult<SeekCharReader<R>> {
+        SeekCharReader::open(source, None)
+    }
+
+    /// Like `new()`, with `path` reported in errors and quotes.
+    pub fn with_path<P: Into<PathBuf>>(source: R, path: P) -> IoResult<SeekCharReader<R>> {
+        SeekCharReader::open(source, Some(path.into()))
+    }
+
+    fn open(mut source: R, path: Option<PathBuf>) -> IoResult<SeekCharReader<R>> {
+        let start = source.seek(SeekFrom::Current(0));
+        let end = start.and_then(|start| {
+            let end = source.seek(SeekFrom::End(0))?;
+            source.seek(SeekFrom::Start(start))?;
+            Ok((start, end))
+        });
+        let (start, end) = match end {
+            Ok(se) => se,
+            Err(err) => return Err(io_error(path.as_ref(), err)),
+        };
+        let mut r = SeekCharReader {
+            source,
+            path,
+            start,
+            len: end.saturating_sub(start) as usize,
+            source_offset: 0,
+            buf: Vec::new(),
+            buf_offset: 0,
+            chunk_size: Self::DEFAULT_CHUNK_SIZE,
+            backtrack: Self::DEFAULT_BACKTRACK,
+            pos: Position::new(),
+            c: '\0',
+            clen: 0,
+            line_breaks: LineBreakPolicy::LF,
+        };
+        r.fill(0, 3)?;
+        if r.buf.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            r.start += 3;
+            r.len -= 3;
+            r.source_offset -= 3;
+            r.buf.drain(..3);
+        }
+        Ok(r)
+    }
+
+    /// Sets number of bytes before the current position retained for seeking back.
+    pub fn with_backtrack(mut self, backtrack: usize) -> SeekCharReader<R> {
+        self.backtrack = backtrack;
+        self
+    }
+
+    /// Sets number of bytes read from the source at once.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> SeekCharReader<R> {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn with_line_break_policy(mut self, policy: LineBreakPolicy) -> SeekCharReader<R> {
+        self.line_breaks = policy;
+        self
+    }
+
+    pub fn backtrack(&self) -> usize {
+        self.backtrack
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Offset of the first retained byte of input. Positions before it cannot be sought to.
+    pub fn window_start(&self) -> usize {
+        self.buf_offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    fn io_error(&self, err: std::io::Error) -> IoErrorDetail {
+        io_error(self.path.as_ref(), err)
+    }
+
+    fn check_retained(&self, offset: usize) -> IoResult<()> {
+        if offset < self.buf_offset {
+            Err(IoErrorDetail::BacktrackLimit {
+                offset,
+                window_start: self.buf_offset,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reads `len` bytes of input at `offset` (or up to the end of input) from the source.
+    fn read_at(&mut self, offset: usize, len: usize) -> IoResult<Vec<u8>> {
+        let mut data = vec![0; len.min(self.len.saturating_sub(offset))];
+        if offset != self.source_offset {
+            if let Err(err) = self.source.seek(SeekFrom::Start(self.start + offset as u64)) {
+                return Err(self.io_error(err));
+            }
+        }
+        if let Err(err) = self.source.read_exact(&mut data) {
+            return Err(self.io_error(err));
+        }
+        self.source_offset = offset + data.len();
+        Ok(data)
+    }
+
+    /// Makes sure that window contains bytes `offset..offset + len` (or up to the end of
+    /// input), discarding input more than `backtrack` bytes before the current position.
+    fn fill(&mut self, offset: usize, len: usize) -> IoResult<()> {
+        self.check_retained(offset)?;
+        let end = (offset + len).min(self.len);
+        let buf_end = self.buf_offset + self.buf.len();
+        if end <= buf_end {
+            return Ok(());
+        }
+        let from = offset.saturating_sub(self.backtrack);
+        if from > buf_end {
+            // skipping forward, there is no need to read input in between
+            self.buf.clear();
+            self.buf_offset = from;
+        }
+        let buf_end = self.buf_offset + self.buf.len();
+        let chunk = self.read_at(buf_end, self.chunk_size.max(end - buf_end))?;
+        self.buf.extend_from_slice(&chunk);
+
+        // discarded input is drained in chunks, to avoid moving the window on every read
+        let keep = self.pos.offset.min(offset).saturating_sub(self.backtrack);
+        if keep >= self.buf_offset + self.chunk_size {
+            self.buf.drain(..keep - self.buf_offset);
+            self.buf_offset = keep;
+        }
+        Ok(())
+    }
+
+    fn byte_at(&mut self, offset: usize) -> IoResult<Option<u8>> {
+        if offset >= self.len {
+            return Ok(None);
+        }
+        self.fill(offset, 1)?;
+        Ok(Some(self.buf[offset - self.buf_offset]))
+    }
+
+    fn decode_at(&mut self, offset: usize) -> IoResult<Option<(char, usize)>> {
+        let b = match self.byte_at(offset)? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        stats::record_char_decoded();
+        let len = if b < 0b10000000u8 {
+            return Ok(Some((b as char, 1)));
+        } else if b < 0b11000000u8 {
+            return Err(IoErrorDetail::Utf8InvalidEncoding { offset, len: 1 });
+        } else if b < 0b11100000u8 {
+            2
+        } else if b < 0b11110000u8 {
+            3
+        } else if b <= 0b11110100u8 {
+            4
+        } else {
+            return Err(IoErrorDetail::Utf8InvalidEncoding { offset, len: 4 });
+        };
+        if offset + len > self.len {
+            return Err(IoErrorDetail::Utf8UnexpectedEof { offset });
+        }
+        self.fill(offset, len)?;
+        let i = offset - self.buf_offset;
+        match std::str::from_utf8(&self.buf[i..i + len]) {
+            Ok(s) => Ok(s.chars().next().map(|c| (c, len))),
+            Err(_) => Err(IoErrorDetail::Utf8InvalidEncoding { offset, len }),
+        }
+    }
+
+    /// Returns position following character `c` of length `len` found at `pos`.
+    fn step(&mut self, mut pos: Position, c: char, len: usize) -> IoResult<Position> {
+        pos.offset += len;
+        let next = if c == '\r' { self.byte_at(pos.offset)? } else { None };
+        if self.line_breaks.is_line_break(c, next) {
+            pos.inc_line();
+        } else {
+            pos.inc_column();
+        }
+        Ok(pos)
+    }
+}
+
+fn io_error(path: Option<&PathBuf>, err: std::io::Error) -> IoErrorDetail {
+    match path {
+        Some(path) => IoErrorDetail::IoPath {
+            kind: err.kind(),
+            op_type: OpType::Read,
+            file_type: FileType::File,
+            path: path.clone(),
+        },
+        None => IoErrorDetail::from(err),
+    }
+}
+
+impl<R: Read + Seek> Reader for SeekCharReader<R> {
+    fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.len)
+    }
+
+    fn eof(&self) -> bool {
+        self.pos.offset >= self.len
+    }
+
+    fn position(&self) -> Position {
+        self.pos
+    }
+
+    /// Fails with `IoErrorDetail::BacktrackLimit` if `pos` is before the retained window.
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        stats::record_seek();
+        self.check_retained(pos.offset)?;
+        self.pos = pos;
+        self.c = '\0';
+        self.clen = 0;
+        Ok(())
+    }
+
+    /// Whole input is only available until the start of input is discarded from the window.
+    fn input(&mut self) -> IoResult<Cow<str>> {
+        let len = self.len;
+        self.slice(0, len)
+    }
+
+    fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+        if end < start {
+            return Err(IoErrorDetail::InvalidOffset { offset: end, len: self.len });
+        }
+        self.fill(start, end - start)?;
+        let data = &self.buf[start - self.buf_offset..end - self.buf_offset];
+        match std::str::from_utf8(data) {
+            Ok(s) => Ok(Cow::Borrowed(s)),
+            Err(err) => Err(IoErrorDetail::Utf8InvalidEncoding {
+                offset: start + err.valid_up_to(),
+                len: err.error_len().unwrap_or(0),
+            }),
+        }
+    }
+
+    /// Quoted lines are read from the source, so quoting is not limited by the retained
+    /// window. Context is limited to `QUOTE_CONTEXT` bytes around the quoted span.
+    fn quote(
+        &mut self,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        let to_offset = to.offset.min(self.len).max(from.offset);
+        let region_start = from.offset.saturating_sub(QUOTE_CONTEXT);
+        let region_len = to_offset + QUOTE_CONTEXT - region_start;
+        let region = self.read_at(region_start, region_len).unwrap_or_default();
+
+        let path = self.path.as_deref().map(Arc::from);
+        let start = Position { offset: from.offset - region_start, ..from };
+        let end = Position { offset: to_offset - region_start, ..to };
+        if end.offset > region.len() {
+            return Quote::with_source(path, from, to, from.offset, from.line, "".into(), message);
+        }
+        // if context is truncated by the region, number of the first line is counted from the region
+        let (off1, off2, line) =
+            LineMap::new(&region).frame(start, end, lines_before, lines_after);
+        let source: Arc<str> = String::from_utf8_lossy(&region[off1..off2]).into();
+        Quote::with_source(path, from, to, region_start + off1, line, source, message)
+    }
+}
+
+impl<R: Read + Seek> CharReader for SeekCharReader<R> {
+    fn next_char(&mut self) -> IoResult<Option<char>> {
+        if self.clen > 0 {
+            self.pos = self.step(self.pos, self.c, self.clen)?;
+            self.clen = 0;
+        }
+        match self.decode_at(self.pos.offset)? {
+            Some((c, len)) => {
+                self.c = c;
+                self.clen = len;
+                Ok(Some(c))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        Ok(self.peek_char_pos(lookahead)?.map(|(c, _)| c))
+    }
+
+    fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+        stats::record_peek();
+        if self.clen == 0 && self.next_char()?.is_none() {
+            return Ok(None);
+        }
+        let (mut c, mut len, mut pos) = (self.c, self.clen, self.pos);
+        for _ in 0..lookahead {
+            pos = self.step(pos, c, len)?;
+            match self.decode_at(pos.offset)? {
+                Some((nc, nlen)) => {
+                    c = nc;
+                    len = nlen;
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some((c, pos)))
+    }
+
+    fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+        for _ in 0..skip {
+            self.next_char()?;
+        }
+        Ok(())
+    }
+
+    fn match_str(&mut self, s: &str) -> IoResult<bool> {
+        let offset = self.pos.offset;
+        if s.len() > self.len.saturating_sub(offset) {
+            return Ok(false);
+        }
+        self.fill(offset, s.len())?;
+        let i = offset - self.buf_offset;
+        Ok(&self.buf[i..i + s.len()] == s.as_bytes())
+    }
+
+    fn match_str_term(
+        &mut self,
+        s: &str,
+        f: &mut dyn FnMut(Option<char>) -> bool,
+    ) -> IoResult<bool> {
+        if self.match_str(s)? {
+            let next = self.decode_at(self.pos.offset + s.len())?;
+            Ok(f(next.map(|(c, _)| c)))
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn seek_within_window() {
+        let input = "0123456789".repeat(10);
+        let mut r = SeekCharReader::new(Cursor::new(input.as_bytes()))
+            .unwrap()
+            .with_chunk_size(4)
+            .with_backtrack(8);
+        r.skip_chars(50).unwrap();
+        let mark = r.position();
+        r.skip_chars(5).unwrap();
+        assert_eq!(r.slice(mark.offset, r.position().offset).unwrap(), "90123");
+        assert_eq!(
+            r.slice(r.position().offset, mark.offset).unwrap_err(),
+            IoErrorDetail::InvalidOffset { offset: mark.offset, len: 100 }
+        );
+        r.seek(mark).unwrap();
+        assert_eq!(r.next_char().unwrap(), Some('9'));
+
+        r.skip_chars(40).unwrap();
+        assert!(r.window_start() > mark.offset);
+        assert_eq!(
+            r.seek(mark).unwrap_err(),
+            IoErrorDetail::BacktrackLimit {
+                offset: mark.offset,
+                window_start: r.window_start(),
+            }
+        );
+        assert_eq!(r.position(), Position::with(89, 0, 89));
+    }
+
+    #[test]
+    fn quote_outside_window() {
+        let input = "line 1\nline 2\n".repeat(20);
+        let mut r = SeekCharReader::with_path(Cursor::new(input.as_bytes()), "big.txt")
+            .unwrap()
+            .with_chunk_size(8)
+            .with_backtrack(0);
+        r.skip_chars(100).unwrap();
+        let q = r.quote(Position::with(7, 1, 0), Position::with(13, 1, 6), 1, 0, "".into());
+        assert_eq!(q.source(), "line 1\nline 2");
+        assert_eq!(q.line(), 0);
+        assert_eq!(q.path(), Some(Path::new("big.txt")));
+    }
+}
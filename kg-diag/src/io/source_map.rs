@@ -0,0 +1,431 @@
+use super::*;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Identifier of a source registered in a [`SourceMap`].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct SourceId(u32);
+
+impl SourceId {
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Hash of source contents (64-bit FNV-1a), stable across runs and platforms.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
+pub struct ContentHash(u64);
+
+impl ContentHash {
+    pub fn of(data: &[u8]) -> ContentHash {
+        let mut h: u64 = 0xcbf29ce484222325;
+        for &b in data {
+            h ^= b as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+        ContentHash(h)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Span within a source registered in a [`SourceMap`].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct SourceSpan {
+    pub source: SourceId,
+    pub span: Span,
+}
+
+impl SourceSpan {
+    pub fn new(source: SourceId, span: Span) -> SourceSpan {
+        SourceSpan { source, span }
+    }
+}
+
+#[derive(Debug)]
+struct Source {
+    /// Shared with quotes of this source.
+    path: Option<Arc<Path>>,
+    hash: ContentHash,
+    data: Arc<[u8]>,
+    line_map: LineMap,
+}
+
+/// Owns source texts so that diagnostics can be related back to them
+/// without re-reading files.
+///
+/// Every added source gets its own `SourceId` (so diagnostics stay attributable to the place
+/// the source was loaded from), but identical contents are stored only once. Likewise, source
+/// text of quotes created with `quote()` is shared between quotes of the same lines.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    sources: Vec<Source>,
+    contents: HashMap<ContentHash, Vec<Arc<[u8]>>>,
+    /// Quoted text keyed by address of the contents buffer and byte range within it.
+    lines: Mutex<HashMap<(usize, usize, usize), Arc<str>>>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap {
+            sources: Vec::new(),
+            contents: HashMap::new(),
+            lines: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn add(&mut self, path: Option<&Path>, data: Vec<u8>) -> SourceId {
+        let hash = ContentHash::of(&data);
+        // equal hashes do not guarantee equal contents, so buffers are compared as well
+        let buffers = self.contents.entry(hash).or_insert_with(Vec::new);
+        let data = match buffers.iter().find(|b| ***b == *data) {
+            Some(b) => b.clone(),
+            None => {
+                let b: Arc<[u8]> = data.into();
+                buffers.push(b.clone());
+                b
+            }
+        };
+        let id = SourceId(self.sources.len() as u32);
+        self.sources.push(Source {
+            path: path.map(Arc::from),
+            hash,
+            line_map: LineMap::new(&data),
+            data,
+        });
+        id
+    }
+
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> IoResult<SourceId> {
+        let data = FileBuffer::open(path.as_ref())?.into_data();
+        Ok(self.add(Some(path.as_ref()), data))
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn path(&self, id: SourceId) -> Option<&Path> {
+        self.sources[id.index()].path.as_deref()
+    }
+
+    /// Returns the most recently added source with path `path`.
+    pub fn find<P: AsRef<Path>>(&self, path: P) -> Option<SourceId> {
+        let path = path.as_ref();
+        self.sources
+            .iter()
+            .rposition(|s| s.path.as_deref() == Some(path))
+            .map(|i| SourceId(i as u32))
+    }
+
+    pub fn data(&self, id: SourceId) -> &[u8] {
+        &self.sources[id.index()].data
+    }
+
+    pub fn content_hash(&self, id: SourceId) -> ContentHash {
+        self.sources[id.index()].hash
+    }
+
+    /// Returns all sources sharing contents with hash `hash`.
+    pub fn sources_with_hash(&self, hash: ContentHash) -> impl Iterator<Item = SourceId> + '_ {
+        self.sources
+            .iter()
+            .enumerate()
+            .filter(move |(_, s)| s.hash == hash)
+            .map(|(i, _)| SourceId(i as u32))
+    }
+
+    /// Number of distinct buffers held, which can be lower than `len()`.
+    pub fn unique_contents(&self) -> usize {
+        self.contents.values().map(|b| b.len()).sum()
+    }
+
+    pub fn char_reader(&self, id: SourceId) -> MemCharReader {
+        let s = &self.sources[id.index()];
+        match s.path {
+            Some(ref path) => MemCharReader::with_path(path, &s.data),
+            None => MemCharReader::new(&s.data),
+        }
+    }
+
+    /// Quotes source `id`, like `Reader::quote()` does. Text of quoted lines is interned, so
+    /// that many diagnostics quoting the same lines do not hold separate copies of it.
+    pub fn quote(
+        &self,
+        id: SourceId,
+        from: Position,
+        to: Position,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        let s = &self.sources[id.index()];
+        let (off1, off2, line) = s.line_map.frame(from, to, lines_before, lines_after);
+        let key = (s.data.as_ptr() as usize, off1, off2);
+        let source = self
+            .lines
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key)
+            .or_insert_with(|| String::from_utf8_lossy(&s.data[off1..off2]).into())
+            .clone();
+        let mut q = Quote::with_source(s.path.clone(), from, to, off1, line, source, message);
+        q.source_id = Some(id);
+        q
+    }
+
+    /// Like `quote()`, for span within its source.
+    pub fn quote_span(
+        &self,
+        span: SourceSpan,
+        lines_before: u32,
+        lines_after: u32,
+        message: Cow<str>,
+    ) -> Quote {
+        let SourceSpan { source, span } = span;
+        self.quote(source, span.start, span.end, lines_before, lines_after, message)
+    }
+
+    /// Number of distinct quoted texts held.
+    pub fn interned_quotes(&self) -> usize {
+        self.lines.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Extracts lines covered by `span` from source `id`, surrounded by up to
+    /// `context_lines` lines on each side.
+    pub fn snippet(&self, id: SourceId, span: Span, context_lines: u32) -> Snippet {
+        let data = self.data(id);
+        let start = span.start.offset.min(data.len());
+        let end = span.end.offset.max(start).min(data.len());
+
+        let mut first = line_start(data, start);
+        let mut first_line = span.start.line;
+        // `first > 0` guards against spans claiming more lines before them than there are
+        while first > 0 && first_line > 0 && span.start.line - first_line < context_lines {
+            first = line_start(data, first - 1);
+            first_line -= 1;
+        }
+
+        let mut last = line_end(data, end);
+        let mut after = 0;
+        while after < context_lines && last < data.len() {
+            last = line_end(data, last + 1);
+            after += 1;
+        }
+
+        let mut lines = Vec::new();
+        let mut number = first_line + 1;
+        let mut off = first;
+        loop {
+            let e = line_end(data, off);
+            let mut text_end = e;
+            if text_end > off && data[text_end - 1] == b'\r' {
+                text_end -= 1;
+            }
+            let highlight = if start == end {
+                if start >= off && start <= e {
+                    let s = start.min(text_end) - off;
+                    Some(s..s)
+                } else {
+                    None
+                }
+            } else if start < text_end.max(off + 1) && end > off {
+                Some(start.max(off) - off..end.min(text_end) - off)
+            } else {
+                None
+            };
+            lines.push(SnippetLine {
+                number,
+                offset: off,
+                text: String::from_utf8_lossy(&data[off..text_end]).into(),
+                highlight,
+            });
+            if e >= last {
+                break;
+            }
+            off = e + 1;
+            number += 1;
+        }
+
+        Snippet {
+            path: self.path(id).map(|p| p.to_path_buf()),
+            lines,
+            highlight: span,
+        }
+    }
+}
+
+fn line_start(data: &[u8], offset: usize) -> usize {
+    data[..offset]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |p| p + 1)
+}
+
+fn line_end(data: &[u8], offset: usize) -> usize {
+    data[offset..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |p| offset + p)
+}
+
+/// Structured excerpt of source text, suitable for custom rendering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub path: Option<PathBuf>,
+    pub lines: Vec<SnippetLine>,
+    pub highlight: Span,
+}
+
+/// Single line of a [`Snippet`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnippetLine {
+    /// One-based line number.
+    pub number: u32,
+    /// Byte offset of the line start in source.
+    pub offset: usize,
+    /// Line text without the line terminator.
+    pub text: String,
+    /// Highlighted byte range within `text`, if the line is covered by the span.
+    pub highlight: Option<std::ops::Range<usize>>,
+}
+
+impl Snippet {
+    pub fn highlighted_lines(&self) -> impl Iterator<Item = &SnippetLine> {
+        self.lines.iter().filter(|l| l.highlight.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> (SourceMap, SourceId) {
+        let mut map = SourceMap::new();
+        let id = map.add(
+            Some(Path::new("test.txt")),
+            b"line 1\nline 2\r\nline 3\nline 4\n".to_vec(),
+        );
+        (map, id)
+    }
+
+    #[test]
+    fn snippet_single_line() {
+        let (map, id) = source();
+        let span = Span::with(12, 1, 5, 13, 1, 6);
+        let s = map.snippet(id, span, 1);
+        assert_eq!(s.path.as_ref().unwrap(), Path::new("test.txt"));
+        assert_eq!(s.lines.len(), 3);
+        assert_eq!(s.lines[0].number, 1);
+        assert_eq!(s.lines[0].highlight, None);
+        assert_eq!(s.lines[1].text, "line 2");
+        assert_eq!(s.lines[1].highlight, Some(5..6));
+        assert_eq!(s.lines[2].number, 3);
+        assert_eq!(s.lines[2].offset, 15);
+        assert_eq!(s.highlighted_lines().count(), 1);
+    }
+
+    #[test]
+    fn snippet_multi_line() {
+        let (map, id) = source();
+        let span = Span::with(10, 1, 3, 18, 2, 3);
+        let s = map.snippet(id, span, 0);
+        assert_eq!(s.lines.len(), 2);
+        assert_eq!(s.lines[0].highlight, Some(3..6));
+        assert_eq!(s.lines[1].highlight, Some(0..3));
+    }
+
+    #[test]
+    fn snippet_context_clamped() {
+        let (map, id) = source();
+        let span = Span::with(0, 0, 0, 4, 0, 4);
+        let s = map.snippet(id, span, 10);
+        assert_eq!(s.lines.first().unwrap().number, 1);
+        assert_eq!(s.lines.last().unwrap().number, 5);
+        assert_eq!(s.lines.last().unwrap().text, "");
+    }
+
+    #[test]
+    fn snippet_line_beyond_offset() {
+        let (map, id) = source();
+        let span = Span::with(8, 5, 1, 9, 5, 2);
+        let s = map.snippet(id, span, 3);
+        assert_eq!(s.lines[0].offset, 0);
+        assert_eq!(s.lines[0].text, "line 1");
+        let highlighted: Vec<_> = s.highlighted_lines().collect();
+        assert_eq!(highlighted.len(), 1);
+        assert_eq!(highlighted[0].text, "line 2");
+        assert_eq!(highlighted[0].highlight, Some(1..2));
+    }
+
+    #[test]
+    fn identical_contents_are_shared() {
+        let mut map = SourceMap::new();
+        let a = map.add(Some(Path::new("a/inc.h")), b"#define X 1\n".to_vec());
+        let b = map.add(Some(Path::new("b/inc.h")), b"#define X 1\n".to_vec());
+        let c = map.add(None, b"#define X 2\n".to_vec());
+        assert_ne!(a, b);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.unique_contents(), 2);
+        assert_eq!(map.content_hash(a), map.content_hash(b));
+        assert_ne!(map.content_hash(a), map.content_hash(c));
+        assert_eq!(map.data(a).as_ptr(), map.data(b).as_ptr());
+        assert_eq!(map.path(b), Some(Path::new("b/inc.h")));
+        assert_eq!(map.sources_with_hash(map.content_hash(a)).collect::<Vec<_>>(), vec![a, b]);
+    }
+
+    #[test]
+    fn quoted_lines_are_interned() {
+        let mut map = SourceMap::new();
+        let a = map.add(Some(Path::new("a.txt")), b"x = bad!();\ny = bad!();\n".to_vec());
+        let b = map.add(Some(Path::new("b.txt")), b"x = bad!();\ny = bad!();\n".to_vec());
+        let q1 = map.quote(a, Position::with(4, 0, 4), Position::with(8, 0, 8), 0, 0, "here".into());
+        let q2 = map.quote(a, Position::with(5, 0, 5), Position::with(7, 0, 7), 0, 0, "".into());
+        let q3 = map.quote(b, Position::with(4, 0, 4), Position::with(8, 0, 8), 0, 0, "".into());
+        let q4 = map.quote(a, Position::with(16, 1, 4), Position::with(20, 1, 8), 0, 0, "".into());
+        assert_eq!(q1.source(), "x = bad!();");
+        assert_eq!(q1.source().as_ptr(), q2.source().as_ptr());
+        assert_eq!(q1.source().as_ptr(), q3.source().as_ptr());
+        assert_eq!(q3.path(), Some(Path::new("b.txt")));
+        assert_eq!(q4.source(), "y = bad!();");
+        assert_eq!(q4.line(), 1);
+        assert_eq!(map.interned_quotes(), 2);
+    }
+
+    #[test]
+    fn quotes_reference_source() {
+        let (map, id) = source();
+        let span = SourceSpan::new(id, Span::with(12, 1, 5, 13, 1, 6));
+        let q1 = map.quote_span(span, 0, 0, "here".into());
+        let q2 = map.quote(id, Position::with(0, 0, 0), Position::with(4, 0, 4), 0, 0, "".into());
+        assert_eq!(q1.source_id(), Some(id));
+        assert_eq!(q1.source_span(), Some(span));
+        assert_eq!(q1.source(), "line 2\r");
+        assert!(std::ptr::eq(q1.path().unwrap(), q2.path().unwrap()));
+
+        let mut r = map.char_reader(id);
+        assert_eq!(r.quote_default(span.span.start, span.span.end, "".into()).source_id(), None);
+    }
+
+    #[test]
+    fn snippet_empty_span() {
+        let (map, id) = source();
+        let span = Span::with(6, 0, 6, 6, 0, 6);
+        let s = map.snippet(id, span, 0);
+        assert_eq!(s.lines.len(), 1);
+        assert_eq!(s.lines[0].highlight, Some(6..6));
+    }
+}
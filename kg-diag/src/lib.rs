@@ -4,58 +4,109 @@
 extern crate kg_display_derive;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate lazy_static;
 
+pub use self::config::{configure, Config};
+pub use self::context::Context;
 pub use self::detail::{Detail, Severity, DetailExt};
-pub use self::diag::{BasicDiag, Diag, ParseDiag, SimpleDiag};
+pub use self::diag::{BasicDiag, Diag, ParseDiag, SimpleDiag, SortKey};
+pub use self::diff::{diff, DiagDiff};
+pub use self::emit::{Emitter, Routing};
 pub use self::io::{
-    ByteReader, CharReader, FileBuffer, FileType, IoErrorDetail, IoResult, LexTerm, LexToken,
-    MemByteReader, MemCharReader, OpType, Position, Quote, Reader, Span,
+    ByteReader, ChainReader, CharReader, CharReaderExt, ColumnMode, ContentHash, FileBuffer,
+    FileCharReader, FileType, IncludeReader, IncludeSite, IoErrorDetail, IoResult, LexTerm,
+    LexToken, LimitKind, Limited, Limits, LineBreakPolicy, LineMap, Lookahead, Mark, MemByteReader,
+    MemCharReader, NormalizedNewlines, OpType, PopMode, Position, PositionConfig, Quote,
+    RawByteReader, Reader, ReaderSnapshot, RecordingReader, SeekCharReader, Snippet, SnippetLine,
+    SourceId, SourceMap, SourceSpan, Span, Transaction,
 };
-pub use self::multi::{Diags, Errors};
+pub use self::multi::{Checkpoint, Diags, Errors};
+pub use self::record::{DiagKey, DiagRecord, QuoteRecord};
 pub use self::stacktrace::Stacktrace;
+pub use self::suggestion::{apply_suggestions, Applicability, Suggestion};
 
+pub mod config;
+pub mod context;
 mod detail;
 mod diag;
+mod diff;
+pub mod emit;
 pub mod io;
 pub mod parse;
 mod multi;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod policy;
+mod record;
+#[cfg(feature = "registry")]
+pub mod registry;
+pub mod render;
+#[cfg(feature = "sarif")]
+pub mod sarif;
+#[cfg(feature = "sentry")]
+pub mod sentry;
 mod stacktrace;
-
+mod stats;
+mod suggestion;
+
+#[cfg(feature = "stats")]
+pub use self::stats::{reset_stats, stats, Stats};
+#[cfg(feature = "async")]
+pub use self::io::{AsyncByteReader, AsyncCharReader, AsyncStreamReader};
+#[cfg(feature = "encoding")]
+pub use self::io::{DecodedCharReader, DecodedInput};
+#[cfg(feature = "metrics")]
+pub use self::io::ReaderMetrics;
+
+#[cfg(feature = "registry")]
+#[doc(hidden)]
+pub use inventory;
+#[doc(hidden)]
+pub use serde;
+
+/// Creates `BasicDiag` from detail `$kind`. If `$hook` (any `Fn(&dyn Diag)`, e.g. a closure
+/// logging diagnostics) is given, it is called with the created diagnostic before it is returned.
 #[macro_export]
 macro_rules! basic_diag {
     ($kind: expr) => {{
         $crate::BasicDiag::from($kind)
     }};
-    ($logger: expr, $kind: expr) => {{
+    ($hook: expr, $kind: expr) => {{
         let e = $crate::BasicDiag::from($kind);
-        slog_debug!($logger, "diagnostic created:\n{}", e);
+        ($hook)(&e as &dyn $crate::Diag);
         e
     }};
 }
 
+/// Creates `ParseDiag` from detail `$kind`, optionally quoting spans `$p1..$p2` from `$reader`
+/// with given messages. Like in `basic_diag!`, an optional `$hook` is called with the created
+/// diagnostic.
 #[macro_export]
 macro_rules! parse_diag {
     ($kind: expr) => {{
         $crate::ParseDiag::from($kind)
     }};
     ($kind: expr, $reader: expr, { $($p1: expr, $p2: expr => $msg: expr),+ $(,)* }) => {{
+        use $crate::Reader as _;
         let mut e = $crate::ParseDiag::from($kind);
         $(
-        e.add_quote($reader.quote($p1, $p2, 2, 2, $msg.into()));
+        e.add_quote($reader.quote_default($p1, $p2, $msg.into()));
         )+
         e
     }};
-    ($logger: expr, $kind: expr) => {{
+    ($hook: expr, $kind: expr) => {{
         let e = $crate::ParseDiag::from($kind);
-        slog_debug!("parse diagnostic created:\n{}", e);
+        ($hook)(&e as &dyn $crate::Diag);
         e
     }};
-    ($logger: expr, $kind: expr, $reader: expr, { $($p1: expr, $p2: expr => $msg: expr),+ $(,)* }) => {{
+    ($hook: expr, $kind: expr, $reader: expr, { $($p1: expr, $p2: expr => $msg: expr),+ $(,)* }) => {{
+        use $crate::Reader as _;
         let mut e = $crate::ParseDiag::from($kind);
         $(
-        e.add_quote($reader.quote($p1, $p2, 2, 2, $msg.into()));
+        e.add_quote($reader.quote_default($p1, $p2, $msg.into()));
         )+
-        slog_debug!("parse diagnostic created:\n{}", e);
+        ($hook)(&e as &dyn $crate::Diag);
         e
     }};
 }
@@ -3,21 +3,37 @@ use super::*;
 #[derive(Debug)]
 pub struct Diags {
     diags: Vec<Box<dyn Diag>>,
+    deferred: Vec<Deferred>,
+    /// Number of diagnostics ever deferred, used to number deferred ones.
+    deferred_count: usize,
     max_severity: Severity,
 }
 
+#[derive(Debug)]
+struct Deferred {
+    index: usize,
+    key: String,
+    diag: Box<dyn Diag>,
+}
+
 impl Diags {
     pub fn new() -> Diags {
         Diags {
             diags: Vec::new(),
+            deferred: Vec::new(),
+            deferred_count: 0,
             max_severity: Severity::Info,
         }
     }
 
     pub fn add_diag<D: Diag>(&mut self, diag: D) -> Result<(), Errors> {
-        self.max_severity = std::cmp::max(self.max_severity, diag.detail().severity());
-        let recover = diag.detail().severity().is_recoverable();
-        self.diags.push(Box::new(diag));
+        self.push(Box::new(diag))
+    }
+
+    fn push(&mut self, diag: Box<dyn Diag>) -> Result<(), Errors> {
+        self.max_severity = std::cmp::max(self.max_severity, diag.effective_severity());
+        let recover = diag.effective_severity().is_recoverable();
+        self.diags.push(diag);
         if recover {
             Ok(())
         } else {
@@ -25,6 +41,91 @@ impl Diags {
         }
     }
 
+    /// Holds `diag` under `key` until it is either confirmed with `confirm()` or discarded with
+    /// `cancel()`, e.g. an undefined symbol reference that might still be defined later.
+    pub fn add_deferred<K: Into<String>, D: Diag>(&mut self, key: K, diag: D) {
+        self.deferred.push(Deferred {
+            index: self.deferred_count,
+            key: key.into(),
+            diag: Box::new(diag),
+        });
+        self.deferred_count += 1;
+    }
+
+    pub fn has_deferred(&self, key: &str) -> bool {
+        self.deferred.iter().any(|d| d.key == key)
+    }
+
+    /// Adds all diagnostics deferred under `key`, in order they were deferred.
+    pub fn confirm(&mut self, key: &str) -> Result<(), Errors> {
+        let mut res = Ok(());
+        for diag in self.take_deferred(key) {
+            if let Err(err) = self.push(diag) {
+                res = Err(err);
+            }
+        }
+        res
+    }
+
+    /// Discards all diagnostics deferred under `key`.
+    pub fn cancel(&mut self, key: &str) {
+        self.deferred.retain(|d| d.key != key);
+    }
+
+    /// Confirms all diagnostics still deferred, typically at the end of analysis.
+    pub fn confirm_all(&mut self) -> Result<(), Errors> {
+        let mut res = Ok(());
+        for d in std::mem::replace(&mut self.deferred, Vec::new()) {
+            if let Err(err) = self.push(d.diag) {
+                res = Err(err);
+            }
+        }
+        res
+    }
+
+    fn take_deferred(&mut self, key: &str) -> Vec<Box<dyn Diag>> {
+        let mut taken = Vec::new();
+        let mut i = 0;
+        while i < self.deferred.len() {
+            if self.deferred[i].key == key {
+                taken.push(self.deferred.remove(i).diag);
+            } else {
+                i += 1;
+            }
+        }
+        taken
+    }
+
+    pub fn diags(&self) -> &[Box<dyn Diag>] {
+        &self.diags
+    }
+
+    /// Orders collected diagnostics by their `Diag::sort_key()`.
+    pub fn sort(&mut self) {
+        self.diags.sort_by_cached_key(|d| d.sort_key());
+    }
+
+    /// Marks current state, so that diagnostics added afterwards can be discarded with
+    /// `rollback_to()`, e.g. when a speculative parsing attempt is abandoned.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            len: self.diags.len(),
+            deferred: self.deferred_count,
+            max_severity: self.max_severity,
+        }
+    }
+
+    /// Discards diagnostics added or deferred since `checkpoint` was taken. Diagnostics must
+    /// not be sorted in between, as that would mix up discarded and retained ones. Diagnostics
+    /// deferred before `checkpoint` and confirmed or cancelled afterwards are not restored.
+    pub fn rollback_to(&mut self, checkpoint: Checkpoint) {
+        debug_assert!(checkpoint.len <= self.diags.len(), "checkpoint taken after rollback");
+        self.diags.truncate(checkpoint.len);
+        self.deferred.retain(|d| d.index < checkpoint.deferred);
+        self.deferred_count = checkpoint.deferred;
+        self.max_severity = checkpoint.max_severity;
+    }
+
     pub fn result<T>(&self, res: T) -> Result<T, Errors> {
         if self.max_severity.is_error() {
             Err(Errors::new(self.max_severity))
@@ -34,6 +135,14 @@ impl Diags {
     }
 }
 
+/// State of `Diags` at some point, see `Diags::checkpoint()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    len: usize,
+    deferred: usize,
+    max_severity: Severity,
+}
+
 pub trait ResultExt<T, E: Diag> {
     fn add_err(self, diags: &mut Diags) -> Result<T, Errors>;
 }
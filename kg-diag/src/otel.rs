@@ -0,0 +1,93 @@
+//! Export of diagnostics to OpenTelemetry. Each diagnostic is recorded as an event on the
+//! currently active span, so that failures can be correlated with traces of the requests
+//! that caused them. `record()` can be passed directly as a hook to `basic_diag!` and
+//! `parse_diag!`:
+//!
+//! ```ignore
+//! let err = basic_diag!(kg_diag::otel::record, IoErrorDetail::Fmt);
+//! ```
+
+use opentelemetry::trace::get_active_span;
+use opentelemetry::KeyValue;
+
+use super::*;
+
+/// Name of events recorded by `record()`.
+pub const EVENT_NAME: &str = "diagnostic";
+
+/// Log level name of `severity`, as used by OpenTelemetry log data model.
+pub fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info => "INFO",
+        Severity::Warning => "WARN",
+        Severity::Error | Severity::Failure => "ERROR",
+        Severity::Critical => "FATAL",
+    }
+}
+
+/// Event attributes describing `diag`. Location attributes are taken from the detail span,
+/// or the first quote if the detail has none.
+pub fn attributes(diag: &dyn Diag) -> Vec<KeyValue> {
+    let detail = diag.detail();
+    let severity = diag.effective_severity();
+    let mut attrs = vec![
+        KeyValue::new("level", level(severity)),
+        KeyValue::new("diag.severity", severity.to_string()),
+        KeyValue::new("diag.code", diag.effective_code_string()),
+        KeyValue::new("diag.message", detail.to_string()),
+    ];
+    let quote = diag.quotes().first();
+    if let Some(path) = quote.and_then(|q| q.path()) {
+        attrs.push(KeyValue::new("code.filepath", path.to_string_lossy().into_owned()));
+    }
+    if let Some(span) = detail.span().or_else(|| quote.map(|q| q.span())) {
+        attrs.push(KeyValue::new("code.lineno", (span.start.line + 1) as i64));
+        attrs.push(KeyValue::new("diag.span", span.to_string()));
+    }
+    if let Some(cause) = diag.cause() {
+        attrs.push(KeyValue::new("diag.cause", cause.detail().to_string()));
+    }
+    attrs
+}
+
+/// Records `diag` as an event on the currently active span.
+pub fn record(diag: &dyn Diag) {
+    let attrs = attributes(diag);
+    get_active_span(|span| span.add_event(EVENT_NAME.to_string(), attrs));
+}
+
+/// Records all diagnostics from `diags`, in order.
+pub fn record_all<'a, I: IntoIterator<Item = &'a dyn Diag>>(diags: I) {
+    for d in diags {
+        record(d);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(attrs: &[KeyValue], key: &str) -> Option<String> {
+        attrs
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.as_str().into_owned())
+    }
+
+    #[test]
+    fn diag_attributes() {
+        let mut r = MemCharReader::with_path("input.txt", b"a = 1\nb = x\n");
+        let d = ParseDiag::with_span_quote(
+            IoErrorDetail::MissingFinalNewline { pos: Position::with(10, 1, 4) },
+            &mut r,
+        );
+        let attrs = attributes(&d);
+        assert_eq!(attr(&attrs, "level").unwrap(), "WARN");
+        assert_eq!(attr(&attrs, "code.filepath").unwrap(), "input.txt");
+        assert_eq!(attr(&attrs, "code.lineno").unwrap(), "2");
+        assert_eq!(attr(&attrs, "diag.code").unwrap(), d.effective_code_string());
+        assert!(attr(&attrs, "diag.cause").is_none());
+
+        record(&d);
+    }
+}
@@ -21,7 +21,7 @@ impl std::fmt::Display for Input {
 }
 
 
-#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone)]
+#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone, Serialize)]
 pub enum Expected {
     Byte(u8),
     ByteRange(u8, u8),
@@ -30,6 +30,8 @@ pub enum Expected {
     Custom(String),
     OneOf(Vec<Expected>),
     Or(Box<Expected>, Box<Expected>),
+    /// Expectation along with an example of matching input, e.g. `"250ms"` for a duration.
+    WithExample(Box<Expected>, &'static str),
 }
 
 impl Expected {
@@ -41,6 +43,10 @@ impl Expected {
             Expected::OneOf(elems)
         }
     }
+
+    pub fn with_example(self, example: &'static str) -> Expected {
+        Expected::WithExample(Box::new(self), example)
+    }
 }
 
 impl std::fmt::Display for Expected {
@@ -53,6 +59,7 @@ impl std::fmt::Display for Expected {
             Expected::Custom(ref s) => write!(f, "{}", s),
             Expected::OneOf(ref e) => write!(f, "one of: {}", ListDisplay(e)),
             Expected::Or(ref a, ref b) => write!(f, "{} or {}", a, b),
+            Expected::WithExample(ref e, example) => write!(f, "{} (e.g. {:?})", e, example),
         }
     }
 }
@@ -66,20 +73,81 @@ pub enum NumericalErrorKind {
     Underflow(f64),
     #[display("invalid format error")]
     Invalid,
+    #[display("empty literal")]
+    Empty,
+    /// Literal contains character `found` at byte `offset` (relative to the literal start).
+    #[display("invalid digit '{found}' at offset {offset}")]
+    InvalidDigit { offset: usize, found: char },
 }
 
 impl NumericalErrorKind {
     pub fn has_float(&self) -> bool {
         match *self {
             NumericalErrorKind::Overflow(n) | NumericalErrorKind::Underflow(n) => !n.is_nan(),
-            NumericalErrorKind::Invalid => false,
+            NumericalErrorKind::Invalid
+            | NumericalErrorKind::Empty
+            | NumericalErrorKind::InvalidDigit { .. } => false,
         }
     }
 
     pub fn as_float(&self) -> f64 {
         match *self {
             NumericalErrorKind::Overflow(n) | NumericalErrorKind::Underflow(n) => n,
-            NumericalErrorKind::Invalid => std::f64::NAN,
+            NumericalErrorKind::Invalid
+            | NumericalErrorKind::Empty
+            | NumericalErrorKind::InvalidDigit { .. } => std::f64::NAN,
+        }
+    }
+
+    /// Maps integer parse error `err` of `literal`. Overflow values are taken from `literal`
+    /// parsed as a float, if possible.
+    pub fn from_int_error(err: &std::num::ParseIntError, literal: &str) -> NumericalErrorKind {
+        use std::num::IntErrorKind;
+
+        match *err.kind() {
+            IntErrorKind::Empty => NumericalErrorKind::Empty,
+            IntErrorKind::InvalidDigit => Self::invalid_digit(literal),
+            IntErrorKind::PosOverflow => {
+                NumericalErrorKind::Overflow(literal.parse().unwrap_or(std::f64::NAN))
+            }
+            IntErrorKind::NegOverflow => {
+                NumericalErrorKind::Underflow(literal.parse().unwrap_or(std::f64::NAN))
+            }
+            _ => NumericalErrorKind::Invalid,
+        }
+    }
+
+    /// Error kind for `literal` which failed to parse as a float.
+    pub fn from_float_literal(literal: &str) -> NumericalErrorKind {
+        if literal.is_empty() {
+            NumericalErrorKind::Empty
+        } else {
+            Self::invalid_digit(literal)
+        }
+    }
+
+    /// Locates the first character of `literal` not matching float literal syntax
+    /// (`[+-]digits[.digits][(e|E)[+-]digits]`).
+    fn invalid_digit(literal: &str) -> NumericalErrorKind {
+        let b = literal.as_bytes();
+        let digits = |mut i: usize| {
+            while i < b.len() && b[i].is_ascii_digit() {
+                i += 1;
+            }
+            i
+        };
+        let sign = |i: usize| if i < b.len() && (b[i] == b'+' || b[i] == b'-') { i + 1 } else { i };
+
+        let mut i = digits(sign(0));
+        if i < b.len() && b[i] == b'.' {
+            i = digits(i + 1);
+        }
+        if i < b.len() && (b[i] == b'e' || b[i] == b'E') {
+            i = digits(sign(i + 1));
+        }
+        match literal[i..].chars().next() {
+            Some(found) => NumericalErrorKind::InvalidDigit { offset: i, found },
+            None => NumericalErrorKind::Invalid,
         }
     }
 }
@@ -90,6 +158,11 @@ impl PartialEq for NumericalErrorKind {
             (NumericalErrorKind::Overflow(_), NumericalErrorKind::Overflow(_)) => true,
             (NumericalErrorKind::Underflow(_), NumericalErrorKind::Underflow(_)) => true,
             (NumericalErrorKind::Invalid, NumericalErrorKind::Invalid) => true,
+            (NumericalErrorKind::Empty, NumericalErrorKind::Empty) => true,
+            (
+                NumericalErrorKind::InvalidDigit { offset: o1, found: f1 },
+                NumericalErrorKind::InvalidDigit { offset: o2, found: f2 },
+            ) => o1 == o2 && f1 == f2,
             _ => false,
         }
     }
@@ -118,13 +191,43 @@ pub enum ParseErrorDetail {
     }
 }
 
+/// Codes of parse errors, guaranteed not to change. I/O errors wrapped in `ParseErrorDetail::Io`
+/// keep their own codes, so `CODE_RANGE` is the same as `IoErrorDetail::CODE_RANGE`.
+impl ParseErrorDetail {
+    pub const CODE_RANGE: std::ops::RangeInclusive<u32> = IoErrorDetail::CODE_RANGE;
+    pub const CODE_UNEXPECTED_EOF: u32 = 40;
+    pub const CODE_UNEXPECTED_INPUT: u32 = 41;
+    pub const CODE_NUMERICAL: u32 = 42;
+
+    /// All codes used by `ParseErrorDetail`, with short descriptions.
+    pub fn codes() -> Vec<(u32, &'static str)> {
+        let mut codes = IoErrorDetail::codes();
+        codes.push((Self::CODE_UNEXPECTED_EOF, "unexpected <EOF>"));
+        codes.push((Self::CODE_UNEXPECTED_INPUT, "unexpected input"));
+        codes.push((Self::CODE_NUMERICAL, "numerical error"));
+        codes.sort_by_key(|c| c.0);
+        codes
+    }
+
+    /// Checks if the error is caused by input ending prematurely, as opposed to malformed
+    /// input. When input arrives incrementally, parsing can be retried once more input is
+    /// available (see `MemCharReader::resume()`).
+    pub fn needs_more_input(&self) -> bool {
+        match *self {
+            ParseErrorDetail::Io(ref err) => err.needs_more_input(),
+            ParseErrorDetail::UnexpectedEof { .. } => true,
+            _ => false,
+        }
+    }
+}
+
 impl Detail for ParseErrorDetail {
     fn code(&self) -> u32 {
         match *self {
             ParseErrorDetail::Io(ref err) => err.code(),
-            ParseErrorDetail::UnexpectedEof { .. } => 40,
-            ParseErrorDetail::UnexpectedInput { .. } => 41,
-            ParseErrorDetail::Numerical { .. } => 42,
+            ParseErrorDetail::UnexpectedEof { .. } => Self::CODE_UNEXPECTED_EOF,
+            ParseErrorDetail::UnexpectedInput { .. } => Self::CODE_UNEXPECTED_INPUT,
+            ParseErrorDetail::Numerical { .. } => Self::CODE_NUMERICAL,
         }
     }
 }
@@ -2,8 +2,14 @@ use super::*;
 
 pub mod error;
 pub mod num;
+pub mod prologue;
+#[cfg(feature = "roman")]
+pub mod roman;
 
 pub use self::error::*;
 pub use self::num::*;
+pub use self::prologue::*;
+#[cfg(feature = "roman")]
+pub use self::roman::*;
 
 pub type ParseResult<T> = Result<T, ParseErrorDetail>;
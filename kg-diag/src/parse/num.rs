@@ -3,27 +3,41 @@ use super::*;
 const PARSE_TASK_NAME: &str = "paring a number literal";
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Notation {
-    #[display("d")]
     Decimal,
-    #[display("f")]
     Float,
-    #[display("e")]
     Exponent,
-    #[display("o")]
     Octal,
-    #[display("x")]
     Hex,
-    #[display("b")]
     Binary,
+    Percent,
+    Fraction,
+    /// Notation registered with `NumberParser::register_notation()`, identified by its index
+    Custom(u8),
+}
+
+impl std::fmt::Display for Notation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Notation::Decimal => write!(f, "d"),
+            Notation::Float => write!(f, "f"),
+            Notation::Exponent => write!(f, "e"),
+            Notation::Octal => write!(f, "o"),
+            Notation::Hex => write!(f, "x"),
+            Notation::Binary => write!(f, "b"),
+            Notation::Percent => write!(f, "p"),
+            Notation::Fraction => write!(f, "r"),
+            Notation::Custom(i) => write!(f, "c{}", i),
+        }
+    }
 }
 
 impl Notation {
     #[inline]
     pub fn radix(&self) -> u32 {
         match *self {
-            Notation::Decimal | Notation::Float | Notation::Exponent => 10,
+            Notation::Decimal | Notation::Float | Notation::Exponent | Notation::Percent | Notation::Fraction | Notation::Custom(_) => 10,
             Notation::Hex => 16,
             Notation::Octal => 8,
             Notation::Binary => 2,
@@ -87,34 +101,127 @@ impl Number {
 impl LexTerm for Number {}
 
 
-fn parse_simple_num<N: NotationConfig>(n: &N,
-                                       sign: Sign,
-                                       r: &mut dyn CharReader) -> ParseResult<LexToken<Number>> {
+/// Input of the number parsing state machine, shared by `parse_number()` and
+/// `parse_number_bytes()`. Byte input is seen as Latin-1 characters, which is exact for
+/// the ASCII characters number literals consist of.
+trait NumInput {
+    fn position(&self) -> Position;
+
+    fn seek(&mut self, pos: Position) -> IoResult<()>;
+
+    fn peek(&mut self, lookahead: usize) -> IoResult<Option<char>>;
+
+    fn skip(&mut self, n: usize) -> IoResult<()>;
+
+    /// Skips a run of decimal digits.
+    fn skip_digits(&mut self) -> IoResult<()>;
+
+    fn found(c: char) -> Input;
+}
+
+struct CharInput<'a>(&'a mut dyn CharReader);
+
+impl<'a> NumInput for CharInput<'a> {
+    fn position(&self) -> Position {
+        self.0.position()
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        self.0.seek(pos)
+    }
+
+    fn peek(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        self.0.peek_char(lookahead)
+    }
+
+    fn skip(&mut self, n: usize) -> IoResult<()> {
+        self.0.skip_chars(n)
+    }
+
+    fn skip_digits(&mut self) -> IoResult<()> {
+        self.0.skip_ascii_while(&mut |b| b.is_ascii_digit())?;
+        Ok(())
+    }
+
+    fn found(c: char) -> Input {
+        Input::Char(c)
+    }
+}
+
+struct ByteInput<'a>(&'a mut dyn ByteReader);
+
+impl<'a> NumInput for ByteInput<'a> {
+    fn position(&self) -> Position {
+        self.0.position()
+    }
+
+    fn seek(&mut self, pos: Position) -> IoResult<()> {
+        self.0.seek(pos)
+    }
+
+    fn peek(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+        Ok(self.0.peek_byte(lookahead)?.map(|b| b as char))
+    }
+
+    fn skip(&mut self, n: usize) -> IoResult<()> {
+        self.0.skip_bytes(n)
+    }
+
+    fn skip_digits(&mut self) -> IoResult<()> {
+        while let Some(b) = self.0.peek_byte(0)? {
+            if !b.is_ascii_digit() {
+                break;
+            }
+            self.0.next_byte()?;
+        }
+        Ok(())
+    }
+
+    fn found(c: char) -> Input {
+        Input::Byte(c as u8)
+    }
+}
+
+fn unexpected<I: NumInput, T>(r: &mut I, pos: Position, expected: Option<Expected>, task: &str) -> ParseResult<T> {
+    Err(match r.peek(0)? {
+        Some(c) => ParseErrorDetail::UnexpectedInput {
+            pos,
+            found: Some(I::found(c)),
+            expected,
+            task: task.into(),
+        },
+        None => ParseErrorDetail::UnexpectedEof {
+            pos,
+            expected,
+            task: task.into(),
+        }
+    })
+}
+
+fn parse_simple_num<N: NotationConfig, I: NumInput>(n: &N,
+                                                    sign: Sign,
+                                                    r: &mut I) -> ParseResult<LexToken<Number>> {
     let p1 = r.position();
     let mut p = p1;
 
     if sign == Sign::None || (sign == Sign::Minus && n.allow_minus()) || (sign == Sign::Plus && n.allow_plus()) {
         if sign != Sign::None {
-            r.skip_chars(1)?;
+            r.skip(1)?;
         }
-        r.skip_chars(n.prefix().len())?;
+        r.skip(n.prefix().len())?;
         p = r.position();
-        if !n.allow_underscores() {
-            r.skip_while(&mut |c| n.is_digit(c))?;
-        } else {
-            let mut digit = false;
-            while let Some(c) = r.peek_char(0)? {
-                if c == '_' {
-                    if !digit {
-                        break;
-                    }
-                } else if n.is_digit(c) {
-                    digit = true;
-                } else {
+        let mut digit = false;
+        while let Some(c) = r.peek(0)? {
+            if c == '_' && n.allow_underscores() {
+                if !digit {
                     break;
                 }
-                r.next_char()?;
+            } else if n.is_digit(c) {
+                digit = true;
+            } else {
+                break;
             }
+            r.skip(1)?;
         }
     }
 
@@ -122,28 +229,30 @@ fn parse_simple_num<N: NotationConfig>(n: &N,
     if p2 > p {
         Ok(LexToken::new(Number::new(sign, n.get_notation()), p1, p2))
     } else {
-        Err(match r.peek_char(0)? {
-            Some(c) => ParseErrorDetail::UnexpectedInput {
-                pos: p2,
-                found: Some(Input::Char(c)),
-                expected: Some(n.get_expected_digit()),
-                task: n.get_task_name().into(),
-            },
-            None => ParseErrorDetail::UnexpectedEof {
-                pos: p2,
-                expected: Some(n.get_expected_digit()),
-                task: n.get_task_name().into(),
-            }
-        })
+        unexpected(r, p2, Some(n.get_expected_digit()), n.get_task_name())
     }
 }
 
 
+/// Number notation that can be plugged into `NumberParser`, for literals the parser does not
+/// handle natively (see `RomanNotation` for an example).
+pub trait CustomNotation: std::fmt::Debug {
+    /// Checks whether literal in this notation starts at current reader position.
+    fn is_at_start(&self, r: &mut dyn CharReader) -> IoResult<bool>;
+
+    /// Consumes the literal, leaving reader right after its last character.
+    fn parse(&self, r: &mut dyn CharReader) -> ParseResult<()>;
+
+    /// Converts literal text previously consumed by `parse()` to its numeric value.
+    fn convert(&self, s: &str) -> Result<f64, NumericalErrorKind>;
+}
+
 pub struct NumberParser {
     pub decimal: DecimalConfig,
     pub hex: HexConfig,
     pub octal: OctalConfig,
     pub binary: BinaryConfig,
+    custom: Vec<Box<dyn CustomNotation>>,
     buffer: String,
 }
 
@@ -154,11 +263,25 @@ impl NumberParser {
             hex: HexConfig::new(),
             octal: OctalConfig::new(),
             binary: BinaryConfig::new(),
+            custom: Vec::new(),
             buffer: String::new(),
         }
     }
 
+    /// Registers custom notation, returning `Notation` that will be reported for its literals.
+    /// Custom notations are tried in registration order, before any of the built-in ones.
+    pub fn register_notation(&mut self, notation: Box<dyn CustomNotation>) -> Notation {
+        assert!(self.custom.len() <= std::u8::MAX as usize, "too many custom notations registered");
+        self.custom.push(notation);
+        Notation::Custom((self.custom.len() - 1) as u8)
+    }
+
     pub fn is_at_start(&self, r: &mut dyn CharReader) -> IoResult<bool> {
+        for n in self.custom.iter() {
+            if n.is_at_start(r)? {
+                return Ok(true);
+            }
+        }
         Ok(self.hex.is_at_start(r)?
             || self.octal.is_at_start(r)?
             || self.binary.is_at_start(r)?
@@ -181,44 +304,57 @@ impl NumberParser {
             });
         }
 
+        for (i, n) in self.custom.iter().enumerate() {
+            if n.is_at_start(r)? {
+                let p1 = r.position();
+                n.parse(r)?;
+                return Ok(LexToken::new(Number::new(Sign::None, Notation::Custom(i as u8)), p1, r.position()));
+            }
+        }
+
         if self.hex.is_at_start(r)? {
-            self.parse_hex(sign, r)
+            parse_simple_num(&self.hex, sign, &mut CharInput(r))
         } else if self.octal.is_at_start(r)? {
-            self.parse_octal(sign, r)
+            parse_simple_num(&self.octal, sign, &mut CharInput(r))
         } else if self.binary.is_at_start(r)? {
-            self.parse_binary(sign, r)
+            parse_simple_num(&self.binary, sign, &mut CharInput(r))
         } else if self.decimal.is_at_start(r)? {
-            self.parse_decimal(sign, r)
+            self.parse_decimal(sign, &mut CharInput(r))
         } else {
-            Err(match r.peek_char(0)? {
-                Some(c) => ParseErrorDetail::UnexpectedInput {
-                    pos: r.position(),
-                    found: Some(Input::Char(c)),
-                    expected: None,
-                    task: PARSE_TASK_NAME.into(),
-                },
-                None => ParseErrorDetail::UnexpectedEof {
-                    pos: r.position(),
-                    expected: None,
-                    task: PARSE_TASK_NAME.into(),
-                }
-            })
+            let pos = r.position();
+            unexpected(&mut CharInput(r), pos, None, PARSE_TASK_NAME)
         }
     }
 
-    fn parse_hex(&self, sign: Sign, r: &mut dyn CharReader) -> ParseResult<LexToken<Number>> {
-        parse_simple_num(&self.hex, sign, r)
-    }
-
-    fn parse_octal(&self, sign: Sign, r: &mut dyn CharReader) -> ParseResult<LexToken<Number>> {
-        parse_simple_num(&self.octal, sign, r)
-    }
+    /// Byte-oriented counterpart of `parse_number()` for ASCII input, avoiding UTF-8 decoding
+    /// overhead. Uses the same configuration, except that custom notations are not consulted.
+    pub fn parse_number_bytes(&self, r: &mut dyn ByteReader) -> ParseResult<LexToken<Number>> {
+        let sign = match r.peek_byte(0)? {
+            Some(b'-') => Sign::Minus,
+            Some(b'+') => Sign::Plus,
+            Some(_) => Sign::None,
+            None => return Err(ParseErrorDetail::UnexpectedEof {
+                pos: r.position(),
+                expected: None,
+                task: PARSE_TASK_NAME.into(),
+            }),
+        };
 
-    fn parse_binary(&self, sign: Sign, r: &mut dyn CharReader) -> ParseResult<LexToken<Number>> {
-        parse_simple_num(&self.binary, sign, r)
+        if self.hex.is_at_start_bytes(r)? {
+            parse_simple_num(&self.hex, sign, &mut ByteInput(r))
+        } else if self.octal.is_at_start_bytes(r)? {
+            parse_simple_num(&self.octal, sign, &mut ByteInput(r))
+        } else if self.binary.is_at_start_bytes(r)? {
+            parse_simple_num(&self.binary, sign, &mut ByteInput(r))
+        } else if self.decimal.is_at_start_bytes(r)? {
+            self.parse_decimal(sign, &mut ByteInput(r))
+        } else {
+            let pos = r.position();
+            unexpected(&mut ByteInput(r), pos, None, PARSE_TASK_NAME)
+        }
     }
 
-    fn parse_decimal(&self, sign: Sign, r: &mut dyn CharReader) -> ParseResult<LexToken<Number>> {
+    fn parse_decimal<I: NumInput>(&self, sign: Sign, r: &mut I) -> ParseResult<LexToken<Number>> {
         let p1 = r.position();
 
         let mut notation = None;
@@ -226,10 +362,10 @@ impl NumberParser {
 
         if sign == Sign::None || (sign == Sign::Minus && self.decimal.allow_minus) || (sign == Sign::Plus && self.decimal.allow_plus) {
             if sign != Sign::None {
-                r.skip_chars(1)?;
+                r.skip(1)?;
             }
 
-            while let Some(c) = r.peek_char(0)? {
+            while let Some(c) = r.peek(0)? {
                 if self.decimal.is_digit(c) {
                     match last {
                         ' ' => notation = Some(Notation::Decimal),
@@ -239,6 +375,9 @@ impl NumberParser {
                         _ => unreachable!(),
                     }
                     last = '0';
+                    // consume the whole digit run at once, state only changes on non-digits
+                    r.skip_digits()?;
+                    continue;
                 } else if c == '_' && self.decimal.allow_underscores && (last == '0' || last == 'e' || last == '-') {
                     // skip
                 } else if c == '.' && self.decimal.allow_float && last == '0' && notation == Some(Notation::Decimal) {
@@ -252,7 +391,7 @@ impl NumberParser {
                 } else {
                     break;
                 }
-                r.next_char()?;
+                r.skip(1)?;
             }
         }
 
@@ -261,7 +400,29 @@ impl NumberParser {
         if notation.is_some() {
             match last {
                 '0' => {
-                    return Ok(LexToken::new(Number::new(sign, notation.unwrap()), p1, p2));
+                    let notation = notation.unwrap();
+                    if self.decimal.allow_percent && r.peek(0)? == Some('%') {
+                        r.skip(1)?;
+                        return Ok(LexToken::new(Number::new(sign, Notation::Percent), p1, r.position()));
+                    }
+                    if self.decimal.allow_fraction && notation == Notation::Decimal && r.peek(0)? == Some('/') {
+                        if let Some(c) = r.peek(1)? {
+                            if self.decimal.is_digit(c) {
+                                r.skip(1)?;
+                                let mut digit = false;
+                                while let Some(c) = r.peek(0)? {
+                                    if self.decimal.is_digit(c) {
+                                        digit = true;
+                                    } else if c != '_' || !digit || !self.decimal.allow_underscores {
+                                        break;
+                                    }
+                                    r.skip(1)?;
+                                }
+                                return Ok(LexToken::new(Number::new(sign, Notation::Fraction), p1, r.position()));
+                            }
+                        }
+                    }
+                    return Ok(LexToken::new(Number::new(sign, notation), p1, p2));
                 }
                 '.' => {
                     let mut p = p2;
@@ -274,7 +435,12 @@ impl NumberParser {
             }
         }
 
-        let expected = match last {
+        let expected = self.decimal_expected(sign, last);
+        unexpected(r, p2, Some(expected), self.decimal.get_task_name())
+    }
+
+    fn decimal_expected(&self, sign: Sign, last: char) -> Expected {
+        match last {
             ' ' | '.' => {
                 let mut expected = Vec::new();
                 if sign == Sign::None {
@@ -299,21 +465,7 @@ impl NumberParser {
                 Expected::CharRange('0', '9')
             },
             _ => unreachable!(),
-        };
-
-        Err(match r.peek_char(0)? {
-            Some(c) => ParseErrorDetail::UnexpectedInput {
-                pos: p2,
-                found: Some(Input::Char(c)),
-                expected: Some(expected),
-                task: self.decimal.get_task_name().into(),
-            },
-            None => ParseErrorDetail::UnexpectedEof {
-                pos: p2,
-                expected: Some(expected),
-                task: self.decimal.get_task_name().into(),
-            }
-        })
+        }
     }
 
     pub fn convert_number_token<N: Numerical>(&mut self, n: &LexToken<Number>, r: &mut dyn CharReader) -> Result<N, ParseErrorDetail> {
@@ -321,25 +473,28 @@ impl NumberParser {
     }
 
     pub fn convert_number<N: Numerical>(&mut self, span: Span, sign: Sign, notation: Notation, r: &mut dyn CharReader) -> Result<N, ParseErrorDetail> {
+        let s = r.slice(span.start.offset, span.end.offset)?;
+        self.convert_str(span, sign, notation, &s)
+    }
+
+    pub fn convert_number_bytes_token<N: Numerical>(&mut self, n: &LexToken<Number>, r: &mut dyn ByteReader) -> Result<N, ParseErrorDetail> {
+        self.convert_number_bytes(n.span(), n.term().sign(), n.term().notation(), r)
+    }
+
+    pub fn convert_number_bytes<N: Numerical>(&mut self, span: Span, sign: Sign, notation: Notation, r: &mut dyn ByteReader) -> Result<N, ParseErrorDetail> {
+        let s = r.slice(span.start.offset, span.end.offset)?;
+        self.convert_str(span, sign, notation, &s)
+    }
+
+    /// Converts literal text `s`, spanning `span` in the input.
+    fn convert_str<N: Numerical>(&mut self, span: Span, sign: Sign, notation: Notation, s: &str) -> Result<N, ParseErrorDetail> {
         let res = match notation {
-            Notation::Decimal => {
-                let s = r.slice(span.start.offset + sign.len(), span.end.offset)?;
-                parse_decimal(sign, s.as_bytes())
-            }
-            Notation::Hex => {
-                let s = r.slice(span.start.offset + sign.len() + self.hex.prefix.len(), span.end.offset)?;
-                parse_hex(sign, s.as_bytes())
-            }
-            Notation::Octal => {
-                let s = r.slice(span.start.offset + sign.len() + self.octal.prefix.len(), span.end.offset)?;
-                parse_octal(sign, s.as_bytes())
-            }
-            Notation::Binary => {
-                let s = r.slice(span.start.offset + sign.len() + self.binary.prefix.len(), span.end.offset)?;
-                parse_binary(sign, s.as_bytes())
-            }
-            Notation::Float | Notation::Exponent => {
-                let s = r.slice(span.start.offset, span.end.offset)?;
+            Notation::Decimal => parse_decimal(sign, s[sign.len()..].as_bytes()),
+            Notation::Hex => parse_hex(sign, s[sign.len() + self.hex.prefix.len()..].as_bytes()),
+            Notation::Octal => parse_octal(sign, s[sign.len() + self.octal.prefix.len()..].as_bytes()),
+            Notation::Binary => parse_binary(sign, s[sign.len() + self.binary.prefix.len()..].as_bytes()),
+            Notation::Float | Notation::Exponent | Notation::Percent => {
+                let s = if notation == Notation::Percent { &s[..s.len() - 1] } else { s };
                 if self.decimal.allow_underscores {
                     self.buffer.clear();
                     for c in s.chars() {
@@ -347,11 +502,30 @@ impl NumberParser {
                             self.buffer.push(c);
                         }
                     }
-                    N::from_float_str(&self.buffer)
+                    match N::from_float_str(&self.buffer) {
+                        // offset into the buffer must account for removed underscores
+                        Err(NumericalErrorKind::InvalidDigit { offset, found }) => {
+                            let offset = s
+                                .char_indices()
+                                .filter(|&(_, c)| c != '_')
+                                .nth(self.buffer[..offset].chars().count())
+                                .map_or(s.len(), |(i, _)| i);
+                            Err(NumericalErrorKind::InvalidDigit { offset, found })
+                        }
+                        res => res,
+                    }
                 } else {
-                    N::from_float_str(&s)
+                    N::from_float_str(s)
                 }
             }
+            Notation::Fraction => match parse_fraction(sign, s[sign.len()..].as_bytes()) {
+                Ok(value) => N::from_float_str(&value.to_string()),
+                Err(err) => Err(err),
+            },
+            Notation::Custom(i) => match self.custom[i as usize].convert(s) {
+                Ok(value) => N::from_float_str(&value.to_string()),
+                Err(err) => Err(err),
+            },
         };
         res.map_err(|err| ParseErrorDetail::Numerical {
             span,
@@ -360,6 +534,28 @@ impl NumberParser {
     }
 }
 
+impl NumberParser {
+    pub fn convert_ratio_token(&mut self, n: &LexToken<Number>, r: &mut dyn CharReader) -> Result<f64, ParseErrorDetail> {
+        self.convert_ratio(n.span(), n.term().sign(), n.term().notation(), r)
+    }
+
+    /// Converts number literal to a ratio in range [0, 1]. Percent literals are divided by 100,
+    /// values outside of the range are reported as underflow/overflow errors.
+    pub fn convert_ratio(&mut self, span: Span, sign: Sign, notation: Notation, r: &mut dyn CharReader) -> Result<f64, ParseErrorDetail> {
+        let mut value: f64 = self.convert_number(span, sign, notation, r)?;
+        if notation == Notation::Percent {
+            value /= 100f64;
+        }
+        if value < 0f64 {
+            Err(ParseErrorDetail::Numerical { span, kind: NumericalErrorKind::Underflow(value) })
+        } else if value > 1f64 {
+            Err(ParseErrorDetail::Numerical { span, kind: NumericalErrorKind::Overflow(value) })
+        } else {
+            Ok(value)
+        }
+    }
+}
+
 impl std::fmt::Debug for NumberParser {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_struct("NumberParser")
@@ -367,6 +563,7 @@ impl std::fmt::Debug for NumberParser {
             .field("hex", &self.hex)
             .field("octal", &self.octal)
             .field("binary", &self.binary)
+            .field("custom", &self.custom)
             .finish()
     }
 }
@@ -427,6 +624,30 @@ trait NotationConfig: Sized {
         Ok(false)
     }
 
+    fn is_at_start_bytes(&self, r: &mut dyn ByteReader) -> IoResult<bool> {
+        if self.is_enabled() {
+            let off = match r.peek_byte(0)? {
+                Some(b'-') if self.allow_minus() => 1,
+                Some(b'+') if self.allow_plus() => 1,
+                Some(_) => 0,
+                None => return Ok(false),
+            };
+            if self.prefix().is_empty() {
+                return Ok(match r.peek_byte(off)? {
+                    Some(b) => self.is_digit(b as char),
+                    None => false,
+                });
+            }
+            for (i, &p) in self.prefix().as_bytes().iter().enumerate() {
+                if r.peek_byte(off + i)? != Some(p) {
+                    return Ok(false);
+                }
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
     fn is_digit(&self, c: char) -> bool;
 
     fn get_notation(&self) -> Notation;
@@ -447,6 +668,10 @@ pub struct DecimalConfig {
     pub allow_underscores: bool,
     pub allow_float: bool,
     pub allow_exponent: bool,
+    /// Accept `%` suffix, producing `Notation::Percent`
+    pub allow_percent: bool,
+    /// Accept `1/3` style literals, producing `Notation::Fraction`
+    pub allow_fraction: bool,
     pub case: Case,
 }
 
@@ -459,6 +684,8 @@ impl DecimalConfig {
             allow_underscores: true,
             allow_float: true,
             allow_exponent: true,
+            allow_percent: false,
+            allow_fraction: false,
             case: Case::Any,
         }
     }
@@ -499,6 +726,19 @@ impl NotationConfig for DecimalConfig {
         Ok(false)
     }
 
+    fn is_at_start_bytes(&self, r: &mut dyn ByteReader) -> IoResult<bool> {
+        if self.is_enabled() {
+            if let Some(b) = r.peek_byte(0)? {
+                if (b == b'-' && self.allow_minus()) || (b == b'+' && self.allow_plus()) {
+                    return Ok(true);
+                } else {
+                    return Ok(self.is_digit(b as char));
+                }
+            }
+        }
+        Ok(false)
+    }
+
     fn is_digit(&self, c: char) -> bool {
         c >= '0' && c <= '9'
     }
@@ -731,9 +971,17 @@ macro_rules! impl_numerical {
 
             #[inline(always)]
             fn from_float_str(s: &str) -> Result<Self, NumericalErrorKind> {
+                // integral literals are parsed exactly, others are truncated from f64
+                let err = match s.parse::<$ty>() {
+                    Ok(n) => return Ok(n),
+                    Err(err) => err,
+                };
+                if *err.kind() != std::num::IntErrorKind::InvalidDigit {
+                    return Err(NumericalErrorKind::from_int_error(&err, s));
+                }
                 let d: f64 = match s.parse::<f64>() {
                     Ok(d) => d,
-                    Err(_) => return Err(NumericalErrorKind::Invalid),
+                    Err(_) => return Err(NumericalErrorKind::from_float_literal(s)),
                 };
                 let min = Self::min_value() as f64;
                 let max = Self::max_value() as f64;
@@ -800,7 +1048,7 @@ impl Numerical for f32 {
 
     #[inline(always)]
     fn from_float_str(s: &str) -> Result<Self, NumericalErrorKind> {
-        s.parse::<f32>().map_err(|_| NumericalErrorKind::Invalid)
+        s.parse::<f32>().map_err(|_| NumericalErrorKind::from_float_literal(s))
     }
 
     #[inline(always)]
@@ -842,7 +1090,7 @@ impl Numerical for f64 {
 
     #[inline(always)]
     fn from_float_str(s: &str) -> Result<Self, NumericalErrorKind> {
-        s.parse::<f64>().map_err(|_| NumericalErrorKind::Invalid)
+        s.parse::<f64>().map_err(|_| NumericalErrorKind::from_float_literal(s))
     }
 
     #[inline(always)]
@@ -1020,6 +1268,18 @@ fn parse_hex<N: Numerical>(sign: Sign, s: &[u8]) -> Result<N, NumericalErrorKind
     Ok(n)
 }
 
+fn parse_fraction(sign: Sign, s: &[u8]) -> Result<f64, NumericalErrorKind> {
+    let slash = s.iter().position(|&b| b == b'/').ok_or(NumericalErrorKind::Invalid)?;
+    let numerator: f64 = parse_decimal(Sign::None, &s[..slash])?;
+    let denominator: f64 = parse_decimal(Sign::None, &s[slash + 1..])?;
+    if denominator == 0f64 {
+        return Err(NumericalErrorKind::Invalid);
+    }
+    let value = numerator / denominator;
+    Ok(if sign == Sign::Minus { -value } else { value })
+}
+
+
 
 #[cfg(test)]
 mod tests {
@@ -1110,4 +1370,225 @@ mod tests {
         assert_eq!(np.convert_number_token::<f32>(&n, &mut r).unwrap(), 0b10010011 as f32);
         assert_eq!(np.convert_number_token::<f64>(&n, &mut r).unwrap(), 0b10010011 as f64);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn can_parse_percent() {
+        let mut np = NumberParser::new();
+        np.decimal.allow_percent = true;
+        let mut r = MemCharReader::new(b"12.5% ");
+        let n = np.parse_number(&mut r).unwrap();
+        assert_eq!(n.term().notation(), Notation::Percent);
+        assert_eq!(n.end().offset, 5);
+        assert_eq!(np.convert_number_token::<f64>(&n, &mut r).unwrap(), 12.5f64);
+        assert_eq!(np.convert_ratio_token(&n, &mut r).unwrap(), 0.125f64);
+    }
+
+    #[test]
+    fn percent_disabled_by_default() {
+        let mut np = NumberParser::new();
+        let mut r = MemCharReader::new(b"50%");
+        let n = np.parse_number(&mut r).unwrap();
+        assert_eq!(n.term().notation(), Notation::Decimal);
+        assert_eq!(n.end().offset, 2);
+    }
+
+    #[test]
+    fn can_parse_fraction() {
+        let mut np = NumberParser::new();
+        np.decimal.allow_fraction = true;
+        let mut r = MemCharReader::new(b"1/4");
+        let n = np.parse_number(&mut r).unwrap();
+        assert_eq!(n.term().notation(), Notation::Fraction);
+        assert_eq!(n.end().offset, 3);
+        assert_eq!(np.convert_ratio_token(&n, &mut r).unwrap(), 0.25f64);
+    }
+
+    #[test]
+    fn fraction_requires_denominator_digit() {
+        let mut np = NumberParser::new();
+        np.decimal.allow_fraction = true;
+        let mut r = MemCharReader::new(b"1/x");
+        let n = np.parse_number(&mut r).unwrap();
+        assert_eq!(n.term().notation(), Notation::Decimal);
+        assert_eq!(n.end().offset, 1);
+    }
+
+    #[test]
+    fn ratio_out_of_range() {
+        let mut np = NumberParser::new();
+        np.decimal.allow_fraction = true;
+        let mut r = MemCharReader::new(b"3/2");
+        let n = np.parse_number(&mut r).unwrap();
+        let err = np.convert_ratio_token(&n, &mut r).unwrap_err();
+        assert_eq!(err, ParseErrorDetail::Numerical { span: n.span(), kind: NumericalErrorKind::Overflow(1.5) });
+
+        let mut r = MemCharReader::new(b"1/0");
+        let n = np.parse_number(&mut r).unwrap();
+        let err = np.convert_ratio_token(&n, &mut r).unwrap_err();
+        assert_eq!(err, ParseErrorDetail::Numerical { span: n.span(), kind: NumericalErrorKind::Invalid });
+    }
+
+    #[test]
+    fn conversion_error_kinds() {
+        assert_eq!(u8::from_float_str("255"), Ok(255));
+        assert_eq!(u8::from_float_str("2.5e1"), Ok(25));
+        assert_eq!(u8::from_float_str(""), Err(NumericalErrorKind::Empty));
+        assert_eq!(u8::from_float_str("256"), Err(NumericalErrorKind::Overflow(256.0)));
+        assert_eq!(i8::from_float_str("-129"), Err(NumericalErrorKind::Underflow(-129.0)));
+        assert_eq!(
+            i32::from_float_str("12.5x"),
+            Err(NumericalErrorKind::InvalidDigit { offset: 4, found: 'x' })
+        );
+        assert_eq!(
+            f64::from_float_str("-1.0e+q"),
+            Err(NumericalErrorKind::InvalidDigit { offset: 6, found: 'q' })
+        );
+        assert_eq!(f64::from_float_str("1e"), Err(NumericalErrorKind::Invalid));
+        assert_eq!(u64::from_float_str("18446744073709551615"), Ok(std::u64::MAX));
+    }
+
+    #[test]
+    fn can_parse_bytes() {
+        let np = NumberParser::new();
+        let inputs: &[(&str, Notation, usize)] = &[
+            ("12_345 ", Notation::Decimal, 6),
+            ("-0.25,", Notation::Float, 5),
+            ("1.5e-3]", Notation::Exponent, 6),
+            ("0xff_FF", Notation::Hex, 7),
+            ("0b101", Notation::Binary, 5),
+            ("12.", Notation::Decimal, 2),
+        ];
+        for &(input, notation, end) in inputs {
+            let mut r = MemByteReader::new(input.as_bytes());
+            let n = np.parse_number_bytes(&mut r).unwrap();
+            assert_eq!(n.term().notation(), notation, "notation of {}", input);
+            assert_eq!(n.end().offset, end, "end of {}", input);
+
+            let mut c = MemCharReader::new(input.as_bytes());
+            let m = np.parse_number(&mut c).unwrap();
+            assert_eq!(n.term(), m.term(), "char and byte parsing of {}", input);
+            assert_eq!(n.span(), m.span(), "char and byte parsing of {}", input);
+        }
+    }
+
+    #[test]
+    fn can_convert_bytes() {
+        let mut np = NumberParser::new();
+        let mut r = MemByteReader::new(b"-1_024");
+        let n = np.parse_number_bytes(&mut r).unwrap();
+        assert_eq!(np.convert_number_bytes_token::<i32>(&n, &mut r).unwrap(), -1024);
+    }
+
+    #[test]
+    fn bytes_unexpected_input() {
+        let np = NumberParser::new();
+        let mut r = MemByteReader::new(b"1e+x");
+        let err = np.parse_number_bytes(&mut r).unwrap_err();
+        match err {
+            ParseErrorDetail::UnexpectedInput { pos, found: Some(Input::Byte(b'x')), .. } => assert_eq!(pos.offset, 3),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    mod differential {
+        use super::*;
+        use std::borrow::Cow;
+        use std::path::Path;
+
+        /// Reader decoding every character, without the digit-run fast path of `MemCharReader`.
+        struct SlowCharReader<'a>(MemCharReader<'a>);
+
+        impl<'a> Reader for SlowCharReader<'a> {
+            fn path(&self) -> Option<&Path> {
+                self.0.path()
+            }
+
+            fn len(&self) -> Option<usize> {
+                self.0.len()
+            }
+
+            fn eof(&self) -> bool {
+                self.0.eof()
+            }
+
+            fn position(&self) -> Position {
+                self.0.position()
+            }
+
+            fn seek(&mut self, pos: Position) -> IoResult<()> {
+                self.0.seek(pos)
+            }
+
+            fn input(&mut self) -> IoResult<Cow<str>> {
+                self.0.input()
+            }
+
+            fn slice(&mut self, start: usize, end: usize) -> IoResult<Cow<str>> {
+                self.0.slice(start, end)
+            }
+
+            fn quote(&mut self, from: Position, to: Position, lines_before: u32, lines_after: u32, message: Cow<str>) -> Quote {
+                self.0.quote(from, to, lines_before, lines_after, message)
+            }
+        }
+
+        impl<'a> CharReader for SlowCharReader<'a> {
+            fn next_char(&mut self) -> IoResult<Option<char>> {
+                self.0.next_char()
+            }
+
+            fn peek_char(&mut self, lookahead: usize) -> IoResult<Option<char>> {
+                self.0.peek_char(lookahead)
+            }
+
+            fn peek_char_pos(&mut self, lookahead: usize) -> IoResult<Option<(char, Position)>> {
+                self.0.peek_char_pos(lookahead)
+            }
+
+            fn skip_chars(&mut self, skip: usize) -> IoResult<()> {
+                self.0.skip_chars(skip)
+            }
+
+            fn match_str(&mut self, s: &str) -> IoResult<bool> {
+                self.0.match_str(s)
+            }
+
+            fn match_str_term(&mut self, s: &str, f: &mut dyn FnMut(Option<char>) -> bool) -> IoResult<bool> {
+                self.0.match_str_term(s, f)
+            }
+        }
+
+        fn check(np: &NumberParser, input: &str) {
+            let mut fast = MemCharReader::new(input.as_bytes());
+            let mut slow = SlowCharReader(MemCharReader::new(input.as_bytes()));
+            let a = np.parse_number(&mut fast).map(|n| (n.term(), n.span()));
+            let b = np.parse_number(&mut slow).map(|n| (n.term(), n.span()));
+            assert_eq!(a, b, "parse result for {:?}", input);
+            assert_eq!(fast.position(), slow.position(), "reader position for {:?}", input);
+            assert_eq!(fast.peek_char(0).unwrap(), slow.peek_char(0).unwrap(), "next char for {:?}", input);
+        }
+
+        #[test]
+        fn fast_digit_scan_matches_char_decoding() {
+            const ALPHABET: &[char] = &['0', '7', '.', '_', 'e', 'E', '-', '+', '%', '/', 'x', ' ', '\n', 'ą'];
+
+            let mut np = NumberParser::new();
+            np.decimal.allow_percent = true;
+            np.decimal.allow_fraction = true;
+
+            let mut input = String::new();
+            for len in 1..=4u32 {
+                for mut k in 0..ALPHABET.len().pow(len) {
+                    input.clear();
+                    for _ in 0..len {
+                        input.push(ALPHABET[k % ALPHABET.len()]);
+                        k /= ALPHABET.len();
+                    }
+                    check(&np, &input);
+                    input.insert(0, '1');
+                    check(&np, &input);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use super::*;
+
+const FRONT_MATTER_TASK_NAME: &str = "skipping front matter";
+
+
+#[derive(Debug, Clone)]
+pub struct PrologueConfig {
+    pub shebang: bool,
+    pub front_matter: bool,
+    pub delimiter: String,
+}
+
+impl PrologueConfig {
+    pub fn new() -> PrologueConfig {
+        PrologueConfig {
+            shebang: true,
+            front_matter: true,
+            delimiter: String::from("---"),
+        }
+    }
+}
+
+impl Default for PrologueConfig {
+    fn default() -> PrologueConfig {
+        PrologueConfig::new()
+    }
+}
+
+
+/// Spans of the prologue parts skipped by `skip_prologue()`, line terminators excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Prologue {
+    pub shebang: Option<Span>,
+    pub front_matter: Option<Span>,
+}
+
+impl Prologue {
+    pub fn is_empty(&self) -> bool {
+        self.shebang.is_none() && self.front_matter.is_none()
+    }
+}
+
+
+fn is_eol(c: Option<char>) -> bool {
+    match c {
+        None | Some('\n') | Some('\r') => true,
+        _ => false,
+    }
+}
+
+fn skip_eol(r: &mut dyn CharReader) -> IoResult<()> {
+    if r.match_char('\r')? {
+        r.skip_chars(1)?;
+    }
+    if r.match_char('\n')? {
+        r.skip_chars(1)?;
+    }
+    Ok(())
+}
+
+/// Skips an optional shebang line (`#!...`) followed by an optional front-matter block
+/// delimited by lines consisting of `config.delimiter`. Reader is expected to be positioned
+/// at the beginning of the input.
+pub fn skip_prologue(r: &mut dyn CharReader, config: &PrologueConfig) -> ParseResult<Prologue> {
+    let mut prologue = Prologue::default();
+
+    if r.peek_char(0)?.is_none() {
+        return Ok(prologue);
+    }
+
+    if config.shebang && r.match_str("#!")? {
+        let p1 = r.position();
+        r.skip_until(&mut |c| c == '\n' || c == '\r')?;
+        prologue.shebang = Some(Span::with_pos(p1, r.position()));
+        skip_eol(r)?;
+    }
+
+    let delimiter = config.delimiter.as_str();
+    let len = delimiter.chars().count();
+    if config.front_matter && !delimiter.is_empty() && r.match_str_term(delimiter, &mut is_eol)? {
+        let p1 = r.position();
+        r.skip_chars(len)?;
+        skip_eol(r)?;
+        loop {
+            if r.peek_char(0)?.is_none() {
+                return Err(ParseErrorDetail::UnexpectedEof {
+                    pos: r.position(),
+                    expected: Some(Expected::Custom(format!("'{}'", delimiter))),
+                    task: FRONT_MATTER_TASK_NAME.into(),
+                });
+            }
+            if r.match_str_term(delimiter, &mut is_eol)? {
+                r.skip_chars(len)?;
+                prologue.front_matter = Some(Span::with_pos(p1, r.position()));
+                skip_eol(r)?;
+                break;
+            }
+            r.skip_until(&mut |c| c == '\n')?;
+            skip_eol(r)?;
+        }
+    }
+
+    Ok(prologue)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_shebang_and_front_matter() {
+        let input = "#!/usr/bin/env tool\n---\ntitle: test\n---\nbody";
+        let mut r = MemCharReader::new(input.as_bytes());
+        let p = skip_prologue(&mut r, &PrologueConfig::new()).unwrap();
+
+        let shebang = p.shebang.unwrap();
+        assert_eq!(shebang.start.offset, 0);
+        assert_eq!(shebang.end.offset, 19);
+
+        let front_matter = p.front_matter.unwrap();
+        assert_eq!(front_matter.start.line, 1);
+        assert_eq!(front_matter.end.line, 3);
+        assert_eq!(front_matter.end.column, 3);
+
+        assert_eq!(r.position().offset, 40);
+        assert_eq!(r.peek_char(0).unwrap(), Some('b'));
+    }
+
+    #[test]
+    fn leaves_input_without_prologue() {
+        let mut r = MemCharReader::new(b"--- not a delimiter line\n");
+        let p = skip_prologue(&mut r, &PrologueConfig::new()).unwrap();
+        assert!(p.is_empty());
+        assert_eq!(r.position().offset, 0);
+    }
+
+    #[test]
+    fn unterminated_front_matter() {
+        let mut r = MemCharReader::new(b"---\ntitle: test\n");
+        let err = skip_prologue(&mut r, &PrologueConfig::new()).unwrap_err();
+        match err {
+            ParseErrorDetail::UnexpectedEof { pos, .. } => assert_eq!(pos.line, 2),
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+}
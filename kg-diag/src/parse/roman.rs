@@ -0,0 +1,118 @@
+use super::*;
+
+const ROMAN_DIGITS: &[(&str, u32)] = &[
+    ("M", 1000),
+    ("CM", 900),
+    ("D", 500),
+    ("CD", 400),
+    ("C", 100),
+    ("XC", 90),
+    ("L", 50),
+    ("XL", 40),
+    ("X", 10),
+    ("IX", 9),
+    ("V", 5),
+    ("IV", 4),
+    ("I", 1),
+];
+
+#[inline]
+fn is_roman_digit(c: char) -> bool {
+    match c {
+        'I' | 'V' | 'X' | 'L' | 'C' | 'D' | 'M' => true,
+        _ => false,
+    }
+}
+
+/// Upper-case roman numerals (`I` to `MMMCMXCIX`), as an example of `CustomNotation`.
+/// Only numerals in canonical form are accepted, so `IIII` or `IC` are reported as invalid.
+#[derive(Debug, Default)]
+pub struct RomanNotation;
+
+impl RomanNotation {
+    pub fn new() -> RomanNotation {
+        RomanNotation
+    }
+}
+
+impl CustomNotation for RomanNotation {
+    fn is_at_start(&self, r: &mut dyn CharReader) -> IoResult<bool> {
+        Ok(match r.peek_char(0)? {
+            Some(c) => is_roman_digit(c),
+            None => false,
+        })
+    }
+
+    fn parse(&self, r: &mut dyn CharReader) -> ParseResult<()> {
+        r.skip_while(&mut |c| is_roman_digit(c))?;
+        Ok(())
+    }
+
+    fn convert(&self, s: &str) -> Result<f64, NumericalErrorKind> {
+        let mut rest = s;
+        let mut value = 0;
+        for &(digit, n) in ROMAN_DIGITS {
+            while rest.starts_with(digit) {
+                rest = &rest[digit.len()..];
+                value += n;
+            }
+        }
+        if rest.is_empty() && value > 0 && value < 4000 && to_roman(value) == s {
+            Ok(value as f64)
+        } else {
+            Err(NumericalErrorKind::Invalid)
+        }
+    }
+}
+
+fn to_roman(mut value: u32) -> String {
+    let mut s = String::new();
+    for &(digit, n) in ROMAN_DIGITS {
+        while value >= n {
+            s.push_str(digit);
+            value -= n;
+        }
+    }
+    s
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<u32, ParseErrorDetail> {
+        let mut np = NumberParser::new();
+        let notation = np.register_notation(Box::new(RomanNotation::new()));
+        let mut r = MemCharReader::new(input.as_bytes());
+        let n = np.parse_number(&mut r)?;
+        assert_eq!(n.term().notation(), notation);
+        np.convert_number_token(&n, &mut r)
+    }
+
+    #[test]
+    fn can_parse_roman() {
+        assert_eq!(parse("XIV").unwrap(), 14);
+        assert_eq!(parse("MCMXCIX").unwrap(), 1999);
+        assert_eq!(parse("MMMCMXCIX").unwrap(), 3999);
+    }
+
+    #[test]
+    fn non_canonical_roman_is_invalid() {
+        for input in &["IIII", "IC", "VV", "MMMM"] {
+            match parse(input) {
+                Err(ParseErrorDetail::Numerical { kind: NumericalErrorKind::Invalid, .. }) => {}
+                res => panic!("expected invalid numeral for {}, got {:?}", input, res),
+            }
+        }
+    }
+
+    #[test]
+    fn decimal_still_parsed() {
+        let mut np = NumberParser::new();
+        np.register_notation(Box::new(RomanNotation::new()));
+        let mut r = MemCharReader::new(b"12");
+        let n = np.parse_number(&mut r).unwrap();
+        assert_eq!(n.term().notation(), Notation::Decimal);
+    }
+}
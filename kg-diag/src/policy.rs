@@ -0,0 +1,170 @@
+//! Crate-wide code policy, re-classifying severities of diagnostics when they are created.
+//!
+//! Allows applications to change severity of codes emitted by libraries they depend on, for
+//! example to make all IO errors recoverable:
+//!
+//! ```
+//! use kg_diag::Severity;
+//! use kg_diag::policy::{self, CodePolicy};
+//!
+//! policy::set(CodePolicy::new().range(101..200, Severity::Error));
+//! ```
+
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    domain: Option<String>,
+    start: u32,
+    end: u32,
+    severity: Severity,
+}
+
+/// Set of rules mapping codes or code ranges to forced severities. Rules are checked in order
+/// they were added, the first matching one wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodePolicy {
+    rules: Vec<Rule>,
+}
+
+impl CodePolicy {
+    pub fn new() -> CodePolicy {
+        CodePolicy { rules: Vec::new() }
+    }
+
+    /// Forces `severity` for a single `code`, regardless of its domain.
+    pub fn code(self, code: u32, severity: Severity) -> CodePolicy {
+        self.range(code..=code, severity)
+    }
+
+    /// Forces `severity` for all codes in `codes`, regardless of their domain.
+    pub fn range<R: RangeBounds<u32>>(mut self, codes: R, severity: Severity) -> CodePolicy {
+        let (start, end) = bounds(&codes);
+        self.rules.push(Rule { domain: None, start, end, severity });
+        self
+    }
+
+    /// Forces `severity` for all codes in `codes` within given `domain`.
+    pub fn domain_range<D: Into<String>, R: RangeBounds<u32>>(mut self, domain: D, codes: R, severity: Severity) -> CodePolicy {
+        let (start, end) = bounds(&codes);
+        self.rules.push(Rule { domain: Some(domain.into()), start, end, severity });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Severity forced for `detail` by this policy, if any rule matches.
+    pub fn severity_for(&self, detail: &dyn Detail) -> Option<Severity> {
+        let code = detail.code();
+        let domain = detail.domain();
+        self.rules
+            .iter()
+            .find(|r| code >= r.start && code <= r.end && (r.domain.is_none() || r.domain.as_deref() == domain))
+            .map(|r| r.severity)
+    }
+}
+
+fn bounds<R: RangeBounds<u32>>(codes: &R) -> (u32, u32) {
+    let start = match codes.start_bound() {
+        Bound::Included(&s) => s,
+        Bound::Excluded(&s) => s.saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let end = match codes.end_bound() {
+        Bound::Included(&e) => e,
+        Bound::Excluded(&e) => e.saturating_sub(1),
+        Bound::Unbounded => std::u32::MAX,
+    };
+    (start, end)
+}
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref POLICY: RwLock<Option<Arc<CodePolicy>>> = RwLock::new(None);
+}
+
+/// Installs crate-wide policy, consulted by all diagnostics created afterwards.
+pub fn set(policy: CodePolicy) {
+    let mut p = POLICY.write().unwrap();
+    ACTIVE.store(!policy.is_empty(), Ordering::Release);
+    *p = Some(Arc::new(policy));
+}
+
+/// Removes crate-wide policy.
+pub fn clear() {
+    let mut p = POLICY.write().unwrap();
+    ACTIVE.store(false, Ordering::Release);
+    *p = None;
+}
+
+/// Currently installed policy, if any.
+pub fn get() -> Option<Arc<CodePolicy>> {
+    POLICY.read().unwrap().clone()
+}
+
+/// Severity forced for `detail` by the installed policy, if any.
+pub fn severity_for(detail: &dyn Detail) -> Option<Severity> {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return None;
+    }
+    match *POLICY.read().unwrap() {
+        Some(ref p) => p.severity_for(detail),
+        None => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestDetail(u32);
+
+    impl std::fmt::Display for TestDetail {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "test detail {}", self.0)
+        }
+    }
+
+    impl Detail for TestDetail {
+        fn code(&self) -> u32 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let p = CodePolicy::new()
+            .code(150, Severity::Warning)
+            .range(101..200, Severity::Error)
+            .domain_range("IO", 1.., Severity::Info);
+
+        assert_eq!(p.severity_for(&TestDetail(150)), Some(Severity::Warning));
+        assert_eq!(p.severity_for(&TestDetail(101)), Some(Severity::Error));
+        assert_eq!(p.severity_for(&TestDetail(199)), Some(Severity::Error));
+        assert_eq!(p.severity_for(&TestDetail(200)), None);
+        assert_eq!(p.severity_for(&TestDetail(100)), None);
+    }
+
+    #[test]
+    fn policy_applied_on_creation() {
+        set(CodePolicy::new().code(4242, Severity::Warning));
+        let d = BasicDiag::from(TestDetail(4242));
+        let other = BasicDiag::from(TestDetail(4243));
+        clear();
+
+        assert_eq!(d.effective_severity(), Severity::Warning);
+        assert_eq!(d.detail().severity(), Severity::Failure);
+        assert_eq!(other.effective_severity(), Severity::Failure);
+        assert_eq!(d.effective_code_string(), "W4242");
+        assert_eq!(d.to_string().lines().next(), Some("warning [W4242]: test detail 4242"));
+    }
+}
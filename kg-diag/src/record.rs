@@ -0,0 +1,252 @@
+//! Serializable records of diagnostics, which can be saved (e.g. by CI jobs) and rendered
+//! later against current sources with `DiagRecord::render_with()`.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::render::{Layout, RenderOptions, Style};
+use super::*;
+
+/// Diagnostic reduced to data that can be serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagRecord {
+    pub severity: Severity,
+    /// Code as returned by `Diag::effective_code_string()`.
+    pub code: String,
+    pub message: String,
+    pub quotes: Vec<QuoteRecord>,
+    #[serde(default)]
+    pub suggestions: Vec<Suggestion>,
+    pub cause: Option<Box<DiagRecord>>,
+}
+
+/// Quote of a recorded diagnostic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuoteRecord {
+    pub path: Option<PathBuf>,
+    pub span: Span,
+    pub message: String,
+    /// Number of the first quoted line.
+    pub line: u32,
+    pub lines_before: u32,
+    pub lines_after: u32,
+    /// Quoted text at the time of recording.
+    pub source: String,
+    /// Hash of `source`, compared with text quoted from current sources to detect drift.
+    pub hash: ContentHash,
+    /// Secondary labels, see `Quote::labels()`.
+    #[serde(default)]
+    pub labels: Vec<(Span, String)>,
+}
+
+/// Key matching records of the same diagnostic across reports, see `diff()`. Quoted location
+/// is identified by text of quoted lines rather than their numbers, so that diagnostics
+/// moved by edits elsewhere in a source keep their keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DiagKey {
+    pub code: String,
+    pub message: String,
+    pub path: Option<PathBuf>,
+    /// Anchor of the first quote, see `QuoteRecord::anchor()`.
+    pub anchor: Option<ContentHash>,
+}
+
+impl DiagRecord {
+    pub fn of(diag: &dyn Diag) -> DiagRecord {
+        let detail = diag.detail();
+        DiagRecord {
+            severity: diag.effective_severity(),
+            code: diag.effective_code_string(),
+            message: detail.to_string(),
+            quotes: diag.quotes().iter().map(QuoteRecord::of).collect(),
+            suggestions: diag.suggestions().to_vec(),
+            cause: diag.cause().map(|c| Box::new(DiagRecord::of(c))),
+        }
+    }
+
+    /// Key matching this record across reports, see `DiagKey`.
+    pub fn key(&self) -> DiagKey {
+        let quote = self.quotes.first();
+        DiagKey {
+            code: self.code.clone(),
+            message: self.message.clone(),
+            path: quote.and_then(|q| q.path.clone()),
+            anchor: quote.map(|q| q.anchor()),
+        }
+    }
+
+    /// Line of the first quoted span.
+    pub fn line(&self) -> Option<u32> {
+        self.quotes.first().map(|q| q.span.start.line)
+    }
+
+    /// Renders this record with spans quoted again from `sources`, matched by path. Quotes
+    /// whose text has changed since recording are followed by a note, and quotes of sources
+    /// not found in `sources` show the recorded text.
+    pub fn render_with(&self, sources: &SourceMap) -> String {
+        self.layout_with(sources, &RenderOptions::from_env()).to_string()
+    }
+
+    pub fn layout_with(&self, sources: &SourceMap, options: &RenderOptions) -> Layout {
+        let mut l = Layout::new();
+        self.push_layout(&mut l, sources, options);
+        l
+    }
+
+    pub(crate) fn push_header(&self, l: &mut Layout) {
+        l.push(self.severity.to_string(), Style::Severity(self.severity));
+        l.push(" [", Style::Plain);
+        l.push(self.code.clone(), Style::Code);
+        l.push("]: ", Style::Plain);
+        l.push(self.message.clone(), Style::Message);
+        l.newline();
+    }
+
+    fn push_layout(&self, l: &mut Layout, sources: &SourceMap, options: &RenderOptions) {
+        self.push_header(l);
+        for q in self.quotes.iter() {
+            let (quote, note) = q.requote(sources);
+            l.push_quote(&quote, options);
+            if let Some(note) = note {
+                l.push(note, Style::Note);
+                l.newline();
+            }
+        }
+        for s in self.suggestions.iter() {
+            l.push_suggestion(s, options);
+        }
+        if let Some(ref c) = self.cause {
+            l.push("caused by: ", Style::Plain);
+            c.push_layout(l, sources, options);
+        }
+    }
+}
+
+impl QuoteRecord {
+    /// Records `quote`, loading quoted lines first if it is deferred (see `Quote::deferred()`).
+    pub fn of(quote: &Quote) -> QuoteRecord {
+        if quote.is_deferred() {
+            if let Ok(q) = quote.resolve() {
+                return QuoteRecord::of(&q);
+            }
+        }
+        let span = quote.span();
+        let lines = quote.source().split('\n').count() as u32;
+        QuoteRecord {
+            path: quote.path().map(|p| p.to_path_buf()),
+            span,
+            message: quote.message().to_string(),
+            line: quote.line(),
+            lines_before: span.start.line.saturating_sub(quote.line()),
+            lines_after: (quote.line() + lines).saturating_sub(span.end.line + 1),
+            source: quote.source().to_string(),
+            hash: ContentHash::of(quote.source().as_bytes()),
+            labels: quote.labels().to_vec(),
+        }
+    }
+
+    /// Hash of the quoted lines spanned by `span`, with surrounding whitespace trimmed from
+    /// each line so that changes of indentation do not affect it.
+    pub fn anchor(&self) -> ContentHash {
+        let first = self.span.start.line.saturating_sub(self.line) as usize;
+        let last = self.span.end.line.saturating_sub(self.line) as usize;
+        let lines: Vec<&str> = self
+            .source
+            .split('\n')
+            .skip(first)
+            .take(last.saturating_sub(first) + 1)
+            .map(|l| l.trim())
+            .collect();
+        ContentHash::of(lines.join("\n").as_bytes())
+    }
+
+    /// Quote of the recorded span from `sources` if possible, along with a note about drift.
+    fn requote(&self, sources: &SourceMap) -> (Quote, Option<&'static str>) {
+        let id = self.path.as_ref().and_then(|p| sources.find(p));
+        if let Some(id) = id {
+            if self.span.end.offset <= sources.data(id).len() {
+                let q = sources.quote(
+                    id,
+                    self.span.start,
+                    self.span.end,
+                    self.lines_before,
+                    self.lines_after,
+                    self.message.as_str().into(),
+                );
+                let q = self.with_labels(q);
+                if ContentHash::of(q.source().as_bytes()) == self.hash {
+                    return (q, None);
+                }
+                return (q, Some("note: source has changed since this diagnostic was recorded"));
+            }
+        }
+        let note = match (id, &self.path) {
+            (None, Some(_)) => Some("note: source not available, showing recorded text"),
+            (None, None) => None,
+            (Some(_), _) => Some("note: source has changed, showing recorded text"),
+        };
+        let q = Quote::with_source(
+            self.path.as_deref().map(Arc::from),
+            self.span.start,
+            self.span.end,
+            0,
+            self.line,
+            self.source.as_str().into(),
+            self.message.as_str().into(),
+        );
+        (self.with_labels(q), note)
+    }
+
+    fn with_labels(&self, mut quote: Quote) -> Quote {
+        for (span, message) in self.labels.iter() {
+            quote.add_label(*span, message.as_str());
+        }
+        quote
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &[u8] = b"a = 1\nb = x\nc = 3\n";
+
+    fn diag() -> ParseDiag {
+        let mut r = MemCharReader::with_path("conf.txt", INPUT);
+        let mut d = ParseDiag::new(IoErrorDetail::Fmt);
+        let (from, to) = (Position::with(10, 1, 4), Position::with(11, 1, 5));
+        d.add_quote(r.quote(from, to, 1, 0, "x".into()));
+        d
+    }
+
+    fn record() -> DiagRecord {
+        DiagRecord::of(&diag())
+    }
+
+    #[test]
+    fn render_against_unchanged_source() {
+        let rec = record();
+        let mut map = SourceMap::new();
+        map.add(Some(std::path::Path::new("conf.txt")), INPUT.to_vec());
+
+        let options = RenderOptions::default();
+        assert_eq!(
+            rec.layout_with(&map, &options).to_string(),
+            Layout::of_diag(&diag(), &options).to_string()
+        );
+    }
+
+    #[test]
+    fn render_flags_drift() {
+        let rec = record();
+        let mut map = SourceMap::new();
+        map.add(Some(std::path::Path::new("conf.txt")), b"a = 1\nb = y\nc = 3\n".to_vec());
+        let text = rec.layout_with(&map, &RenderOptions::default()).to_string();
+        assert!(text.contains("b = y"));
+        assert!(text.contains("source has changed"));
+
+        let text = rec.layout_with(&SourceMap::new(), &RenderOptions::default()).to_string();
+        assert!(text.contains("b = x"));
+        assert!(text.contains("source not available"));
+    }
+}
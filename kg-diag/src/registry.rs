@@ -0,0 +1,116 @@
+//! Registry of diagnostic codes declared with `#[diag(register)]` across all linked crates,
+//! allowing applications to verify that no two types claim the same code.
+
+/// Code declared by a single variant of a `Detail` type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+    pub domain: Option<&'static str>,
+    pub code: u32,
+    pub type_name: &'static str,
+    pub variant: &'static str,
+}
+
+impl Registration {
+    pub fn new(
+        domain: Option<&'static str>,
+        code: u32,
+        type_name: &'static str,
+        variant: &'static str,
+    ) -> Registration {
+        Registration {
+            domain,
+            code,
+            type_name,
+            variant,
+        }
+    }
+}
+
+inventory::collect!(Registration);
+
+/// Code claimed by more than one type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    pub first: &'static Registration,
+    pub second: &'static Registration,
+}
+
+impl std::fmt::Display for Collision {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(domain) = self.first.domain {
+            write!(f, "{}-", domain)?;
+        }
+        write!(
+            f,
+            "{:04} is declared by both {}::{} and {}::{}",
+            self.first.code,
+            self.first.type_name,
+            self.first.variant,
+            self.second.type_name,
+            self.second.variant
+        )
+    }
+}
+
+/// All registered codes, in unspecified order.
+pub fn registrations() -> impl Iterator<Item = &'static Registration> {
+    inventory::iter::<Registration>.into_iter()
+}
+
+/// Checks that every (domain, code) pair is registered by at most one type.
+pub fn check_collisions() -> Result<(), Vec<Collision>> {
+    find_collisions(registrations())
+}
+
+fn find_collisions<I: Iterator<Item = &'static Registration>>(
+    regs: I,
+) -> Result<(), Vec<Collision>> {
+    let mut regs: Vec<&'static Registration> = regs.collect();
+    regs.sort_by(|a, b| (a.domain, a.code, a.type_name).cmp(&(b.domain, b.code, b.type_name)));
+
+    let mut collisions = Vec::new();
+    for pair in regs.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if a.domain == b.domain && a.code == b.code && a.type_name != b.type_name {
+            collisions.push(Collision {
+                first: a,
+                second: b,
+            });
+        }
+    }
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(collisions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leak(r: Registration) -> &'static Registration {
+        Box::leak(Box::new(r))
+    }
+
+    #[test]
+    fn detects_collision_between_types() {
+        let a = leak(Registration::new(Some("IO"), 1, "a::ErrorKind", "NotFound"));
+        let b = leak(Registration::new(Some("IO"), 1, "b::ErrorKind", "Missing"));
+        let c = leak(Registration::new(None, 1, "c::ErrorKind", "Other"));
+
+        let collisions = find_collisions(vec![a, c, b].into_iter()).unwrap_err();
+        assert_eq!(
+            collisions,
+            vec![Collision {
+                first: a,
+                second: b
+            }]
+        );
+        assert_eq!(
+            collisions[0].to_string(),
+            "IO-0001 is declared by both a::ErrorKind::NotFound and b::ErrorKind::Missing"
+        );
+        assert!(find_collisions(vec![a, c].into_iter()).is_ok());
+    }
+}
@@ -0,0 +1,1579 @@
+//! Two-phase rendering of diagnostics. A [`Layout`] of styled segments is built first and then
+//! written out, either as plain text (which is what `Display` of diagnostics and quotes does),
+//! or by the application painting segments with its own means (like TUI widgets). With `color`
+//! feature enabled, layouts can also be written with ANSI colors (see [`Layout::write_ansi`]).
+//!
+//! Output format of `Display` can be changed globally by installing a [`DiagRenderer`] with
+//! [`set_renderer()`].
+
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+
+use super::*;
+
+/// Role of a segment text, to be mapped to colors or other presentation by writers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Style {
+    Plain,
+    /// Severity in the diagnostic header.
+    Severity(Severity),
+    /// Diagnostic code in the diagnostic header.
+    Code,
+    /// Diagnostic message in the diagnostic header.
+    Message,
+    /// Quoted file path and position.
+    Path,
+    /// Line numbers, separators and other gutter decorations.
+    Gutter,
+    /// Quoted source text.
+    Source,
+    /// Marks (carets) underlining quoted spans.
+    Marker,
+    /// Message attached to a quoted span.
+    Label,
+    /// Additional notes, like context values or summaries of omitted quotes.
+    Note,
+    /// Fix-it suggestions.
+    Help,
+    Stacktrace,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub text: String,
+    pub style: Style,
+    /// Target the segment refers to, like a file path.
+    pub link: Option<String>,
+}
+
+/// ANSI colors of segment styles, as SGR parameters (like `"1;31"` for bold red). Empty
+/// parameters leave segments of the style uncolored. Default colors resemble rustc output.
+#[cfg(feature = "color")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub info: &'static str,
+    pub warning: &'static str,
+    pub error: &'static str,
+    pub code: &'static str,
+    pub message: &'static str,
+    pub path: &'static str,
+    pub gutter: &'static str,
+    pub marker: &'static str,
+    pub label: &'static str,
+    pub note: &'static str,
+    pub help: &'static str,
+}
+
+#[cfg(feature = "color")]
+impl Palette {
+    /// SGR parameters of segments styled with `style`.
+    pub fn sgr(&self, style: Style) -> &'static str {
+        match style {
+            Style::Severity(Severity::Info) => self.info,
+            Style::Severity(Severity::Warning) => self.warning,
+            Style::Severity(_) => self.error,
+            Style::Code => self.code,
+            Style::Message => self.message,
+            Style::Path => self.path,
+            Style::Gutter => self.gutter,
+            Style::Marker => self.marker,
+            Style::Label => self.label,
+            Style::Note => self.note,
+            Style::Help => self.help,
+            Style::Plain | Style::Source | Style::Stacktrace => "",
+        }
+    }
+}
+
+#[cfg(feature = "color")]
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            info: "1;36",
+            warning: "1;33",
+            error: "1;31",
+            code: "1",
+            message: "1",
+            path: "",
+            gutter: "1;34",
+            marker: "1;31",
+            label: "1;31",
+            note: "2",
+            help: "1;32",
+        }
+    }
+}
+
+/// Renders diagnostics and quotes. The renderer installed with `set_renderer()` is used by
+/// `Display` of diagnostics and quotes.
+///
+/// Renderers producing text from layouts only implement `write()`, leaving layout of quotes
+/// and markers to `Layout`. Renderers of other formats override `render()` and
+/// `render_quote()`.
+pub trait DiagRenderer: Send + Sync + 'static {
+    /// Writes `layout` of a diagnostic or a quote.
+    fn write(&self, layout: &Layout, w: &mut dyn Write) -> std::fmt::Result;
+
+    fn render(
+        &self,
+        diag: &dyn Diag,
+        options: &RenderOptions,
+        w: &mut dyn Write,
+    ) -> std::fmt::Result {
+        self.write(&Layout::of_diag(diag, options), w)
+    }
+
+    fn render_quote(
+        &self,
+        quote: &Quote,
+        options: &RenderOptions,
+        w: &mut dyn Write,
+    ) -> std::fmt::Result {
+        self.write(&Layout::of_quote(quote, options), w)
+    }
+}
+
+/// Renderer producing plain text, the default one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlainText;
+
+impl DiagRenderer for PlainText {
+    fn write(&self, layout: &Layout, w: &mut dyn Write) -> std::fmt::Result {
+        layout.write_plain(w)
+    }
+}
+
+/// Renderer producing text colored with `palette`, regardless of `ColorPolicy`.
+#[cfg(feature = "color")]
+#[derive(Debug, Clone, Default)]
+pub struct Ansi {
+    pub palette: Palette,
+}
+
+#[cfg(feature = "color")]
+impl DiagRenderer for Ansi {
+    fn write(&self, layout: &Layout, w: &mut dyn Write) -> std::fmt::Result {
+        layout.write_ansi(w, &self.palette)
+    }
+}
+
+/// Renderer producing JSON of `DiagRecord` (or `QuoteRecord` for quotes), pretty-printed if
+/// `RenderOptions::verbose` is set. Laid out text is written as it is.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Json {
+    fn write_json<T: serde::Serialize>(
+        value: &T,
+        options: &RenderOptions,
+        w: &mut dyn Write,
+    ) -> std::fmt::Result {
+        let json = if options.verbose {
+            serde_json::to_string_pretty(value)
+        } else {
+            serde_json::to_string(value)
+        };
+        w.write_str(&json.map_err(|_| std::fmt::Error)?)
+    }
+}
+
+#[cfg(feature = "json")]
+impl DiagRenderer for Json {
+    fn write(&self, layout: &Layout, w: &mut dyn Write) -> std::fmt::Result {
+        layout.write_plain(w)
+    }
+
+    fn render(
+        &self,
+        diag: &dyn Diag,
+        options: &RenderOptions,
+        w: &mut dyn Write,
+    ) -> std::fmt::Result {
+        Json::write_json(&DiagRecord::of(diag), options, w)
+    }
+
+    fn render_quote(
+        &self,
+        quote: &Quote,
+        options: &RenderOptions,
+        w: &mut dyn Write,
+    ) -> std::fmt::Result {
+        Json::write_json(&QuoteRecord::of(quote), options, w)
+    }
+}
+
+lazy_static! {
+    static ref RENDERER: RwLock<Arc<dyn DiagRenderer>> = RwLock::new(Arc::new(PlainText));
+}
+
+/// Installs renderer used by `Display` of diagnostics and quotes. Meant to be called once at
+/// startup, like `config::configure()`.
+pub fn set_renderer<R: DiagRenderer>(renderer: R) {
+    *RENDERER.write().unwrap() = Arc::new(renderer);
+}
+
+/// Currently installed renderer (`PlainText` unless `set_renderer()` was called).
+pub fn renderer() -> Arc<dyn DiagRenderer> {
+    RENDERER.read().unwrap().clone()
+}
+
+/// Appearance of quote frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuoteStyle {
+    /// Show header line with the file path and position of the quoted span (if quoted from
+    /// a file), starting with `header`.
+    pub show_header: bool,
+    pub header: &'static str,
+    /// Separator of line numbers and quoted lines.
+    pub separator: &'static str,
+    /// Characters marking spans, the first one for the primary span.
+    pub markers: [char; 4],
+    /// Maximum number of context lines shown before the first and after the last marked
+    /// line. All quoted lines are shown if not set (see `Reader::quote()`).
+    pub context_lines: Option<u32>,
+    /// Maximum number of lines shown, lines in the middle of higher snippets (e.g. of spans
+    /// covering whole blocks) are replaced with `...`.
+    pub max_lines: Option<usize>,
+}
+
+impl Default for QuoteStyle {
+    fn default() -> QuoteStyle {
+        QuoteStyle {
+            show_header: true,
+            header: " -->",
+            separator: "| ",
+            markers: ['^', '-', '~', '+'],
+            context_lines: None,
+            max_lines: None,
+        }
+    }
+}
+
+/// Options controlling how diagnostics are laid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Include details shown by alternate (`{:#}`) formatting.
+    pub verbose: bool,
+    /// Screen-reader friendly output: locations are described with sentences instead of
+    /// caret art, and only ASCII characters are used.
+    pub accessible: bool,
+    /// Maximum number of source columns shown for a quoted line. Longer lines are cut to
+    /// a window around the quoted span, with `…` marking omitted parts.
+    pub max_width: Option<usize>,
+    /// Width of output lines, e.g. of the terminal (see `detect_terminal_width()`). Quoted
+    /// lines are cut like with `max_width`, so that they fit along with the gutter.
+    pub terminal_width: Option<usize>,
+    /// Escape control characters (including terminal escape sequences and bidirectional
+    /// overrides) in quoted source and labels, for reports quoting untrusted input.
+    pub sanitize: bool,
+    /// Column semantics of quoted spans, used to lay out quoted lines so that markers line up
+    /// with the text. Should match configuration of readers that created the quotes.
+    pub positions: PositionConfig,
+    /// Number of columns between tab stops, to which tab characters of quoted lines are
+    /// expanded if they count as a single column of quoted spans (i.e. `positions` has no
+    /// `tab_width`). Markers are widened accordingly, so that they stay aligned.
+    pub tab_width: usize,
+    /// Format of diagnostic codes in headers.
+    pub code_format: CodeFormat,
+    pub quote_style: QuoteStyle,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            verbose: false,
+            accessible: false,
+            max_width: None,
+            terminal_width: None,
+            sanitize: false,
+            positions: PositionConfig::default(),
+            tab_width: 4,
+            code_format: CodeFormat::default(),
+            quote_style: QuoteStyle::default(),
+        }
+    }
+}
+
+/// Format of diagnostic codes in headers. Default format matches `Diag::effective_code_string()`,
+/// e.g. `PARSE-E0042`; with `prefixes` set to `[("PARSE", "MYAPP")]`, `separator` set to
+/// `"-"` and `width` set to 5 the same code is shown as `MYAPP-E-00042`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeFormat {
+    /// Minimal number of digits, codes are padded with zeros.
+    pub width: usize,
+    /// Separator between severity letter and digits.
+    pub separator: &'static str,
+    /// Prefixes shown in place of domains, as `(domain, prefix)` pairs.
+    pub prefixes: Vec<(&'static str, String)>,
+    /// Prefix of codes of details without domain.
+    pub default_prefix: Option<String>,
+    /// Omit codes of informational diagnostics.
+    pub omit_info: bool,
+    /// Function formatting codes, used instead of all other settings if set. Returning `None`
+    /// omits the code.
+    pub custom: Option<fn(&dyn Detail, Severity) -> Option<String>>,
+}
+
+impl CodeFormat {
+    /// Formatted code of `detail` with the letter of `severity` (effective severity of the
+    /// diagnostic), or `None` if the code should be omitted.
+    pub fn format(&self, detail: &dyn Detail, severity: Severity) -> Option<String> {
+        if let Some(custom) = self.custom {
+            return custom(detail, severity);
+        }
+        if self.omit_info && severity == Severity::Info {
+            return None;
+        }
+        let prefix = match detail.domain() {
+            Some(domain) => self
+                .prefixes
+                .iter()
+                .find(|&&(d, _)| d == domain)
+                .map_or(domain, |(_, p)| p.as_str()),
+            None => self.default_prefix.as_deref().unwrap_or(""),
+        };
+        let code = format!(
+            "{}{}{:0width$}",
+            severity.code_char(),
+            self.separator,
+            detail.code(),
+            width = self.width
+        );
+        if prefix.is_empty() {
+            Some(code)
+        } else {
+            Some(format!("{}-{}", prefix, code))
+        }
+    }
+}
+
+impl Default for CodeFormat {
+    fn default() -> CodeFormat {
+        CodeFormat {
+            width: 4,
+            separator: "",
+            prefixes: Vec::new(),
+            default_prefix: None,
+            omit_info: false,
+            custom: None,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Environment variable enabling accessible output when set to anything but `0` or empty.
+    pub const ACCESSIBLE_ENV: &'static str = "KG_DIAG_ACCESSIBLE";
+
+    /// Options from crate configuration adjusted by environment variables. Used by `Display`
+    /// of diagnostics.
+    pub fn from_env() -> RenderOptions {
+        let accessible = match std::env::var_os(Self::ACCESSIBLE_ENV) {
+            Some(v) => !v.is_empty() && v != "0",
+            None => false,
+        };
+        let config = config::config();
+        RenderOptions {
+            verbose: config.verbose,
+            accessible,
+            tab_width: config.tab_width,
+            ..Default::default()
+        }
+    }
+
+    /// Environment variable with width of the terminal, taking precedence over the width
+    /// reported by the terminal itself.
+    pub const COLUMNS_ENV: &'static str = "COLUMNS";
+
+    /// Width of the terminal the standard error (or output) stream is attached to, if any.
+    pub fn detect_terminal_width() -> Option<usize> {
+        if let Some(columns) = std::env::var(Self::COLUMNS_ENV).ok().and_then(|c| c.parse().ok()) {
+            if columns > 0 {
+                return Some(columns);
+            }
+        }
+        #[cfg(unix)]
+        for &fd in [libc::STDERR_FILENO, libc::STDOUT_FILENO].iter() {
+            let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+            if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+                return Some(size.ws_col as usize);
+            }
+        }
+        None
+    }
+
+    /// Number of source columns shown for a quoted line, given width of the gutter.
+    fn source_width(&self, gutter: usize) -> Option<usize> {
+        // two columns for `…` on both sides of a cut line
+        let terminal = self
+            .terminal_width
+            .map(|w| w.saturating_sub(gutter + 2).max(MIN_SOURCE_WIDTH));
+        match (self.max_width, terminal) {
+            (Some(max), Some(terminal)) => Some(max.min(terminal)),
+            (max, terminal) => max.or(terminal),
+        }
+    }
+}
+
+/// Minimal number of source columns shown for a quoted line on narrow terminals.
+const MIN_SOURCE_WIDTH: usize = 20;
+
+/// Sequence of styled text segments. Line breaks are always separate `Style::Plain` segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Layout {
+    segments: Vec<Segment>,
+}
+
+impl Layout {
+    pub fn new() -> Layout {
+        Layout {
+            segments: Vec::new(),
+        }
+    }
+
+    /// Lays out diagnostic header, quotes, context, cause and stacktrace of `diag`.
+    pub fn of_diag(diag: &dyn Diag, options: &RenderOptions) -> Layout {
+        let mut l = Layout::new();
+        l.push_diag(diag, options);
+        l
+    }
+
+    /// Lays out all `diags` one after another. With `parallel` feature enabled, layouts of
+    /// individual diagnostics are built concurrently and then joined in the original order,
+    /// so the result is always the same.
+    pub fn of_report(diags: &[Box<dyn Diag>], options: &RenderOptions) -> Layout {
+        #[cfg(feature = "parallel")]
+        let layouts: Vec<Layout> = {
+            use rayon::prelude::*;
+            diags
+                .par_iter()
+                .map(|d| Layout::of_diag(d.as_ref(), options))
+                .collect()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let layouts = diags.iter().map(|d| Layout::of_diag(d.as_ref(), options));
+
+        let mut l = Layout::new();
+        for d in layouts {
+            l.append(d);
+        }
+        l
+    }
+
+    pub fn of_quote(quote: &Quote, options: &RenderOptions) -> Layout {
+        let mut l = Layout::new();
+        l.push_quote(quote, options);
+        l
+    }
+
+    /// Appends `text` with `style`, merging it with the last segment if it has the same style.
+    /// Line breaks in `text` are split into separate segments.
+    pub fn push<S: Into<String>>(&mut self, text: S, style: Style) {
+        let text = text.into();
+        if text.is_empty() {
+            return;
+        }
+        if text != "\n" && text.contains('\n') {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    self.newline();
+                }
+                self.push(line, style);
+            }
+            return;
+        }
+        if let Some(last) = self.segments.last_mut() {
+            if last.style == style && last.link.is_none() && last.text != "\n" && text != "\n" {
+                last.text.push_str(&text);
+                return;
+            }
+        }
+        self.segments.push(Segment {
+            text,
+            style,
+            link: None,
+        });
+    }
+
+    pub fn push_link<S: Into<String>, L: Into<String>>(&mut self, text: S, style: Style, link: L) {
+        self.segments.push(Segment {
+            text: text.into(),
+            style,
+            link: Some(link.into()),
+        });
+    }
+
+    pub fn newline(&mut self) {
+        self.push("\n", Style::Plain);
+    }
+
+    pub fn append(&mut self, other: Layout) {
+        self.segments.extend(other.segments);
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn into_segments(self) -> Vec<Segment> {
+        self.segments
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Splits segments into lines, without line break segments.
+    pub fn lines(&self) -> Vec<&[Segment]> {
+        let mut lines: Vec<&[Segment]> = self.segments.split(|s| s.text == "\n").collect();
+        if lines.last().map_or(false, |l| l.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+
+    /// Writer stage producing plain text, ignoring styles and links.
+    pub fn write_plain(&self, w: &mut dyn Write) -> std::fmt::Result {
+        for s in self.segments.iter() {
+            w.write_str(&s.text)?;
+        }
+        Ok(())
+    }
+
+    /// Writer stage producing text with segments colored by ANSI escape sequences according to
+    /// `palette`. Links are ignored.
+    #[cfg(feature = "color")]
+    pub fn write_ansi(&self, w: &mut dyn Write, palette: &Palette) -> std::fmt::Result {
+        for s in self.segments.iter() {
+            match palette.sgr(s.style) {
+                "" => w.write_str(&s.text)?,
+                sgr => write!(w, "\x1b[{}m{}\x1b[0m", sgr, s.text)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Text with ANSI colors from crate configuration, or plain text if colors are disabled
+    /// (see `ColorPolicy::enabled()`). Output is assumed to be a terminal.
+    #[cfg(feature = "color")]
+    pub fn to_colored_string(&self) -> String {
+        let config = config::config();
+        let mut s = String::new();
+        if config.color.enabled(true) {
+            let _ = self.write_ansi(&mut s, &config.palette);
+        } else {
+            let _ = self.write_plain(&mut s);
+        }
+        s
+    }
+
+    pub fn push_diag(&mut self, diag: &dyn Diag, options: &RenderOptions) {
+        let d = diag.detail();
+        let severity = diag.effective_severity();
+        self.push(severity.to_string(), Style::Severity(severity));
+        match options.code_format.format(d, severity) {
+            Some(code) => {
+                self.push(" [", Style::Plain);
+                self.push(code, Style::Code);
+                self.push("]: ", Style::Plain);
+            }
+            None => self.push(": ", Style::Plain),
+        }
+        self.push(d.to_string(), Style::Message);
+        self.newline();
+
+        let quotes = diag.quotes();
+        let shown = diag.max_quotes().map_or(quotes.len(), |max| max.min(quotes.len()));
+        // quotes of the same source line are merged into one frame (in place of the first one)
+        let mut merged = vec![false; shown];
+        for i in 0..shown {
+            if merged[i] {
+                continue;
+            }
+            let mut group = vec![&quotes[i]];
+            if !options.accessible {
+                for j in i + 1..shown {
+                    if !merged[j] && same_line(&quotes[i], &quotes[j]) {
+                        merged[j] = true;
+                        group.push(&quotes[j]);
+                    }
+                }
+            }
+            self.push_quote_impl(&group, Some(severity), options);
+        }
+        let ellipsis = if options.accessible { "..." } else { "\u{2026}" };
+        match quotes.len() - shown {
+            0 => {}
+            1 => {
+                self.push(format!("{} and 1 more location", ellipsis), Style::Note);
+                self.newline();
+            }
+            n => {
+                self.push(format!("{} and {} more locations", ellipsis, n), Style::Note);
+                self.newline();
+            }
+        }
+
+        for s in diag.suggestions() {
+            self.push_suggestion(s, options);
+        }
+
+        if let Some(c) = diag.context() {
+            if !c.is_empty() {
+                self.push(format!("context: {}", c), Style::Note);
+                self.newline();
+            }
+            if options.verbose {
+                let mut s = String::new();
+                let _ = c.fmt_verbose(&mut s);
+                self.push(s, Style::Note);
+            }
+        }
+        if let Some(c) = diag.cause() {
+            self.push("caused by: ", Style::Plain);
+            // cause is rendered with its own `Display`, which for details is just the message
+            self.push(c.to_string(), Style::Plain);
+        }
+        if let Some(s) = diag.stacktrace() {
+            self.push(s.to_string(), Style::Stacktrace);
+        }
+    }
+
+    /// Lays out `suggestion` as a `help:` line, see `Suggestion::describe()`.
+    pub fn push_suggestion(&mut self, suggestion: &Suggestion, options: &RenderOptions) {
+        self.push("help: ", Style::Help);
+        let text = if suggestion.message.is_empty() {
+            suggestion.describe()
+        } else {
+            format!("{}: {}", suggestion.message, suggestion.describe())
+        };
+        self.push(sanitize(&text, options).into_owned(), Style::Plain);
+        self.newline();
+    }
+
+    pub fn push_quote(&mut self, quote: &Quote, options: &RenderOptions) {
+        self.push_quote_impl(&[quote], None, options)
+    }
+
+    /// Lays out a frame of the first quote from `quotes`, with spans of all of them marked.
+    /// Quotes after the first must have single-line spans on the same line (see `same_line()`).
+    fn push_quote_impl(&mut self, quotes: &[&Quote], severity: Option<Severity>, options: &RenderOptions) {
+        use std::cmp;
+
+        let quote = quotes[0];
+        if quote.is_deferred() {
+            return match quote.resolve() {
+                Ok(q) => {
+                    let mut group = quotes.to_vec();
+                    group[0] = &q;
+                    self.push_quote_impl(&group, severity, options)
+                }
+                Err(err) => self.push_unresolved_quote(quotes, &err, options),
+            };
+        }
+        if options.accessible {
+            return self.push_quote_accessible(quote, severity, options);
+        }
+
+        let source = quote.source();
+        let span = quote.span();
+        let show_line_numbers = quote.path().is_some() || quote.line() != 0 || source.len() > 1;
+        let line_chars = if show_line_numbers {
+            cmp::max(
+                ((quote.line() + source.len() as u32 + 1) as f64)
+                    .log10()
+                    .ceil() as usize,
+                3,
+            )
+        } else {
+            0
+        };
+        let style = &options.quote_style;
+        let gutter = |ln: Option<u32>| match ln {
+            Some(ln) => format!("{0:>1$}{2}", ln + 1, line_chars, style.separator),
+            None => format!("{0:1$}{2}", "", line_chars, style.separator),
+        };
+        if let Some(path) = quote.path().filter(|_| style.show_header) {
+            self.push(format!("{0:>1$} ", style.header, line_chars), Style::Gutter);
+            let link = path.to_string_lossy();
+            self.push_link(format!("{}:{}", path.display(), span.start), Style::Path, link);
+            self.newline();
+        }
+        for site in quote.included_from() {
+            self.push(format!("{0:>1$} ", "=", line_chars), Style::Gutter);
+            self.push("included from ", Style::Note);
+            self.push_include_site(site);
+        }
+        // marked spans: spans of quotes, followed by their secondary labels
+        let mut marked: Vec<(Span, &str)> =
+            quotes.iter().map(|q| (q.span(), q.message())).collect();
+        for q in quotes.iter() {
+            marked.extend(q.labels().iter().map(|(s, m)| (*s, m.as_str())));
+        }
+        let lines: Vec<Vec<Cell>> = source
+            .lines()
+            .map(|s| cells(s, &options.positions, options.tab_width))
+            .collect();
+        let first_line = quote.line();
+        let last_line = first_line + lines.len() as u32;
+        // lines of a multi-line span are marked one by one, with the label after the last marks
+        let label_lines: Vec<Option<u32>> = marked
+            .iter()
+            .map(|&(s, _)| {
+                (first_line..last_line)
+                    .rev()
+                    .find(|&l| marked_columns(s, l, &lines[(l - first_line) as usize]).is_some())
+            })
+            .collect();
+        // all lines share the window around the span, so that columns stay aligned
+        let focus = if span.start.line == span.end.line {
+            let on_line = marked
+                .iter()
+                .map(|m| m.0)
+                .filter(|s| s.start.line == span.start.line && s.end.line == span.start.line);
+            let from = on_line.clone().map(|s| s.start.column).min().unwrap();
+            let to = on_line.map(|s| s.end.column).max().unwrap();
+            (from as usize, to as usize)
+        } else {
+            (span.start.column as usize, span.start.column as usize + 1)
+        };
+        let shown = shown_lines(&label_lines, &marked, first_line, last_line, style);
+        let gutter_width = if show_line_numbers {
+            line_chars + style.separator.chars().count()
+        } else {
+            0
+        };
+        let width = options.source_width(gutter_width);
+        let mut omitted = false;
+        for (ln, cells) in (first_line..).zip(lines.iter()) {
+            if !shown(ln) {
+                if !omitted && (first_line..ln).any(&shown) && (ln..last_line).any(&shown) {
+                    self.push(format!("{0:>1$}", "...", line_chars), Style::Gutter);
+                    self.newline();
+                    omitted = true;
+                }
+                continue;
+            }
+            if show_line_numbers {
+                self.push(gutter(Some(ln)), Style::Gutter);
+            }
+            let len = cells.len();
+            let (start, end) = match width {
+                Some(width) if len > width => window(len, focus, width),
+                _ => (0, len),
+            };
+            if start > 0 {
+                self.push("\u{2026}", Style::Gutter);
+            }
+            let text: String = cells[start..end].iter().map(|c| c.text.as_str()).collect();
+            self.push(sanitize(&text, options), Style::Source);
+            if end < len {
+                self.push("\u{2026}", Style::Gutter);
+            }
+            self.newline();
+
+            let on_line: Vec<(usize, usize, usize)> = marked
+                .iter()
+                .enumerate()
+                .filter_map(|(i, &(s, _))| marked_columns(s, ln, cells).map(|(f, t)| (i, f, t)))
+                .collect();
+            if on_line.is_empty() {
+                continue;
+            }
+            if show_line_numbers {
+                self.push(gutter(None), Style::Gutter);
+            }
+            // marks are clipped to the window, shifted by the leading `…`; marks of multi-line
+            // spans may extend past line end
+            let shift = if start > 0 { start - 1 } else { 0 };
+            // primary span is marked last, so that it is visible where spans overlap
+            let mut marks = Vec::new();
+            for &(i, from, to) in on_line.iter().rev() {
+                let single = marked[i].0.start.line == marked[i].0.end.line;
+                let from = from.max(start).min(end.max(start));
+                let to = if single || end < len { to.min(end) } else { to }.max(from);
+                if marks.len() < to - shift {
+                    marks.resize(to - shift, ' ');
+                }
+                for m in marks[from - shift..to - shift].iter_mut() {
+                    *m = style.markers[i % style.markers.len()];
+                }
+            }
+            for (k, m) in marks.into_iter().enumerate() {
+                // marks are as wide as marked cells, the leading `…` is one column wide
+                let width = match cells.get(k + shift) {
+                    Some(cell) if start == 0 || k > 0 => cell.width,
+                    _ => 1,
+                };
+                if m == ' ' {
+                    self.push(" ".repeat(width), Style::Plain);
+                } else {
+                    self.push(m.to_string().repeat(width), Style::Marker);
+                }
+            }
+            let labeled: Vec<usize> = on_line
+                .iter()
+                .map(|&(i, _, _)| i)
+                .filter(|&i| label_lines[i] == Some(ln))
+                .collect();
+            if on_line.len() == 1 {
+                let (s, message) = marked[on_line[0].0];
+                if !labeled.is_empty() && (s.start.line == s.end.line || !message.is_empty()) {
+                    self.push(" ", Style::Plain);
+                    self.push(sanitize(message, options), Style::Label);
+                }
+                self.newline();
+            } else {
+                // legend of markers, as messages cannot be all placed after the marks
+                self.newline();
+                for i in labeled {
+                    if show_line_numbers {
+                        self.push(gutter(None), Style::Gutter);
+                    }
+                    self.push(style.markers[i % style.markers.len()].to_string(), Style::Marker);
+                    self.push(" ", Style::Plain);
+                    self.push(sanitize(marked[i].1, options), Style::Label);
+                    self.newline();
+                }
+            }
+        }
+        // labels of spans outside quoted lines
+        for (i, &(s, message)) in marked.iter().enumerate().skip(quotes.len()) {
+            if label_lines[i].is_none() {
+                self.push(format!("{0:>1$} ", "=", line_chars), Style::Gutter);
+                self.push(format!("note: {}: {}", s, sanitize(message, options)), Style::Note);
+                self.newline();
+            }
+        }
+    }
+
+    /// Lays out deferred quotes whose file could not be read, with their spans and labels
+    /// listed as notes.
+    fn push_unresolved_quote(
+        &mut self,
+        quotes: &[&Quote],
+        err: &IoErrorDetail,
+        options: &RenderOptions,
+    ) {
+        let quote = quotes[0];
+        if let Some(path) = quote.path() {
+            self.push(format!("{0:>1$} ", options.quote_style.header, 3), Style::Gutter);
+            let link = path.to_string_lossy();
+            self.push_link(format!("{}:{}", path.display(), quote.span().start), Style::Path, link);
+            self.newline();
+        }
+        let marked = quotes
+            .iter()
+            .map(|q| (q.span(), q.message()))
+            .chain(quotes.iter().flat_map(|q| q.labels().iter().map(|(s, m)| (*s, m.as_str()))));
+        for (s, message) in marked {
+            self.push(format!("{0:>1$} ", "=", 3), Style::Gutter);
+            self.push(format!("note: {}: {}", s, sanitize(message, options)), Style::Note);
+            self.newline();
+        }
+        self.push(format!("{0:>1$} ", "=", 3), Style::Gutter);
+        self.push(format!("note: source not available: {}", err), Style::Note);
+        self.newline();
+    }
+
+    fn push_include_site(&mut self, site: &IncludeSite) {
+        match site.path {
+            Some(ref path) => {
+                let link = path.to_string_lossy();
+                self.push_link(site.to_string(), Style::Path, link);
+            }
+            None => self.push(site.to_string(), Style::Plain),
+        }
+        self.newline();
+    }
+
+    fn push_quote_accessible(&mut self, quote: &Quote, severity: Option<Severity>, options: &RenderOptions) {
+        let span = quote.span();
+        if let Some(path) = quote.path() {
+            self.push("in file ", Style::Plain);
+            let path = path.to_string_lossy();
+            self.push_link(path.clone(), Style::Path, path);
+            self.newline();
+        }
+        for site in quote.included_from() {
+            self.push("included from ", Style::Plain);
+            self.push_include_site(site);
+        }
+        for (i, s) in quote.source().lines().enumerate() {
+            self.push(format!("line {}: ", quote.line() as usize + i + 1), Style::Gutter);
+            self.push(sanitize(s, options), Style::Source);
+            self.newline();
+        }
+        let mut sentence = match severity {
+            Some(severity) => format!("{} at ", severity),
+            None => "at ".to_string(),
+        };
+        sentence += &describe_span(span);
+        if !quote.message().is_empty() {
+            sentence += ": ";
+            sentence += &sanitize(quote.message(), options);
+        }
+        self.push(sentence, Style::Label);
+        self.newline();
+        for (span, message) in quote.labels() {
+            let mut sentence = format!("see {}", describe_span(*span));
+            if !message.is_empty() {
+                sentence += ": ";
+                sentence += &sanitize(message, options);
+            }
+            self.push(sentence, Style::Label);
+            self.newline();
+        }
+    }
+}
+
+/// Describes location of `span` with words, for accessible output.
+fn describe_span(span: Span) -> String {
+    if span.start.line == span.end.line {
+        let (start, end) = (span.start.column + 1, span.end.column);
+        if end > start {
+            format!("line {}, columns {} through {}", span.start.line + 1, start, end)
+        } else {
+            format!("line {}, column {}", span.start.line + 1, start)
+        }
+    } else {
+        format!(
+            "line {}, column {} through line {}, column {}",
+            span.start.line + 1,
+            span.start.column + 1,
+            span.end.line + 1,
+            span.end.column
+        )
+    }
+}
+
+/// Text shown for a column of a quoted line.
+#[derive(Debug)]
+struct Cell {
+    text: String,
+    /// Number of terminal columns taken by `text`.
+    width: usize,
+}
+
+impl Cell {
+    fn new(text: String, width: usize) -> Cell {
+        Cell { text, width }
+    }
+}
+
+/// Splits line `s` into cells, one for each column as counted according to `config`. Tabs
+/// are expanded to spaces (to multiples of `tab_width` if tabs are single columns in
+/// `config`), characters taking more columns are followed by empty cells and zero-width
+/// characters are appended to the preceding cell.
+fn cells(s: &str, config: &PositionConfig, tab_width: usize) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = Vec::with_capacity(s.len());
+    // terminal column of the next cell
+    let mut display = 0;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().map(|n| {
+            let mut buf = [0; 4];
+            n.encode_utf8(&mut buf).as_bytes()[0]
+        });
+        let w = config.column_width(c, cells.len() as u32, next);
+        if c == '\t' && config.tab_width.is_some() {
+            cells.extend((0..w).map(|_| Cell::new(" ".to_string(), 1)));
+            display += w as usize;
+        } else if c == '\t' {
+            let n = tab_width.max(1) - display % tab_width.max(1);
+            cells.push(Cell::new(" ".repeat(n), n));
+            display += n;
+        } else if w == 0 && !cells.is_empty() {
+            cells.last_mut().unwrap().text.push(c);
+        } else {
+            // unless columns are display columns already, cells are as wide as their text, so
+            // that markers stay aligned under wide characters and combining marks
+            let (first, rest) = match config.columns {
+                ColumnMode::DisplayWidth => (1, 1),
+                _ => (io::char_width(c).unwrap_or(1), 0),
+            };
+            cells.push(Cell::new(c.to_string(), first));
+            cells.extend((1..w).map(|_| Cell::new(String::new(), rest)));
+            display += first + rest * (w as usize - 1);
+        }
+    }
+    cells
+}
+
+/// Replaces control characters in `s` if `options.sanitize` is set. Each character is replaced
+/// with a single one (from Unicode "Control Pictures" block if possible) to keep markers
+/// aligned, or, in accessible mode, with an ASCII escape sequence.
+fn sanitize<'a>(s: &'a str, options: &RenderOptions) -> Cow<'a, str> {
+    fn is_unsafe(c: char) -> bool {
+        (c.is_control() && c != '\t') || ('\u{202A}'..='\u{202E}').contains(&c) || ('\u{2066}'..='\u{2069}').contains(&c)
+    }
+
+    if !options.sanitize || !s.chars().any(is_unsafe) {
+        return Cow::Borrowed(s);
+    }
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if !is_unsafe(c) {
+            out.push(c);
+        } else if options.accessible {
+            let _ = write!(out, "{}", c.escape_unicode());
+        } else {
+            out.push(match c as u32 {
+                n @ 0x00..=0x1F => std::char::from_u32(0x2400 + n).unwrap(),
+                0x7F => '\u{2421}',
+                _ => '\u{FFFD}',
+            });
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Columns of line `ln` (split into `cells`) marked for `span`, if any. Single-line spans are
+/// marked as they are, even if empty. For multi-line spans, leading whitespace of lines after
+/// the first one is not marked, and an empty part of the first line (span starting at line
+/// end) is marked with the column past the last one.
+fn marked_columns(span: Span, ln: u32, cells: &[Cell]) -> Option<(usize, usize)> {
+    if ln < span.start.line || ln > span.end.line {
+        return None;
+    }
+    if span.start.line == span.end.line {
+        return Some((span.start.column as usize, span.end.column as usize));
+    }
+    let from = if ln == span.start.line {
+        span.start.column as usize
+    } else {
+        cells.iter().position(|c| !c.text.trim().is_empty()).unwrap_or(cells.len())
+    };
+    let to = if ln == span.end.line {
+        span.end.column as usize
+    } else if ln == span.start.line {
+        cells.len().max(from + 1)
+    } else {
+        cells.len()
+    };
+    if to > from {
+        Some((from, to))
+    } else {
+        None
+    }
+}
+
+/// Returns predicate telling which of quoted lines `first_line..last_line` are shown, given
+/// lines of labels of `marked` spans and limits of `style`.
+fn shown_lines(
+    label_lines: &[Option<u32>],
+    marked: &[(Span, &str)],
+    first_line: u32,
+    last_line: u32,
+    style: &QuoteStyle,
+) -> impl Fn(u32) -> bool {
+    let (mut from, mut to) = (first_line, last_line.saturating_sub(1));
+    if let Some(context) = style.context_lines {
+        // lines from the first to the last marked one, with `context` lines around them
+        let marked_lines = label_lines
+            .iter()
+            .zip(marked.iter())
+            .filter_map(|(&l, &(s, _))| l.map(|l| (s.start.line.max(first_line), l)));
+        let lo = marked_lines.clone().map(|(s, _)| s).min();
+        let hi = marked_lines.map(|(_, e)| e).max();
+        if let (Some(lo), Some(hi)) = (lo, hi) {
+            from = from.max(lo.saturating_sub(context));
+            to = to.min(hi + context);
+        }
+    }
+    // lines in the middle of too high snippets are omitted
+    let (head, tail) = match style.max_lines {
+        Some(max) if max > 0 && (to + 1 - from) as usize > max => {
+            (from + ((max + 1) / 2) as u32, to + 1 - (max / 2) as u32)
+        }
+        _ => (to + 1, to + 1),
+    };
+    move |ln| ln >= from && ln <= to && (ln < head || ln >= tail)
+}
+
+/// Tells if `b` can be shown in the frame of `a`, i.e. both quote single-line spans
+/// on the same line of the same source excerpt.
+fn same_line(a: &Quote, b: &Quote) -> bool {
+    let (sa, sb) = (a.span(), b.span());
+    sa.start.line == sa.end.line
+        && sb.start.line == sb.end.line
+        && sa.start.line == sb.start.line
+        && a.path() == b.path()
+        && a.line() == b.line()
+        && a.source() == b.source()
+}
+
+/// Computes columns `start..end` of a line with `len` columns to be shown within `width`,
+/// centered on `focus` columns.
+fn window(len: usize, focus: (usize, usize), width: usize) -> (usize, usize) {
+    let (from, to) = focus;
+    let start = if to.saturating_sub(from) >= width {
+        from
+    } else {
+        ((from + to) / 2).saturating_sub(width / 2)
+    };
+    let start = start.min(len.saturating_sub(width));
+    (start, (start + width).min(len))
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.write_plain(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_layout_matches_display() {
+        let mut r = MemCharReader::with_path("file.txt", b"line 1;\nline 2;\nline 3;\n");
+        let q = r.quote(Position::with(13, 1, 5), Position::with(14, 1, 6), 1, 1, "here".into());
+        let l = Layout::of_quote(&q, &RenderOptions::default());
+        assert_eq!(l.to_string(), q.to_string());
+        assert!(l.segments().iter().any(|s| s.style == Style::Marker && s.text == "^"));
+        let path = l.segments().iter().find(|s| s.style == Style::Path).unwrap();
+        assert_eq!(path.link.as_ref().unwrap(), "file.txt");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quote_with_non_utf8_path() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let path = std::path::PathBuf::from(std::ffi::OsString::from_vec(b"fil\xffe.txt".to_vec()));
+        let mut r = MemCharReader::with_path(&path, b"line 1;\n");
+        let q = r.quote(Position::with(0, 0, 0), Position::with(4, 0, 4), 0, 0, "here".into());
+        assert!(q.to_string().contains("fil\u{FFFD}e.txt:1:1"));
+    }
+
+    #[test]
+    fn diag_layout_lines() {
+        let mut r = MemCharReader::new(b"line 1;\nline 2;\n");
+        let mut d = ParseDiag::from(IoErrorDetail::Fmt);
+        d.add_quote(r.quote(Position::with(8, 1, 0), Position::with(12, 1, 4), 2, 2, "quoted".into()));
+        let l = Layout::of_diag(&d, &RenderOptions::default());
+        let lines = l.lines();
+        assert_eq!(lines[0][0].style, Style::Severity(Severity::Failure));
+        assert_eq!(lines[0][0].text, "error");
+        assert!(lines.iter().all(|l| l.iter().all(|s| !s.text.contains('\n'))));
+        assert_eq!(l.to_string().lines().count(), lines.len());
+    }
+
+    #[test]
+    fn code_format() {
+        let d = ParseDiag::new(IoErrorDetail::Fmt);
+        let mut options = RenderOptions::default();
+        assert_eq!(
+            options.code_format.format(d.detail(), d.effective_severity()),
+            Some(d.effective_code_string())
+        );
+
+        options.code_format = CodeFormat {
+            width: 5,
+            separator: "-",
+            default_prefix: Some("MYAPP".into()),
+            ..Default::default()
+        };
+        let text = Layout::of_diag(&d, &options).to_string();
+        assert!(text.starts_with("error [MYAPP-F-00099]: "));
+
+        options.code_format.custom = Some(|_, _| None);
+        let text = Layout::of_diag(&d, &options).to_string();
+        assert!(text.starts_with(&format!("error: {}", d.detail())));
+    }
+
+    #[test]
+    fn accessible_quote() {
+        let mut r = MemCharReader::with_path("file.txt", b"line 1;\nline 2;\n");
+        let mut d = ParseDiag::new(IoErrorDetail::Fmt);
+        d.add_quote(r.quote(Position::with(8, 1, 0), Position::with(12, 1, 4), 1, 0, "quoted".into()));
+        let options = RenderOptions {
+            accessible: true,
+            ..Default::default()
+        };
+        let text = Layout::of_diag(&d, &options).to_string();
+        assert!(text.contains("in file file.txt\nline 1: line 1;\nline 2: line 2;\n"));
+        assert!(text.contains("error at line 2, columns 1 through 4: quoted\n"));
+        assert!(!text.contains('^'));
+        assert!(text.is_ascii());
+    }
+
+    #[test]
+    fn long_line_window() {
+        let line = format!("{}needle{}", "a".repeat(100), "b".repeat(100));
+        let mut r = MemCharReader::new(line.as_bytes());
+        let q = r.quote(Position::with(100, 0, 100), Position::with(106, 0, 106), 0, 0, "x".into());
+        let options = RenderOptions {
+            max_width: Some(20),
+            ..Default::default()
+        };
+        let text = Layout::of_quote(&q, &options).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], format!("  1| \u{2026}{}needle{}\u{2026}", "a".repeat(7), "b".repeat(7)));
+        assert_eq!(lines[1], format!("   | {}^^^^^^ x", " ".repeat(8)));
+
+        let q = r.quote(Position::with(0, 0, 0), Position::with(1, 0, 1), 0, 0, "x".into());
+        let text = Layout::of_quote(&q, &options).to_string();
+        assert_eq!(text.lines().next().unwrap(), format!("  1| {}\u{2026}", "a".repeat(20)));
+    }
+
+    #[test]
+    fn terminal_width_window() {
+        let line = format!("{}needle{}", "a".repeat(100), "b".repeat(100));
+        let mut r = MemCharReader::new(line.as_bytes());
+        let q = r.quote(Position::with(100, 0, 100), Position::with(106, 0, 106), 0, 0, "x".into());
+        let mut options = RenderOptions {
+            terminal_width: Some(30),
+            ..Default::default()
+        };
+        let text = Layout::of_quote(&q, &options).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0].chars().count(), 30);
+        assert_eq!(lines[0], format!("  1| \u{2026}{}needle{}\u{2026}", "a".repeat(8), "b".repeat(9)));
+        assert_eq!(lines[1], format!("   | {}^^^^^^ x", " ".repeat(9)));
+
+        // narrower `max_width` wins
+        options.max_width = Some(20);
+        let text = Layout::of_quote(&q, &options).to_string();
+        assert_eq!(text.lines().next().unwrap().chars().count(), 27);
+    }
+
+    #[test]
+    fn report_layout_in_order() {
+        let mut diags = Diags::new();
+        for i in 0..50u32 {
+            let mut r = MemCharReader::with_path("file.txt", b"line 1;\nline 2;\n");
+            let mut d = ParseDiag::new(IoErrorDetail::MissingFinalNewline {
+                pos: Position::with(i as usize % 8, 0, i % 8),
+            });
+            d.add_quote(r.quote(Position::with(8, 1, 0), Position::with(12, 1, 4), 0, 0, format!("{}", i).into()));
+            diags.add_diag(d).unwrap();
+        }
+        let options = RenderOptions::default();
+        let report = Layout::of_report(diags.diags(), &options);
+        let mut expected = Layout::new();
+        for d in diags.diags() {
+            expected.append(Layout::of_diag(d.as_ref(), &options));
+        }
+        assert_eq!(report, expected);
+    }
+
+    #[test]
+    fn sanitized_source() {
+        let mut r = MemCharReader::new(b"ab \x1b[31mred\x1b[0m x\n");
+        let q = r.quote(Position::with(16, 0, 16), Position::with(17, 0, 17), 0, 0, "\x1b]0;t\x07".into());
+        let mut options = RenderOptions {
+            sanitize: true,
+            ..Default::default()
+        };
+        let text = Layout::of_quote(&q, &options).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "  1| ab \u{241b}[31mred\u{241b}[0m x");
+        assert_eq!(lines[1], format!("   | {}^ \u{241b}]0;t\u{2407}", " ".repeat(16)));
+
+        options.accessible = true;
+        let text = Layout::of_quote(&q, &options).to_string();
+        assert!(text.contains("line 1: ab \\u{1b}[31mred\\u{1b}[0m x\n"));
+        assert!(!text.contains('\x1b'));
+    }
+
+    #[cfg(feature = "unicode-width")]
+    #[test]
+    fn quote_columns_follow_position_config() {
+        let positions = PositionConfig {
+            tab_width: Some(4),
+            columns: ColumnMode::DisplayWidth,
+            ..PositionConfig::CHARS
+        };
+        let input = "\t老 = x;\n";
+        let mut r = MemCharReader::new(input.as_bytes()).with_position_config(positions);
+        r.skip_chars(6).unwrap();
+        let (_, p) = r.peek_char_pos(0).unwrap().unwrap();
+        assert_eq!(p, Position::with(7, 0, 9));
+        let q = r.quote(p, Position::with(8, 0, 10), 0, 0, "x".into());
+        let options = RenderOptions {
+            positions,
+            ..Default::default()
+        };
+        let text = Layout::of_quote(&q, &options).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "  1|     老 = x;");
+        assert_eq!(lines[1], format!("   | {}^ x", " ".repeat(9)));
+    }
+
+    #[cfg(feature = "color")]
+    #[test]
+    fn ansi_colors() {
+        let mut r = MemCharReader::with_path("file.txt", b"let x;\n");
+        let mut d = ParseDiag::new(IoErrorDetail::Fmt);
+        d.add_quote(r.quote(Position::with(4, 0, 4), Position::with(5, 0, 5), 0, 0, "here".into()));
+        let l = Layout::of_diag(&d, &RenderOptions::default());
+        let mut text = String::new();
+        l.write_ansi(&mut text, &Palette::default()).unwrap();
+        assert!(text.starts_with("\x1b[1;31merror\x1b[0m ["));
+        assert!(text.contains("\x1b[1;34m  1| \x1b[0mlet x;\n"));
+        assert!(text.contains("    \x1b[1;31m^\x1b[0m \x1b[1;31mhere\x1b[0m\n"));
+
+        let palette = Palette {
+            error: "",
+            gutter: "",
+            marker: "",
+            label: "",
+            code: "",
+            message: "",
+            ..Palette::default()
+        };
+        text.clear();
+        l.write_ansi(&mut text, &palette).unwrap();
+        assert_eq!(text, l.to_string());
+
+        assert!(!config::ColorPolicy::Never.enabled(true));
+        assert!(config::ColorPolicy::Always.enabled(false));
+        assert!(!config::ColorPolicy::Auto.enabled(false));
+    }
+
+    #[test]
+    fn multi_line_span() {
+        let mut r = MemCharReader::new(b"fn main() {\n    body();\n}\n");
+        let q = r.quote(Position::with(10, 0, 10), Position::with(25, 2, 1), 0, 0, "block".into());
+        let text = Layout::of_quote(&q, &RenderOptions::default()).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "  1| fn main() {",
+                "   |           ^",
+                "  2|     body();",
+                "   |     ^^^^^^^",
+                "  3| }",
+                "   | ^ block",
+            ]
+        );
+
+        // span ending at line start leaves the last line unmarked
+        let q = r.quote(Position::with(10, 0, 10), Position::with(24, 2, 0), 0, 0, "block".into());
+        let text = Layout::of_quote(&q, &RenderOptions::default()).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[3], "   |     ^^^^^^^ block");
+        assert_eq!(lines[4], "  3| }");
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn secondary_labels() {
+        let mut r = MemCharReader::new(b"let v = (1, 2];\n");
+        let opened = Span::with(8, 0, 8, 9, 0, 9);
+        let (from, to) = (Position::with(13, 0, 13), Position::with(14, 0, 14));
+        let q = r.quote_labeled(from, to, &[(opened, "opened here")], "closed here".into());
+        let text = Layout::of_quote(&q, &RenderOptions::default()).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "  1| let v = (1, 2];",
+                "   |         -    ^",
+                "   | ^ closed here",
+                "   | - opened here",
+            ]
+        );
+
+        let mut r = MemCharReader::new(b"f(a,\n  b];\n");
+        let opened = Span::with(1, 0, 1, 2, 0, 2);
+        let (from, to) = (Position::with(8, 1, 3), Position::with(9, 1, 4));
+        let q = r.quote_labeled(from, to, &[(opened, "opened here")], "closed here".into());
+        let text = Layout::of_quote(&q, &RenderOptions::default()).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines,
+            vec!["  1| f(a,", "   |  - opened here", "  2|   b];", "   |    ^ closed here"]
+        );
+
+        // labels outside quoted lines are shown as notes
+        let q = r
+            .quote(opened.start, opened.end, 0, 0, "opened here".into())
+            .with_label(Span::with_pos(from, to), "closed here");
+        let text = Layout::of_quote(&q, &RenderOptions::default()).to_string();
+        assert!(text.ends_with("  = note: 2:4-5: closed here\n"));
+    }
+
+    #[test]
+    fn suggestion_help() {
+        let mut r = MemCharReader::new(b"let x = foo(a b);\n");
+        let mut d = ParseDiag::new(IoErrorDetail::Fmt);
+        let span = Span::with(8, 0, 8, 11, 0, 11);
+        d.add_quote(r.quote(span.start, span.end, 0, 0, "unknown function".into()));
+        d.add_suggestion(Suggestion::new(span, "bar", "did you mean"));
+        d.add_suggestion(Suggestion::new(Span::with(13, 0, 13, 13, 0, 13), ",", ""));
+        let text = Layout::of_diag(&d, &RenderOptions::default()).to_string();
+        assert!(text.contains(
+            "^^^ unknown function\nhelp: did you mean: replace 1:9-12 with `bar`\n\
+             help: insert `,` at 1:14\n"
+        ));
+    }
+
+    #[test]
+    fn custom_renderer() {
+        /// Renderer writing only the header line.
+        struct Headers;
+
+        impl DiagRenderer for Headers {
+            fn write(&self, layout: &Layout, w: &mut dyn Write) -> std::fmt::Result {
+                for s in layout.lines()[0] {
+                    w.write_str(&s.text)?;
+                }
+                Ok(())
+            }
+        }
+
+        let mut r = MemCharReader::new(b"line 1;\n");
+        let mut d = ParseDiag::new(IoErrorDetail::Fmt);
+        d.add_quote(r.quote(Position::with(0, 0, 0), Position::with(4, 0, 4), 0, 0, "x".into()));
+        let options = RenderOptions::default();
+        let mut text = String::new();
+        PlainText.render(&d, &options, &mut text).unwrap();
+        assert_eq!(text, Layout::of_diag(&d, &options).to_string());
+
+        text.clear();
+        Headers.render(&d, &options, &mut text).unwrap();
+        assert_eq!(text, format!("error [{}]: {}", d.effective_code_string(), d.detail()));
+
+        #[cfg(feature = "json")]
+        {
+            text.clear();
+            Json.render(&d, &options, &mut text).unwrap();
+            let record: DiagRecord = serde_json::from_str(&text).unwrap();
+            assert_eq!(record, DiagRecord::of(&d));
+        }
+    }
+
+    #[test]
+    fn tabs_expanded() {
+        let mut r = MemCharReader::new(b"\tx\t= 1;\n");
+        let q = r.quote(Position::with(1, 0, 1), Position::with(3, 0, 3), 0, 0, "here".into());
+        let mut options = RenderOptions::default();
+        let text = Layout::of_quote(&q, &options).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines, vec!["  1|     x   = 1;", "   |     ^^^^ here"]);
+
+        options.tab_width = 2;
+        let text = Layout::of_quote(&q, &options).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines, vec!["  1|   x = 1;", "   |   ^^ here"]);
+    }
+
+    #[cfg(feature = "unicode-width")]
+    #[test]
+    fn wide_characters_aligned() {
+        let input = "老x = e\u{301}y;\n";
+        let mut r = MemCharReader::new(input.as_bytes());
+        let q = r.quote(Position::with(3, 0, 1), Position::with(4, 0, 2), 0, 0, "x".into());
+        let text = Layout::of_quote(&q, &RenderOptions::default()).to_string();
+        assert_eq!(text.lines().nth(1).unwrap(), "   |   ^ x");
+
+        // combining mark takes a column of its own, but no terminal column
+        let q = r.quote(Position::with(7, 0, 5), Position::with(11, 0, 8), 0, 0, "y".into());
+        let text = Layout::of_quote(&q, &RenderOptions::default()).to_string();
+        assert_eq!(text.lines().nth(1).unwrap(), "   |       ^^ y");
+
+        let positions = PositionConfig {
+            columns: ColumnMode::Utf16,
+            ..PositionConfig::CHARS
+        };
+        let mut r = MemCharReader::new("\u{1F600}x;\n".as_bytes()).with_position_config(positions);
+        let q = r.quote(Position::with(4, 0, 2), Position::with(5, 0, 3), 0, 0, "x".into());
+        let options = RenderOptions {
+            positions,
+            ..Default::default()
+        };
+        let text = Layout::of_quote(&q, &options).to_string();
+        assert_eq!(text.lines().nth(1).unwrap(), "   |   ^ x");
+    }
+
+    #[test]
+    fn quote_style() {
+        let mut r = MemCharReader::with_path("f.txt", b"a\n{\n  1\n  2\n  3\n  4\n}\nz\n");
+        let q = r.quote(Position::with(2, 1, 0), Position::with(21, 6, 1), 1, 1, "block".into());
+        let options = RenderOptions {
+            quote_style: QuoteStyle {
+                show_header: false,
+                separator: ": ",
+                markers: ['*', '-', '~', '+'],
+                context_lines: Some(0),
+                max_lines: Some(4),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let text = Layout::of_quote(&q, &options).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "  2: {",
+                "   : *",
+                "  3:   1",
+                "   :   *",
+                "...",
+                "  6:   4",
+                "   :   *",
+                "  7: }",
+                "   : * block",
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_of_same_line_merged() {
+        let mut r = MemCharReader::new(b"let x = y + z;\nnext\n");
+        let mut d = ParseDiag::new(IoErrorDetail::Fmt);
+        d.add_quote(r.quote(Position::with(8, 0, 8), Position::with(9, 0, 9), 0, 0, "first".into()));
+        d.add_quote(r.quote(Position::with(12, 0, 12), Position::with(13, 0, 13), 0, 0, "second".into()));
+        let text = Layout::of_diag(&d, &RenderOptions::default()).to_string();
+        let lines: Vec<_> = text.lines().skip(1).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "  1| let x = y + z;",
+                "   |         ^   -",
+                "   | ^ first",
+                "   | - second",
+            ]
+        );
+    }
+
+    #[test]
+    fn deferred_quote() {
+        use std::io::Write;
+
+        let data = b"a = 1\nb = x\nc = 3\n";
+        let mut f = tempfile::NamedTempFile::new().unwrap();
+        f.write_all(data).unwrap();
+        let (from, to) = (Position::with(10, 1, 4), Position::with(11, 1, 5));
+        let q = Quote::deferred(f.path(), Span::with_pos(from, to), "x".into())
+            .with_label(Span::with(0, 0, 0, 1, 0, 1), "defined here");
+        assert!(q.is_deferred());
+        assert_eq!(q.source(), "");
+
+        let lines = crate::config::config().quote_lines;
+        let eager = Quote::new(Some(f.path()), data, from, to, lines, lines, "x".into())
+            .with_label(Span::with(0, 0, 0, 1, 0, 1), "defined here");
+        let options = RenderOptions::default();
+        assert_eq!(
+            Layout::of_quote(&q, &options).to_string(),
+            Layout::of_quote(&eager, &options).to_string()
+        );
+        assert!(!q.resolve().unwrap().is_deferred());
+
+        let path = f.path().to_path_buf();
+        drop(f);
+        let text = Layout::of_quote(&q, &options).to_string();
+        assert!(text.contains("  = note: 2:5-6: x\n"));
+        assert!(text.contains("  = note: 1:1-2: defined here\n"));
+        assert!(text.contains("source not available"));
+        assert!(Quote::deferred(path, Span::with_pos(from, to), "x".into()).resolve().is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! Conversion of diagnostics into SARIF 2.1.0 logs, accepted by GitHub code scanning and
+//! other static analysis result viewers. Each diagnostic becomes a result of a rule named by
+//! its code. The first quote gives the result location, remaining quotes and secondary labels
+//! become related locations, and suggestions become fixes.
+
+use std::path::{Component, Path};
+
+use serde_json::{json, Map, Value};
+
+use super::*;
+
+pub const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Analysis tool reported as the producer of the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tool {
+    pub name: String,
+    pub version: Option<String>,
+    pub information_uri: Option<String>,
+}
+
+impl Tool {
+    pub fn new<S: Into<String>>(name: S) -> Tool {
+        Tool {
+            name: name.into(),
+            version: None,
+            information_uri: None,
+        }
+    }
+
+    pub fn with_version<S: Into<String>>(mut self, version: S) -> Tool {
+        self.version = Some(version.into());
+        self
+    }
+
+    pub fn with_information_uri<S: Into<String>>(mut self, uri: S) -> Tool {
+        self.information_uri = Some(uri.into());
+        self
+    }
+}
+
+pub fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info => "note",
+        Severity::Warning => "warning",
+        Severity::Error | Severity::Failure | Severity::Critical => "error",
+    }
+}
+
+/// Converts `diags` into a SARIF log with a single run of `tool`.
+pub fn to_log<'a, I>(tool: &Tool, diags: I) -> Value
+where
+    I: IntoIterator<Item = &'a dyn Diag>,
+{
+    let mut rules: Vec<String> = Vec::new();
+    let mut results = Vec::new();
+    for diag in diags {
+        let code = diag.effective_code_string();
+        let index = match rules.iter().position(|r| *r == code) {
+            Some(index) => index,
+            None => {
+                rules.push(code);
+                rules.len() - 1
+            }
+        };
+        results.push(result(diag, index, &rules[index]));
+    }
+
+    let mut driver = Map::new();
+    driver.insert("name".into(), tool.name.clone().into());
+    if let Some(ref version) = tool.version {
+        driver.insert("version".into(), version.clone().into());
+    }
+    if let Some(ref uri) = tool.information_uri {
+        driver.insert("informationUri".into(), uri.clone().into());
+    }
+    let rules: Vec<Value> = rules.into_iter().map(|id| json!({ "id": id })).collect();
+    driver.insert("rules".into(), rules.into());
+
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": { "driver": driver },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+fn result(diag: &dyn Diag, rule_index: usize, rule_id: &str) -> Value {
+    let mut result = Map::new();
+    result.insert("ruleId".into(), rule_id.into());
+    result.insert("ruleIndex".into(), rule_index.into());
+    result.insert("level".into(), level(diag.effective_severity()).into());
+    result.insert("message".into(), json!({ "text": diag.detail().to_string() }));
+
+    let quotes = diag.quotes();
+    if let Some(q) = quotes.first() {
+        result.insert("locations".into(), json!([location(q, q.span(), q.message())]));
+    }
+    let mut related = Vec::new();
+    for (i, q) in quotes.iter().enumerate() {
+        if i > 0 {
+            related.push(location(q, q.span(), q.message()));
+        }
+        for (span, message) in q.labels() {
+            related.push(location(q, *span, message));
+        }
+    }
+    for (i, r) in related.iter_mut().enumerate() {
+        r["id"] = i.into();
+    }
+    if !related.is_empty() {
+        result.insert("relatedLocations".into(), related.into());
+    }
+
+    let fixes: Vec<Value> = diag
+        .suggestions()
+        .iter()
+        .filter_map(|s| fix(s, quotes.first()))
+        .collect();
+    if !fixes.is_empty() {
+        result.insert("fixes".into(), fixes.into());
+    }
+    result.into()
+}
+
+/// Location of `span` within `quote` source, with `message` if not empty.
+fn location(quote: &Quote, span: Span, message: &str) -> Value {
+    let mut physical = Map::new();
+    if let Some(uri) = quote.path().map(uri) {
+        physical.insert("artifactLocation".into(), json!({ "uri": uri }));
+    }
+    physical.insert("region".into(), region(span));
+    // deferred quotes have no text to give as context until rendered
+    if !quote.is_deferred() {
+        let lines = quote.source().lines().count().max(1) as u32;
+        physical.insert(
+            "contextRegion".into(),
+            json!({
+                "startLine": quote.line() + 1,
+                "endLine": quote.line() + lines,
+                "snippet": { "text": quote.source() },
+            }),
+        );
+    }
+    let mut location = Map::new();
+    location.insert("physicalLocation".into(), physical.into());
+    if !message.is_empty() {
+        location.insert("message".into(), json!({ "text": message }));
+    }
+    location.into()
+}
+
+fn region(span: Span) -> Value {
+    json!({
+        "startLine": span.start.line + 1,
+        "startColumn": span.start.column + 1,
+        "endLine": span.end.line + 1,
+        "endColumn": span.end.column + 1,
+        "byteOffset": span.start.offset,
+        "byteLength": span.end.offset.saturating_sub(span.start.offset),
+    })
+}
+
+/// Fix of `suggestion`, applied to the source of `quote` (the first quote of a diagnostic).
+/// SARIF requires location of changed artifact, so there is no fix if the source has no path.
+fn fix(suggestion: &Suggestion, quote: Option<&Quote>) -> Option<Value> {
+    let uri = uri(quote?.path()?);
+    Some(json!({
+        "description": { "text": suggestion.message },
+        "artifactChanges": [{
+            "artifactLocation": { "uri": uri },
+            "replacements": [{
+                "deletedRegion": region(suggestion.span),
+                "insertedContent": { "text": suggestion.replacement },
+            }],
+        }],
+    }))
+}
+
+/// `file://` URI of absolute `path`, or relative reference to `path` otherwise, with `/` as
+/// the separator and path segments percent-encoded.
+fn uri(path: &Path) -> String {
+    let mut segments = Vec::new();
+    for c in path.components() {
+        match c {
+            // drive letter of windows paths, like `C:`
+            Component::Prefix(p) => segments.push(p.as_os_str().to_string_lossy().into_owned()),
+            Component::RootDir => {}
+            Component::CurDir => segments.push(".".into()),
+            Component::ParentDir => segments.push("..".into()),
+            Component::Normal(s) => segments.push(percent_encode(&s.to_string_lossy())),
+        }
+    }
+    if path.has_root() {
+        format!("file:///{}", segments.join("/"))
+    } else {
+        segments.join("/")
+    }
+}
+
+/// Percent-encodes characters not allowed in URI path segments. Colons are encoded as well,
+/// so that the first segment of a relative reference is not taken for a scheme.
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&'
+            | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' | b'@' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diags_to_log() {
+        let mut r = MemCharReader::with_path("src/input.txt", b"a = 1\nb = x\n");
+        let mut d1 = ParseDiag::new(IoErrorDetail::Fmt);
+        let (from, to) = (Position::with(10, 1, 4), Position::with(11, 1, 5));
+        d1.add_quote(
+            r.quote(from, to, 1, 0, "x".into())
+                .with_label(Span::with(0, 0, 0, 1, 0, 1), "defined here"),
+        );
+        d1.add_suggestion(Suggestion::new(Span::with_pos(from, to), "1", "use a number"));
+        let d2 = ParseDiag::new(IoErrorDetail::Fmt);
+        let d3 = ParseDiag::new(IoErrorDetail::MissingFinalNewline { pos: Position::new() });
+
+        let tool = Tool::new("checker").with_version("1.0.0");
+        let diags: Vec<&dyn Diag> = vec![&d1, &d2, &d3];
+        let log = to_log(&tool, diags);
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["name"], "checker");
+        assert_eq!(run["tool"]["driver"]["rules"].as_array().unwrap().len(), 2);
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["ruleId"], IoErrorDetail::Fmt.code_string());
+        assert_eq!(results[1]["ruleIndex"], 0);
+        assert_eq!(results[2]["ruleIndex"], 1);
+        assert_eq!(results[2]["level"], "warning");
+
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/input.txt");
+        assert_eq!(location["region"]["startLine"], 2);
+        assert_eq!(location["region"]["startColumn"], 5);
+        assert_eq!(location["region"]["endColumn"], 6);
+        assert_eq!(location["contextRegion"]["snippet"]["text"], "a = 1\nb = x");
+        let related = &results[0]["relatedLocations"][0];
+        assert_eq!(related["message"]["text"], "defined here");
+        assert_eq!(related["physicalLocation"]["region"]["startLine"], 1);
+        let fix = &results[0]["fixes"][0]["artifactChanges"][0];
+        assert_eq!(fix["replacements"][0]["insertedContent"]["text"], "1");
+        assert!(results[1].get("locations").is_none());
+    }
+
+    #[test]
+    fn uris_percent_encoded() {
+        assert_eq!(uri(Path::new("src/a b#1.txt")), "src/a%20b%231.txt");
+        assert_eq!(uri(Path::new("../c:d/żółw.txt")), "../c%3Ad/%C5%BC%C3%B3%C5%82w.txt");
+        #[cfg(unix)]
+        assert_eq!(uri(Path::new("/tmp/x y.txt")), "file:///tmp/x%20y.txt");
+        #[cfg(windows)]
+        assert_eq!(uri(Path::new("C:\\tmp\\x y.txt")), "file:///C:/tmp/x%20y.txt");
+    }
+
+    #[test]
+    fn fix_omitted_without_path() {
+        let mut r = MemCharReader::new(b"a = x\n");
+        let mut d = ParseDiag::new(IoErrorDetail::Fmt);
+        let (from, to) = (Position::with(4, 0, 4), Position::with(5, 0, 5));
+        d.add_quote(r.quote(from, to, 0, 0, "x".into()));
+        d.add_suggestion(Suggestion::new(Span::with_pos(from, to), "1", "use a number"));
+
+        let diags: Vec<&dyn Diag> = vec![&d];
+        let log = to_log(&Tool::new("checker"), diags);
+        let result = &log["runs"][0]["results"][0];
+        assert!(result["locations"][0]["physicalLocation"].get("artifactLocation").is_none());
+        assert!(result.get("fixes").is_none());
+    }
+}
@@ -0,0 +1,134 @@
+//! Conversion of diagnostics into Sentry events. Causes become chained exceptions (each
+//! with its code as the exception type), stacktraces become exception stacktraces, quotes
+//! are kept as structured data in `extra` and context values in the `diag` entry of `contexts`,
+//! so that crash reporting does not reduce diagnostics to a flat message.
+
+use sentry_types::protocol::v7::{self as protocol, Event, Exception, Frame, Level};
+use serde_json::{json, Value};
+
+use super::*;
+
+pub fn level(severity: Severity) -> Level {
+    match severity {
+        Severity::Info => Level::Info,
+        Severity::Warning => Level::Warning,
+        Severity::Error | Severity::Failure => Level::Error,
+        Severity::Critical => Level::Fatal,
+    }
+}
+
+/// Converts `diag` into a Sentry event. Exceptions are listed from the innermost cause
+/// to `diag` itself, as Sentry expects.
+pub fn to_event(diag: &dyn Diag) -> Event<'static> {
+    let detail = diag.detail();
+    let mut exceptions = Vec::new();
+    let mut quotes = Vec::new();
+    let mut d = Some(diag);
+    while let Some(diag) = d {
+        exceptions.push(exception(diag));
+        quotes.extend(diag.quotes().iter().map(quote_value));
+        d = diag.cause();
+    }
+    exceptions.reverse();
+
+    let mut event = Event {
+        level: level(diag.effective_severity()),
+        message: Some(detail.to_string()),
+        exception: exceptions.into(),
+        ..Default::default()
+    };
+    event.tags.insert("diag.code".into(), diag.effective_code_string());
+    event.tags.insert("diag.severity".into(), diag.effective_severity().to_string());
+    if let Some(domain) = detail.domain() {
+        event.tags.insert("diag.domain".into(), domain.into());
+    }
+    if !quotes.is_empty() {
+        event.extra.insert("quotes".into(), Value::Array(quotes));
+    }
+    if let Some(c) = diag.context() {
+        if !c.is_empty() {
+            let values = c.iter().map(|(k, v)| (k.to_string(), Value::String(v.to_string()))).collect();
+            event.contexts.insert("diag".into(), protocol::Context::Other(values));
+        }
+    }
+    event
+}
+
+fn exception(diag: &dyn Diag) -> Exception {
+    let detail = diag.detail();
+    Exception {
+        ty: diag.effective_code_string(),
+        value: Some(detail.to_string()),
+        module: detail.domain().map(|d| d.to_string()),
+        stacktrace: diag.stacktrace().map(stacktrace),
+        ..Default::default()
+    }
+}
+
+fn stacktrace(s: &Stacktrace) -> protocol::Stacktrace {
+    let mut frames = s.with_backtrace(|b| {
+        b.frames()
+            .iter()
+            .flat_map(|f| f.symbols().iter().map(move |s| (f, s)))
+            .map(|(f, s)| Frame {
+                function: s.name().map(|n| n.to_string()),
+                filename: s.filename().map(|p| p.to_string_lossy().into_owned()),
+                lineno: s.lineno().map(|n| n as u64),
+                colno: s.colno().map(|n| n as u64),
+                instruction_addr: Some(protocol::Addr(f.ip() as u64)),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>()
+    });
+    // Sentry lists frames from the outermost one
+    frames.reverse();
+    protocol::Stacktrace {
+        frames,
+        ..Default::default()
+    }
+}
+
+fn quote_value(q: &Quote) -> Value {
+    json!({
+        "path": q.path().map(|p| p.to_string_lossy().into_owned()),
+        "line": q.span().start.line + 1,
+        "column": q.span().start.column + 1,
+        "span": q.span().to_string(),
+        "message": q.message(),
+        "source": q.source(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diag_to_event() {
+        let _request = context::scoped("request", "42");
+        let mut r = MemCharReader::with_path("input.txt", b"a = 1\nb = x\n");
+        let mut cause = ParseDiag::new(IoErrorDetail::Fmt);
+        let (from, to) = (Position::with(10, 1, 4), Position::with(11, 1, 5));
+        cause.add_quote(r.quote(from, to, 0, 0, "x".into()));
+        let d = BasicDiag::with_cause(
+            IoErrorDetail::MissingFinalNewline { pos: Position::with(12, 2, 0) },
+            cause,
+        );
+
+        let event = to_event(&d);
+        assert_eq!(event.level, Level::Warning);
+        assert_eq!(event.exception.values.len(), 2);
+        assert_eq!(event.exception.values[0].ty, IoErrorDetail::Fmt.code_string());
+        assert_eq!(event.exception.values[1].ty, d.effective_code_string());
+        assert_eq!(event.tags["diag.code"], d.effective_code_string());
+        let quotes = event.extra["quotes"].as_array().unwrap();
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(quotes[0]["path"], "input.txt");
+        assert_eq!(quotes[0]["line"], 2);
+        assert_eq!(quotes[0]["source"], "b = x");
+        match event.contexts["diag"] {
+            protocol::Context::Other(ref values) => assert_eq!(values["request"], "42"),
+            ref c => panic!("unexpected context {:?}", c),
+        }
+    }
+}
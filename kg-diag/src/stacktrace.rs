@@ -39,7 +39,7 @@ impl Inner {
                 }
             }
             if let Some(i) = first {
-                frames.drain(0..=(i + self.skip));
+                frames.drain(0..(i + self.skip + 1).min(frames.len()));
             }
             let mut last = None;
             for (mut i, f) in frames.iter().enumerate() {
@@ -53,7 +53,7 @@ impl Inner {
                             } else if n
                                 .starts_with("_ZN4test8run_test28_$u7b$$u7b$closure$u7d$$u7d$")
                             {
-                                i -= 1;
+                                i = i.saturating_sub(1);
                                 true
                             } else {
                                 false
@@ -71,7 +71,6 @@ impl Inner {
             if let Some(i) = last {
                 frames.drain(i..);
             }
-            assert!(!frames.is_empty());
             self.backtrace = Some(frames.into());
             self.resolved = true;
         }
@@ -93,11 +92,18 @@ impl Stacktrace {
     pub fn new() -> Self {
         Self::new_skip(0)
     }
+
+    /// Calls `f` with resolved backtrace, innermost frame first.
+    #[cfg(feature = "sentry")]
+    pub(crate) fn with_backtrace<R, F: FnOnce(&Backtrace) -> R>(&self, f: F) -> R {
+        let mut inner = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        f(inner.backtrace())
+    }
 }
 
 impl std::fmt::Display for Stacktrace {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let mut inner = self.0.lock().unwrap();
+        let mut inner = self.0.lock().unwrap_or_else(|e| e.into_inner());
         write!(f, "{:?}", inner.backtrace())
     }
 }
@@ -112,7 +118,7 @@ impl std::fmt::Debug for Stacktrace {
             }
         }
 
-        let inner = self.0.lock().unwrap();
+        let inner = self.0.lock().unwrap_or_else(|e| e.into_inner());
         f.debug_struct("Stacktrace")
             .field(
                 "backtrace",
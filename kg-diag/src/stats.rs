@@ -0,0 +1,137 @@
+//! Instrumentation counters for performance debugging of parser hot paths, collected only
+//! with `stats` feature enabled; recording functions compile to nothing otherwise.
+
+#[cfg(feature = "stats")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::*;
+
+#[cfg(feature = "stats")]
+static CHARS_DECODED: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "stats")]
+static PEEKS: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "stats")]
+static SEEKS: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "stats")]
+static QUOTES: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "stats")]
+static DIAGS: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Snapshot of counters, see `kg_diag::stats()`.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub chars_decoded: u64,
+    pub peeks: u64,
+    pub seeks: u64,
+    pub quotes: u64,
+    diags: [u64; 5],
+}
+
+#[cfg(feature = "stats")]
+impl Stats {
+    /// Number of diagnostics created with given severity.
+    pub fn diags(&self, severity: Severity) -> u64 {
+        self.diags[severity_index(severity)]
+    }
+
+    pub fn diags_total(&self) -> u64 {
+        self.diags.iter().sum()
+    }
+}
+
+#[cfg(feature = "stats")]
+fn severity_index(severity: Severity) -> usize {
+    match severity {
+        Severity::Info => 0,
+        Severity::Warning => 1,
+        Severity::Error => 2,
+        Severity::Failure => 3,
+        Severity::Critical => 4,
+    }
+}
+
+/// Current values of all counters, accumulated since start or last `reset_stats()`.
+#[cfg(feature = "stats")]
+pub fn stats() -> Stats {
+    let mut diags = [0; 5];
+    for (d, c) in diags.iter_mut().zip(DIAGS.iter()) {
+        *d = c.load(Ordering::Relaxed);
+    }
+    Stats {
+        chars_decoded: CHARS_DECODED.load(Ordering::Relaxed),
+        peeks: PEEKS.load(Ordering::Relaxed),
+        seeks: SEEKS.load(Ordering::Relaxed),
+        quotes: QUOTES.load(Ordering::Relaxed),
+        diags,
+    }
+}
+
+#[cfg(feature = "stats")]
+pub fn reset_stats() {
+    for c in [&CHARS_DECODED, &PEEKS, &SEEKS, &QUOTES]
+        .iter()
+        .copied()
+        .chain(DIAGS.iter())
+    {
+        c.store(0, Ordering::Relaxed);
+    }
+}
+
+#[inline(always)]
+pub(crate) fn record_char_decoded() {
+    #[cfg(feature = "stats")]
+    CHARS_DECODED.fetch_add(1, Ordering::Relaxed);
+}
+
+#[inline(always)]
+pub(crate) fn record_peek() {
+    #[cfg(feature = "stats")]
+    PEEKS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[inline(always)]
+pub(crate) fn record_seek() {
+    #[cfg(feature = "stats")]
+    SEEKS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[inline(always)]
+pub(crate) fn record_quote() {
+    #[cfg(feature = "stats")]
+    QUOTES.fetch_add(1, Ordering::Relaxed);
+}
+
+#[inline(always)]
+pub(crate) fn record_diag(_severity: Severity) {
+    #[cfg(feature = "stats")]
+    DIAGS[severity_index(_severity)].fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_increase() {
+        // counters are global and tests run in parallel, so only lower bounds can be checked
+        let before = stats();
+        let mut r = MemCharReader::new(b"abc");
+        r.peek_char(0).unwrap();
+        r.next_char().unwrap();
+        r.seek(Position::new()).unwrap();
+        let _ = BasicDiag::new(String::from("error"));
+        let after = stats();
+
+        assert!(after.peeks >= before.peeks + 1);
+        assert!(after.chars_decoded >= before.chars_decoded + 2);
+        assert!(after.seeks >= before.seeks + 1);
+        assert!(after.diags(Severity::Failure) >= before.diags(Severity::Failure) + 1);
+    }
+}
@@ -0,0 +1,115 @@
+use super::*;
+
+/// Confidence that a suggestion is correct, telling tools whether it can be applied
+/// automatically. Levels match those used by rustc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Applicability {
+    /// Suggestion is definitely what the user intended and can be applied automatically.
+    MachineApplicable,
+    /// Suggestion may be what the user intended, but it is uncertain.
+    MaybeIncorrect,
+    /// Replacement contains placeholders (like `<type>`) to be filled in by the user.
+    HasPlaceholders,
+    Unspecified,
+}
+
+impl Default for Applicability {
+    fn default() -> Applicability {
+        Applicability::Unspecified
+    }
+}
+
+/// Fix-it suggestion replacing `span` of the source with `replacement`. Empty span inserts
+/// the replacement, empty replacement removes the span.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+    pub message: String,
+    pub applicability: Applicability,
+}
+
+impl Suggestion {
+    pub fn new<R: Into<String>, M: Into<String>>(
+        span: Span,
+        replacement: R,
+        message: M,
+    ) -> Suggestion {
+        Suggestion {
+            span,
+            replacement: replacement.into(),
+            message: message.into(),
+            applicability: Applicability::Unspecified,
+        }
+    }
+
+    pub fn with_applicability(mut self, applicability: Applicability) -> Suggestion {
+        self.applicability = applicability;
+        self
+    }
+
+    /// Describes the edit, e.g. ``replace 1:5-8 with `foo` ``.
+    pub fn describe(&self) -> String {
+        if self.replacement.is_empty() {
+            format!("remove {}", self.span)
+        } else if self.span.start.offset == self.span.end.offset {
+            format!("insert `{}` at {}", self.replacement, self.span.start)
+        } else {
+            format!("replace {} with `{}`", self.span, self.replacement)
+        }
+    }
+}
+
+/// Applies `suggestions` to `source`, which must be the text their spans were taken from.
+/// Suggestions overlapping an already applied one (in order of span start) or not matching
+/// `source` are skipped and returned along with the edited text, so that tools can report
+/// them or apply them in another pass.
+pub fn apply_suggestions<'a, I>(source: &str, suggestions: I) -> (String, Vec<&'a Suggestion>)
+where
+    I: IntoIterator<Item = &'a Suggestion>,
+{
+    let mut sorted: Vec<&Suggestion> = suggestions.into_iter().collect();
+    sorted.sort_by_key(|s| (s.span.start.offset, s.span.end.offset));
+
+    let mut out = String::with_capacity(source.len());
+    let mut skipped = Vec::new();
+    let mut copied = 0;
+    for s in sorted {
+        let (start, end) = (s.span.start.offset, s.span.end.offset);
+        if start < copied
+            || start > end
+            || end > source.len()
+            || !source.is_char_boundary(start)
+            || !source.is_char_boundary(end)
+        {
+            skipped.push(s);
+            continue;
+        }
+        out.push_str(&source[copied..start]);
+        out.push_str(&s.replacement);
+        copied = end;
+    }
+    out.push_str(&source[copied..]);
+    (out, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let source = "let x = foo(a b);\n";
+        let insert = Suggestion::new(Span::with(13, 0, 13, 13, 0, 13), ",", "missing comma")
+            .with_applicability(Applicability::MachineApplicable);
+        let replace = Suggestion::new(Span::with(8, 0, 8, 11, 0, 11), "bar", "did you mean");
+        let overlapping = Suggestion::new(Span::with(10, 0, 10, 12, 0, 12), "", "");
+        assert_eq!(insert.describe(), "insert `,` at 1:14");
+        assert_eq!(replace.describe(), "replace 1:9-12 with `bar`");
+        assert_eq!(overlapping.describe(), "remove 1:11-13");
+
+        let (fixed, skipped) = apply_suggestions(source, vec![&insert, &overlapping, &replace]);
+        assert_eq!(fixed, "let x = bar(a, b);\n");
+        assert_eq!(skipped, vec![&overlapping]);
+    }
+}
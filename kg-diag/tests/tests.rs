@@ -66,6 +66,49 @@ fn macro_diags_with_kind_and_quotes() {
     assert!(es.contains("  2| line 2;\n   | ^^^^^^^ msg\n"));
 }
 
+#[test]
+fn macro_hooks_called_with_created_diag() {
+    let input = "line 1;\nline 2;\n";
+    let ref mut r = MemCharReader::new(input.as_bytes());
+    let (p1, p2) = (Position::with(8, 1, 0), Position::with(12, 1, 4));
+    let seen = std::cell::RefCell::new(Vec::new());
+    let hook = |d: &dyn Diag| seen.borrow_mut().push((d.detail().code(), d.quotes().len()));
+
+    let e = basic_diag!(hook, TestErrorKind::ErrorEmpty);
+    assert_eq!(e.detail().code(), 1);
+    let e = parse_diag!(hook, TestErrorKind::ErrorWithPair(1, 2));
+    assert_eq!(e.detail().code(), 2);
+    let e = parse_diag!(hook, TestErrorKind::ErrorWithString("a".into()), r, {
+        p1, p2 => "first",
+        p1, p1 => "second",
+    });
+    assert_eq!(e.quotes().len(), 2);
+
+    assert_eq!(*seen.borrow(), vec![(1, 0), (2, 0), (3, 2)]);
+}
+
+mod hygiene {
+    // no imports from kg_diag, macros must not depend on names in scope at call site
+
+    #[test]
+    fn macros_expand_without_imports() {
+        let mut reader = kg_diag::MemCharReader::new(b"abc\n");
+        let r = &mut reader;
+        let p = kg_diag::Position::with(1, 0, 1);
+
+        let e = kg_diag::basic_diag!(super::TestErrorKind::ErrorEmpty);
+        assert!(e.to_string().contains("empty"));
+        let e = kg_diag::basic_diag!(|_| (), super::TestErrorKind::ErrorEmpty);
+        assert!(e.to_string().contains("empty"));
+        let e = kg_diag::parse_diag!(super::TestErrorKind::ErrorEmpty);
+        assert!(e.to_string().contains("empty"));
+        let e = kg_diag::parse_diag!(super::TestErrorKind::ErrorEmpty, r, { p, p => "here" });
+        assert!(e.to_string().contains("here"));
+        let e = kg_diag::parse_diag!(|_| (), super::TestErrorKind::ErrorEmpty, r, { p, p => "there" });
+        assert!(e.to_string().contains("there"));
+    }
+}
+
 #[test]
 fn consume_bom() {
     let input = "\u{EF}\u{BB}\u{BF} and characters after BOM";
@@ -81,3 +124,149 @@ fn consume_bom_without_bom() {
     let c = r.peek_char(0).unwrap().unwrap();
     assert_eq!('c', c);
 }
+
+#[test]
+fn sort_key_orders_by_location_then_code() {
+    let input = "line 1;\nline 2;\n";
+    let ref mut r = MemCharReader::with_path("src/example.txt", input.as_bytes());
+    r.peek_char(0).unwrap();
+    let p1 = r.position();
+    r.skip_chars(8).unwrap();
+    let p2 = r.position();
+
+    let late = parse_diag!(TestErrorKind::ErrorEmpty, r, { p2, p2 => "late" });
+    let early = parse_diag!(TestErrorKind::ErrorWithPair(1, 2), r, { p1, p1 => "early" });
+    let unquoted = parse_diag!(TestErrorKind::ErrorWithPair(1, 2));
+
+    assert!(early.sort_key() < late.sort_key());
+    assert!(unquoted.sort_key() < early.sort_key());
+    assert_eq!(early.sort_key().code, 2);
+
+    let mut diags = Diags::new();
+    let _ = diags.add_diag(late);
+    let _ = diags.add_diag(early);
+    diags.sort();
+    assert_eq!(diags.diags()[0].quotes()[0].message(), "early");
+}
+
+#[test]
+fn max_quotes_summarizes_remaining() {
+    let input = "line 1;\nline 2;\nline 3;\nline 4;\n";
+    let ref mut r = MemCharReader::with_path("src/example.txt", input.as_bytes());
+    r.peek_char(0).unwrap();
+    let p1 = r.position();
+
+    let mut e = parse_diag!(TestErrorKind::ErrorEmpty, r, {
+        p1, p1 => "first",
+        p1, p1 => "second",
+        p1, p1 => "third",
+    });
+    e.set_max_quotes(1);
+
+    let es = e.to_string();
+    assert!(es.contains("first"));
+    assert!(!es.contains("second"));
+    assert!(es.contains("\u{2026} and 2 more locations\n"));
+    assert_eq!(e.quotes().len(), 3);
+}
+
+#[test]
+fn rollback_discards_speculative_diags() {
+    let mut diags = Diags::new();
+    let _ = diags.add_diag(SimpleDiag::new(String::from("kept")));
+    let checkpoint = diags.checkpoint();
+
+    let _ = diags.add_diag(SimpleDiag::new(String::from("speculative")));
+    assert_eq!(diags.diags().len(), 2);
+
+    diags.rollback_to(checkpoint);
+    assert_eq!(diags.diags().len(), 1);
+    assert_eq!(diags.diags()[0].detail().to_string(), "kept");
+    assert_eq!(diags.checkpoint(), checkpoint);
+}
+
+#[test]
+fn rollback_discards_speculative_deferred_diags() {
+    let mut diags = Diags::new();
+    diags.add_deferred("kept", SimpleDiag::new(String::from("undefined kept")));
+    let checkpoint = diags.checkpoint();
+
+    diags.add_deferred("foo", SimpleDiag::new(String::from("undefined foo")));
+    diags.add_deferred("bar", SimpleDiag::new(String::from("undefined bar")));
+    let _ = diags.confirm("bar");
+    assert_eq!(diags.diags().len(), 1);
+
+    diags.rollback_to(checkpoint);
+    assert!(diags.diags().is_empty());
+    assert!(diags.has_deferred("kept"));
+    assert!(!diags.has_deferred("foo"));
+    assert_eq!(diags.checkpoint(), checkpoint);
+
+    assert!(diags.confirm_all().is_err());
+    assert_eq!(diags.diags().len(), 1);
+    assert_eq!(diags.diags()[0].detail().to_string(), "undefined kept");
+}
+
+#[test]
+fn deferred_diags_confirmed_or_cancelled() {
+    let mut diags = Diags::new();
+    diags.add_deferred("foo", SimpleDiag::new(String::from("undefined foo")));
+    diags.add_deferred("bar", SimpleDiag::new(String::from("undefined bar")));
+    assert!(diags.diags().is_empty());
+    assert!(diags.has_deferred("foo"));
+
+    diags.cancel("foo");
+    assert!(!diags.has_deferred("foo"));
+
+    assert!(diags.confirm("bar").is_err());
+    assert_eq!(diags.diags().len(), 1);
+    assert_eq!(diags.diags()[0].detail().to_string(), "undefined bar");
+    assert!(diags.confirm_all().is_ok());
+}
+
+#[test]
+fn builtin_codes_within_range() {
+    use kg_diag::parse::ParseErrorDetail;
+
+    let codes = ParseErrorDetail::codes();
+    assert!(codes.iter().all(|c| ParseErrorDetail::CODE_RANGE.contains(&c.0)));
+    assert!(codes.windows(2).all(|w| w[0].0 < w[1].0));
+    assert!(codes.contains(&(IoErrorDetail::CODE_FMT, "formatting error")));
+
+    let err = IoErrorDetail::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+    assert!(IoErrorDetail::codes().iter().any(|c| c.0 == err.code()));
+    assert_eq!(IoErrorDetail::Fmt.code(), IoErrorDetail::CODE_FMT);
+}
+
+#[test]
+fn io_codes_are_stable() {
+    use std::io::ErrorKind;
+
+    let code = |kind| IoErrorDetail::from(std::io::Error::from(kind)).code();
+    assert_eq!(code(ErrorKind::NotFound), IoErrorDetail::CODE_IO_BASE);
+    assert_eq!(code(ErrorKind::Other), IoErrorDetail::CODE_IO_OTHER);
+    assert_eq!(code(ErrorKind::UnexpectedEof), 18);
+    assert_eq!(code(ErrorKind::OutOfMemory), IoErrorDetail::CODE_IO_OTHER);
+}
+
+#[test]
+fn io_error_of_unlisted_kind_displayed() {
+    let err = IoErrorDetail::from(std::io::Error::from(std::io::ErrorKind::OutOfMemory));
+    assert_eq!(err.to_string(), "other I/O error");
+}
+
+#[test]
+fn expected_with_example() {
+    use kg_diag::parse::{Expected, ParseErrorDetail};
+
+    let err = ParseErrorDetail::UnexpectedInput {
+        pos: Position::with(4, 0, 4),
+        found: None,
+        expected: Some(Expected::Custom("a duration".into()).with_example("250ms")),
+        task: "parsing timeout".into(),
+    };
+    assert_eq!(
+        err.to_string(),
+        "unexpected input at 1:5 while parsing timeout, expecting a duration (e.g. \"250ms\")"
+    );
+}